@@ -2,6 +2,7 @@ extern crate autograd as ag;
 #[macro_use(s)]
 extern crate ndarray;
 
+use ag::gradient_descent_ops::Optimizer;
 use std::time::Instant;
 
 type Tensor = ag::Tensor<f32>;
@@ -51,7 +52,6 @@ fn main() {
     let ref b2 = ag::variable(ag::ndarray_ext::zeros(&[1, 64, 14, 14]));
     let ref b3 = ag::variable(ag::ndarray_ext::zeros(&[1, 10]));
     let params = &[w1, w2, w3, b1, b2, b3];
-    let ref params_adam = ag::gradient_descent_ops::Adam::vars_with_states(params);
     let (x, y) = inputs();
     let z1 = conv_pool(&x, w1, b1); // map to 32 channel
     let z2 = conv_pool(&z1, w2, b2); // map to 64 channel
@@ -60,7 +60,7 @@ fn main() {
     let loss = ag::sparse_softmax_cross_entropy(&logits, &y);
     let grads = &ag::grad(&[&loss], params);
     let adam = ag::gradient_descent_ops::Adam::default();
-    let update_ops: &[Tensor] = &adam.compute_updates(params_adam, grads);
+    let update_ops: &[Tensor] = &adam.compute_updates(params, grads);
 
     // -- actual training --
     let max_epoch = 5;
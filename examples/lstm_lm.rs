@@ -5,6 +5,8 @@ type Tensor = ag::Tensor<f32>;
 
 struct LSTM {
     vector_dim: usize,
+    // Recomputes each step in backprop instead of keeping its activations.
+    checkpointing: bool,
     hs: Vec<Tensor>,
     cells: Vec<Tensor>,
     wx: Tensor,
@@ -13,9 +15,10 @@ struct LSTM {
 }
 
 impl LSTM {
-    fn new(vector_dim: usize) -> LSTM {
+    fn new(vector_dim: usize, checkpointing: bool) -> LSTM {
         LSTM {
             vector_dim,
+            checkpointing,
             hs: vec![],
             cells: vec![],
             wx: ag::variable(ag::ndarray_ext::random_normal(
@@ -38,28 +41,39 @@ impl LSTM {
     /// # Returns
     /// Output tensor of this unit with shape `(batch_size, state_size)`.
     fn step(&mut self, x: &Tensor) -> &Tensor {
-        let (cell, h) = {
-            let ref last_output = self.hs.pop().unwrap_or_else(|| ag::zeros(&x.shape()));
-            let ref last_cell = self.cells.pop().unwrap_or_else(|| ag::zeros(&x.shape()));
+        let ref last_output = self.hs.pop().unwrap_or_else(|| ag::zeros(&x.shape()));
+        let ref last_cell = self.cells.pop().unwrap_or_else(|| ag::zeros(&x.shape()));
+        let inputs = [x, last_output, last_cell, &self.wx, &self.wh, &self.b];
 
-            let ref xh = ag::matmul(x, &self.wx) + ag::matmul(last_output, &self.wh) + &self.b;
-
-            let size = self.vector_dim as isize;
-            let ref i = ag::slice(xh, &[0, 0 * size], &[-1, 1 * size]);
-            let ref f = ag::slice(xh, &[0, 1 * size], &[-1, 2 * size]);
-            let ref c = ag::slice(xh, &[0, 2 * size], &[-1, 3 * size]);
-            let ref o = ag::slice(xh, &[0, 3 * size], &[-1, 4 * size]);
-
-            let cell = ag::sigmoid(f) * last_cell + ag::sigmoid(i) * ag::tanh(c);
-            let h = ag::sigmoid(o) * ag::tanh(&cell);
-            (cell, h)
+        let size = self.vector_dim as isize;
+        let mut ys = if self.checkpointing {
+            // Gradients flow only into `inputs`, so the variables are passed as well.
+            ag::checkpoint(move |xs| lstm_cell(xs, size), &inputs)
+        } else {
+            lstm_cell(&inputs.iter().map(|&a| a.clone()).collect::<Vec<_>>(), size)
         };
-        self.cells.push(cell);
-        self.hs.push(h);
+        self.hs.push(ys.pop().unwrap());
+        self.cells.push(ys.pop().unwrap());
         self.hs.last().as_ref().unwrap()
     }
 }
 
+/// Returns `[cell, h]` from `[x, last_output, last_cell, wx, wh, b]`.
+fn lstm_cell(xs: &[Tensor], size: isize) -> Vec<Tensor> {
+    let (x, last_output, last_cell) = (&xs[0], &xs[1], &xs[2]);
+    let (wx, wh, b) = (&xs[3], &xs[4], &xs[5]);
+    let ref xh = ag::matmul(x, wx) + ag::matmul(last_output, wh) + b;
+
+    let ref i = ag::slice(xh, &[0, 0 * size], &[-1, 1 * size]);
+    let ref f = ag::slice(xh, &[0, 1 * size], &[-1, 2 * size]);
+    let ref c = ag::slice(xh, &[0, 2 * size], &[-1, 3 * size]);
+    let ref o = ag::slice(xh, &[0, 3 * size], &[-1, 4 * size]);
+
+    let cell = ag::sigmoid(f) * last_cell + ag::sigmoid(i) * ag::tanh(c);
+    let h = ag::sigmoid(o) * ag::tanh(&cell);
+    vec![cell, h]
+}
+
 // TODO: Use real-world data
 // TODO: Write in define-by-run style
 pub fn main() {
@@ -67,8 +81,10 @@ pub fn main() {
     let max_sent = 2;
     let vocab_size = 5;
 
-    let ref sentences = ag::placeholder(&[-1, max_sent]);
-    let ref mut rnn = LSTM::new(vec_dim);
+    let ref sentences = ag::placeholder(&[-1, max_sent + 1]);
+    // Trades computation for memory in backprop
+    let checkpointing = true;
+    let ref mut rnn = LSTM::new(vec_dim, checkpointing);
 
     let lookup_table = &ag::variable(ag::ndarray_ext::random_normal(
         &[vocab_size, vec_dim],
@@ -86,7 +102,10 @@ pub fn main() {
         .map(|i| {
             let cur_id = ag::slice(sentences, &[0, i], &[-1, i + 1]);
             let next_id = ag::slice(sentences, &[0, i + 1], &[-1, i + 2]);
-            let x = ag::gather(lookup_table, &cur_id, 0);
+            let x = ag::reshape(
+                &ag::gather(lookup_table, &cur_id, 0),
+                &[-1, vec_dim as isize],
+            );
             let h = rnn.step(&x);
             let prediction = ag::matmul(h, w_pred);
             ag::sparse_softmax_cross_entropy(prediction, next_id)
@@ -2,6 +2,7 @@ extern crate autograd as ag;
 #[macro_use(s)]
 extern crate ndarray;
 
+use ag::gradient_descent_ops::Optimizer;
 use std::time::Instant;
 
 type Tensor = ag::Tensor<f32>;
@@ -42,13 +43,14 @@ fn main() {
     // -- variable tensors (target of optimization) --
     let w = &ag::variable(ag::ndarray_ext::glorot_uniform(&[28 * 28, 10]));
     let b = &ag::variable(ag::ndarray_ext::zeros(&[1, 10]));
-    let params = &ag::gradient_descent_ops::Adam::vars_with_states(&[w, b]);
     let (x, y) = inputs();
     let z = logits(&x, w, b);
     let loss = ag::sparse_softmax_cross_entropy(z, &y);
     let grads = &ag::grad(&[&loss], &[w, b]);
     let adam = ag::gradient_descent_ops::Adam::default();
-    let update_ops: &[Tensor] = &adam.compute_updates(params, grads);
+    let update_ops: &[Tensor] = &adam.compute_updates(&[w, b], grads);
+    // Traverses the graph only once; each `run` just executes ops.
+    let train_step = ag::CompiledGraph::new(update_ops, &[&x, &y]);
 
     // -- actual training --
     let max_epoch = 3;
@@ -63,7 +65,7 @@ fn main() {
                 let i = *i as isize;
                 let x_batch = x_train.slice(s![i..i + batch_size, ..]).into_dyn();
                 let y_batch = y_train.slice(s![i..i + batch_size, ..]).into_dyn();
-                ag::eval(update_ops, &[ag::Feed(&x, x_batch), ag::Feed(&y, y_batch)]);
+                train_step.run(&[x_batch, y_batch]);
             }
         });
         println!("finish epoch {}", epoch);
@@ -0,0 +1,289 @@
+//! Saving and loading variables.
+//!
+//! A checkpoint is a file of named arrays with their shapes and float types.
+//!
+//! ```
+//! extern crate autograd as ag;
+//!
+//! let ref w = ag::variable(ag::ndarray_ext::standard_normal::<f32>(&[3, 2]));
+//! let ref b = ag::variable(ag::ndarray_ext::zeros::<f32>(&[1, 2]));
+//! let path = std::env::temp_dir().join("autograd_doctest_checkpoint");
+//! ag::checkpoint::save(&path, &[("w", w), ("b", b)]).unwrap();
+//!
+//! // Load into the variables of another model of the same shapes.
+//! let ref w2 = ag::variable(ag::ndarray_ext::zeros::<f32>(&[3, 2]));
+//! let ref b2 = ag::variable(ag::ndarray_ext::zeros::<f32>(&[1, 2]));
+//! ag::checkpoint::load(&path, &[("w", w2), ("b", b2)]).unwrap();
+//! assert_eq!(*w.get_persistent_array().unwrap(), *w2.get_persistent_array().unwrap());
+//! ```
+use crate::ndarray_ext::NdArray;
+use crate::tensor::Tensor;
+use crate::Float;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"AGCKPT01";
+
+/// Error in loading a checkpoint.
+#[derive(Debug)]
+pub enum CheckpointError {
+    /// Failed to read the file, or it's broken.
+    Io(io::Error),
+    /// Names of the given variables don't match the ones in the checkpoint.
+    KeyMismatch {
+        /// Given, but not in the checkpoint
+        missing: Vec<String>,
+        /// In the checkpoint, but not given
+        unexpected: Vec<String>,
+    },
+    /// A variable's shape differs from the array in the checkpoint.
+    ShapeMismatch {
+        key: String,
+        expected: Vec<usize>,
+        actual: Vec<usize>,
+    },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "{}", e),
+            CheckpointError::KeyMismatch {
+                missing,
+                unexpected,
+            } => write!(
+                f,
+                "missing keys: {:?}, unexpected keys: {:?}",
+                missing, unexpected
+            ),
+            CheckpointError::ShapeMismatch {
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "variable {} has shape {:?}, but got {:?}",
+                key, expected, actual
+            ),
+        }
+    }
+}
+
+impl error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> CheckpointError {
+        CheckpointError::Io(e)
+    }
+}
+
+/// Named arrays of a checkpoint file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint<T: Float> {
+    pub arrays: BTreeMap<String, NdArray<T>>,
+}
+
+impl<T: Float> Checkpoint<T> {
+    /// Copies the arrays of the named variables.
+    pub fn from_variables(vars: &[(&str, &Tensor<T>)]) -> Checkpoint<T> {
+        let arrays = vars
+            .iter()
+            .map(|&(key, var)| {
+                let arr = var
+                    .get_persistent_array()
+                    .expect("Can't save non-variable.");
+                (key.to_string(), arr.clone())
+            })
+            .collect();
+        Checkpoint { arrays }
+    }
+
+    /// Writes the arrays to `path` in the precision of `T`.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        write_u64(&mut w, self.arrays.len() as u64)?;
+        for (key, arr) in &self.arrays {
+            write_str(&mut w, key)?;
+            write_array(&mut w, arr)?;
+        }
+        w.flush()
+    }
+
+    /// Reads a file written by `write`.
+    ///
+    /// Arrays saved in another float type are converted to `T`.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint<T>> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not a checkpoint file".to_string()));
+        }
+        let mut arrays = BTreeMap::new();
+        for _ in 0..read_u64(&mut r)? {
+            let key = read_str(&mut r)?;
+            arrays.insert(key, read_array(&mut r)?);
+        }
+        Ok(Checkpoint { arrays })
+    }
+
+    /// Copies the arrays into the named variables.
+    ///
+    /// Nothing is copied unless the names match the keys one to one and the shapes match.
+    pub fn restore(&self, vars: &[(&str, &Tensor<T>)]) -> Result<(), CheckpointError> {
+        let missing = vars
+            .iter()
+            .filter(|(key, _)| !self.arrays.contains_key(*key))
+            .map(|(key, _)| key.to_string())
+            .collect::<Vec<_>>();
+        let unexpected = self
+            .arrays
+            .keys()
+            .filter(|key| !vars.iter().any(|(k, _)| k == key))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() || !unexpected.is_empty() {
+            return Err(CheckpointError::KeyMismatch {
+                missing,
+                unexpected,
+            });
+        }
+        for &(key, var) in vars {
+            let expected = var
+                .get_persistent_array()
+                .expect("Can't restore non-variable.")
+                .shape()
+                .to_vec();
+            let actual = self.arrays[key].shape();
+            if expected != actual {
+                return Err(CheckpointError::ShapeMismatch {
+                    key: key.to_string(),
+                    expected,
+                    actual: actual.to_vec(),
+                });
+            }
+        }
+        for &(key, var) in vars {
+            let mut dst = var
+                .get_persistent_array_mut()
+                .expect("Can't restore constant.");
+            dst.assign(&self.arrays[key]);
+        }
+        Ok(())
+    }
+}
+
+/// Saves the named variables to `path`.
+pub fn save<T: Float, P: AsRef<Path>>(path: P, vars: &[(&str, &Tensor<T>)]) -> io::Result<()> {
+    Checkpoint::from_variables(vars).write(path)
+}
+
+/// Loads the checkpoint at `path` into the named variables.
+///
+/// See `Checkpoint::restore`.
+pub fn load<T: Float, P: AsRef<Path>>(
+    path: P,
+    vars: &[(&str, &Tensor<T>)],
+) -> Result<(), CheckpointError> {
+    Checkpoint::read(path)?.restore(vars)
+}
+
+pub(crate) fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn write_u64<W: Write>(w: &mut W, n: u64) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+pub(crate) fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write_u64(w, s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+/// Writes the float size of `T`, the shape and the elements of `arr` in little endian.
+pub(crate) fn write_array<T: Float, W: Write>(w: &mut W, arr: &NdArray<T>) -> io::Result<()> {
+    w.write_all(&[mem::size_of::<T>() as u8])?;
+    write_u64(w, arr.ndim() as u64)?;
+    for &d in arr.shape() {
+        write_u64(w, d as u64)?;
+    }
+    for &a in arr.iter() {
+        if mem::size_of::<T>() == 4 {
+            w.write_all(&a.to_f32().unwrap().to_le_bytes())?;
+        } else {
+            w.write_all(&a.to_f64().unwrap().to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn read_str<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_u64(r)?;
+    let buf = read_bytes(r, len)?;
+    String::from_utf8(buf).map_err(|e| invalid_data(e.to_string()))
+}
+
+// Reads `len` bytes without trusting `len` for the allocation, which grows with the
+// bytes actually read.
+fn read_bytes<R: Read>(r: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        let msg = format!(
+            "Expected {} bytes, but the data ends after {}",
+            len,
+            buf.len()
+        );
+        return Err(invalid_data(msg));
+    }
+    Ok(buf)
+}
+
+/// Reads an array written by `write_array`.
+pub(crate) fn read_array<T: Float, R: Read>(r: &mut R) -> io::Result<NdArray<T>> {
+    let mut size = [0];
+    r.read_exact(&mut size)?;
+    let size = size[0] as usize;
+    if size != 4 && size != 8 {
+        return Err(invalid_data(format!("Unsupported float size: {}", size)));
+    }
+    let ndim = read_u64(r)?;
+    let mut shape = Vec::new();
+    let mut num_bytes = Some(size as u64);
+    for _ in 0..ndim {
+        let d = read_u64(r)?;
+        num_bytes = num_bytes.and_then(|n| n.checked_mul(d));
+        shape.push(d as usize);
+    }
+    let num_bytes =
+        num_bytes.ok_or_else(|| invalid_data(format!("Too large shape: {:?}", shape)))?;
+    let bytes = read_bytes(r, num_bytes)?;
+    let data = bytes
+        .chunks_exact(size)
+        .map(|b| {
+            if size == 4 {
+                let mut buf = [0; 4];
+                buf.copy_from_slice(b);
+                T::from(f32::from_le_bytes(buf)).unwrap()
+            } else {
+                let mut buf = [0; 8];
+                buf.copy_from_slice(b);
+                T::from(f64::from_le_bytes(buf)).unwrap()
+            }
+        })
+        .collect();
+    Ok(NdArray::from_shape_vec(ndarray::IxDyn(&shape), data).unwrap())
+}
@@ -0,0 +1,125 @@
+//! Export of graphs to the Graphviz DOT language
+use crate::tensor::Tensor;
+use crate::Float;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Returns a Graphviz DOT description of the graph that `targets` depend on.
+///
+/// Each node is labelled with its name if any, op name, static shape if known, and
+/// whether it's a placeholder, variable or constant. Edges go from inputs to their consumers, labelled
+/// with the input index if there are several inputs.
+///
+/// Nodes made by `ag::grad` etc. are grouped in a cluster if `include_gradients`, and
+/// omitted otherwise (their inputs are still traversed).
+///
+/// ```
+/// extern crate autograd as ag;
+///
+/// let ref x = ag::placeholder::<f32>(&[-1, 3]);
+/// let ref w = ag::variable(ag::ndarray_ext::zeros(&[3, 2]));
+/// let ref y = ag::reduce_sum(&ag::matmul(x, w), &[0, 1], false);
+/// let ref g = ag::grad(&[y], &[w]);
+///
+/// let dot = ag::graph_to_dot(&[g[0].clone()], true);
+/// assert!(dot.starts_with("digraph {"));
+/// assert!(dot.contains("MatMul"));
+/// // e.g. `dot -Tsvg graph.dot -o graph.svg`
+/// std::fs::write(std::env::temp_dir().join("graph.dot"), dot).unwrap();
+/// ```
+pub fn graph_to_dot<T: Float, A: AsRef<Tensor<T>>>(
+    targets: &[A],
+    include_gradients: bool,
+) -> String {
+    // Collects the reachable nodes once each.
+    let mut visited = BTreeSet::new();
+    let mut nodes = Vec::new();
+    let mut stack = targets.iter().map(|t| t.as_ref()).collect::<Vec<_>>();
+    while let Some(t) = stack.pop() {
+        if !visited.insert(t.id()) {
+            continue;
+        }
+        if include_gradients || !t.in_gradient_graph {
+            nodes.push(t);
+        }
+        stack.extend(t.inputs.iter());
+    }
+    nodes.sort_by_key(|t| t.top_rank);
+    let names = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.id(), format!("n{}", i)))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut forward = String::new();
+    let mut backward = String::new();
+    let mut edges = String::new();
+    for t in &nodes {
+        let name = &names[&t.id()];
+        let dst = if t.in_gradient_graph {
+            &mut backward
+        } else {
+            &mut forward
+        };
+        writeln!(dst, "    {} [{}];", name, attributes(t)).unwrap();
+        for (i, input) in t.inputs.iter().enumerate() {
+            if let Some(src) = names.get(&input.id()) {
+                if t.inputs.len() > 1 {
+                    writeln!(edges, "    {} -> {} [label=\"{}\"];", src, name, i).unwrap();
+                } else {
+                    writeln!(edges, "    {} -> {};", src, name).unwrap();
+                }
+            }
+        }
+    }
+
+    let mut dot = "digraph {\n".to_string();
+    dot.push_str(&forward);
+    if !backward.is_empty() {
+        dot.push_str("    subgraph cluster_gradients {\n        label=\"gradients\";\n");
+        dot.push_str("        style=dashed;\n");
+        for line in backward.lines() {
+            writeln!(dot, "    {}", line).unwrap();
+        }
+        dot.push_str("    }\n");
+    }
+    dot.push_str(&edges);
+    dot.push_str("}\n");
+    dot
+}
+
+fn attributes<T: Float>(t: &Tensor<T>) -> String {
+    let mut label = escape(t.op.name());
+    if let Some(name) = t.name() {
+        label = format!("{}\\n{}", escape(name), label);
+    }
+    let shape = match (&t.known_shape, t.get_persistent_array()) {
+        (Some(shape), _) => Some(format!("{:?}", shape.get())),
+        (None, Some(arr)) => Some(format!("{:?}", arr.shape())),
+        _ => None,
+    };
+    if let Some(shape) = shape {
+        label.push_str("\\n");
+        label.push_str(&shape);
+    }
+    let kind = if t.is_placeholder {
+        Some(("placeholder", "khaki"))
+    } else if t.is_variable() {
+        Some(("variable", "lightblue"))
+    } else if t.has_persistent_array() {
+        Some(("constant", "lightgray"))
+    } else {
+        None
+    };
+    match kind {
+        Some((kind, color)) => format!(
+            "label=\"{}\\n{}\", style=filled, fillcolor={}",
+            label, kind, color
+        ),
+        None => format!("label=\"{}\", shape=box", label),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -1,10 +1,80 @@
 use crate::tensor::Tensor;
 use crate::Float;
+use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::collections::binary_heap::BinaryHeap;
+use std::collections::HashMap;
 use std::fmt;
 use std::mem;
-use std::rc::Rc;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+
+thread_local! {
+    // `symbolic_gradients` calls running in this thread, innermost last
+    static GRADIENT_SCOPES: RefCell<Vec<ScopeEntry>> = const { RefCell::new(Vec::new()) };
+}
+
+static NEXT_SCOPE_ID: AtomicUsize = AtomicUsize::new(0);
+
+struct ScopeEntry {
+    id: usize,
+    on_exit: Vec<Box<dyn FnOnce()>>,
+}
+
+/// Returns `true` while `symbolic_gradients` is building a gradient graph in this thread.
+#[inline]
+pub fn in_gradient_construction() -> bool {
+    GRADIENT_SCOPES.with(|s| !s.borrow().is_empty())
+}
+
+/// Returns an id unique to the innermost `symbolic_gradients` call running in this thread.
+#[inline]
+pub(crate) fn current_gradient_scope() -> Option<usize> {
+    GRADIENT_SCOPES.with(|s| s.borrow().last().map(|e| e.id))
+}
+
+/// Registers `f` to be called when the innermost `symbolic_gradients` call returns.
+///
+/// `f` is called immediately if no gradient is under construction.
+pub(crate) fn on_gradient_scope_exit(f: Box<dyn FnOnce()>) {
+    let f = GRADIENT_SCOPES.with(|s| match s.borrow_mut().last_mut() {
+        Some(e) => {
+            e.on_exit.push(f);
+            None
+        }
+        None => Some(f),
+    });
+    if let Some(f) = f {
+        f();
+    }
+}
+
+// Marks the tensors built in its lifetime as a part of a gradient graph.
+struct GradientScope;
+
+impl GradientScope {
+    #[inline]
+    fn enter() -> GradientScope {
+        let id = NEXT_SCOPE_ID.fetch_add(1, atomic::Ordering::Relaxed);
+        GRADIENT_SCOPES.with(|s| {
+            s.borrow_mut().push(ScopeEntry {
+                id,
+                on_exit: Vec::new(),
+            })
+        });
+        GradientScope
+    }
+}
+
+impl Drop for GradientScope {
+    #[inline]
+    fn drop(&mut self) {
+        let entry = GRADIENT_SCOPES.with(|s| s.borrow_mut().pop());
+        for f in entry.into_iter().flat_map(|e| e.on_exit) {
+            f();
+        }
+    }
+}
 
 struct GradInfo<'a, T: Float + 'a> {
     node: &'a Tensor<T>, // information of this node
@@ -16,7 +86,7 @@ struct GradInfo<'a, T: Float + 'a> {
 
 impl<'a, T: Float> fmt::Display for GradInfo<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "name={}", self.node.op.name(),)
+        write!(f, "name={}", self.node.label())
     }
 }
 
@@ -39,24 +109,39 @@ impl<'a, T: Float> GradInfo<'a, T> {
 
 impl<'a, T: Float> fmt::Debug for GradInfo<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.node.op.name())
+        write!(f, "{}", self.node.label())
     }
 }
 
 impl<T: Float> fmt::Debug for Tensor<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.op.name())
+        write!(f, "{}", self.label())
+    }
+}
+
+// GradInfo of the nodes reachable from the objectives.
+struct GradPath<'a, T: Float + 'a> {
+    infos: Vec<GradInfo<'a, T>>,
+    // `Tensor::id` => index in `infos`
+    lookup: HashMap<usize, usize>,
+}
+
+impl<'a, T: Float> GradPath<'a, T> {
+    #[inline]
+    fn push(&mut self, info: GradInfo<'a, T>) {
+        self.lookup.insert(info.node.id(), self.infos.len());
+        self.infos.push(info);
     }
 }
 
 macro_rules! access_grad_info_of {
     ($node:expr, $path:expr) => {
-        $path[$node.resource_lookup_key.get()]
+        $path.infos[$path.lookup[&$node.id()]]
     };
 }
 
 #[inline]
-fn has_marked_child<T: Float>(parent: &Tensor<T>, path: &Vec<GradInfo<T>>) -> bool {
+fn has_marked_child<T: Float>(parent: &Tensor<T>, path: &GradPath<T>) -> bool {
     let it = parent.get_backprop_inputs().iter();
     for child in it {
         if access_grad_info_of!(child, path).has_gradient {
@@ -67,9 +152,8 @@ fn has_marked_child<T: Float>(parent: &Tensor<T>, path: &Vec<GradInfo<T>>) -> bo
 }
 
 #[inline]
-fn visited<T: Float>(node: &Tensor<T>, path: &Vec<GradInfo<T>>) -> bool {
-    let k = node.resource_lookup_key.get();
-    k < path.len() && Rc::ptr_eq(node, path[k].node)
+fn visited<T: Float>(node: &Tensor<T>, path: &GradPath<T>) -> bool {
+    path.lookup.contains_key(&node.id())
 }
 
 #[inline]
@@ -85,9 +169,12 @@ fn is_wrt<T: Float>(node: &Tensor<T>, wrt: &[&Tensor<T>]) -> bool {
 fn mark_gradient_path<'a, T: Float>(
     ys: &[&'a Tensor<T>],
     wrt: &[&'a Tensor<T>],
-) -> Vec<GradInfo<'a, T>> {
-    // Randomly accessible by use of each node's lookup key.
-    let mut path: Vec<GradInfo<'a, T>> = Vec::new();
+) -> GradPath<'a, T> {
+    // Randomly accessible by use of each node's id.
+    let mut path = GradPath {
+        infos: Vec::new(),
+        lookup: HashMap::new(),
+    };
 
     // Builds GradInfo while performing depth-first-search.
     // `has_gradient` properties are filled at the same time.
@@ -98,7 +185,6 @@ fn mark_gradient_path<'a, T: Float>(
         if should_visit {
             let marker =
                 node.is_differentiable && (is_wrt(node, wrt) || has_marked_child(node, &path));
-            node.resource_lookup_key.set(path.len());
             path.push(GradInfo::new(node, marker, None));
         } else {
             // Put self on the stack top (should visit next time)
@@ -109,7 +195,6 @@ fn mark_gradient_path<'a, T: Float>(
                     if child.is_source() || !child.is_differentiable {
                         // Add to result, but don't allow any more recursive search
                         // because there will be no `wrt` nodes in this direction....
-                        child.resource_lookup_key.set(path.len());
                         path.push(GradInfo::new(
                             child,
                             child.is_differentiable && is_wrt(child, wrt),
@@ -139,7 +224,7 @@ fn test_gradient_path() {
     let ref d = b + c; // rank 3
     let ref y = d + x3; // rank 4
     let path = mark_gradient_path(&[y], &[x1, x2]);
-    let path_: Vec<&Tensor<f64>> = path.iter().map(|a| a.node).collect();
+    let path_: Vec<&Tensor<f64>> = path.infos.iter().map(|a| a.node).collect();
 
     assert!(path_.contains(&x1));
     assert!(path_.contains(&x2));
@@ -152,14 +237,14 @@ fn test_gradient_path() {
     assert_eq!(path_.len(), 10); // number of nodes in the grad path
 
     // Topological ordering test
-    let ix1 = path_.iter().position(|x| Rc::ptr_eq(x, x1)).unwrap();
-    let ix2 = path_.iter().position(|x| Rc::ptr_eq(x, x2)).unwrap();
-    let ix3 = path_.iter().position(|x| Rc::ptr_eq(x, x3)).unwrap();
-    let ia = path_.iter().position(|x| Rc::ptr_eq(x, a)).unwrap();
-    let ic = path_.iter().position(|x| Rc::ptr_eq(x, c)).unwrap();
-    let ib = path_.iter().position(|x| Rc::ptr_eq(x, b)).unwrap();
-    let id = path_.iter().position(|x| Rc::ptr_eq(x, d)).unwrap();
-    let iy = path_.iter().position(|x| Rc::ptr_eq(x, y)).unwrap();
+    let ix1 = path_.iter().position(|x| Arc::ptr_eq(x, x1)).unwrap();
+    let ix2 = path_.iter().position(|x| Arc::ptr_eq(x, x2)).unwrap();
+    let ix3 = path_.iter().position(|x| Arc::ptr_eq(x, x3)).unwrap();
+    let ia = path_.iter().position(|x| Arc::ptr_eq(x, a)).unwrap();
+    let ic = path_.iter().position(|x| Arc::ptr_eq(x, c)).unwrap();
+    let ib = path_.iter().position(|x| Arc::ptr_eq(x, b)).unwrap();
+    let id = path_.iter().position(|x| Arc::ptr_eq(x, d)).unwrap();
+    let iy = path_.iter().position(|x| Arc::ptr_eq(x, y)).unwrap();
     assert!(ix1 < ia);
     assert!(ix2 < ic);
     assert!(ix3 < iy);
@@ -168,7 +253,7 @@ fn test_gradient_path() {
 
     // Ensure continuity of keys
     for (i, node) in path_.iter().enumerate() {
-        assert_eq!(i, node.resource_lookup_key.get());
+        assert_eq!(i, path.lookup[&node.id()]);
     }
 
     // Connection test
@@ -179,13 +264,16 @@ fn test_gradient_path() {
         .cloned()
         .collect::<BTreeSet<usize>>();
     for &id in should_be_has_gradient.iter() {
-        if !path[id].has_gradient {
-            panic!("{} is not has_gradient", path[id].node.op.name());
+        if !path.infos[id].has_gradient {
+            panic!("{} is not has_gradient", path.infos[id].node.op.name());
         }
     }
     for &id in all.difference(&should_be_has_gradient).into_iter() {
-        if path[id].has_gradient {
-            panic!("{} should not be has_gradient", path[id].node.op.name());
+        if path.infos[id].has_gradient {
+            panic!(
+                "{} should not be has_gradient",
+                path.infos[id].node.op.name()
+            );
         }
     }
 }
@@ -204,14 +292,28 @@ pub fn symbolic_gradients<T: Float>(
     wrt: &[&Tensor<T>],
     gys: &[&Tensor<T>],
 ) -> Vec<Tensor<T>> {
+    symbolic_gradients_or_none(ys, wrt, gys)
+        .into_iter()
+        .map(|gx| gx.expect("Not differentiable with given tensor(s)."))
+        .collect()
+}
+
+/// Same as `symbolic_gradients` except that `None` is returned for `wrt`s
+/// not reachable from `ys`.
+pub(crate) fn symbolic_gradients_or_none<T: Float>(
+    ys: &[&Tensor<T>],
+    wrt: &[&Tensor<T>],
+    gys: &[&Tensor<T>],
+) -> Vec<Option<Tensor<T>>> {
     assert_eq!(ys.len(), gys.len(), "`ys.len()` must match `gys.len()`");
+    let _scope = GradientScope::enter();
 
     // Setup gradient path.
     let mut path = mark_gradient_path(ys, wrt);
 
     // Set default grads.
     for (y, gy) in ys.iter().zip(gys) {
-        let mut y_info = &mut access_grad_info_of!(y, path);
+        let y_info = &mut access_grad_info_of!(y, path);
         y_info.default_grad = Some(gy);
     }
 
@@ -242,7 +344,7 @@ pub fn symbolic_gradients<T: Float>(
         // Register computed gradients
         let xs = y.inner.get_backprop_inputs();
         for (gx, x) in gxs.into_iter().zip(xs) {
-            let mut x_info = &mut access_grad_info_of!(x, path);
+            let x_info = &mut access_grad_info_of!(x, path);
             if x_info.has_gradient {
                 if let Some(gx) = gx {
                     x_info.computed_grads.push(gx);
@@ -259,24 +361,110 @@ pub fn symbolic_gradients<T: Float>(
     // Aggregate and return xs's gradients
     wrt.iter()
         .map(|x| {
-            let xk = x.resource_lookup_key.get();
-            assert!(
-                xk < path.len() && {
-                    let info = &path[xk];
-                    Rc::ptr_eq(x, info.node) && info.has_gradient
-                },
-                "Not differentiable with given tensor(s)."
-            );
-            let info = &mut path[xk];
+            let info = &mut path.infos[*path.lookup.get(&x.id())?];
             assert!(
                 info.default_grad.is_none(),
                 "Can't differentiate with objective itself"
             );
             let gxs = &mut info.computed_grads;
+            if gxs.is_empty() {
+                return None;
+            }
             accumulate_grads_if_needed(gxs);
-            gxs.remove(0)
+            Some(gxs.remove(0))
+        })
+        .collect()
+}
+
+/// Returns symbolic Jacobian-vector products of `ys` in the direction of `tangents`.
+///
+/// Tangents are propagated from `xs` to `ys` in topological order (forward mode).
+/// Each node uses `Op::jvp`, or differentiates `Op::grad` twice if it's not implemented.
+/// `tangents[i]` must have the same shape as `xs[i]`.
+pub fn symbolic_jvp<T: Float>(
+    ys: &[&Tensor<T>],
+    xs: &[&Tensor<T>],
+    tangents: &[&Tensor<T>],
+) -> Vec<Tensor<T>> {
+    assert_eq!(
+        xs.len(),
+        tangents.len(),
+        "`xs.len()` must match `tangents.len()`"
+    );
+    let _scope = GradientScope::enter();
+
+    // Tangent of each visited node; `None` means zero.
+    let mut computed: HashMap<usize, Option<Tensor<T>>> = HashMap::new();
+    for (x, &t) in xs.iter().zip(tangents) {
+        computed.insert(x.id(), Some(t.clone()));
+    }
+
+    // dfs_stack: (node, should_visit)
+    let mut dfs_stack: Vec<(&Tensor<T>, bool)> = ys.iter().map(|&y| (y, false)).collect();
+    while let Some((node, should_visit)) = dfs_stack.pop() {
+        if computed.contains_key(&node.id()) {
+            continue;
+        }
+        if should_visit {
+            // Tangents are propagated along the same inputs as gradients.
+            let xs = node.get_backprop_inputs_ref();
+            let txs = xs
+                .iter()
+                .map(|x| computed[&x.id()].as_ref())
+                .collect::<Vec<_>>();
+            let tangent = if !node.is_differentiable || txs.iter().all(Option::is_none) {
+                None
+            } else {
+                // Call Op::jvp
+                let t = node.op.jvp(xs.as_slice(), node, txs.as_slice());
+                Some(t.unwrap_or_else(|| jvp_with_grad(node, txs.as_slice())))
+            };
+            computed.insert(node.id(), tangent);
+        } else {
+            // Put self on the stack top (should visit next time)
+            dfs_stack.push((node, true));
+            for x in node.get_backprop_inputs() {
+                if !computed.contains_key(&x.id()) {
+                    dfs_stack.push((x, false));
+                }
+            }
+        }
+    }
+
+    ys.iter()
+        .map(|y| match computed[&y.id()] {
+            Some(ref t) => t.clone(),
+            None => crate::ops::zeros(&y.shape()),
         })
-        .collect::<Vec<Tensor<T>>>()
+        .collect()
+}
+
+// Jacobian-vector product of `y` computed by double backprop.
+//
+// `vjp(u) = y.op.grad(u, ..)` is linear in `u`, so `d/du <vjp(u), txs>` is the product.
+// If the grad doesn't depend on `u` at all, the product is zero.
+fn jvp_with_grad<T: Float>(y: &Tensor<T>, txs: &[Option<&Tensor<T>>]) -> Tensor<T> {
+    let u = &crate::ops::zeros(&y.shape());
+    let vjps = y.op.grad(u, y.get_input_refs().as_slice(), y);
+    let dots = vjps
+        .iter()
+        .zip(txs)
+        .filter_map(|pair| match pair {
+            (Some(g), Some(t)) => Some(crate::ops::reduce_sum_to_scalar(g * *t)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if dots.is_empty() {
+        return crate::ops::zeros(&y.shape());
+    }
+    let dots = dots.iter().collect::<Vec<_>>();
+    symbolic_gradients_or_none(
+        &[&crate::ops::add_n(dots.as_slice())],
+        &[u],
+        &[&crate::ops::scalar(T::one())],
+    )
+    .remove(0)
+    .unwrap_or_else(|| crate::ops::zeros(&y.shape()))
 }
 
 struct TensorWrapper<'a, T: Float + 'a> {
@@ -303,13 +491,13 @@ impl<'a, T: Float> Eq for TensorWrapper<'a, T> {}
 impl<'a, T: Float> PartialEq for TensorWrapper<'a, T> {
     #[inline]
     fn eq(&self, other: &TensorWrapper<'a, T>) -> bool {
-        Rc::ptr_eq(&self.inner, &other.inner)
+        Arc::ptr_eq(self.inner, other.inner)
     }
 }
 
 impl<T: Float> Tensor<T> {
     #[inline]
-    fn wrapped(&self) -> TensorWrapper<T> {
+    fn wrapped(&self) -> TensorWrapper<'_, T> {
         TensorWrapper { inner: self }
     }
 }
@@ -0,0 +1,527 @@
+//! Serializable definitions of graphs
+//!
+//! A `GraphDef` holds the ops of a graph by name and attributes (see `op::Op::attributes`),
+//! and the arrays of its variables and constants. It's rebuilt into tensors with an
+//! `OpRegistry`, without the code that constructed the original graph.
+//!
+//! ```
+//! extern crate autograd as ag;
+//! use ag::graph_def::GraphDef;
+//!
+//! let ref x = ag::placeholder::<f32>(&[-1, 3]);
+//! let ref w = ag::variable(ag::ndarray_ext::standard_normal(&[3, 2]));
+//! let ref y = ag::softmax(&ag::matmul(x, w), 1);
+//!
+//! let path = std::env::temp_dir().join("autograd_doctest_graph");
+//! GraphDef::new(&[x], &[y]).unwrap().save(&path).unwrap();
+//!
+//! // Rebuilds the graph from the file.
+//! let graph = GraphDef::<f32>::load(&path).unwrap().build().unwrap();
+//! let x_val = ag::ndarray_ext::standard_normal(&[4, 3]);
+//! let expected = y.eval(&[ag::Feed(x, x_val.view())]).unwrap();
+//! let actual = graph.outputs[0].eval(&[ag::Feed(&graph.inputs[0], x_val.view())]).unwrap();
+//! assert_eq!(expected, actual);
+//! ```
+use crate::checkpoint::{
+    invalid_data, read_array, read_str, read_u64, write_array, write_str, write_u64,
+};
+use crate::ndarray_ext::NdArray;
+use crate::op::{AttrValue, Attributes, Op};
+use crate::tensor::{KnownShape, Tensor};
+use crate::Float;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"AGGRAPH1";
+
+type OpConstructor<T> = Box<dyn Fn(&Attributes) -> io::Result<Box<dyn Op<T>>> + Send + Sync>;
+
+/// Constructors of ops by name, which rebuild ops from their attributes.
+///
+/// `OpRegistry::default()` knows the built-in ops except the ones holding closures
+/// (e.g. hooks and custom gradients), graphs (e.g. `ag::checkpoint`) or optimizer states.
+/// Custom ops can be registered as follows.
+///
+/// ```
+/// extern crate autograd as ag;
+/// use ag::graph_def::OpRegistry;
+/// use ag::op::Attributes;
+///
+/// struct Scale(f32);
+///
+/// impl ag::op::Op<f32> for Scale {
+///     fn name(&self) -> &str {
+///         "Scale"
+///     }
+///
+///     fn attributes(&self) -> Attributes {
+///         Attributes::new().with("factor", self.0)
+///     }
+///
+///     fn compute<'v>(
+///         &self,
+///         ctx: ag::runtime::OpComputeContext<'v, f32>,
+///     ) -> ag::op::ComputeResults<'v, f32> {
+///         vec![Ok(ag::ArrRepr::Owned(ctx.grab_inputs()[0].mapv(|a| a * self.0)))]
+///     }
+///
+///     fn grad(&self, gy: &ag::Tensor<f32>, _: &[&ag::Tensor<f32>], _: &ag::Tensor<f32>)
+///         -> Vec<Option<ag::Tensor<f32>>> {
+///         vec![Some(gy * self.0)]
+///     }
+/// }
+///
+/// let mut registry = OpRegistry::default();
+/// registry.register("Scale", |a| Ok(Box::new(Scale(a.get("factor")?))));
+/// assert!(registry.contains("Scale"));
+/// ```
+pub struct OpRegistry<T: Float> {
+    constructors: BTreeMap<String, OpConstructor<T>>,
+}
+
+impl<T: Float> Default for OpRegistry<T> {
+    fn default() -> OpRegistry<T> {
+        let mut registry = OpRegistry::new();
+        crate::ops::register_builtins(&mut registry);
+        registry
+    }
+}
+
+impl<T: Float> OpRegistry<T> {
+    /// Creates an empty registry.
+    pub fn new() -> OpRegistry<T> {
+        OpRegistry {
+            constructors: BTreeMap::new(),
+        }
+    }
+
+    /// Registers the constructor of the op `name`, which must match `Op::name`.
+    pub fn register<F>(&mut self, name: &str, constructor: F)
+    where
+        F: Fn(&Attributes) -> io::Result<Box<dyn Op<T>>> + Send + Sync + 'static,
+    {
+        self.constructors
+            .insert(name.to_string(), Box::new(constructor));
+    }
+
+    /// Returns `true` if the op `name` is registered.
+    pub fn contains(&self, name: &str) -> bool {
+        self.constructors.contains_key(name)
+    }
+
+    /// Constructs the op `name` from `attributes`.
+    pub fn construct(&self, name: &str, attributes: &Attributes) -> io::Result<Box<dyn Op<T>>> {
+        match self.constructors.get(name) {
+            Some(constructor) => constructor(attributes),
+            None => Err(invalid_data(format!("Unknown op: {}", name))),
+        }
+    }
+}
+
+/// A node of `GraphDef`, which mirrors the fields of `Tensor`.
+///
+/// Other nodes are referred to by their indices in `GraphDef::nodes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeDef<T: Float> {
+    /// Name of the op
+    pub op: String,
+    /// Attributes of the op
+    pub attributes: Attributes,
+    pub inputs: Vec<usize>,
+    /// Output index of each input
+    pub input_indices: Vec<usize>,
+    pub backprop_inputs: Option<Vec<usize>>,
+    /// Node of the symbolic shape
+    pub shape: Option<usize>,
+    /// Static shape of placeholders
+    pub known_shape: Option<Vec<isize>>,
+    pub is_placeholder: bool,
+    pub is_differentiable: bool,
+    /// Array of a variable or a constant
+    pub array: Option<NdArray<T>>,
+    pub is_variable: bool,
+    /// Name of the tensor with its name scopes
+    pub name: Option<String>,
+}
+
+/// Graph of nodes in topological order, with its inputs and outputs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphDef<T: Float> {
+    pub nodes: Vec<NodeDef<T>>,
+    /// Nodes to be fed
+    pub inputs: Vec<usize>,
+    pub outputs: Vec<usize>,
+}
+
+/// Tensors rebuilt from a `GraphDef`
+pub struct LoadedGraph<T: Float> {
+    /// Tensors of `GraphDef::inputs` in the same order
+    pub inputs: Vec<Tensor<T>>,
+    /// Tensors of `GraphDef::outputs` in the same order
+    pub outputs: Vec<Tensor<T>>,
+}
+
+impl<T: Float> GraphDef<T> {
+    /// Describes the graph from `inputs` to `outputs` with the built-in ops.
+    ///
+    /// See `new_with_registry`.
+    pub fn new<A, B>(inputs: &[A], outputs: &[B]) -> io::Result<GraphDef<T>>
+    where
+        A: AsRef<Tensor<T>>,
+        B: AsRef<Tensor<T>>,
+    {
+        GraphDef::new_with_registry(inputs, outputs, &OpRegistry::default())
+    }
+
+    /// Describes the graph from `inputs` to `outputs`.
+    ///
+    /// Fails if an op isn't in `registry`, or if a placeholder `outputs` depend on
+    /// isn't in `inputs`.
+    pub fn new_with_registry<A, B>(
+        inputs: &[A],
+        outputs: &[B],
+        registry: &OpRegistry<T>,
+    ) -> io::Result<GraphDef<T>>
+    where
+        A: AsRef<Tensor<T>>,
+        B: AsRef<Tensor<T>>,
+    {
+        let inputs = inputs.iter().map(|t| t.as_ref()).collect::<Vec<_>>();
+        let outputs = outputs.iter().map(|t| t.as_ref()).collect::<Vec<_>>();
+
+        // Sorts the nodes in post-order so that nodes come after their dependencies.
+        let mut indices = BTreeMap::new();
+        let mut sorted = Vec::new();
+        let mut stack = outputs
+            .iter()
+            .chain(&inputs)
+            .rev()
+            .map(|&t| (t, false))
+            .collect::<Vec<_>>();
+        while let Some((t, expanded)) = stack.pop() {
+            if indices.contains_key(&t.id()) {
+                continue;
+            }
+            if expanded {
+                indices.insert(t.id(), sorted.len());
+                sorted.push(t);
+                continue;
+            }
+            stack.push((t, true));
+            for dep in dependencies(t).into_iter().rev() {
+                if !indices.contains_key(&dep.id()) {
+                    stack.push((dep, false));
+                }
+            }
+        }
+
+        let input_ids = inputs.iter().map(|t| t.id()).collect::<Vec<_>>();
+        let mut nodes = Vec::with_capacity(sorted.len());
+        for t in sorted {
+            let name = t.op.name();
+            if !registry.contains(name) {
+                let msg = format!("Op {} is not registered", name);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+            if t.is_placeholder && !input_ids.contains(&t.id()) {
+                let msg = "Placeholder not in the inputs".to_string();
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+            let index_of = |ts: &[Tensor<T>]| ts.iter().map(|t| indices[&t.id()]).collect();
+            nodes.push(NodeDef {
+                op: name.to_string(),
+                attributes: t.op.attributes(),
+                inputs: index_of(&t.inputs),
+                input_indices: t.input_indices.clone(),
+                backprop_inputs: t.inputs_on_backprop.as_ref().map(|ts| index_of(ts)),
+                shape: t.shape.as_ref().map(|s| indices[&s.id()]),
+                known_shape: t.known_shape.as_ref().map(|s| s.get().to_vec()),
+                is_placeholder: t.is_placeholder,
+                is_differentiable: t.is_differentiable,
+                array: t.get_persistent_array().map(|a| a.clone()),
+                is_variable: t.is_variable(),
+                name: t.name().map(|s| s.to_owned()),
+            });
+        }
+        Ok(GraphDef {
+            nodes,
+            inputs: inputs.iter().map(|t| indices[&t.id()]).collect(),
+            outputs: outputs.iter().map(|t| indices[&t.id()]).collect(),
+        })
+    }
+
+    /// Rebuilds the graph with the built-in ops.
+    pub fn build(&self) -> io::Result<LoadedGraph<T>> {
+        self.build_with_registry(&OpRegistry::default())
+    }
+
+    /// Rebuilds the graph with the ops in `registry`.
+    ///
+    /// Variables are created with copies of the arrays in this definition. Names of the
+    /// nodes are prefixed with the current name scopes (see `ag::name_scope`).
+    pub fn build_with_registry(&self, registry: &OpRegistry<T>) -> io::Result<LoadedGraph<T>> {
+        let mut tensors: Vec<Tensor<T>> = Vec::with_capacity(self.nodes.len());
+        for (i, node) in self.nodes.iter().enumerate() {
+            let get = |j: usize| {
+                tensors
+                    .get(j)
+                    .ok_or_else(|| invalid_data(format!("Node {} refers to node {}", i, j)))
+            };
+            if node.input_indices.len() != node.inputs.len() {
+                return Err(invalid_data(format!("Node {} has broken inputs", i)));
+            }
+            let op = registry.construct(&node.op, &node.attributes)?;
+            let inputs = node
+                .inputs
+                .iter()
+                .map(|&j| get(j))
+                .collect::<io::Result<Vec<_>>>()?;
+            let mut builder = Tensor::builder()
+                .set_inputs(inputs)
+                .set_input_indices(node.input_indices.clone())
+                .set_is_placeholder(node.is_placeholder)
+                .set_differentiable(node.is_differentiable);
+            if let Some(j) = node.shape {
+                builder = builder.set_shape(get(j)?.clone());
+            }
+            if let Some(ref shape) = node.known_shape {
+                if shape.iter().any(|&d| d != -1 && d <= 0) {
+                    return Err(invalid_data(format!("Node {} has broken shape", i)));
+                }
+                builder = builder.set_known_shape(KnownShape::new(shape.clone()));
+            }
+            if let Some(ref backprop_inputs) = node.backprop_inputs {
+                let backprop_inputs = backprop_inputs
+                    .iter()
+                    .map(|&j| get(j).cloned())
+                    .collect::<io::Result<Vec<_>>>()?;
+                builder = builder.set_backprop_inputs(backprop_inputs);
+            }
+            if let Some(ref arr) = node.array {
+                builder = if node.is_variable {
+                    builder.set_variable_array(arr.clone())
+                } else {
+                    builder.set_constant_array(arr.clone())
+                };
+            }
+            if let Some(ref name) = node.name {
+                if name.is_empty() {
+                    return Err(invalid_data(format!("Node {} has an empty name", i)));
+                }
+                builder = builder.set_name(name);
+            }
+            tensors.push(builder.build_boxed(op));
+        }
+        let get = |indices: &[usize]| {
+            indices
+                .iter()
+                .map(|&i| {
+                    tensors
+                        .get(i)
+                        .cloned()
+                        .ok_or_else(|| invalid_data(format!("No node {}", i)))
+                })
+                .collect::<io::Result<Vec<_>>>()
+        };
+        Ok(LoadedGraph {
+            inputs: get(&self.inputs)?,
+            outputs: get(&self.outputs)?,
+        })
+    }
+
+    /// Writes this to `path` in a little-endian binary format.
+    ///
+    /// Arrays are written in the precision of `T`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        write_u64(&mut w, self.nodes.len() as u64)?;
+        for node in &self.nodes {
+            write_str(&mut w, &node.op)?;
+            write_u64(&mut w, node.attributes.values.len() as u64)?;
+            for (name, value) in &node.attributes.values {
+                write_str(&mut w, name)?;
+                write_attr_value(&mut w, value)?;
+            }
+            write_indices(&mut w, &node.inputs)?;
+            write_indices(&mut w, &node.input_indices)?;
+            let flags = [
+                node.is_placeholder,
+                node.is_differentiable,
+                node.is_variable,
+                node.backprop_inputs.is_some(),
+                node.shape.is_some(),
+                node.known_shape.is_some(),
+                node.array.is_some(),
+                node.name.is_some(),
+            ];
+            let flags = flags
+                .iter()
+                .enumerate()
+                .fold(0u8, |acc, (i, &f)| acc | (f as u8) << i);
+            w.write_all(&[flags])?;
+            if let Some(ref backprop_inputs) = node.backprop_inputs {
+                write_indices(&mut w, backprop_inputs)?;
+            }
+            if let Some(shape) = node.shape {
+                write_u64(&mut w, shape as u64)?;
+            }
+            if let Some(ref shape) = node.known_shape {
+                write_u64(&mut w, shape.len() as u64)?;
+                for &d in shape {
+                    w.write_all(&(d as i64).to_le_bytes())?;
+                }
+            }
+            if let Some(ref arr) = node.array {
+                write_array(&mut w, arr)?;
+            }
+            if let Some(ref name) = node.name {
+                write_str(&mut w, name)?;
+            }
+        }
+        write_indices(&mut w, &self.inputs)?;
+        write_indices(&mut w, &self.outputs)?;
+        w.flush()
+    }
+
+    /// Reads a graph written by `save`, converting arrays to `T` if needed.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<GraphDef<T>> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not a graph file".to_string()));
+        }
+        let num_nodes = read_u64(&mut r)?;
+        let mut nodes = Vec::new();
+        for _ in 0..num_nodes {
+            let op = read_str(&mut r)?;
+            let mut attributes = Attributes::new();
+            for _ in 0..read_u64(&mut r)? {
+                let name = read_str(&mut r)?;
+                attributes.values.insert(name, read_attr_value(&mut r)?);
+            }
+            let inputs = read_indices(&mut r)?;
+            let input_indices = read_indices(&mut r)?;
+            let mut flags = [0];
+            r.read_exact(&mut flags)?;
+            let flag = |i: usize| flags[0] & (1 << i) != 0;
+            let backprop_inputs = if flag(3) {
+                Some(read_indices(&mut r)?)
+            } else {
+                None
+            };
+            let shape = if flag(4) {
+                Some(read_u64(&mut r)? as usize)
+            } else {
+                None
+            };
+            let known_shape = if flag(5) {
+                let len = read_u64(&mut r)?;
+                let shape = (0..len)
+                    .map(|_| read_i64(&mut r).map(|d| d as isize))
+                    .collect::<io::Result<Vec<_>>>()?;
+                Some(shape)
+            } else {
+                None
+            };
+            let array = if flag(6) {
+                Some(read_array(&mut r)?)
+            } else {
+                None
+            };
+            let name = if flag(7) {
+                Some(read_str(&mut r)?)
+            } else {
+                None
+            };
+            nodes.push(NodeDef {
+                op,
+                attributes,
+                inputs,
+                input_indices,
+                backprop_inputs,
+                shape,
+                known_shape,
+                is_placeholder: flag(0),
+                is_differentiable: flag(1),
+                array,
+                is_variable: flag(2),
+                name,
+            });
+        }
+        Ok(GraphDef {
+            nodes,
+            inputs: read_indices(&mut r)?,
+            outputs: read_indices(&mut r)?,
+        })
+    }
+}
+
+// Tensors that `t` refers to
+fn dependencies<T: Float>(t: &Tensor<T>) -> Vec<&Tensor<T>> {
+    let mut deps = t.inputs.iter().collect::<Vec<_>>();
+    deps.extend(t.shape.as_ref());
+    if let Some(ref backprop_inputs) = t.inputs_on_backprop {
+        deps.extend(backprop_inputs);
+    }
+    deps
+}
+
+fn write_attr_value<W: Write>(w: &mut W, value: &AttrValue) -> io::Result<()> {
+    match value {
+        AttrValue::Int(a) => {
+            w.write_all(&[0])?;
+            w.write_all(&a.to_le_bytes())
+        }
+        AttrValue::Float(a) => {
+            w.write_all(&[1])?;
+            w.write_all(&a.to_le_bytes())
+        }
+        AttrValue::Ints(a) => {
+            w.write_all(&[2])?;
+            write_u64(w, a.len() as u64)?;
+            a.iter().try_for_each(|a| w.write_all(&a.to_le_bytes()))
+        }
+        AttrValue::Floats(a) => {
+            w.write_all(&[3])?;
+            write_u64(w, a.len() as u64)?;
+            a.iter().try_for_each(|a| w.write_all(&a.to_le_bytes()))
+        }
+    }
+}
+
+fn read_attr_value<R: Read>(r: &mut R) -> io::Result<AttrValue> {
+    let mut tag = [0];
+    r.read_exact(&mut tag)?;
+    Ok(match tag[0] {
+        0 => AttrValue::Int(read_i64(r)?),
+        1 => AttrValue::Float(f64::from_bits(read_u64(r)?)),
+        2 => {
+            let len = read_u64(r)?;
+            AttrValue::Ints((0..len).map(|_| read_i64(r)).collect::<io::Result<_>>()?)
+        }
+        3 => {
+            let len = read_u64(r)?;
+            let values = (0..len).map(|_| read_u64(r).map(f64::from_bits));
+            AttrValue::Floats(values.collect::<io::Result<_>>()?)
+        }
+        t => return Err(invalid_data(format!("Unknown attribute type: {}", t))),
+    })
+}
+
+fn write_indices<W: Write>(w: &mut W, indices: &[usize]) -> io::Result<()> {
+    write_u64(w, indices.len() as u64)?;
+    indices.iter().try_for_each(|&i| write_u64(w, i as u64))
+}
+
+fn read_indices<R: Read>(r: &mut R) -> io::Result<Vec<usize>> {
+    let len = read_u64(r)?;
+    (0..len).map(|_| read_u64(r).map(|i| i as usize)).collect()
+}
+
+fn read_i64<R: Read>(r: &mut R) -> io::Result<i64> {
+    read_u64(r).map(|a| a as i64)
+}
@@ -30,6 +30,16 @@ pub mod ndarray_ext;
 
 pub mod op;
 
+pub mod profiler;
+
+pub mod checkpoint;
+
+mod dot;
+
+pub mod graph_def;
+
+pub mod onnx;
+
 use std::any::TypeId;
 use std::fmt;
 
@@ -102,9 +112,15 @@ pub use crate::ops::gradient_descent_ops;
 
 pub use crate::ndarray_ext::NdArray;
 
-pub use crate::runtime::{eval, Eval, Feed};
+pub use crate::runtime::{
+    eval, try_eval, CompiledGraph, Eval, EvalError, EvalErrorKind, Feed, NumericStats,
+};
+
+pub use crate::tensor::{get_tensor_by_name, name_scope, named_tensors, IndexedSlices, Tensor};
+
+pub use crate::profiler::Profile;
 
-pub use crate::tensor::Tensor;
+pub use crate::dot::graph_to_dot;
 
 pub use crate::ndarray_ext::ArrRepr;
 
@@ -142,7 +158,7 @@ pub unsafe fn uninitialized_vec<T>(size: usize) -> Vec<T> {
 /// // [2, 3]
 /// ```
 pub enum Hook<T: Float> {
-    Raw(Box<dyn Fn(&crate::ndarray_ext::NdArrayView<T>) -> ()>),
+    Raw(crate::ops::hook_ops::HookFn<T>),
     Print,
     PrintShape,
 }
@@ -155,11 +171,11 @@ pub fn hook<T: Float>(hook: Hook<T>, node: &Tensor<T>) -> Tensor<T> {
         Hook::Raw(func) => crate::ops::hook_ops::Hook { func, name: None },
         Hook::PrintShape => crate::ops::hook_ops::Hook {
             func: Box::new(|arr| println!("{:?}\n", arr.shape())),
-            name: Some(format!("Shape of {}", node.op.name())),
+            name: Some(format!("Shape of {}", node.label())),
         },
         Hook::Print => crate::ops::hook_ops::Hook {
             func: Box::new(|arr| println!("{:?}\n", arr)),
-            name: Some(node.op.name().to_owned()),
+            name: Some(node.label()),
         },
     };
     Tensor::builder().set_input(node).build(op)
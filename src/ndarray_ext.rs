@@ -8,9 +8,13 @@ pub type NdArray<T> = ndarray::Array<T, ndarray::IxDyn>;
 
 pub type NdArrayView<'a, T> = ndarray::ArrayView<'a, T, ndarray::IxDyn>;
 
+pub type NdArrayViewMut<'a, T> = ndarray::ArrayViewMut<'a, T, ndarray::IxDyn>;
+
 // expose array_gen
 pub use crate::array_gen::*;
 
+pub mod npy;
+
 /// Op::compute's output
 #[derive(Clone)]
 pub enum ArrRepr<'v, T: Float> {
@@ -198,8 +202,8 @@ pub mod array_gen {
     use super::*;
     use rand::distributions::IndependentSample;
     use rand::{self, Rng, XorShiftRng};
-    use std::cell::RefCell;
     use std::marker::PhantomData;
+    use std::sync::Mutex;
 
     /// Range.
     pub fn range<T: Float>(shape: &[usize]) -> NdArray<T> {
@@ -218,14 +222,14 @@ pub mod array_gen {
     /// see https://github.com/raskr/rust-autograd/issues/1.
     pub struct ArrRng<T: Float, R = XorShiftRng> {
         phantom: PhantomData<T>,
-        rng: RefCell<R>,
+        rng: Mutex<R>,
     }
 
     impl<T: Float> Default for ArrRng<T, XorShiftRng> {
         fn default() -> Self {
             ArrRng {
                 phantom: PhantomData,
-                rng: RefCell::new(rand::weak_rng()),
+                rng: Mutex::new(rand::weak_rng()),
             }
         }
     }
@@ -235,7 +239,7 @@ pub mod array_gen {
         pub fn new(rng: R) -> Self {
             ArrRng {
                 phantom: PhantomData,
-                rng: RefCell::new(rng),
+                rng: Mutex::new(rng),
             }
         }
 
@@ -252,7 +256,7 @@ pub mod array_gen {
             I: IndependentSample<f64>,
         {
             let size: usize = shape.iter().cloned().product();
-            let mut rng = self.rng.borrow_mut();
+            let mut rng = self.rng.lock().unwrap();
             unsafe {
                 let mut buf = Self::alloc(size);
                 for i in 0..size {
@@ -266,7 +270,7 @@ pub mod array_gen {
             let mut data: Vec<usize> = (0..size).collect();
             let slice = data.as_mut_slice();
 
-            let mut rng = self.rng.borrow_mut();
+            let mut rng = self.rng.lock().unwrap();
             rng.shuffle(slice);
             ndarray::Array1::<usize>::from_vec(slice.to_vec())
         }
@@ -317,7 +321,7 @@ pub mod array_gen {
 
         pub fn bernoulli(&self, shape: &[usize], p: f64) -> ndarray::Array<T, ndarray::IxDyn> {
             let dist = rand::distributions::Range::new(0., 1.);
-            let mut rng = self.rng.borrow_mut();
+            let mut rng = self.rng.lock().unwrap();
             let size: usize = shape.iter().cloned().product();
             unsafe {
                 let mut buf = Self::alloc(size);
@@ -0,0 +1,404 @@
+//! Reading and writing NumPy's `.npy` and `.npz` files.
+//!
+//! Arrays of `float32` or `float64` in either byte order and in C or Fortran order can be
+//! read, and are converted to `T`. `.npz` entries must be stored without compression
+//! (i.e. written by `numpy.savez`, not `numpy.savez_compressed`).
+//!
+//! ```
+//! extern crate autograd as ag;
+//! use ag::ndarray_ext::npy;
+//!
+//! let a = ag::ndarray_ext::standard_normal::<f32>(&[2, 3]);
+//! let path = std::env::temp_dir().join("autograd_doctest_npy.npy");
+//! npy::save_npy(&path, &a).unwrap();
+//! assert_eq!(npy::load_npy::<f32, _>(&path).unwrap(), a);
+//!
+//! // Loads it straight into a variable.
+//! let w = ag::variable_from_npy::<f32, _>(&path).unwrap();
+//! assert_eq!(*w.get_persistent_array().unwrap(), a);
+//! ```
+use super::NdArray;
+use crate::checkpoint::invalid_data;
+use crate::Float;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::Path;
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// Reads an array in the `.npy` format from `r`.
+pub fn read_npy<T: Float, R: Read>(r: &mut R) -> io::Result<NdArray<T>> {
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic[..6] != MAGIC {
+        return Err(invalid_data("Not a .npy file".to_string()));
+    }
+    let header_len = match magic[6] {
+        1 => {
+            let mut buf = [0; 2];
+            r.read_exact(&mut buf)?;
+            u16::from_le_bytes(buf) as usize
+        }
+        2 | 3 => {
+            let mut buf = [0; 4];
+            r.read_exact(&mut buf)?;
+            u32::from_le_bytes(buf) as usize
+        }
+        v => return Err(invalid_data(format!("Unsupported .npy version: {}", v))),
+    };
+    let mut header = vec![0; header_len];
+    r.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+    let (descr, fortran_order, shape) = parse_header(&header)?;
+
+    let size = match &descr[1..] {
+        "f4" => 4,
+        "f8" => 8,
+        _ => return Err(invalid_data(format!("Unsupported dtype: {}", descr))),
+    };
+    let big_endian = match &descr[..1] {
+        "<" => false,
+        ">" => true,
+        _ => cfg!(target_endian = "big"),
+    };
+    let len = shape.iter().product::<usize>();
+    let mut bytes = vec![0; len * size];
+    r.read_exact(&mut bytes)?;
+    let data = bytes
+        .chunks(size)
+        .map(|b| {
+            let a = if size == 4 {
+                let b = [b[0], b[1], b[2], b[3]];
+                let a = if big_endian {
+                    f32::from_be_bytes(b)
+                } else {
+                    f32::from_le_bytes(b)
+                };
+                T::from(a)
+            } else {
+                let b = [b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]];
+                let a = if big_endian {
+                    f64::from_be_bytes(b)
+                } else {
+                    f64::from_le_bytes(b)
+                };
+                T::from(a)
+            };
+            a.unwrap()
+        })
+        .collect::<Vec<T>>();
+
+    if fortran_order {
+        // Elements are in the order of the reversed axes.
+        let rev = shape.iter().rev().cloned().collect::<Vec<_>>();
+        let arr = NdArray::from_shape_vec(ndarray::IxDyn(&rev), data).unwrap();
+        Ok(super::deep_copy(&arr.t()))
+    } else {
+        Ok(NdArray::from_shape_vec(ndarray::IxDyn(&shape), data).unwrap())
+    }
+}
+
+/// Writes `arr` to `w` in the `.npy` format, with the precision of `T`.
+///
+/// The elements are written in Fortran order if `arr` is laid out so, and in C order otherwise.
+pub fn write_npy<T: Float, W: Write>(w: &mut W, arr: &NdArray<T>) -> io::Result<()> {
+    let fortran_order = !arr.is_standard_layout() && arr.t().is_standard_layout();
+    let shape = match arr.shape() {
+        [d] => format!("({},)", d),
+        s => format!(
+            "({})",
+            s.iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '<f{}', 'fortran_order': {}, 'shape': {}, }}",
+        mem::size_of::<T>(),
+        if fortran_order { "True" } else { "False" },
+        shape
+    );
+    // Pads the header with spaces so that the data is 64-byte aligned.
+    let prefix_len = if header.len() + 11 <= u16::MAX as usize {
+        10
+    } else {
+        12
+    };
+    while (prefix_len + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    w.write_all(MAGIC)?;
+    if prefix_len == 10 {
+        w.write_all(&[1, 0])?;
+        w.write_all(&(header.len() as u16).to_le_bytes())?;
+    } else {
+        w.write_all(&[2, 0])?;
+        w.write_all(&(header.len() as u32).to_le_bytes())?;
+    }
+    w.write_all(header.as_bytes())?;
+    let elems: Box<dyn Iterator<Item = &T>> = if fortran_order {
+        Box::new(arr.t().into_iter())
+    } else {
+        Box::new(arr.iter())
+    };
+    for &a in elems {
+        if mem::size_of::<T>() == 4 {
+            w.write_all(&a.to_f32().unwrap().to_le_bytes())?;
+        } else {
+            w.write_all(&a.to_f64().unwrap().to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Loads an array from a `.npy` file.
+pub fn load_npy<T: Float, P: AsRef<Path>>(path: P) -> io::Result<NdArray<T>> {
+    read_npy(&mut BufReader::new(File::open(path)?))
+}
+
+/// Saves `arr` to a `.npy` file.
+pub fn save_npy<T: Float, P: AsRef<Path>>(path: P, arr: &NdArray<T>) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_npy(&mut w, arr)?;
+    w.flush()
+}
+
+/// Loads the arrays of a `.npz` file by name.
+///
+/// Names are those of the entries without the `.npy` extension, as with `numpy.load`.
+pub fn load_npz<T: Float, P: AsRef<Path>>(path: P) -> io::Result<BTreeMap<String, NdArray<T>>> {
+    let mut buf = Vec::new();
+    File::open(path)?.read_to_end(&mut buf)?;
+    let mut ret = BTreeMap::new();
+    for entry in zip_entries(&buf)? {
+        if entry.method != 0 {
+            let msg = format!("{} is compressed, which is not supported", entry.name);
+            return Err(invalid_data(msg));
+        }
+        let local = slice(&buf, entry.offset, 30)?;
+        if le_u32(&local[0..]) != 0x0403_4b50 {
+            return Err(invalid_data("Broken zip local header".to_string()));
+        }
+        let start =
+            entry.offset + 30 + le_u16(&local[26..]) as usize + le_u16(&local[28..]) as usize;
+        let data = slice(&buf, start, entry.size)?;
+        if crc32(data) != entry.crc {
+            return Err(invalid_data(format!("CRC mismatch of {}", entry.name)));
+        }
+        let name = entry.name.trim_end_matches(".npy").to_string();
+        ret.insert(name, read_npy(&mut &data[..])?);
+    }
+    Ok(ret)
+}
+
+/// Saves the named arrays to a `.npz` file without compression, as `numpy.savez` does.
+pub fn save_npz<T: Float, P: AsRef<Path>>(
+    path: P,
+    arrays: &[(&str, &NdArray<T>)],
+) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    let mut central = Vec::new();
+    let mut offset = 0;
+    for &(name, arr) in arrays {
+        let mut data = Vec::new();
+        write_npy(&mut data, arr)?;
+        let name = format!("{}.npy", name);
+        if data.len() > u32::MAX as usize || offset > u32::MAX as usize {
+            return Err(invalid_data("Too large for a .npz file".to_string()));
+        }
+        let crc = crc32(&data);
+
+        // Local file header
+        let mut header = Vec::new();
+        header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        write_entry_info(&mut header, crc, data.len(), name.len());
+        header.extend_from_slice(name.as_bytes());
+        w.write_all(&header)?;
+        w.write_all(&data)?;
+
+        // Central directory entry
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        write_entry_info(&mut central, crc, data.len(), name.len());
+        central.extend_from_slice(&[0; 10]); // comment len, disk, attributes
+        central.extend_from_slice(&(offset as u32).to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        offset += header.len() + data.len();
+    }
+    if arrays.len() > u16::MAX as usize || offset > u32::MAX as usize {
+        return Err(invalid_data("Too large for a .npz file".to_string()));
+    }
+    w.write_all(&central)?;
+
+    // End of central directory
+    let mut end = Vec::new();
+    end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    end.extend_from_slice(&[0; 4]); // disk numbers
+    end.extend_from_slice(&(arrays.len() as u16).to_le_bytes());
+    end.extend_from_slice(&(arrays.len() as u16).to_le_bytes());
+    end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    end.extend_from_slice(&(offset as u32).to_le_bytes());
+    end.extend_from_slice(&[0; 2]); // comment len
+    w.write_all(&end)?;
+    w.flush()
+}
+
+// Parses the python dict literal of a .npy header.
+fn parse_header(header: &str) -> io::Result<(String, bool, Vec<usize>)> {
+    let value_of = |key: &str| -> io::Result<&str> {
+        let pat = format!("'{}':", key);
+        match header.find(&pat) {
+            Some(i) => Ok(header[i + pat.len()..].trim_start()),
+            None => Err(invalid_data(format!("Missing {} in .npy header", key))),
+        }
+    };
+    let broken = || invalid_data(format!("Broken .npy header: {}", header));
+
+    let descr = value_of("descr")?;
+    let descr = descr
+        .get(1..)
+        .and_then(|d| d.split('\'').next())
+        .filter(|d| d.len() == 3)
+        .ok_or_else(broken)?;
+    let fortran_order = value_of("fortran_order")?.starts_with("True");
+    let shape = value_of("shape")?;
+    let shape = shape
+        .get(1..)
+        .and_then(|s| s.split(')').next())
+        .ok_or_else(broken)?
+        .split(',')
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(|d| d.trim_end_matches('L').parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| broken())?;
+    Ok((descr.to_string(), fortran_order, shape))
+}
+
+struct ZipEntry {
+    name: String,
+    method: u16,
+    crc: u32,
+    size: usize,
+    offset: usize,
+}
+
+// Reads the central directory of a zip archive.
+fn zip_entries(buf: &[u8]) -> io::Result<Vec<ZipEntry>> {
+    // The end record is at the end, followed by a comment of at most 65535 bytes.
+    let not_zip = || invalid_data("Not a zip file".to_string());
+    let end = (0..=buf.len().checked_sub(22).ok_or_else(not_zip)?)
+        .rev()
+        .take(65536)
+        .find(|&i| le_u32(&buf[i..]) == 0x0605_4b50)
+        .ok_or_else(not_zip)?;
+    let num_entries = le_u16(&buf[end + 10..]) as usize;
+    let mut pos = le_u32(&buf[end + 16..]) as usize;
+    let mut entries = Vec::with_capacity(num_entries);
+    for _ in 0..num_entries {
+        let header = slice(buf, pos, 46)?;
+        if le_u32(header) != 0x0201_4b50 {
+            return Err(invalid_data("Broken zip central directory".to_string()));
+        }
+        let name_len = le_u16(&header[28..]) as usize;
+        let extra_len = le_u16(&header[30..]) as usize;
+        let comment_len = le_u16(&header[32..]) as usize;
+        let name = slice(buf, pos + 46, name_len)?;
+        let extra = slice(buf, pos + 46 + name_len, extra_len)?;
+
+        // Sizes and offset of 0xFFFFFFFF are in the zip64 extra field in this order.
+        let mut fields = [
+            le_u32(&header[24..]) as u64,
+            le_u32(&header[20..]) as u64,
+            le_u32(&header[42..]) as u64,
+        ];
+        let mut i = 0;
+        while i + 4 <= extra.len() {
+            let (id, len) = (le_u16(&extra[i..]), le_u16(&extra[i + 2..]) as usize);
+            if id == 1 {
+                let mut values = extra[i + 4..(i + 4 + len).min(extra.len())].chunks(8);
+                for f in fields.iter_mut().filter(|f| **f == 0xFFFF_FFFF) {
+                    if let Some(v) = values.next().filter(|v| v.len() == 8) {
+                        *f = le_u32(v) as u64 | (le_u32(&v[4..]) as u64) << 32;
+                    }
+                }
+            }
+            i += 4 + len;
+        }
+        if fields[0] != fields[1] && le_u16(&header[10..]) == 0 {
+            return Err(invalid_data("Broken zip entry sizes".to_string()));
+        }
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: le_u16(&header[10..]),
+            crc: le_u32(&header[16..]),
+            size: fields[1] as usize,
+            offset: fields[2] as usize,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+// Fields shared by the local header and the central directory entry of a stored file
+fn write_entry_info(buf: &mut Vec<u8>, crc: u32, size: usize, name_len: usize) {
+    buf.extend_from_slice(&20u16.to_le_bytes()); // version needed
+    buf.extend_from_slice(&0u16.to_le_bytes()); // flags
+    buf.extend_from_slice(&0u16.to_le_bytes()); // method: stored
+    buf.extend_from_slice(&0u16.to_le_bytes()); // time
+    buf.extend_from_slice(&0x21u16.to_le_bytes()); // date: 1980-01-01
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf.extend_from_slice(&(size as u32).to_le_bytes()); // compressed
+    buf.extend_from_slice(&(size as u32).to_le_bytes()); // uncompressed
+    buf.extend_from_slice(&(name_len as u16).to_le_bytes());
+    buf.extend_from_slice(&0u16.to_le_bytes()); // extra len
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let table = (0..256u32)
+        .map(|i| {
+            (0..8).fold(i, |c, _| {
+                if c & 1 == 1 {
+                    0xEDB8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    !data.iter().fold(!0u32, |c, &b| {
+        table[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8)
+    })
+}
+
+fn slice(buf: &[u8], start: usize, len: usize) -> io::Result<&[u8]> {
+    buf.get(start..start + len)
+        .ok_or_else(|| invalid_data("Unexpected end of zip file".to_string()))
+}
+
+fn le_u16(b: &[u8]) -> u16 {
+    u16::from_le_bytes([b[0], b[1]])
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+#[test]
+fn test_npy_header() {
+    let header = "{'descr': '<f8', 'fortran_order': True, 'shape': (2, 3), }";
+    let (descr, fortran_order, shape) = parse_header(header).unwrap();
+    assert_eq!(descr, "<f8");
+    assert!(fortran_order);
+    assert_eq!(shape, vec![2, 3]);
+
+    let header = "{'descr': '>f4', 'fortran_order': False, 'shape': (), }";
+    assert_eq!(parse_header(header).unwrap().2, Vec::<usize>::new());
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
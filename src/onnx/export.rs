@@ -0,0 +1,764 @@
+use super::proto::Message;
+use super::*;
+use crate::ndarray_ext::NdArray;
+use crate::op::Attributes;
+use crate::tensor::Tensor;
+use crate::Float;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// Ops that must not be folded into initializers
+const STATEFUL_OPS: &[&str] = &[
+    "Hook",
+    "RandomUniform",
+    "RandomNormal",
+    "StandardNormal",
+    "StandardUniform",
+    "Bernoulli",
+    "Exponential",
+    "LogNormal",
+    "Gamma",
+];
+
+/// Writes the graph from `inputs` to `outputs` to an ONNX file.
+///
+/// See `export_bytes`.
+pub fn export<T, A, B, P>(path: P, inputs: &[A], outputs: &[B]) -> Result<(), OnnxError>
+where
+    T: Float,
+    A: AsRef<Tensor<T>>,
+    B: AsRef<Tensor<T>>,
+    P: AsRef<Path>,
+{
+    let bytes = export_bytes(inputs, outputs)?;
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(&bytes)?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Encodes the graph from `inputs` to `outputs` as an ONNX model.
+///
+/// The placeholders in `inputs` become the graph inputs `input_0`, `input_1`, ...
+/// with their known shapes, and `outputs` become `output_0`, `output_1`, ...
+/// Named variables and constants keep their names (see `Tensor::name`) in the
+/// initializers unless the names are taken.
+///
+/// Fails with `OnnxError::Unsupported` listing all the ops that have no mapping
+/// to ONNX, or if `outputs` depend on a placeholder not in `inputs`.
+pub fn export_bytes<T, A, B>(inputs: &[A], outputs: &[B]) -> Result<Vec<u8>, OnnxError>
+where
+    T: Float,
+    A: AsRef<Tensor<T>>,
+    B: AsRef<Tensor<T>>,
+{
+    let mut exporter = Exporter::new();
+    exporter
+        .taken
+        .extend((0..inputs.len()).map(|i| format!("input_{}", i)));
+    exporter
+        .taken
+        .extend((0..outputs.len()).map(|i| format!("output_{}", i)));
+    let mut graph = Message::new();
+    graph.string(GRAPH_NAME, "autograd");
+    for (i, x) in inputs.iter().enumerate() {
+        let x = x.as_ref();
+        if !x.is_placeholder {
+            let msg = format!("input {} is not a placeholder", i);
+            return Err(OnnxError::InvalidGraph(msg));
+        }
+        let name = format!("input_{}", i);
+        exporter.names.insert(x.id(), name.clone());
+        graph.message(GRAPH_INPUT, &exporter.value_info(&name, x));
+    }
+    for (i, y) in outputs.iter().enumerate() {
+        let y = y.as_ref();
+        let value = exporter.value(y)?;
+        let name = format!("output_{}", i);
+        exporter.node_with_output("Identity", &[value], vec![], name.clone());
+        graph.message(GRAPH_OUTPUT, &exporter.value_info(&name, y));
+    }
+    if !exporter.unsupported.is_empty() {
+        let ops = exporter.unsupported.into_iter().collect();
+        return Err(OnnxError::Unsupported(ops));
+    }
+    for node in &exporter.nodes {
+        graph.message(GRAPH_NODE, node);
+    }
+    for initializer in &exporter.initializers {
+        graph.message(GRAPH_INITIALIZER, initializer);
+    }
+
+    let mut opset = Message::new();
+    opset.int(OPSET_VERSION_FIELD, OPSET_VERSION);
+    let mut model = Message::new();
+    model
+        .int(MODEL_IR_VERSION, IR_VERSION)
+        .string(MODEL_PRODUCER_NAME, "autograd")
+        .message(MODEL_GRAPH, &graph)
+        .message(MODEL_OPSET_IMPORT, &opset);
+    Ok(model.into_bytes())
+}
+
+struct Exporter<T: Float> {
+    // Names of the ONNX values of tensors, keyed by `Tensor::id`
+    names: HashMap<usize, String>,
+    // Names of the INT64 versions for the inputs ONNX takes as integers
+    int_names: HashMap<usize, String>,
+    // Names of the ONNX values so far
+    taken: HashSet<String>,
+    // Whether tensors depend on no placeholder
+    constants: HashMap<usize, bool>,
+    nodes: Vec<Message>,
+    initializers: Vec<Message>,
+    unsupported: BTreeSet<String>,
+    num_values: usize,
+    data_type: i64,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T: Float> Exporter<T> {
+    fn new() -> Exporter<T> {
+        Exporter {
+            names: HashMap::new(),
+            int_names: HashMap::new(),
+            taken: HashSet::new(),
+            constants: HashMap::new(),
+            nodes: Vec::new(),
+            initializers: Vec::new(),
+            unsupported: BTreeSet::new(),
+            num_values: 0,
+            data_type: if crate::same_type::<T, f32>() {
+                DATA_TYPE_FLOAT
+            } else {
+                DATA_TYPE_DOUBLE
+            },
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    fn new_name(&mut self, prefix: &str) -> String {
+        loop {
+            self.num_values += 1;
+            let name = format!("{}_{}", prefix, self.num_values);
+            if self.taken.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+
+    fn value_info(&self, name: &str, t: &Tensor<T>) -> Message {
+        let mut tensor_type = Message::new();
+        tensor_type.int(TENSOR_TYPE_ELEM_TYPE, self.data_type);
+        if let Some(ref known_shape) = t.known_shape {
+            let mut shape = Message::new();
+            for &d in known_shape.get() {
+                let mut dim = Message::new();
+                if d >= 0 {
+                    dim.int(DIM_VALUE, d as i64);
+                }
+                shape.message(SHAPE_DIM, &dim);
+            }
+            tensor_type.message(TENSOR_TYPE_SHAPE, &shape);
+        }
+        let mut ty = Message::new();
+        ty.message(TYPE_TENSOR_TYPE, &tensor_type);
+        let mut info = Message::new();
+        info.string(VALUE_INFO_NAME, name)
+            .message(VALUE_INFO_TYPE, &ty);
+        info
+    }
+
+    fn is_constant(&mut self, t: &Tensor<T>) -> bool {
+        if let Some(&ret) = self.constants.get(&t.id()) {
+            return ret;
+        }
+        let ret = !t.is_placeholder
+            && !STATEFUL_OPS.contains(&t.op.name())
+            && t.inputs.iter().all(|x| self.is_constant(x));
+        self.constants.insert(t.id(), ret);
+        ret
+    }
+
+    fn eval(&self, t: &Tensor<T>) -> Result<NdArray<T>, OnnxError> {
+        match t.get_persistent_array() {
+            Some(arr) => Ok(arr.clone()),
+            None => t.eval(&[]).ok_or_else(|| {
+                OnnxError::InvalidGraph(format!("failed to evaluate {}", t.op.name()))
+            }),
+        }
+    }
+
+    fn initializer(&mut self, name: &str, shape: &[usize], data_type: i64, raw_data: &[u8]) {
+        let mut tensor = Message::new();
+        for &d in shape {
+            tensor.int(TENSOR_DIMS, d as i64);
+        }
+        tensor
+            .int(TENSOR_DATA_TYPE, data_type)
+            .string(TENSOR_NAME, name)
+            .bytes(TENSOR_RAW_DATA, raw_data);
+        self.initializers.push(tensor);
+    }
+
+    fn raw_data(&self, values: impl Iterator<Item = T>) -> Vec<u8> {
+        let mut ret = Vec::new();
+        for v in values {
+            if self.data_type == DATA_TYPE_FLOAT {
+                ret.extend_from_slice(&v.to_f32().unwrap().to_le_bytes());
+            } else {
+                ret.extend_from_slice(&v.to_f64().unwrap().to_le_bytes());
+            }
+        }
+        ret
+    }
+
+    fn float_initializer(&mut self, name: &str, arr: &NdArray<T>) {
+        let raw_data = self.raw_data(arr.iter().cloned());
+        self.initializer(name, arr.shape(), self.data_type, &raw_data);
+    }
+
+    fn int_initializer(&mut self, name: &str, shape: &[usize], values: &[i64]) {
+        let mut raw_data = Vec::with_capacity(values.len() * 8);
+        for v in values {
+            raw_data.extend_from_slice(&v.to_le_bytes());
+        }
+        self.initializer(name, shape, DATA_TYPE_INT64, &raw_data);
+    }
+
+    fn scalar(&mut self, v: f64) -> String {
+        let name = self.new_name("constant");
+        let raw_data = self.raw_data(std::iter::once(T::from(v).unwrap()));
+        self.initializer(&name, &[], self.data_type, &raw_data);
+        name
+    }
+
+    fn ints(&mut self, values: &[i64]) -> String {
+        let name = self.new_name("constant");
+        self.int_initializer(&name, &[values.len()], values);
+        name
+    }
+
+    fn node_with_output(
+        &mut self,
+        op_type: &str,
+        inputs: &[String],
+        attributes: Vec<Message>,
+        output: String,
+    ) -> String {
+        let mut node = Message::new();
+        for input in inputs {
+            node.string(NODE_INPUT, input);
+        }
+        node.string(NODE_OUTPUT, &output)
+            .string(NODE_OP_TYPE, op_type);
+        for attr in &attributes {
+            node.message(NODE_ATTRIBUTE, attr);
+        }
+        self.nodes.push(node);
+        output
+    }
+
+    fn node(&mut self, op_type: &str, inputs: &[String], attributes: Vec<Message>) -> String {
+        let output = self.new_name("value");
+        self.node_with_output(op_type, inputs, attributes, output)
+    }
+
+    fn cast(&mut self, input: String, data_type: i64) -> String {
+        self.node("Cast", &[input], vec![attr_int("to", data_type)])
+    }
+
+    /// Name of the ONNX value of `t`
+    fn value(&mut self, t: &Tensor<T>) -> Result<String, OnnxError> {
+        if let Some(name) = self.names.get(&t.id()) {
+            return Ok(name.clone());
+        }
+        let name = if t.is_placeholder {
+            let msg = "outputs depend on a placeholder not in the inputs".to_string();
+            return Err(OnnxError::InvalidGraph(msg));
+        } else if self.is_constant(t) {
+            let name = match t.name() {
+                Some(name) if self.taken.insert(name.to_owned()) => name.to_owned(),
+                _ => self.new_name(if t.is_variable() {
+                    "variable"
+                } else {
+                    "constant"
+                }),
+            };
+            match t.get_persistent_array() {
+                Some(arr) => self.float_initializer(&name, &arr),
+                None => {
+                    let arr = self.eval(t)?;
+                    self.float_initializer(&name, &arr);
+                }
+            }
+            name
+        } else {
+            self.convert(t)?
+        };
+        self.names.insert(t.id(), name.clone());
+        Ok(name)
+    }
+
+    /// Name of the INT64 version of `t` for the inputs of shapes, axes or indices
+    fn int_value(&mut self, t: &Tensor<T>) -> Result<String, OnnxError> {
+        if let Some(name) = self.int_names.get(&t.id()) {
+            return Ok(name.clone());
+        }
+        let name = if self.is_constant(t) {
+            let name = self.new_name("constant");
+            let arr = self.eval(t)?;
+            self.int_initializer(&name, arr.shape(), &to_ints(&arr));
+            name
+        } else {
+            let value = self.value(t)?;
+            self.cast(value, DATA_TYPE_INT64)
+        };
+        self.int_names.insert(t.id(), name.clone());
+        Ok(name)
+    }
+
+    /// Values of `t` if it's constant, for the integers ONNX takes as attributes
+    fn const_ints(&mut self, t: &Tensor<T>) -> Result<Option<Vec<i64>>, OnnxError> {
+        if !self.is_constant(t) {
+            return Ok(None);
+        }
+        Ok(Some(to_ints(&self.eval(t)?)))
+    }
+
+    fn inputs(&mut self, t: &Tensor<T>) -> Result<Vec<String>, OnnxError> {
+        t.inputs.iter().map(|x| self.value(x)).collect()
+    }
+
+    // Records `reason` and goes on with the inputs to find other unsupported ops.
+    fn unsupported(&mut self, t: &Tensor<T>, reason: String) -> Result<String, OnnxError> {
+        self.unsupported.insert(reason);
+        self.inputs(t)?;
+        Ok(String::new())
+    }
+
+    fn convert(&mut self, t: &Tensor<T>) -> Result<String, OnnxError> {
+        let op = t.op.name();
+        let a = t.op.attributes();
+        let unary_op_type = match op {
+            "Identity" | "StopGradient" => Some("Identity"),
+            "ReLU" => Some("Relu"),
+            "Sigmoid" | "Softplus" | "Tanh" | "Exp" | "Sqrt" | "Abs" | "Floor" | "Ceil"
+            | "Sign" | "Reciprocal" | "Sin" | "Cos" | "Tan" | "Asin" | "Acos" | "Atan" | "Sinh"
+            | "Cosh" | "Asinh" | "Acosh" | "Atanh" | "Neg" => Some(op),
+            _ => None,
+        };
+        if let Some(op_type) = unary_op_type {
+            let x = self.value(&t.inputs[0])?;
+            return Ok(self.node(op_type, &[x], vec![]));
+        }
+
+        let ret = match op {
+            "Add" | "Sub" | "Mul" | "Div" => {
+                let inputs = self.inputs(t)?;
+                self.node(op, &inputs, vec![])
+            }
+            "InplaceAdd" | "InplaceSub" | "InplaceMul" | "InplaceDiv" => {
+                let inputs = self.inputs(t)?;
+                self.node(&op["Inplace".len()..], &inputs, vec![])
+            }
+            "Maximum" | "Minimum" => {
+                let inputs = self.inputs(t)?;
+                self.node(&op[..3], &inputs, vec![])
+            }
+            "Square" => {
+                let x = self.value(&t.inputs[0])?;
+                self.node("Mul", &[x.clone(), x], vec![])
+            }
+            "ELU" => {
+                let x = self.value(&t.inputs[0])?;
+                let alpha = attr_float("alpha", a.get("alpha")?);
+                self.node("Elu", &[x], vec![alpha])
+            }
+            "Softmax" | "LogSoftmax" => {
+                let x = self.value(&t.inputs[0])?;
+                self.node(op, &[x], vec![attr_int("axis", a.get("axis")?)])
+            }
+            "Log" => {
+                let x = self.value(&t.inputs[0])?;
+                let base = a.get::<f64>("a")?;
+                let ln = self.node("Log", &[x], vec![]);
+                if base == std::f64::consts::E {
+                    ln
+                } else {
+                    let ln_base = self.scalar(base.ln());
+                    self.node("Div", &[ln, ln_base], vec![])
+                }
+            }
+            "Pow" => {
+                let x = self.value(&t.inputs[0])?;
+                let exponent = self.scalar(a.get("a")?);
+                self.node("Pow", &[x, exponent], vec![])
+            }
+            "Clip" => {
+                let x = self.value(&t.inputs[0])?;
+                let min = self.scalar(a.get("min")?);
+                let max = self.scalar(a.get("max")?);
+                self.node("Clip", &[x, min, max], vec![])
+            }
+            "AddN" => {
+                let inputs = self.inputs(t)?;
+                self.node("Sum", &inputs, vec![])
+            }
+            "MatMul" | "BatchMatMul" => {
+                let trans_a = a.get::<bool>("transpose_a")?;
+                let trans_b = a.get::<bool>("transpose_b")?;
+                if !trans_a && !trans_b {
+                    let inputs = self.inputs(t)?;
+                    self.node("MatMul", &inputs, vec![])
+                } else if op == "MatMul" {
+                    let inputs = self.inputs(t)?;
+                    let attrs = vec![
+                        attr_int("transA", trans_a as i64),
+                        attr_int("transB", trans_b as i64),
+                    ];
+                    self.node("Gemm", &inputs, attrs)
+                } else {
+                    return self.unsupported(t, "BatchMatMul with transposition".to_string());
+                }
+            }
+            "Conv2D" | "Conv2DTranspose" => {
+                let inputs = self.inputs(t)?;
+                let pad = a.get::<i64>("pad")?;
+                let stride = a.get::<i64>("stride")?;
+                let dilation = a.get::<i64>("dilation")?;
+                let attrs = vec![
+                    attr_ints("pads", &[pad; 4]),
+                    attr_ints("strides", &[stride; 2]),
+                    attr_ints("dilations", &[dilation; 2]),
+                ];
+                let op_type = if op == "Conv2D" {
+                    "Conv"
+                } else {
+                    "ConvTranspose"
+                };
+                self.node(op_type, &inputs, attrs)
+            }
+            "MaxPool" => {
+                let x = self.value(&t.inputs[0])?;
+                let pad = a.get::<i64>("pad")?;
+                let attrs = vec![
+                    attr_ints("kernel_shape", &[a.get("size")?; 2]),
+                    attr_ints("pads", &[pad; 4]),
+                    attr_ints("strides", &[a.get("stride")?; 2]),
+                ];
+                self.node("MaxPool", &[x], attrs)
+            }
+            "Reshape" => {
+                let x = self.value(&t.inputs[0])?;
+                let shape = self.int_value(&t.inputs[1])?;
+                self.node("Reshape", &[x, shape], vec![])
+            }
+            "Concat" => {
+                let inputs = self.inputs(t)?;
+                self.node("Concat", &inputs, vec![attr_int("axis", a.get("axis")?)])
+            }
+            "Transpose" => match self.const_ints(&t.inputs[1])? {
+                Some(axes) => {
+                    let x = self.value(&t.inputs[0])?;
+                    let mut perm = axes.clone();
+                    if a.get("invert_axes")? {
+                        for (i, &axis) in axes.iter().enumerate() {
+                            perm[axis as usize] = i as i64;
+                        }
+                    }
+                    self.node("Transpose", &[x], vec![attr_ints("perm", &perm)])
+                }
+                None => return self.unsupported(t, "Transpose with non-constant axes".into()),
+            },
+            "ReduceSum" | "ReduceMean" | "ReduceProd" | "ReduceMin" | "ReduceMax" => {
+                if a.get("sparse_axes")? {
+                    return self.unsupported(t, format!("{} with sparse axes", op));
+                }
+                let x = self.value(&t.inputs[0])?;
+                let keepdims = attr_int("keepdims", a.get::<bool>("keep_dims")? as i64);
+                if op == "ReduceSum" {
+                    // Takes the axes as an input since opset 13
+                    let axes = self.int_value(&t.inputs[1])?;
+                    self.node(op, &[x, axes], vec![keepdims])
+                } else {
+                    match self.const_ints(&t.inputs[1])? {
+                        Some(axes) => self.node(op, &[x], vec![attr_ints("axes", &axes), keepdims]),
+                        None => {
+                            return self.unsupported(t, format!("{} with non-constant axes", op))
+                        }
+                    }
+                }
+            }
+            "ReduceSumToScalar" => {
+                let x = self.value(&t.inputs[0])?;
+                self.node("ReduceSum", &[x], vec![attr_int("keepdims", 0)])
+            }
+            "LogSumExp" => {
+                let x = self.value(&t.inputs[0])?;
+                let attrs = vec![
+                    attr_ints("axes", &[a.get("axis")?]),
+                    attr_int("keepdims", a.get::<bool>("keep_dims")? as i64),
+                ];
+                self.node("ReduceLogSumExp", &[x], attrs)
+            }
+            "ArgMax" => {
+                let x = self.value(&t.inputs[0])?;
+                let attrs = vec![
+                    attr_int("axis", a.get("axis")?),
+                    attr_int("keepdims", a.get::<bool>("keep_dim")? as i64),
+                ];
+                let indices = self.node("ArgMax", &[x], attrs);
+                self.cast(indices, self.data_type)
+            }
+            "ExpandDims" => match self.const_ints(&t.inputs[1])? {
+                // Negative axes count from the end of the input, not of the output.
+                Some(ref axes) if axes.iter().all(|&axis| axis >= 0) => {
+                    let x = self.value(&t.inputs[0])?;
+                    let axes = self.ints(axes);
+                    self.node("Unsqueeze", &[x, axes], vec![])
+                }
+                _ => return self.unsupported(t, "ExpandDims with negative axes".into()),
+            },
+            "Squeeze" => {
+                let x = self.value(&t.inputs[0])?;
+                let axes = self.int_value(&t.inputs[1])?;
+                self.node("Squeeze", &[x, axes], vec![])
+            }
+            "Slice" => match slice_ranges(&a)? {
+                Some(ranges) => {
+                    let x = self.value(&t.inputs[0])?;
+                    let column = |i: usize| ranges.iter().map(|r| r[i]).collect::<Vec<_>>();
+                    let axes = (0..ranges.len() as i64).collect::<Vec<_>>();
+                    let inputs = vec![
+                        x,
+                        self.ints(&column(0)),
+                        self.ints(&column(1)),
+                        self.ints(&axes),
+                        self.ints(&column(2)),
+                    ];
+                    self.node("Slice", &inputs, vec![])
+                }
+                None => return self.unsupported(t, "Slice with indices or negative steps".into()),
+            },
+            "Split" => {
+                let x = self.value(&t.inputs[0])?;
+                let inputs = vec![
+                    x,
+                    self.ints(&[a.get("start_index")?]),
+                    self.ints(&[a.get("end_index")?]),
+                    self.ints(&[a.get("axis")?]),
+                ];
+                self.node("Slice", &inputs, vec![])
+            }
+            "Gather" => {
+                // The inputs are indices and params in this order.
+                let indices = self.int_value(&t.inputs[0])?;
+                let params = self.value(&t.inputs[1])?;
+                let axis = attr_int("axis", a.get("axis")?);
+                self.node("Gather", &[params, indices], vec![axis])
+            }
+            "Shape" | "Size" | "Rank" => {
+                let x = self.value(&t.inputs[0])?;
+                let ret = if op == "Rank" {
+                    let shape = self.node("Shape", &[x], vec![]);
+                    self.node("Size", &[shape], vec![])
+                } else {
+                    self.node(op, &[x], vec![])
+                };
+                self.cast(ret, self.data_type)
+            }
+            "Zeros" | "Ones" => {
+                let shape = self.int_value(&t.inputs[0])?;
+                let v = if op == "Zeros" { T::zero() } else { T::one() };
+                let mut value = Message::new();
+                value
+                    .int(TENSOR_DIMS, 1)
+                    .int(TENSOR_DATA_TYPE, self.data_type)
+                    .bytes(TENSOR_RAW_DATA, &self.raw_data(std::iter::once(v)));
+                let mut attr = Message::new();
+                attr.string(ATTR_NAME, "value")
+                    .int(ATTR_TYPE, ATTR_TYPE_TENSOR)
+                    .message(ATTR_T, &value);
+                self.node("ConstantOfShape", &[shape], vec![attr])
+            }
+            _ => return self.unsupported(t, op.to_string()),
+        };
+        Ok(ret)
+    }
+}
+
+fn to_ints<T: Float>(arr: &NdArray<T>) -> Vec<i64> {
+    arr.iter().map(|v| v.to_f64().unwrap() as i64).collect()
+}
+
+// `[start, end, step]` of `Slice` for each axis
+fn slice_ranges(a: &Attributes) -> Result<Option<Vec<[i64; 3]>>, OnnxError> {
+    let indices = a.get::<Vec<i64>>("indices")?;
+    // Flattened as `[start, end, step, kind]` for each axis
+    let ranges = indices
+        .chunks(4)
+        .map(|index| match *index {
+            [start, end, step, kind] if step > 0 && kind != 2 => {
+                Some([start, if kind == 0 { end } else { i64::MAX }, step])
+            }
+            _ => None,
+        })
+        .collect();
+    Ok(ranges)
+}
+
+fn attr_int(name: &str, v: i64) -> Message {
+    let mut ret = Message::new();
+    ret.string(ATTR_NAME, name)
+        .int(ATTR_TYPE, ATTR_TYPE_INT)
+        .int(ATTR_I, v);
+    ret
+}
+
+fn attr_float(name: &str, v: f64) -> Message {
+    let mut ret = Message::new();
+    ret.string(ATTR_NAME, name)
+        .int(ATTR_TYPE, ATTR_TYPE_FLOAT)
+        .float(ATTR_F, v as f32);
+    ret
+}
+
+fn attr_ints(name: &str, values: &[i64]) -> Message {
+    let mut ret = Message::new();
+    ret.string(ATTR_NAME, name).int(ATTR_TYPE, ATTR_TYPE_INTS);
+    for &v in values {
+        ret.int(ATTR_INTS, v);
+    }
+    ret
+}
+
+#[test]
+fn test_export() {
+    use super::proto::Fields;
+
+    let ref x = crate::ops::placeholder::<f32>(&[-1, 1, 4, 4]);
+    let ref w = crate::ops::variable(crate::ndarray_ext::standard_normal(&[2, 1, 3, 3]));
+    let ref h = crate::ops::relu(&crate::ops::conv2d(x, w, 1, 1));
+    let ref h = crate::ops::max_pool2d(h, 2, 0, 2);
+    let ref h = crate::ops::reshape(h, &[-1, 8]);
+    let ref w2 = crate::ops::variable(crate::ndarray_ext::standard_normal(&[8, 3]));
+    let ref b2 = crate::ops::constant(crate::ndarray_ext::zeros(&[1, 3]));
+    let ref logits = crate::ops::matmul(h, w2) + b2;
+    let ref y = crate::ops::concat(
+        &[
+            &crate::ops::softmax(logits, 1),
+            &crate::ops::sigmoid(logits),
+        ],
+        1,
+    );
+
+    let bytes = export_bytes(&[x], &[y]).unwrap();
+    let model = Fields::decode(&bytes).unwrap();
+    assert_eq!(model.int(MODEL_IR_VERSION), IR_VERSION);
+    let opset = &model.messages(MODEL_OPSET_IMPORT).unwrap()[0];
+    assert_eq!(opset.int(OPSET_VERSION_FIELD), OPSET_VERSION);
+    let graph = &model.messages(MODEL_GRAPH).unwrap()[0];
+
+    let nodes = graph.messages(GRAPH_NODE).unwrap();
+    let op_types = nodes
+        .iter()
+        .map(|n| n.string(NODE_OP_TYPE))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        op_types,
+        vec![
+            "Conv", "Relu", "MaxPool", "Reshape", "MatMul", "Add", "Softmax", "Sigmoid", "Concat",
+            "Identity"
+        ]
+    );
+    let conv = &nodes[0];
+    assert_eq!(conv.strings(NODE_INPUT)[0], "input_0");
+    let attrs = conv.messages(NODE_ATTRIBUTE).unwrap();
+    assert_eq!(attrs[0].string(ATTR_NAME), "pads");
+    assert_eq!(attrs[0].ints(ATTR_INTS), vec![1; 4]);
+    assert_eq!(nodes[9].strings(NODE_OUTPUT), vec!["output_0"]);
+
+    // Weights, the reshape target as INT64, and the bias
+    let initializers = graph.messages(GRAPH_INITIALIZER).unwrap();
+    let dims = initializers
+        .iter()
+        .map(|t| (t.int(TENSOR_DATA_TYPE), t.ints(TENSOR_DIMS)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        dims,
+        vec![
+            (DATA_TYPE_FLOAT, vec![2, 1, 3, 3]),
+            (DATA_TYPE_INT64, vec![2]),
+            (DATA_TYPE_FLOAT, vec![8, 3]),
+            (DATA_TYPE_FLOAT, vec![1, 3]),
+        ]
+    );
+    let raw_data = initializers[0].bytes(TENSOR_RAW_DATA)[0];
+    let w_arr = w.get_persistent_array().unwrap();
+    assert_eq!(&raw_data[..4], &w_arr[[0, 0, 0, 0]].to_le_bytes());
+    assert_eq!(
+        initializers[1].bytes(TENSOR_RAW_DATA)[0],
+        &[(-1i64).to_le_bytes(), 8i64.to_le_bytes()].concat()[..]
+    );
+
+    let input = &graph.messages(GRAPH_INPUT).unwrap()[0];
+    assert_eq!(input.string(VALUE_INFO_NAME), "input_0");
+    let ty = &input.messages(VALUE_INFO_TYPE).unwrap()[0];
+    let tensor_type = &ty.messages(TYPE_TENSOR_TYPE).unwrap()[0];
+    assert_eq!(tensor_type.int(TENSOR_TYPE_ELEM_TYPE), DATA_TYPE_FLOAT);
+    let shape = &tensor_type.messages(TENSOR_TYPE_SHAPE).unwrap()[0];
+    let dims = shape
+        .messages(SHAPE_DIM)
+        .unwrap()
+        .iter()
+        .map(|d| d.ints(DIM_VALUE))
+        .collect::<Vec<_>>();
+    assert_eq!(dims, vec![vec![], vec![1], vec![4], vec![4]]);
+
+    // Axes and slices are written as attributes or INT64 inputs.
+    let ref x2 = crate::ops::placeholder::<f64>(&[-1, 4]);
+    let ref h = crate::ops::slice(x2, &[0, 1], &[-1, 3]);
+    let ref h = crate::ops::reduce_mean(&crate::ops::transpose(h, &[1, 0]), &[1], true);
+    let bytes = export_bytes(&[x2], &[h]).unwrap();
+    let model = Fields::decode(&bytes).unwrap();
+    let graph = &model.messages(MODEL_GRAPH).unwrap()[0];
+    let nodes = graph.messages(GRAPH_NODE).unwrap();
+    let op_types = nodes
+        .iter()
+        .map(|n| n.string(NODE_OP_TYPE))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        op_types,
+        vec!["Slice", "Transpose", "ReduceMean", "Identity"]
+    );
+    let initializers = graph.messages(GRAPH_INITIALIZER).unwrap();
+    let ends = initializers[1].bytes(TENSOR_RAW_DATA)[0];
+    assert_eq!(&ends[..8], &i64::MAX.to_le_bytes());
+    let perm = &nodes[1].messages(NODE_ATTRIBUTE).unwrap()[0];
+    assert_eq!(perm.ints(ATTR_INTS), vec![1, 0]);
+    let axes = &nodes[2].messages(NODE_ATTRIBUTE).unwrap()[0];
+    assert_eq!(axes.ints(ATTR_INTS), vec![1]);
+
+    // All the unsupported ops are reported.
+    let ref t = crate::ops::tile(y, 0, 2);
+    let ref z =
+        crate::ops::batch_matmul_t(t, t, false, true) + crate::ops::softmax_cross_entropy(t, t);
+    match export_bytes(&[x], &[z]) {
+        Err(OnnxError::Unsupported(ops)) => assert_eq!(
+            ops,
+            vec![
+                "BatchMatMul with transposition",
+                "SoftmaxCrossEntropy",
+                "Tile"
+            ]
+        ),
+        _ => panic!("expected unsupported ops"),
+    }
+    match export_bytes::<f32, &crate::Tensor<f32>, _>(&[], &[y]) {
+        Err(OnnxError::InvalidGraph(_)) => {}
+        _ => panic!("expected an invalid graph"),
+    }
+}
@@ -0,0 +1,1087 @@
+use super::proto::Fields;
+use super::*;
+use crate::graph_def::OpRegistry;
+use crate::ndarray_ext::NdArray;
+use crate::op::Attributes;
+use crate::ops;
+use crate::tensor::Tensor;
+use crate::Float;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::RwLockReadGuard;
+
+/// Tensors built from an ONNX model by `import`
+pub struct ImportedGraph<T: Float> {
+    /// Tensors by the names of the ONNX values, i.e. the graph inputs, initializers
+    /// and outputs of the nodes
+    pub tensors: BTreeMap<String, Tensor<T>>,
+    /// Names of the graph inputs, which are placeholders
+    pub inputs: Vec<String>,
+    /// Names of the graph outputs
+    pub outputs: Vec<String>,
+    /// Names of the variables made from the float initializers
+    pub variables: Vec<String>,
+}
+
+/// Reads an ONNX file and builds its graph.
+///
+/// See `import_bytes`.
+pub fn import<T: Float, P: AsRef<Path>>(path: P) -> Result<ImportedGraph<T>, OnnxError> {
+    import_bytes(&fs::read(path)?)
+}
+
+/// Builds the graph of an ONNX model.
+///
+/// Float initializers become variables, and the other initializers (e.g. shapes and
+/// axes) become constants. Graph inputs that aren't initializers become placeholders
+/// with their declared shapes, where symbolic dimensions are `-1`.
+/// They're named after the ONNX values in the current name scope (see `ag::name_scope`).
+/// All the values are converted to `T`.
+///
+/// Fails with `OnnxError::Unsupported` listing all the ops (or their attributes)
+/// that have no counterparts in this crate.
+pub fn import_bytes<T: Float>(bytes: &[u8]) -> Result<ImportedGraph<T>, OnnxError> {
+    let model = Fields::decode(bytes)?;
+    let mut opset = OPSET_VERSION;
+    for opset_import in model.messages(MODEL_OPSET_IMPORT)? {
+        let domain = opset_import.string(OPSET_DOMAIN);
+        if domain.is_empty() || domain == "ai.onnx" {
+            opset = opset_import.int(OPSET_VERSION_FIELD);
+        }
+    }
+    let graph = model.message(MODEL_GRAPH)?;
+    let mut importer = Importer::new(opset);
+    let mut variables = Vec::new();
+    for initializer in graph.messages(GRAPH_INITIALIZER)? {
+        let name = initializer.string(TENSOR_NAME);
+        if name.is_empty() {
+            return Err(OnnxError::InvalidGraph("unnamed initializer".to_string()));
+        }
+        let (arr, is_float) = parse_tensor(&initializer)?;
+        importer.ranks.insert(name.clone(), arr.ndim());
+        let t = if is_float {
+            variables.push(name.clone());
+            ops::variable_named(&name, arr)
+        } else {
+            ops::constant_named(&name, arr)
+        };
+        importer.tensors.insert(name, t);
+    }
+    for info in graph.messages(GRAPH_VALUE_INFO)? {
+        if let Some(shape) = declared_shape(&info)? {
+            importer
+                .ranks
+                .insert(info.string(VALUE_INFO_NAME), shape.len());
+        }
+    }
+    let mut inputs = Vec::new();
+    for info in graph.messages(GRAPH_INPUT)? {
+        let name = info.string(VALUE_INFO_NAME);
+        // Old models list initializers in the inputs too.
+        if importer.tensors.contains_key(&name) {
+            continue;
+        }
+        if name.is_empty() {
+            return Err(OnnxError::InvalidGraph("unnamed input".to_string()));
+        }
+        let t = match declared_shape(&info)? {
+            Some(shape) => {
+                importer.ranks.insert(name.clone(), shape.len());
+                ops::placeholder_named(&name, &shape)
+            }
+            None => {
+                let op = importer
+                    .registry
+                    .construct("Placeholder", &Attributes::new())?;
+                Tensor::builder()
+                    .set_is_placeholder(true)
+                    .set_name(&name)
+                    .build_boxed(op)
+            }
+        };
+        importer.tensors.insert(name.clone(), t);
+        inputs.push(name);
+    }
+    for node in graph.messages(GRAPH_NODE)? {
+        importer.node(&node)?;
+    }
+    if !importer.unsupported.is_empty() {
+        let ops = importer.unsupported.into_iter().collect();
+        return Err(OnnxError::Unsupported(ops));
+    }
+
+    let mut outputs = Vec::new();
+    for info in graph.messages(GRAPH_OUTPUT)? {
+        let name = info.string(VALUE_INFO_NAME);
+        if !importer.tensors.contains_key(&name) {
+            return Err(OnnxError::InvalidGraph(format!(
+                "undefined output {}",
+                name
+            )));
+        }
+        outputs.push(name);
+    }
+    Ok(ImportedGraph {
+        tensors: importer.tensors,
+        inputs,
+        outputs,
+        variables,
+    })
+}
+
+// Shape in the `TypeProto` of a `ValueInfoProto`, where unknown dimensions are -1
+fn declared_shape(info: &Fields) -> Result<Option<Vec<isize>>, OnnxError> {
+    let tensor_type = info.message(VALUE_INFO_TYPE)?.message(TYPE_TENSOR_TYPE)?;
+    if tensor_type.bytes(TENSOR_TYPE_SHAPE).is_empty() {
+        return Ok(None);
+    }
+    let dims = tensor_type
+        .message(TENSOR_TYPE_SHAPE)?
+        .messages(SHAPE_DIM)?;
+    let shape = dims
+        .iter()
+        .map(|dim| match dim.ints(DIM_VALUE).last() {
+            Some(&d) => d as isize,
+            None => -1,
+        })
+        .collect();
+    Ok(Some(shape))
+}
+
+// Converts a `TensorProto` to an array, telling whether it's of a float type.
+fn parse_tensor<T: Float>(tensor: &Fields) -> Result<(NdArray<T>, bool), OnnxError> {
+    let name = tensor.string(TENSOR_NAME);
+    if tensor.int(TENSOR_DATA_LOCATION) == DATA_LOCATION_EXTERNAL {
+        return Err(OnnxError::Unsupported(vec![format!(
+            "external data of {}",
+            name
+        )]));
+    }
+    let data_type = tensor.int(TENSOR_DATA_TYPE);
+    let values: Vec<f64> = match tensor.bytes(TENSOR_RAW_DATA).pop() {
+        Some(raw) => {
+            let size = match data_type {
+                DATA_TYPE_UINT8 | DATA_TYPE_INT8 | DATA_TYPE_BOOL => 1,
+                DATA_TYPE_UINT16 | DATA_TYPE_INT16 => 2,
+                DATA_TYPE_FLOAT | DATA_TYPE_INT32 | DATA_TYPE_UINT32 => 4,
+                DATA_TYPE_DOUBLE | DATA_TYPE_INT64 | DATA_TYPE_UINT64 => 8,
+                _ => 0,
+            };
+            if size == 0 {
+                let msg = format!("data type {} of {}", data_type, name);
+                return Err(OnnxError::Unsupported(vec![msg]));
+            }
+            raw.chunks_exact(size)
+                .map(|c| {
+                    let mut b = [0; 8];
+                    b[..size].copy_from_slice(c);
+                    match data_type {
+                        DATA_TYPE_FLOAT => f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                        DATA_TYPE_DOUBLE => f64::from_le_bytes(b),
+                        DATA_TYPE_INT8 => f64::from(b[0] as i8),
+                        DATA_TYPE_INT16 => f64::from(i16::from_le_bytes([b[0], b[1]])),
+                        DATA_TYPE_INT32 => f64::from(i32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                        DATA_TYPE_INT64 => i64::from_le_bytes(b) as f64,
+                        // Unsigned integers are zero-extended.
+                        _ => u64::from_le_bytes(b) as f64,
+                    }
+                })
+                .collect()
+        }
+        None => match data_type {
+            DATA_TYPE_FLOAT => tensor
+                .floats(TENSOR_FLOAT_DATA)
+                .into_iter()
+                .map(f64::from)
+                .collect(),
+            DATA_TYPE_DOUBLE => tensor.doubles(TENSOR_DOUBLE_DATA),
+            DATA_TYPE_INT64 => tensor
+                .ints(TENSOR_INT64_DATA)
+                .into_iter()
+                .map(|v| v as f64)
+                .collect(),
+            DATA_TYPE_UINT32 | DATA_TYPE_UINT64 => tensor
+                .ints(TENSOR_UINT64_DATA)
+                .into_iter()
+                .map(|v| v as u64 as f64)
+                .collect(),
+            DATA_TYPE_UINT8 | DATA_TYPE_INT8 | DATA_TYPE_UINT16 | DATA_TYPE_INT16
+            | DATA_TYPE_INT32 | DATA_TYPE_BOOL => tensor
+                .ints(TENSOR_INT32_DATA)
+                .into_iter()
+                .map(|v| v as i32 as f64)
+                .collect(),
+            _ => {
+                let msg = format!("data type {} of {}", data_type, name);
+                return Err(OnnxError::Unsupported(vec![msg]));
+            }
+        },
+    };
+    let shape = tensor
+        .ints(TENSOR_DIMS)
+        .iter()
+        .map(|&d| d as usize)
+        .collect::<Vec<_>>();
+    let values = values.into_iter().map(|v| T::from(v).unwrap()).collect();
+    let arr = NdArray::from_shape_vec(ndarray::IxDyn(&shape), values)
+        .map_err(|_| OnnxError::InvalidGraph(format!("size of {} doesn't match its dims", name)))?;
+    let is_float = data_type == DATA_TYPE_FLOAT || data_type == DATA_TYPE_DOUBLE;
+    Ok((arr, is_float))
+}
+
+fn unsupported<R>(reason: String) -> Result<R, OnnxError> {
+    Err(OnnxError::Unsupported(vec![reason]))
+}
+
+// 1-D tensor of integers, e.g. axes and shapes
+fn ints_tensor<T: Float>(values: &[i64]) -> Tensor<T> {
+    let arr = values
+        .iter()
+        .map(|&v| T::from(v).unwrap())
+        .collect::<Vec<_>>();
+    ops::convert_to_tensor(NdArray::from_shape_vec(ndarray::IxDyn(&[values.len()]), arr).unwrap())
+}
+
+// The single value that `values` repeats, e.g. of symmetric pads
+fn uniform(values: &[i64], attr: &str) -> Result<usize, OnnxError> {
+    match values.split_first() {
+        Some((&v, rest)) if v >= 0 && rest.iter().all(|&w| w == v) => Ok(v as usize),
+        _ => unsupported(format!("{} {:?}", attr, values)),
+    }
+}
+
+struct Node<'a, T: Float> {
+    op_type: String,
+    input_names: Vec<String>,
+    inputs: Vec<Option<Tensor<T>>>,
+    ranks: Vec<Option<usize>>,
+    attributes: HashMap<String, Fields<'a>>,
+}
+
+impl<'a, T: Float> Node<'a, T> {
+    fn input(&self, i: usize) -> Result<&Tensor<T>, OnnxError> {
+        match self.inputs.get(i) {
+            Some(Some(t)) => Ok(t),
+            _ => {
+                let msg = format!("{} misses input {}", self.op_type, i);
+                Err(OnnxError::InvalidGraph(msg))
+            }
+        }
+    }
+
+    fn optional_input(&self, i: usize) -> Option<&Tensor<T>> {
+        self.inputs.get(i).and_then(|t| t.as_ref())
+    }
+
+    fn rank(&self, i: usize) -> Option<usize> {
+        self.ranks.get(i).cloned().unwrap_or(None)
+    }
+
+    fn int(&self, name: &str) -> Option<i64> {
+        self.attributes.get(name).map(|a| a.int(ATTR_I))
+    }
+
+    fn ints(&self, name: &str) -> Option<Vec<i64>> {
+        self.attributes.get(name).map(|a| a.ints(ATTR_INTS))
+    }
+
+    fn float(&self, name: &str) -> Option<f32> {
+        self.attributes
+            .get(name)
+            .and_then(|a| a.floats(ATTR_F).last().cloned())
+    }
+
+    fn string(&self, name: &str) -> Option<String> {
+        self.attributes.get(name).map(|a| a.string(ATTR_S))
+    }
+
+    // Value of a constant input, e.g. axes given as an input since opset 13
+    fn const_input(&self, i: usize) -> Result<Option<RwLockReadGuard<'_, NdArray<T>>>, OnnxError> {
+        match self.optional_input(i) {
+            Some(t) => match t.get_persistent_array() {
+                Some(arr) => Ok(Some(arr)),
+                None => unsupported(format!(
+                    "{} with non-constant input {}",
+                    self.op_type, self.input_names[i]
+                )),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Integers of an attribute, or of the constant input that replaced it in newer opsets
+    fn ints_arg(&self, name: &str, i: usize) -> Result<Option<Vec<i64>>, OnnxError> {
+        if let Some(values) = self.ints(name) {
+            return Ok(Some(values));
+        }
+        let values = self
+            .const_input(i)?
+            .map(|arr| arr.iter().map(|v| v.to_f64().unwrap() as i64).collect());
+        Ok(values)
+    }
+
+    // Scalar of a constant input or of an attribute
+    fn float_arg(&self, name: &str, i: usize) -> Result<Option<T>, OnnxError> {
+        if let Some(v) = self.float(name) {
+            return Ok(Some(T::from(v).unwrap()));
+        }
+        Ok(self
+            .const_input(i)?
+            .and_then(|arr| arr.iter().next().cloned()))
+    }
+
+    // Normalizes negative axes, which needs the rank.
+    fn axes(&self, axes: Vec<i64>, rank: Option<usize>) -> Result<Vec<i64>, OnnxError> {
+        match rank {
+            Some(r) => Ok(axes
+                .into_iter()
+                .map(|a| if a < 0 { a + r as i64 } else { a })
+                .collect()),
+            None if axes.iter().all(|&a| a >= 0) => Ok(axes),
+            None => unsupported(format!(
+                "{} with negative axes of unknown rank",
+                self.op_type
+            )),
+        }
+    }
+}
+
+type Output<T> = (Tensor<T>, Option<usize>);
+
+// Bound of the axes of `Slice` whose input rank is unknown
+const MAX_SLICE_RANK: usize = 32;
+
+struct Importer<T: Float> {
+    tensors: BTreeMap<String, Tensor<T>>,
+    // Ranks of the values if known
+    ranks: HashMap<String, usize>,
+    // Outputs of unsupported nodes
+    failed: HashSet<String>,
+    unsupported: BTreeSet<String>,
+    opset: i64,
+    registry: OpRegistry<T>,
+}
+
+impl<T: Float> Importer<T> {
+    fn new(opset: i64) -> Importer<T> {
+        Importer {
+            tensors: BTreeMap::new(),
+            ranks: HashMap::new(),
+            failed: HashSet::new(),
+            unsupported: BTreeSet::new(),
+            opset,
+            registry: OpRegistry::default(),
+        }
+    }
+
+    fn node(&mut self, fields: &Fields) -> Result<(), OnnxError> {
+        let outputs = fields.strings(NODE_OUTPUT);
+        let mut node = Node {
+            op_type: fields.string(NODE_OP_TYPE),
+            input_names: fields.strings(NODE_INPUT),
+            inputs: Vec::new(),
+            ranks: Vec::new(),
+            attributes: HashMap::new(),
+        };
+        for attr in fields.messages(NODE_ATTRIBUTE)? {
+            node.attributes.insert(attr.string(ATTR_NAME), attr);
+        }
+        for name in &node.input_names {
+            // Omitted optional inputs have empty names.
+            if name.is_empty() {
+                node.inputs.push(None);
+            } else if let Some(t) = self.tensors.get(name) {
+                node.inputs.push(Some(t.clone()));
+            } else if self.failed.contains(name) {
+                self.failed.extend(outputs);
+                return Ok(());
+            } else {
+                let msg = format!("undefined value {}", name);
+                return Err(OnnxError::InvalidGraph(msg));
+            }
+            node.ranks.push(self.ranks.get(name).cloned());
+        }
+
+        let domain = fields.string(NODE_DOMAIN);
+        let result = if domain.is_empty() || domain == "ai.onnx" {
+            self.convert(&node)
+        } else {
+            unsupported(format!("{}.{}", domain, node.op_type))
+        };
+        match result {
+            Ok(ys) => {
+                for (name, (t, rank)) in outputs.into_iter().zip(ys) {
+                    if let Some(r) = rank {
+                        self.ranks.insert(name.clone(), r);
+                    }
+                    self.tensors.insert(name, t);
+                }
+            }
+            Err(OnnxError::Unsupported(reasons)) => {
+                self.unsupported.extend(reasons);
+                self.failed.extend(outputs);
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    fn convert(&self, node: &Node<T>) -> Result<Vec<Output<T>>, OnnxError> {
+        let op = node.op_type.as_str();
+        let unary: Option<fn(Tensor<T>) -> Tensor<T>> = match op {
+            "Identity" | "Dropout" => Some(ops::identity),
+            "Relu" => Some(ops::relu),
+            "Sigmoid" => Some(ops::sigmoid),
+            "Softplus" => Some(ops::softplus),
+            "Tanh" => Some(ops::tanh),
+            "Exp" => Some(ops::exp),
+            "Sqrt" => Some(ops::sqrt),
+            "Neg" => Some(ops::neg),
+            "Abs" => Some(ops::abs),
+            "Floor" => Some(ops::floor),
+            "Ceil" => Some(ops::ceil),
+            "Sign" => Some(ops::sign),
+            "Reciprocal" => Some(ops::reciprocal),
+            "Sin" => Some(ops::sin),
+            "Cos" => Some(ops::cos),
+            "Tan" => Some(ops::tan),
+            "Asin" => Some(ops::asin),
+            "Acos" => Some(ops::acos),
+            "Atan" => Some(ops::atan),
+            "Sinh" => Some(ops::sinh),
+            "Cosh" => Some(ops::cosh),
+            "Asinh" => Some(ops::asinh),
+            "Acosh" => Some(ops::acosh),
+            "Atanh" => Some(ops::atanh),
+            _ => None,
+        };
+        if let Some(f) = unary {
+            return Ok(vec![(f(node.input(0)?.clone()), node.rank(0))]);
+        }
+
+        let x = node.input(0)?;
+        let rank = node.rank(0);
+        let y = match op {
+            "Log" => (ops::log(x, T::from(std::f64::consts::E).unwrap()), rank),
+            "Elu" | "LeakyRelu" => {
+                let default = if op == "Elu" { 1. } else { 0.01 };
+                let alpha = T::from(node.float("alpha").unwrap_or(default)).unwrap();
+                if op == "Elu" {
+                    (ops::elu(x, alpha), rank)
+                } else {
+                    (ops::leaky_relu(x, alpha), rank)
+                }
+            }
+            "Softmax" | "LogSoftmax" => {
+                // The default axis was 1 before opset 13.
+                let default = if self.opset >= 13 { -1 } else { 1 };
+                let axis = node.int("axis").unwrap_or(default) as isize;
+                if op == "Softmax" {
+                    (ops::softmax(x, axis), rank)
+                } else {
+                    (ops::log_softmax(x, axis), rank)
+                }
+            }
+            "Add" | "Sub" | "Mul" | "Div" | "Max" | "Min" | "Sum" | "Mean" => {
+                let (xs, rank) = self.broadcast_inputs(node)?;
+                let mut y = xs[0].clone();
+                for x in &xs[1..] {
+                    y = match op {
+                        "Sub" => y - x,
+                        "Mul" => y * x,
+                        "Div" => y / x,
+                        "Max" => ops::maximum(&y, x),
+                        "Min" => ops::minimum(&y, x),
+                        _ => y + x,
+                    };
+                }
+                if op == "Mean" {
+                    y = y / T::from(xs.len()).unwrap();
+                }
+                (y, rank)
+            }
+            "Pow" => match node.const_input(1)? {
+                Some(arr) if arr.len() == 1 => {
+                    (ops::pow(x, arr.iter().cloned().next().unwrap()), rank)
+                }
+                _ => return unsupported("Pow with non-scalar exponents".to_string()),
+            },
+            "MatMul" => {
+                let w = node.input(1)?;
+                match (rank, node.rank(1)) {
+                    (Some(r), Some(2)) if r > 2 => {
+                        let axes = (ints_tensor(&[r as i64 - 1]), ints_tensor(&[0]));
+                        (ops::tensordot(x, w, &axes.0, &axes.1), Some(r))
+                    }
+                    (Some(r), Some(s)) if r > 2 && r == s => (ops::batch_matmul(x, w), Some(r)),
+                    (Some(2), Some(2)) | (None, _) | (_, None) => (ops::matmul(x, w), Some(2)),
+                    (r, s) => return unsupported(format!("MatMul of ranks {:?} and {:?}", r, s)),
+                }
+            }
+            "Gemm" => {
+                let trans_a = node.int("transA").unwrap_or(0) != 0;
+                let trans_b = node.int("transB").unwrap_or(0) != 0;
+                let mut y = ops::matmul_t(x, node.input(1)?, trans_a, trans_b);
+                let alpha = node.float("alpha").unwrap_or(1.);
+                if alpha != 1. {
+                    y = y * T::from(alpha).unwrap();
+                }
+                if let Some(c) = node.optional_input(2) {
+                    let mut c = match node.rank(2) {
+                        Some(1) => ops::expand_dims(c, &[0]),
+                        _ => c.clone(),
+                    };
+                    let beta = node.float("beta").unwrap_or(1.);
+                    if beta != 1. {
+                        c = c * T::from(beta).unwrap();
+                    }
+                    y = y + c;
+                }
+                (y, Some(2))
+            }
+            "Conv" | "ConvTranspose" => {
+                if node.int("group").unwrap_or(1) != 1 {
+                    return unsupported(format!("{} with groups", op));
+                }
+                if node
+                    .ints("output_padding")
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|&p| p != 0)
+                    || node.ints("output_shape").is_some()
+                {
+                    return unsupported(format!("{} with output shapes", op));
+                }
+                self.check_auto_pad(node)?;
+                let pad = match node.ints("pads") {
+                    Some(pads) => uniform(&pads, "pads")?,
+                    None => 0,
+                };
+                let stride = uniform(&node.ints("strides").unwrap_or_else(|| vec![1]), "strides")?;
+                let dilation = uniform(
+                    &node.ints("dilations").unwrap_or_else(|| vec![1]),
+                    "dilations",
+                )?;
+                let w = node.input(1)?;
+                let mut y = if op == "Conv" {
+                    ops::dilated_conv2d(x, w, pad, stride, dilation)
+                } else {
+                    ops::dilated_conv2d_transpose(x, w, pad, stride, dilation)
+                };
+                if let Some(b) = node.optional_input(2) {
+                    y = y + ops::reshape(b, &[1, -1, 1, 1]);
+                }
+                (y, Some(4))
+            }
+            "MaxPool" => {
+                if node.int("ceil_mode").unwrap_or(0) != 0
+                    || node.int("storage_order").unwrap_or(0) != 0
+                    || node
+                        .ints("dilations")
+                        .unwrap_or_default()
+                        .iter()
+                        .any(|&d| d != 1)
+                {
+                    return unsupported(
+                        "MaxPool with ceil_mode, storage_order or dilations".into(),
+                    );
+                }
+                self.check_auto_pad(node)?;
+                let size = uniform(
+                    &node.ints("kernel_shape").unwrap_or_default(),
+                    "kernel_shape",
+                )?;
+                let pad = match node.ints("pads") {
+                    Some(pads) => uniform(&pads, "pads")?,
+                    None => 0,
+                };
+                let stride = uniform(&node.ints("strides").unwrap_or_else(|| vec![1]), "strides")?;
+                (ops::max_pool2d(x, size, pad, stride), Some(4))
+            }
+            "GlobalAveragePool" => {
+                let r = rank.unwrap_or(4) as i64;
+                let axes = (2..r).collect::<Vec<_>>();
+                (
+                    ops::reduce_mean(x, &ints_tensor(&axes), true),
+                    Some(r as usize),
+                )
+            }
+            "BatchNormalization" => {
+                let eps = node.float("epsilon").unwrap_or(1e-5);
+                // Reshapes the parameters of channels for the broadcast.
+                let mut shape = vec![1, -1];
+                shape.resize(rank.unwrap_or(4).max(2), 1);
+                let shape = ints_tensor(&shape);
+                let param = |i| node.input(i).map(|t| ops::reshape(t, &shape));
+                let (scale, bias, mean, var) = (param(1)?, param(2)?, param(3)?, param(4)?);
+                let std = ops::sqrt(var + T::from(eps).unwrap());
+                ((x - mean) * scale / std + bias, rank)
+            }
+            "Flatten" => {
+                let axis = node.axes(vec![node.int("axis").unwrap_or(1)], rank)?[0];
+                let shape = if axis == 0 {
+                    ints_tensor(&[1, -1])
+                } else {
+                    let leading = ops::slice(ops::shape(x), &[0], &[axis as isize]);
+                    let leading = ops::reduce_prod(&leading, &[0], true);
+                    ops::concat(&[&leading, &ints_tensor(&[-1])], 0)
+                };
+                (ops::reshape(x, &shape), Some(2))
+            }
+            "Reshape" => {
+                if node.int("allowzero").unwrap_or(0) != 0 {
+                    return unsupported("Reshape with allowzero".to_string());
+                }
+                match node.ints_arg("shape", 1)? {
+                    Some(shape) => {
+                        // Zeros copy the dimensions of the input.
+                        let num_zeros = shape.iter().take_while(|&&d| d == 0).count();
+                        if shape[num_zeros..].contains(&0) {
+                            return unsupported("Reshape with zeros after other dimensions".into());
+                        }
+                        let target = if num_zeros == 0 {
+                            ints_tensor(&shape)
+                        } else {
+                            let leading = ops::slice(ops::shape(x), &[0], &[num_zeros as isize]);
+                            ops::concat(&[&leading, &ints_tensor(&shape[num_zeros..])], 0)
+                        };
+                        (ops::reshape(x, &target), Some(shape.len()))
+                    }
+                    None => (ops::reshape(x, node.input(1)?), None),
+                }
+            }
+            "Concat" => {
+                let xs = node.inputs.iter().flatten().collect::<Vec<_>>();
+                let axis = node.int("axis").unwrap_or(0) as isize;
+                (ops::concat(&xs, axis), rank)
+            }
+            "Transpose" => {
+                let perm = match (node.ints("perm"), rank) {
+                    (Some(perm), _) => perm,
+                    (None, Some(r)) => (0..r as i64).rev().collect(),
+                    (None, None) => return unsupported("Transpose of unknown rank".to_string()),
+                };
+                (ops::transpose(x, &ints_tensor(&perm)), rank)
+            }
+            "ReduceSum" | "ReduceMean" | "ReduceMax" | "ReduceMin" | "ReduceProd" => {
+                let keep_dims = node.int("keepdims").unwrap_or(1) != 0;
+                let axes = match (node.ints_arg("axes", 1)?, rank) {
+                    (Some(ref axes), _) if !axes.is_empty() => node.axes(axes.clone(), rank)?,
+                    _ if node.int("noop_with_empty_axes").unwrap_or(0) != 0 => {
+                        return Ok(vec![(ops::identity(x), rank)]);
+                    }
+                    (_, Some(r)) => (0..r as i64).collect(),
+                    (_, None) if op == "ReduceSum" && !keep_dims => {
+                        return Ok(vec![(ops::reduce_sum_to_scalar(x), Some(0))]);
+                    }
+                    _ => return unsupported(format!("{} over all the axes of unknown rank", op)),
+                };
+                let axes_tensor = ints_tensor(&axes);
+                let y = match op {
+                    "ReduceSum" => ops::reduce_sum(x, &axes_tensor, keep_dims),
+                    "ReduceMean" => ops::reduce_mean(x, &axes_tensor, keep_dims),
+                    "ReduceMax" => ops::reduce_max(x, &axes_tensor, keep_dims),
+                    "ReduceMin" => ops::reduce_min(x, &axes_tensor, keep_dims),
+                    _ => ops::reduce_prod(x, &axes_tensor, keep_dims),
+                };
+                let rank = if keep_dims {
+                    rank
+                } else {
+                    rank.map(|r| r - axes.len())
+                };
+                (y, rank)
+            }
+            "ReduceLogSumExp" | "ArgMax" => {
+                let keep_dims = node.int("keepdims").unwrap_or(1) != 0;
+                let axis = if op == "ArgMax" {
+                    if node.int("select_last_index").unwrap_or(0) != 0 {
+                        return unsupported("ArgMax with select_last_index".to_string());
+                    }
+                    node.int("axis").unwrap_or(0)
+                } else {
+                    match node.ints_arg("axes", 1)? {
+                        Some(ref axes) if axes.len() == 1 => axes[0],
+                        _ => return unsupported("ReduceLogSumExp over multiple axes".into()),
+                    }
+                };
+                let y = if op == "ArgMax" {
+                    ops::argmax(x, axis as isize, keep_dims)
+                } else {
+                    ops::reduce_logsumexp(x, axis as isize, keep_dims)
+                };
+                let rank = if keep_dims { rank } else { rank.map(|r| r - 1) };
+                (y, rank)
+            }
+            "Squeeze" => match node.ints_arg("axes", 1)? {
+                Some(axes) => {
+                    let rank = rank.map(|r| r - axes.len());
+                    (ops::squeeze(x, &ints_tensor(&axes)), rank)
+                }
+                None => return unsupported("Squeeze without axes".to_string()),
+            },
+            "Unsqueeze" => match node.ints_arg("axes", 1)? {
+                Some(axes) => {
+                    // Axes are of the output.
+                    let output_rank = rank.map(|r| r + axes.len());
+                    let axes = node.axes(axes, output_rank)?;
+                    (ops::expand_dims(x, &ints_tensor(&axes)), output_rank)
+                }
+                None => return unsupported("Unsqueeze without axes".to_string()),
+            },
+            "Slice" => (self.slice(node)?, rank),
+            "Split" => {
+                let axis = node.int("axis").unwrap_or(0) as isize;
+                let sizes = match node.ints_arg("split", 1)? {
+                    Some(sizes) => sizes.iter().map(|&s| s as usize).collect::<Vec<_>>(),
+                    None => return unsupported("Split without sizes".to_string()),
+                };
+                return Ok(ops::split(x, &sizes, axis)
+                    .into_iter()
+                    .map(|y| (y, rank))
+                    .collect());
+            }
+            "Gather" => {
+                let axis = node.int("axis").unwrap_or(0) as isize;
+                let rank = match (rank, node.rank(1)) {
+                    (Some(r), Some(s)) => Some(r + s - 1),
+                    _ => None,
+                };
+                (ops::gather(x, node.input(1)?, axis), rank)
+            }
+            "Shape" => (ops::shape(x), Some(1)),
+            "Size" => (ops::size(x), Some(0)),
+            "Clip" => {
+                let min = node.float_arg("min", 1)?.unwrap_or_else(T::min_value);
+                let max = node.float_arg("max", 2)?.unwrap_or_else(T::max_value);
+                (ops::clip(x, min, max), rank)
+            }
+            "Cast" => match node.int("to").unwrap_or(0) {
+                DATA_TYPE_FLOAT | DATA_TYPE_DOUBLE | DATA_TYPE_FLOAT16 => (ops::identity(x), rank),
+                DATA_TYPE_BOOL => (ops::not_equal(x, ops::scalar(T::zero())), rank),
+                DATA_TYPE_UINT8 | DATA_TYPE_INT8 | DATA_TYPE_UINT16 | DATA_TYPE_INT16
+                | DATA_TYPE_INT32 | DATA_TYPE_INT64 | DATA_TYPE_UINT32 | DATA_TYPE_UINT64 => {
+                    // Truncates toward zero.
+                    (ops::sign(x) * ops::floor(ops::abs(x)), rank)
+                }
+                to => return unsupported(format!("Cast to data type {}", to)),
+            },
+            _ => return unsupported(op.to_string()),
+        };
+        Ok(vec![y])
+    }
+
+    fn check_auto_pad(&self, node: &Node<T>) -> Result<(), OnnxError> {
+        match node.string("auto_pad").as_deref() {
+            None | Some("NOTSET") | Some("VALID") => Ok(()),
+            Some(auto_pad) => unsupported(format!("{} with auto_pad {}", node.op_type, auto_pad)),
+        }
+    }
+
+    // Inputs of an elementwise op, where the ones of lower ranks get leading axes for
+    // the gradients to be reduced correctly.
+    fn broadcast_inputs(
+        &self,
+        node: &Node<T>,
+    ) -> Result<(Vec<Tensor<T>>, Option<usize>), OnnxError> {
+        let xs = (0..node.inputs.len())
+            .map(|i| node.input(i).cloned())
+            .collect::<Result<Vec<_>, _>>()?;
+        let ranks = (0..xs.len())
+            .map(|i| node.rank(i))
+            .collect::<Option<Vec<_>>>();
+        let ranks = match ranks {
+            Some(ranks) => ranks,
+            None => return Ok((xs, None)),
+        };
+        let rank = ranks.iter().cloned().max().unwrap_or(0);
+        let xs = xs
+            .into_iter()
+            .zip(ranks)
+            .map(|(x, r)| {
+                // Scalars are broadcast as they are.
+                if r == 0 || r == rank {
+                    x
+                } else {
+                    let axes = (0..(rank - r) as i64).collect::<Vec<_>>();
+                    ops::expand_dims(x, &ints_tensor(&axes))
+                }
+            })
+            .collect();
+        Ok((xs, Some(rank)))
+    }
+
+    fn slice(&self, node: &Node<T>) -> Result<Tensor<T>, OnnxError> {
+        // Starts, ends and axes are attributes before opset 10.
+        let starts = node.ints_arg("starts", 1)?;
+        let ends = node.ints_arg("ends", 2)?;
+        let (starts, ends) = match (starts, ends) {
+            (Some(starts), Some(ends)) => (starts, ends),
+            _ => return unsupported("Slice without starts or ends".to_string()),
+        };
+        let rank = node.rank(0);
+        let axes = match node.ints_arg("axes", 3)? {
+            Some(axes) => node.axes(axes, rank)?,
+            None => (0..starts.len() as i64).collect(),
+        };
+        let steps = node
+            .ints_arg("steps", 4)?
+            .unwrap_or_else(|| vec![1; starts.len()]);
+        let n = starts.len();
+        if ends.len() != n || axes.len() != n || steps.len() != n {
+            let msg = "Slice with starts, ends, axes and steps of different lengths";
+            return Err(OnnxError::InvalidGraph(msg.to_string()));
+        }
+        // Bounds the axes of unknown rank too, as `indices` below has an item for each axis.
+        let max_rank = rank.unwrap_or(MAX_SLICE_RANK) as i64;
+        if let Some(&axis) = axes.iter().find(|&&a| a < 0 || a >= max_rank) {
+            let msg = format!("Slice along axis {} out of range", axis);
+            return Err(OnnxError::InvalidGraph(msg));
+        }
+        if (1..n).any(|i| axes[..i].contains(&axes[i])) {
+            return Err(OnnxError::InvalidGraph(
+                "Slice with repeated axes".to_string(),
+            ));
+        }
+        if steps.iter().any(|&s| s <= 0) {
+            return unsupported("Slice with non-positive steps".to_string());
+        }
+
+        // `[start, end, step, kind]` for each axis, as in the attributes of `Slice`,
+        // where kind 1 is a slice to the end.
+        let num_axes = axes.iter().max().map_or(0, |&a| a as usize + 1);
+        let mut indices = vec![[0, 0, 1, 1]; num_axes];
+        for (i, &axis) in axes.iter().enumerate() {
+            let end = ends[i];
+            // Large ends, e.g. `i64::MAX`, mean the end of the axis.
+            let kind = if end >= i64::from(i32::MAX) { 1 } else { 0 };
+            indices[axis as usize] = [starts[i], end, steps[i], kind];
+        }
+        let indices = indices.concat();
+        let op = self
+            .registry
+            .construct("Slice", &Attributes::new().with("indices", indices))?;
+        Ok(Tensor::builder().set_input(node.input(0)?).build_boxed(op))
+    }
+}
+
+#[test]
+fn test_import() {
+    use super::proto::Message;
+    use crate::runtime::Feed;
+
+    // Round trip of an exported CNN
+    let ref x = ops::placeholder::<f64>(&[-1, 1, 4, 4]);
+    let ref w = ops::variable(crate::ndarray_ext::standard_normal(&[2, 1, 3, 3]));
+    let ref h = ops::relu(&ops::conv2d(x, w, 1, 1));
+    let ref h = ops::reshape(&ops::max_pool2d(h, 2, 0, 2), &[-1, 8]);
+    let ref w2 = ops::variable_named("dense/w", crate::ndarray_ext::standard_normal(&[8, 3]));
+    let ref logits = ops::matmul(h, w2) + ops::scalar(0.5);
+    let ref p = ops::softmax(logits, 1);
+    let ref y = ops::concat(&[p, &ops::sigmoid(logits)], 1);
+    let bytes = super::export_bytes(&[x], &[y, p]).unwrap();
+
+    let g = import_bytes::<f64>(&bytes).unwrap();
+    assert_eq!(g.inputs, vec!["input_0"]);
+    assert_eq!(g.outputs, vec!["output_0", "output_1"]);
+    assert_eq!(g.variables.len(), 3);
+    assert!(g.variables.contains(&"dense/w".to_string()));
+    assert_eq!(g.tensors["dense/w"].name(), Some("dense/w"));
+    let x2 = &g.tensors["input_0"];
+    assert_eq!(x2.name(), Some("input_0"));
+    assert_eq!(x2.known_shape.as_ref().unwrap().get(), &[-1, 1, 4, 4]);
+    let x_val = crate::ndarray_ext::standard_normal(&[2, 1, 4, 4]);
+    let expected = y.eval(&[Feed(x, x_val.view())]).unwrap();
+    let actual = g.tensors["output_0"]
+        .eval(&[Feed(x2, x_val.view())])
+        .unwrap();
+    assert!(expected.all_close(&actual, 1e-12));
+
+    // Fine-tuning the imported variables
+    let vars = g
+        .variables
+        .iter()
+        .map(|v| &g.tensors[v])
+        .collect::<Vec<_>>();
+    let ref loss = ops::reduce_sum_to_scalar(&g.tensors["output_1"]);
+    let grads = ops::grad(&[loss], &vars);
+    let grads = crate::runtime::eval(&grads, &[Feed(x2, x_val.view())]);
+    for (g, v) in grads.iter().zip(&vars) {
+        let v = v.get_persistent_array().unwrap();
+        assert_eq!(g.as_ref().unwrap().shape(), v.shape());
+    }
+
+    // Nodes the exporter doesn't write
+    let float_tensor = |name: &str, dims: &[i64], values: &[f32]| {
+        let mut t = Message::new();
+        for &d in dims {
+            t.int(TENSOR_DIMS, d);
+        }
+        t.int(TENSOR_DATA_TYPE, DATA_TYPE_FLOAT)
+            .string(TENSOR_NAME, name);
+        for &v in values {
+            t.float(TENSOR_FLOAT_DATA, v);
+        }
+        t
+    };
+    let int_tensor = |name: &str, values: &[i64]| {
+        let mut t = Message::new();
+        t.int(TENSOR_DIMS, values.len() as i64)
+            .int(TENSOR_DATA_TYPE, DATA_TYPE_INT64)
+            .string(TENSOR_NAME, name);
+        for &v in values {
+            t.int(TENSOR_INT64_DATA, v);
+        }
+        t
+    };
+    let node = |op_type: &str, inputs: &[&str], output: &str, attrs: &[(&str, i64)]| {
+        let mut n = Message::new();
+        for input in inputs {
+            n.string(NODE_INPUT, input);
+        }
+        n.string(NODE_OUTPUT, output).string(NODE_OP_TYPE, op_type);
+        for &(name, v) in attrs {
+            let mut a = Message::new();
+            a.string(ATTR_NAME, name)
+                .int(ATTR_TYPE, ATTR_TYPE_INT)
+                .int(ATTR_I, v);
+            n.message(NODE_ATTRIBUTE, &a);
+        }
+        n
+    };
+    let value_info = |name: &str, dims: &[i64]| {
+        let mut shape = Message::new();
+        for &d in dims {
+            let mut dim = Message::new();
+            if d >= 0 {
+                dim.int(DIM_VALUE, d);
+            }
+            shape.message(SHAPE_DIM, &dim);
+        }
+        let mut tensor_type = Message::new();
+        tensor_type
+            .int(TENSOR_TYPE_ELEM_TYPE, DATA_TYPE_FLOAT)
+            .message(TENSOR_TYPE_SHAPE, &shape);
+        let mut ty = Message::new();
+        ty.message(TYPE_TENSOR_TYPE, &tensor_type);
+        let mut info = Message::new();
+        info.string(VALUE_INFO_NAME, name)
+            .message(VALUE_INFO_TYPE, &ty);
+        info
+    };
+    let w_val = (0..24).map(|i| i as f32 / 24.).collect::<Vec<_>>();
+    let mut graph = Message::new();
+    graph
+        .message(GRAPH_INPUT, &value_info("x", &[-1, 2, 2, 2]))
+        .message(GRAPH_INITIALIZER, &float_tensor("scale", &[2], &[1., 2.]))
+        .message(GRAPH_INITIALIZER, &float_tensor("bias", &[2], &[0., 1.]))
+        .message(GRAPH_INITIALIZER, &float_tensor("mean", &[2], &[0.5, -0.5]))
+        .message(GRAPH_INITIALIZER, &float_tensor("var", &[2], &[1., 4.]))
+        .message(GRAPH_INITIALIZER, &float_tensor("w", &[3, 8], &w_val))
+        .message(GRAPH_INITIALIZER, &float_tensor("c", &[3], &[1., 2., 3.]))
+        .message(GRAPH_INITIALIZER, &int_tensor("shape", &[0, -1]))
+        .message(GRAPH_INITIALIZER, &int_tensor("starts", &[1]))
+        .message(GRAPH_INITIALIZER, &int_tensor("ends", &[i64::MAX]))
+        .message(GRAPH_INITIALIZER, &int_tensor("axes", &[-1]))
+        .message(
+            GRAPH_NODE,
+            &node(
+                "BatchNormalization",
+                &["x", "scale", "bias", "mean", "var"],
+                "bn",
+                &[],
+            ),
+        )
+        .message(
+            GRAPH_NODE,
+            &node("Flatten", &["bn"], "flat", &[("axis", 1)]),
+        )
+        .message(
+            GRAPH_NODE,
+            &node("Gemm", &["flat", "w", "c"], "gemm", &[("transB", 1)]),
+        )
+        .message(GRAPH_NODE, &node("Relu", &["gemm"], "y", &[]))
+        .message(GRAPH_NODE, &node("Reshape", &["x", "shape"], "r", &[]))
+        .message(
+            GRAPH_NODE,
+            &node("Slice", &["r", "starts", "ends", "axes"], "z", &[]),
+        )
+        .message(GRAPH_OUTPUT, &value_info("y", &[-1, 3]))
+        .message(GRAPH_OUTPUT, &value_info("z", &[-1, 7]));
+    let mut model = Message::new();
+    model.message(MODEL_GRAPH, &graph);
+    let g = import_bytes::<f32>(&model.into_bytes()).unwrap();
+    assert_eq!(g.variables, vec!["scale", "bias", "mean", "var", "w", "c"]);
+
+    let x_val = crate::ndarray_ext::standard_normal::<f32>(&[3, 2, 2, 2]);
+    let channel = |v: [f32; 2]| ndarray::arr1(&v).into_shape((1, 2, 1, 1)).unwrap();
+    let bn = (&x_val - &channel([0.5, -0.5])) * channel([1., 2.])
+        / channel([1., 4.]).mapv(|v: f32| (v + 1e-5).sqrt())
+        + channel([0., 1.]);
+    let flat = bn.into_shape((3, 8)).unwrap();
+    let w_arr = ndarray::Array::from_shape_vec((3, 8), w_val).unwrap();
+    let expected_y = (flat.dot(&w_arr.t()) + ndarray::arr1(&[1., 2., 3.])).mapv(|v| v.max(0.));
+    let x = &g.tensors["x"];
+    let ys = crate::runtime::eval(
+        &[&g.tensors["y"], &g.tensors["z"]],
+        &[Feed(x, x_val.view())],
+    );
+    let y_val = ys[0].as_ref().unwrap();
+    assert!(y_val.all_close(&expected_y.into_dyn(), 1e-4));
+    let z_val = ys[1].as_ref().unwrap();
+    let expected_z = x_val.into_shape((3, 8)).unwrap();
+    assert_eq!(z_val, &expected_z.slice(s![.., 1..]).into_dyn());
+
+    // Malformed Slice nodes
+    let slice_graph = |axes: &[i64], steps: &[i64]| {
+        let mut graph = Message::new();
+        graph
+            .message(GRAPH_INPUT, &value_info("x", &[-1, 3]))
+            .message(GRAPH_INITIALIZER, &int_tensor("starts", &[0]))
+            .message(GRAPH_INITIALIZER, &int_tensor("ends", &[2]))
+            .message(GRAPH_INITIALIZER, &int_tensor("axes", axes))
+            .message(GRAPH_INITIALIZER, &int_tensor("steps", steps))
+            .message(
+                GRAPH_NODE,
+                &node("Slice", &["x", "starts", "ends", "axes", "steps"], "y", &[]),
+            )
+            .message(GRAPH_OUTPUT, &value_info("y", &[-1, 2]));
+        let mut model = Message::new();
+        model.message(MODEL_GRAPH, &graph);
+        import_bytes::<f32>(&model.into_bytes())
+    };
+    assert!(slice_graph(&[-1], &[1]).is_ok());
+    for &(axes, steps) in &[
+        (&[1i64 << 40][..], &[1i64][..]),
+        (&[2], &[1]),
+        (&[-3], &[1]),
+        (&[0, 1], &[1, 1]),
+        (&[1], &[1, 1]),
+    ] {
+        match slice_graph(axes, steps) {
+            Err(OnnxError::InvalidGraph(_)) => {}
+            _ => panic!("expected an invalid Slice: {:?} {:?}", axes, steps),
+        }
+    }
+
+    // All the unsupported ops are reported.
+    let mut graph = Message::new();
+    graph
+        .message(GRAPH_INPUT, &value_info("x", &[-1, 2, 4, 4]))
+        .message(
+            GRAPH_INITIALIZER,
+            &float_tensor("w", &[2, 1, 1, 1], &[1., 1.]),
+        )
+        .message(GRAPH_NODE, &node("Conv", &["x", "w"], "h", &[("group", 2)]))
+        .message(GRAPH_NODE, &node("LRN", &["h"], "h2", &[("size", 3)]))
+        .message(GRAPH_NODE, &node("Hardmax", &["x"], "h3", &[]))
+        .message(GRAPH_OUTPUT, &value_info("h2", &[]));
+    let mut model = Message::new();
+    model.message(MODEL_GRAPH, &graph);
+    match import_bytes::<f32>(&model.into_bytes()) {
+        Err(OnnxError::Unsupported(ops)) => {
+            assert_eq!(ops, vec!["Conv with groups", "Hardmax"])
+        }
+        _ => panic!("expected unsupported ops"),
+    }
+}
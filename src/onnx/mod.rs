@@ -0,0 +1,145 @@
+//! Conversion of graphs from and to the ONNX format
+//!
+//! `export` writes the graph from placeholders to outputs as an ONNX model
+//! (IR version 7, opset 13). Variables, constants and the other subgraphs that
+//! depend on no placeholder become initializers of the model.
+//!
+//! `import` builds the graph of an ONNX model with variables for its weights, so that
+//! pretrained models can be fine-tuned.
+//!
+//! ```
+//! extern crate autograd as ag;
+//!
+//! let ref x = ag::placeholder::<f32>(&[-1, 3]);
+//! let ref w = ag::variable(ag::ndarray_ext::standard_normal(&[3, 2]));
+//! let ref y = ag::softmax(&ag::relu(&ag::matmul(x, w)), 1);
+//!
+//! let path = std::env::temp_dir().join("autograd_doctest_model.onnx");
+//! ag::onnx::export(&path, &[x], &[y]).unwrap();
+//!
+//! // Ops without ONNX counterparts are reported by name.
+//! let ref z = ag::tile(x, 0, 2);
+//! match ag::onnx::export(&path, &[x], &[z]) {
+//!     Err(ag::onnx::OnnxError::Unsupported(ops)) => assert_eq!(ops, vec!["Tile"]),
+//!     _ => unreachable!(),
+//! }
+//!
+//! // Imports the model and attaches a loss to its output.
+//! let g = ag::onnx::import::<f32, _>(&path).unwrap();
+//! let ref y = g.tensors[&g.outputs[0]];
+//! let ref t = ag::placeholder(&[-1, 2]);
+//! let ref loss = ag::reduce_mean(ag::square(y - t), &[0, 1], false);
+//! let vars = g.variables.iter().map(|v| &g.tensors[v]).collect::<Vec<_>>();
+//! let grads = ag::grad(&[loss], &vars);
+//! assert_eq!(grads.len(), 1);
+//! ```
+use std::error;
+use std::fmt;
+use std::io;
+
+mod export;
+mod import;
+mod proto;
+
+pub use self::export::{export, export_bytes};
+pub use self::import::{import, import_bytes, ImportedGraph};
+
+/// Error in converting a graph to or from ONNX.
+#[derive(Debug)]
+pub enum OnnxError {
+    /// Failed to read or write the file, or it's broken.
+    Io(io::Error),
+    /// Ops that have no mapping between ONNX and this crate, with the reasons if their
+    /// attributes matter.
+    Unsupported(Vec<String>),
+    /// The graph can't be converted, e.g. it depends on a placeholder not in the inputs
+    /// or on an undefined value.
+    InvalidGraph(String),
+}
+
+impl fmt::Display for OnnxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OnnxError::Io(e) => write!(f, "{}", e),
+            OnnxError::Unsupported(ops) => write!(f, "unsupported ops: {}", ops.join(", ")),
+            OnnxError::InvalidGraph(msg) => write!(f, "invalid graph: {}", msg),
+        }
+    }
+}
+
+impl error::Error for OnnxError {}
+
+impl From<io::Error> for OnnxError {
+    fn from(e: io::Error) -> OnnxError {
+        OnnxError::Io(e)
+    }
+}
+
+const IR_VERSION: i64 = 7;
+const OPSET_VERSION: i64 = 13;
+
+// Field numbers of the messages in onnx.proto
+const MODEL_IR_VERSION: u32 = 1;
+const MODEL_PRODUCER_NAME: u32 = 2;
+const MODEL_GRAPH: u32 = 7;
+const MODEL_OPSET_IMPORT: u32 = 8;
+const OPSET_DOMAIN: u32 = 1;
+const OPSET_VERSION_FIELD: u32 = 2;
+const GRAPH_NODE: u32 = 1;
+const GRAPH_NAME: u32 = 2;
+const GRAPH_INITIALIZER: u32 = 5;
+const GRAPH_INPUT: u32 = 11;
+const GRAPH_OUTPUT: u32 = 12;
+const GRAPH_VALUE_INFO: u32 = 13;
+const NODE_INPUT: u32 = 1;
+const NODE_OUTPUT: u32 = 2;
+const NODE_OP_TYPE: u32 = 4;
+const NODE_ATTRIBUTE: u32 = 5;
+const NODE_DOMAIN: u32 = 7;
+const ATTR_NAME: u32 = 1;
+const ATTR_F: u32 = 2;
+const ATTR_I: u32 = 3;
+const ATTR_S: u32 = 4;
+const ATTR_T: u32 = 5;
+const ATTR_INTS: u32 = 8;
+const ATTR_TYPE: u32 = 20;
+const VALUE_INFO_NAME: u32 = 1;
+const VALUE_INFO_TYPE: u32 = 2;
+const TYPE_TENSOR_TYPE: u32 = 1;
+const TENSOR_TYPE_ELEM_TYPE: u32 = 1;
+const TENSOR_TYPE_SHAPE: u32 = 2;
+const SHAPE_DIM: u32 = 1;
+const DIM_VALUE: u32 = 1;
+const TENSOR_DIMS: u32 = 1;
+const TENSOR_DATA_TYPE: u32 = 2;
+const TENSOR_FLOAT_DATA: u32 = 4;
+const TENSOR_INT32_DATA: u32 = 5;
+const TENSOR_INT64_DATA: u32 = 7;
+const TENSOR_NAME: u32 = 8;
+const TENSOR_RAW_DATA: u32 = 9;
+const TENSOR_DOUBLE_DATA: u32 = 10;
+const TENSOR_UINT64_DATA: u32 = 11;
+const TENSOR_DATA_LOCATION: u32 = 14;
+
+// TensorProto.DataLocation
+const DATA_LOCATION_EXTERNAL: i64 = 1;
+
+// AttributeProto.AttributeType
+const ATTR_TYPE_FLOAT: i64 = 1;
+const ATTR_TYPE_INT: i64 = 2;
+const ATTR_TYPE_TENSOR: i64 = 4;
+const ATTR_TYPE_INTS: i64 = 7;
+
+// TensorProto.DataType
+const DATA_TYPE_FLOAT: i64 = 1;
+const DATA_TYPE_UINT8: i64 = 2;
+const DATA_TYPE_INT8: i64 = 3;
+const DATA_TYPE_UINT16: i64 = 4;
+const DATA_TYPE_INT16: i64 = 5;
+const DATA_TYPE_INT32: i64 = 6;
+const DATA_TYPE_INT64: i64 = 7;
+const DATA_TYPE_BOOL: i64 = 9;
+const DATA_TYPE_FLOAT16: i64 = 10;
+const DATA_TYPE_DOUBLE: i64 = 11;
+const DATA_TYPE_UINT32: i64 = 12;
+const DATA_TYPE_UINT64: i64 = 13;
@@ -0,0 +1,243 @@
+//! Minimal protocol buffers encoding for the ONNX messages
+use crate::checkpoint::invalid_data;
+use std::io;
+
+const VARINT: u32 = 0;
+const FIXED64: u32 = 1;
+const LENGTH_DELIMITED: u32 = 2;
+const FIXED32: u32 = 5;
+
+/// An encoded message, whose fields are appended in place.
+#[derive(Default)]
+pub(crate) struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    pub(crate) fn new() -> Message {
+        Message::default()
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn key(&mut self, field: u32, wire_type: u32) {
+        write_varint(&mut self.buf, u64::from(field << 3 | wire_type));
+    }
+
+    pub(crate) fn int(&mut self, field: u32, v: i64) -> &mut Message {
+        self.key(field, VARINT);
+        write_varint(&mut self.buf, v as u64);
+        self
+    }
+
+    pub(crate) fn float(&mut self, field: u32, v: f32) -> &mut Message {
+        self.key(field, FIXED32);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn bytes(&mut self, field: u32, v: &[u8]) -> &mut Message {
+        self.key(field, LENGTH_DELIMITED);
+        write_varint(&mut self.buf, v.len() as u64);
+        self.buf.extend_from_slice(v);
+        self
+    }
+
+    pub(crate) fn string(&mut self, field: u32, v: &str) -> &mut Message {
+        self.bytes(field, v.as_bytes())
+    }
+
+    pub(crate) fn message(&mut self, field: u32, v: &Message) -> &mut Message {
+        self.bytes(field, &v.buf)
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> io::Result<u64> {
+    let mut ret = 0;
+    for shift in (0..64).step_by(7) {
+        let b = *buf
+            .get(*pos)
+            .ok_or_else(|| invalid_data("Truncated varint".to_string()))?;
+        *pos += 1;
+        ret |= u64::from(b & 0x7f) << shift;
+        if b < 0x80 {
+            return Ok(ret);
+        }
+    }
+    Err(invalid_data("Too long varint".to_string()))
+}
+
+/// Value of a decoded field
+#[derive(Clone, Copy)]
+pub(crate) enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Fields of a decoded message in the encoded order
+pub(crate) struct Fields<'a> {
+    fields: Vec<(u32, Value<'a>)>,
+}
+
+impl<'a> Fields<'a> {
+    pub(crate) fn decode(buf: &'a [u8]) -> io::Result<Fields<'a>> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let key = read_varint(buf, &mut pos)?;
+            let value = match key as u32 & 7 {
+                VARINT => Value::Varint(read_varint(buf, &mut pos)?),
+                FIXED64 => Value::Fixed64(u64::from_le_bytes(take(buf, &mut pos, 8)?)),
+                LENGTH_DELIMITED => {
+                    let len = read_varint(buf, &mut pos)? as usize;
+                    if buf.len() - pos < len {
+                        return Err(invalid_data("Truncated field".to_string()));
+                    }
+                    pos += len;
+                    Value::Bytes(&buf[pos - len..pos])
+                }
+                FIXED32 => Value::Fixed32(u32::from_le_bytes(take(buf, &mut pos, 4)?)),
+                _ => return Err(invalid_data("Unsupported wire type".to_string())),
+            };
+            fields.push(((key >> 3) as u32, value));
+        }
+        Ok(Fields { fields })
+    }
+
+    fn values(&self, field: u32) -> impl Iterator<Item = Value<'a>> + '_ {
+        self.fields
+            .iter()
+            .filter(move |&&(f, _)| f == field)
+            .map(|&(_, v)| v)
+    }
+
+    /// The last varint of `field`, or 0
+    pub(crate) fn int(&self, field: u32) -> i64 {
+        self.ints(field).last().cloned().unwrap_or(0)
+    }
+
+    /// Varints of `field`, which may be packed
+    pub(crate) fn ints(&self, field: u32) -> Vec<i64> {
+        let mut ret = Vec::new();
+        for v in self.values(field) {
+            match v {
+                Value::Varint(i) => ret.push(i as i64),
+                Value::Bytes(b) => {
+                    let mut pos = 0;
+                    while let Ok(i) = read_varint(b, &mut pos) {
+                        ret.push(i as i64);
+                    }
+                }
+                _ => {}
+            }
+        }
+        ret
+    }
+
+    /// Floats of `field`, which may be packed
+    pub(crate) fn floats(&self, field: u32) -> Vec<f32> {
+        let mut ret = Vec::new();
+        for v in self.values(field) {
+            match v {
+                Value::Fixed32(i) => ret.push(f32::from_bits(i)),
+                Value::Bytes(b) => ret.extend(
+                    b.chunks_exact(4)
+                        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])),
+                ),
+                _ => {}
+            }
+        }
+        ret
+    }
+
+    /// Doubles of `field`, which may be packed
+    pub(crate) fn doubles(&self, field: u32) -> Vec<f64> {
+        let mut ret = Vec::new();
+        for v in self.values(field) {
+            match v {
+                Value::Fixed64(i) => ret.push(f64::from_bits(i)),
+                Value::Bytes(b) => ret.extend(b.chunks_exact(8).map(|c| {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(c);
+                    f64::from_le_bytes(bytes)
+                })),
+                _ => {}
+            }
+        }
+        ret
+    }
+
+    /// Length-delimited values of `field`
+    pub(crate) fn bytes(&self, field: u32) -> Vec<&'a [u8]> {
+        self.values(field)
+            .filter_map(|v| match v {
+                Value::Bytes(b) => Some(b),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The last string of `field`, or an empty string
+    pub(crate) fn string(&self, field: u32) -> String {
+        self.strings(field).pop().unwrap_or_default()
+    }
+
+    pub(crate) fn strings(&self, field: u32) -> Vec<String> {
+        self.bytes(field)
+            .into_iter()
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+            .collect()
+    }
+
+    /// The last message of `field`, or an empty one
+    pub(crate) fn message(&self, field: u32) -> io::Result<Fields<'a>> {
+        Fields::decode(self.bytes(field).pop().unwrap_or(&[]))
+    }
+
+    pub(crate) fn messages(&self, field: u32) -> io::Result<Vec<Fields<'a>>> {
+        self.bytes(field).into_iter().map(Fields::decode).collect()
+    }
+}
+
+fn take<A: Default + AsMut<[u8]>>(buf: &[u8], pos: &mut usize, n: usize) -> io::Result<A> {
+    let mut ret = A::default();
+    let src = buf
+        .get(*pos..*pos + n)
+        .ok_or_else(|| invalid_data("Truncated field".to_string()))?;
+    ret.as_mut().copy_from_slice(src);
+    *pos += n;
+    Ok(ret)
+}
+
+#[test]
+fn test_message_round_trip() {
+    let mut inner = Message::new();
+    inner.string(1, "x").float(2, 1.5);
+    let mut m = Message::new();
+    m.int(1, 300)
+        .int(1, -1)
+        .message(3, &inner)
+        .message(3, &inner);
+    let bytes = m.into_bytes();
+    assert_eq!(&bytes[..3], &[0x08, 0xac, 0x02]);
+
+    let fields = Fields::decode(&bytes).unwrap();
+    assert_eq!(fields.ints(1), vec![300, -1]);
+    let inner = fields.messages(3).unwrap();
+    assert_eq!(inner.len(), 2);
+    assert_eq!(inner[0].string(1), "x");
+    assert_eq!(inner[1].floats(2), vec![1.5]);
+    assert!(Fields::decode(&bytes[..bytes.len() - 1]).is_err());
+}
@@ -1,20 +1,33 @@
 //! Defining things related to `ag::op::Op`.
 //!
+use crate::checkpoint::invalid_data;
 use crate::tensor::Tensor;
 use crate::Float;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io;
 
 // Op can have multiple output arrays.
 pub type ComputeResults<'v, T> = Vec<Result<crate::ArrRepr<'v, T>, ComputeException>>;
 
-#[derive(Clone, Copy, Debug)]
-/// This is an `exception`, not an error.
+#[derive(Clone, Debug)]
+/// Non-array outcome of `Op::compute`.
+///
+/// `NoOutput` is an `exception`, not an error.
+/// `Error` aborts the evaluation; `ag::try_eval` reports it as `ag::EvalError`.
 pub enum ComputeException {
     /// Computation finished correctly with no output
     NoOutput,
+    /// Computation failed, e.g. because of bad input arrays.
+    /// The string describes the reason.
+    Error(String),
 }
 
 /// Operation trait. `Tensor` wraps trait-object of this.
 ///
+/// Ops must be `Send + Sync` since independent nodes can be computed concurrently
+/// (see [CompiledGraph::set_parallel](../struct.CompiledGraph.html#method.set_parallel)).
+///
 /// # Implementing differentiable operations
 ///
 /// Many of well-known ops are pre-defined in `ag::ops`, but you can also
@@ -36,8 +49,8 @@ pub enum ComputeException {
 ///         "Sigmoid"
 ///     }
 ///
-///     // In this method, any errors caused by bad user-inputs should results in "panic".
-///     // (`ag::op::ComputeException` represents an exception rather than an error.)
+///     // Errors caused by bad user-inputs should be returned as
+///     // `Err(ag::op::ComputeException::Error(reason))`.
 ///     fn compute<'v>(
 ///         &self,
 ///         ctx: ag::runtime::OpComputeContext<'v, T>,
@@ -68,10 +81,21 @@ pub enum ComputeException {
 ///         .build(Sigmoid)
 /// }
 /// ```
-pub trait Op<T: Float> {
+pub trait Op<T: Float>: Send + Sync {
     /// Name of this op
     fn name(&self) -> &str;
 
+    /// Returns `true` if `compute` does anything other than returning its outputs,
+    /// e.g. updating variables, drawing random numbers or printing.
+    ///
+    /// The parallel runtime never runs such ops concurrently with other ops,
+    /// and keeps them in the sequential order, where independent evaluation targets
+    /// are computed in the given order. Only these ops can update their input variables
+    /// with `OpComputeContext::input_variable_mut`. Defaults to `false`.
+    fn has_side_effects(&self) -> bool {
+        false
+    }
+
     /// Runs this op.
     fn compute<'v>(&self, ctx: crate::runtime::OpComputeContext<'v, T>) -> ComputeResults<'v, T>;
 
@@ -86,4 +110,151 @@ pub trait Op<T: Float> {
     /// NOTE:
     /// The number of return values must match `xs.len()`.
     fn grad(&self, gy: &Tensor<T>, xs: &[&Tensor<T>], y: &Tensor<T>) -> Vec<Option<Tensor<T>>>;
+
+    /// Returns the symbolic Jacobian-vector product of this op, used in forward-mode
+    /// differentiation (`ag::jvp`).
+    ///
+    /// # Arguments
+    ///
+    /// * `xs` - Symbolic representation of `compute::xs`, or the backprop inputs if the
+    ///   tensor has them (see `TensorBuilder::set_backprop_inputs`)
+    /// * `y` - Symbolic representation of `compute`'s return value
+    /// * `txs` - Tangents of `xs`. `None` means zero, but at least one of them is `Some`.
+    ///
+    /// The return value must have the same shape as `y`.
+    /// Returning `None` (default) means "not implemented";
+    /// then the product is computed from `grad` by differentiating it twice.
+    fn jvp(
+        &self,
+        _xs: &[&Tensor<T>],
+        _y: &Tensor<T>,
+        _txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        None
+    }
+
+    /// Returns the parameters of this op, from which it's rebuilt when a saved graph
+    /// is loaded (see `ag::graph_def`).
+    ///
+    /// Ops with parameters must override this to be saved. Defaults to empty.
+    fn attributes(&self) -> Attributes {
+        Attributes::new()
+    }
+}
+
+/// Value of an op attribute
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttrValue {
+    Int(i64),
+    Float(f64),
+    Ints(Vec<i64>),
+    Floats(Vec<f64>),
+}
+
+/// Named parameters of an op, which are saved in graph definitions.
+///
+/// Flags are saved as `Int`s of 0 or 1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Attributes {
+    pub values: BTreeMap<String, AttrValue>,
+}
+
+impl Attributes {
+    /// Creates an empty set.
+    pub fn new() -> Attributes {
+        Attributes {
+            values: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the attribute `name`.
+    pub fn with<V: Into<AttrValue>>(mut self, name: &str, value: V) -> Attributes {
+        self.values.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Returns the attribute `name`, or `InvalidData` error if it's missing or of another type.
+    pub fn get<V: FromAttrValue>(&self, name: &str) -> io::Result<V> {
+        self.values
+            .get(name)
+            .and_then(V::from_attr_value)
+            .ok_or_else(|| invalid_data(format!("Missing or invalid attribute: {}", name)))
+    }
+}
+
+/// Conversion from `AttrValue`; see `Attributes::get`.
+pub trait FromAttrValue: Sized {
+    fn from_attr_value(value: &AttrValue) -> Option<Self>;
+}
+
+// `$convert` converts the stored value to `$ty`, returning `None` if it's out of range.
+macro_rules! impl_attr_conversions {
+    ($variant:ident, $repr:ty, $convert:expr, $($ty:ty),*) => {
+        $(
+            impl From<$ty> for AttrValue {
+                fn from(a: $ty) -> AttrValue {
+                    AttrValue::$variant(a as $repr)
+                }
+            }
+
+            impl FromAttrValue for $ty {
+                // `$convert` is the identity for `$repr` itself.
+                #[allow(clippy::useless_conversion)]
+                fn from_attr_value(value: &AttrValue) -> Option<$ty> {
+                    match *value {
+                        AttrValue::$variant(a) => ($convert)(a),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_attr_conversions!(Int, i64, |a| TryFrom::try_from(a).ok(), i64, isize, usize);
+impl_attr_conversions!(Float, f64, |a| Some(a as _), f64, f32);
+
+impl From<bool> for AttrValue {
+    fn from(a: bool) -> AttrValue {
+        AttrValue::Int(a as i64)
+    }
+}
+
+impl FromAttrValue for bool {
+    fn from_attr_value(value: &AttrValue) -> Option<bool> {
+        match *value {
+            AttrValue::Int(a) => Some(a != 0),
+            _ => None,
+        }
+    }
+}
+
+impl From<Vec<i64>> for AttrValue {
+    fn from(a: Vec<i64>) -> AttrValue {
+        AttrValue::Ints(a)
+    }
+}
+
+impl FromAttrValue for Vec<i64> {
+    fn from_attr_value(value: &AttrValue) -> Option<Vec<i64>> {
+        match value {
+            AttrValue::Ints(a) => Some(a.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Vec<f64>> for AttrValue {
+    fn from(a: Vec<f64>) -> AttrValue {
+        AttrValue::Floats(a)
+    }
+}
+
+impl FromAttrValue for Vec<f64> {
+    fn from_attr_value(value: &AttrValue) -> Option<Vec<f64>> {
+        match value {
+            AttrValue::Floats(a) => Some(a.clone()),
+            _ => None,
+        }
+    }
 }
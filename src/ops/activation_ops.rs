@@ -61,6 +61,10 @@ impl<T: Float> op::Op<T> for Softmax {
         "Softmax"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("axis", self.axis)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -121,6 +125,15 @@ impl<T: Float> op::Op<T> for Sigmoid {
     fn grad(&self, gy: &Tensor<T>, _: &[&Tensor<T>], y: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
         vec![Some(gy * (y - ops::square(y)))]
     }
+
+    fn jvp(
+        &self,
+        _: &[&Tensor<T>],
+        y: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        txs[0].map(|t| t * (y - ops::square(y)))
+    }
 }
 
 impl<T: Float> op::Op<T> for ReLU {
@@ -166,6 +179,10 @@ impl<T: Float> op::Op<T> for ELU<T> {
         "ELU"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("alpha", self.alpha.to_f64().unwrap())
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -195,6 +212,10 @@ impl<T: Float> op::Op<T> for ELUGrad<T> {
         "ELUGrad"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("alpha", self.alpha.to_f64().unwrap())
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -225,7 +225,11 @@ impl<T: Float> op::Op<T> for Reshape {
                 if let Ok(a) = copy.into_shape(ndarray::IxDyn(target.as_slice())) {
                     Ok(crate::ArrRepr::Owned(a))
                 } else {
-                    panic!("Reshape failed: {:?} vs {:?}", x.shape(), target);
+                    Err(op::ComputeException::Error(format!(
+                        "Reshape failed: {:?} vs {:?}",
+                        x.shape(),
+                        target
+                    )))
                 }
             }
         } else if let Ok(a) =
@@ -233,7 +237,11 @@ impl<T: Float> op::Op<T> for Reshape {
         {
             Ok(crate::ArrRepr::Owned(a))
         } else {
-            panic!("Reshape failed: {:?} vs {:?}", x.shape(), target);
+            Err(op::ComputeException::Error(format!(
+                "Reshape failed: {:?} vs {:?}",
+                x.shape(),
+                target
+            )))
         };
 
         vec![ret]
@@ -245,6 +253,18 @@ impl<T: Float> op::Op<T> for Reshape {
             .build(Reshape);
         vec![Some(gx), None]
     }
+
+    fn jvp(
+        &self,
+        xs: &[&Tensor<T>],
+        _: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        let t = Tensor::builder()
+            .set_inputs(vec![txs[0]?, xs[1]])
+            .build(Reshape);
+        Some(t)
+    }
 }
 
 impl<T: Float> op::Op<T> for SetDiff1D {
@@ -300,6 +320,10 @@ impl<T: Float> op::Op<T> for IndexOp {
         "IndexOp"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("index", self.index)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -316,7 +340,9 @@ impl<T: Float> op::Op<T> for IndexOp {
         let ret = if let Some(ret) = flat_x.get(i) {
             Ok(crate::ArrRepr::Owned(ndarray::arr0(*ret).into_dyn()))
         } else {
-            panic!("Index out of bounds");
+            Err(op::ComputeException::Error(
+                "Index out of bounds".to_owned(),
+            ))
         };
         vec![ret]
     }
@@ -336,6 +362,10 @@ impl<T: Float> op::Op<T> for IndexOpGrad {
         "IndexOpGrad"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("index", self.index)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -359,13 +389,19 @@ impl<T: Float> op::Op<T> for IndexOpGrad {
         {
             *a = gy[ndarray::IxDyn(&[])];
         } else {
-            panic!("Index out of bounds");
+            return vec![Err(op::ComputeException::Error(
+                "Index out of bounds".to_owned(),
+            ))];
         }
         vec![Ok(crate::ArrRepr::Owned(result))]
     }
 
-    fn grad(&self, _: &Tensor<T>, _: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
-        vec![None]
+    fn grad(&self, gy: &Tensor<T>, _: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        // Linear in `gy`
+        let ggy = Tensor::builder()
+            .set_input(gy)
+            .build(IndexOp { index: self.index });
+        vec![None, Some(ggy)]
     }
 }
 
@@ -374,6 +410,13 @@ impl<T: Float> op::Op<T> for Gather {
         "Gather"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("axis", self.axis).with(
+            "should_normalize_negative_indices",
+            self.should_normalize_negative_indices,
+        )
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -409,11 +452,27 @@ impl<T: Float> op::Op<T> for Gather {
     }
 
     fn grad(&self, gy: &Tensor<T>, inputs: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
-        let gx = Tensor::builder()
-            .set_shape(inputs[0].shape())
-            .set_inputs(vec![inputs[0], inputs[1], gy])
-            .build(GatherGrad { axis: self.axis });
-        vec![None, Some(gx)]
+        let (indices, param) = (inputs[0], inputs[1]);
+        let b = Tensor::builder()
+            .set_shape(param.shape())
+            .set_inputs(vec![indices, param, gy]);
+        let b = if self.axis == 0 {
+            // gy's shape: indices.shape + param.shape[1..]
+            let values_shape = ops::concat(
+                &[
+                    &ops::convert_to_tensor(ndarray::arr1(&[-T::one()]).into_dyn()),
+                    &ops::slice(ops::shape(param), &[1], &[-1]),
+                ],
+                0,
+            );
+            b.set_indexed_slices(crate::tensor::IndexedSlices {
+                indices: ops::flatten(indices),
+                values: ops::reshape(gy, &values_shape),
+            })
+        } else {
+            b
+        };
+        vec![None, Some(b.build(GatherGrad { axis: self.axis }))]
     }
 }
 
@@ -422,6 +481,10 @@ impl<T: Float> op::Op<T> for GatherGrad {
         "GatherGrad"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("axis", self.axis)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -531,6 +594,12 @@ impl<T: Float> op::Op<T> for Clip<T> {
         "Clip"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("min", self.min.to_f64().unwrap())
+            .with("max", self.max.to_f64().unwrap())
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -557,6 +626,12 @@ impl<T: Float> op::Op<T> for ClipGrad<T> {
     fn name(&self) -> &str {
         "ClipGrad"
     }
+
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("min", self.min.to_f64().unwrap())
+            .with("max", self.max.to_f64().unwrap())
+    }
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -580,6 +655,10 @@ impl<T: Float> op::Op<T> for Concat {
         "Concat"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("axis", self.axis)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -599,7 +678,9 @@ impl<T: Float> op::Op<T> for Concat {
         let ret = if let Ok(y) = ndarray::stack(ndarray::Axis(axis), views.as_slice()) {
             Ok(crate::ArrRepr::Owned(y))
         } else {
-            panic!("Can't concat arrays whose shapes are incompatible.");
+            Err(op::ComputeException::Error(
+                "Can't concat arrays whose shapes are incompatible.".to_owned(),
+            ))
         };
         vec![ret]
     }
@@ -630,6 +711,12 @@ impl<T: Float> op::Op<T> for ConcatGrad {
         "ConcatGrad"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("axis", self.axis)
+            .with("index", self.index)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -688,6 +775,12 @@ impl<T: Float> op::Op<T> for Tile {
         "Tile"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("axis", self.axis)
+            .with("num", self.num)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -699,7 +792,7 @@ impl<T: Float> op::Op<T> for Tile {
         let ret = if let Ok(ret) = ndarray::stack(ndarray::Axis(axis), views.as_slice()) {
             Ok(crate::ArrRepr::Owned(ret))
         } else {
-            panic!("Shape Incompatible");
+            Err(op::ComputeException::Error("Shape Incompatible".to_owned()))
         };
         vec![ret]
     }
@@ -714,6 +807,13 @@ impl<T: Float> op::Op<T> for Split {
         "Split"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("axis", self.axis)
+            .with("start_index", self.start_index)
+            .with("end_index", self.end_index)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -745,6 +845,13 @@ impl<T: Float> op::Op<T> for SplitGrad {
         "SplitGrad"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("axis", self.axis)
+            .with("start_index", self.start_index)
+            .with("end_index", self.end_index)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -805,12 +912,17 @@ impl<T: Float> op::Op<T> for Slice {
         "Slice"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("indices", slice_indices_to_attr(&self.indices))
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
     ) -> op::ComputeResults<'v, T> {
         let mut y = ctx.grab_inputs()[0].clone();
-        y.slice_collapse(&self.indices);
+        let indices = fill_slice_indices(&self.indices, y.ndim());
+        y.slice_collapse(&indices);
         vec![Ok(crate::ArrRepr::View(y))]
     }
 
@@ -831,6 +943,10 @@ impl<T: Float> op::Op<T> for SliceGrad {
         "SliceGrad"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("indices", slice_indices_to_attr(&self.indices))
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -839,9 +955,10 @@ impl<T: Float> op::Op<T> for SliceGrad {
         let x = &xs[0];
         let gy = &xs[1];
         let mut gx = NdArray::zeros(x.shape());
+        let indices = fill_slice_indices(&self.indices, x.ndim());
         // sliced view
         gx.slice_mut(
-            ndarray::SliceInfo::<_, ndarray::IxDyn>::new(&self.indices)
+            ndarray::SliceInfo::<_, ndarray::IxDyn>::new(indices)
                 .unwrap()
                 .as_ref(),
         )
@@ -925,3 +1042,61 @@ impl<T: Float> op::Op<T> for ExpandDims {
         vec![Some(ops::squeeze(gy, inputs[1])), None]
     }
 }
+
+// Appends full slices for the trailing axes that `indices` leaves out.
+fn fill_slice_indices(
+    indices: &[ndarray::SliceOrIndex],
+    ndim: usize,
+) -> Vec<ndarray::SliceOrIndex> {
+    let mut ret = indices.to_vec();
+    let full = ndarray::SliceOrIndex::Slice {
+        start: 0,
+        end: None,
+        step: 1,
+    };
+    if ret.len() < ndim {
+        ret.resize(ndim, full);
+    }
+    ret
+}
+
+// Flattens slice indices into `[start, end, step, kind]` for each axis, where
+// kind is 0 for a slice, 1 for a slice without end and 2 for an index.
+fn slice_indices_to_attr(indices: &[ndarray::SliceOrIndex]) -> Vec<i64> {
+    let mut ret = Vec::with_capacity(indices.len() * 4);
+    for si in indices {
+        match *si {
+            ndarray::SliceOrIndex::Slice {
+                start,
+                end: Some(end),
+                step,
+            } => ret.extend(&[start as i64, end as i64, step as i64, 0]),
+            ndarray::SliceOrIndex::Slice {
+                start,
+                end: None,
+                step,
+            } => ret.extend(&[start as i64, 0, step as i64, 1]),
+            ndarray::SliceOrIndex::Index(i) => ret.extend(&[i as i64, 0, 0, 2]),
+        }
+    }
+    ret
+}
+
+// Inverse of `slice_indices_to_attr`
+pub(crate) fn slice_indices_from_attr(attr: &[i64]) -> Option<Vec<ndarray::SliceOrIndex>> {
+    let chunks = attr.chunks_exact(4);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    chunks
+        .map(|a| match a[3] {
+            0 | 1 => Some(ndarray::SliceOrIndex::Slice {
+                start: a[0] as isize,
+                end: if a[3] == 0 { Some(a[1] as isize) } else { None },
+                step: a[2] as isize,
+            }),
+            2 => Some(ndarray::SliceOrIndex::Index(a[0] as isize)),
+            _ => None,
+        })
+        .collect()
+}
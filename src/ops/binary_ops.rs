@@ -4,8 +4,7 @@ use crate::ops;
 use crate::tensor::Tensor;
 use crate::Float;
 /// Implement +, -, *, / operators for Tensor
-/// +=, -=, *=, /= are provided as methods of ops::inplace_*.
-/// *=, /= don't propagate gradients.
+/// +=, -=, *=, /= are provided as methods of ops::*_inplace.
 use ndarray;
 use std::mem;
 
@@ -129,7 +128,11 @@ impl<T: Float> op::Op<T> for PreprocessBinOpGradGrad {
         if let Some(ret) = gy.broadcast(target_shape) {
             vec![Ok(crate::ArrRepr::Owned(ret.to_owned()))]
         } else {
-            panic!("Cant't broadcast.");
+            vec![Err(op::ComputeException::Error(format!(
+                "Can't broadcast {:?} to {:?}",
+                gy.shape(),
+                target_shape
+            )))]
         }
     }
 
@@ -158,6 +161,19 @@ impl<T: Float> op::Op<T> for AddOp {
         let (gy1, gy2) = preprocess_gy(inputs[0], inputs[1], gy);
         vec![Some(gy1), Some(gy2)]
     }
+
+    fn jvp(
+        &self,
+        _: &[&Tensor<T>],
+        y: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        Some(match (txs[0], txs[1]) {
+            (Some(t0), Some(t1)) => t0 + t1,
+            (Some(t), None) | (None, Some(t)) => broadcast_tangent(t.clone(), y),
+            (None, None) => unreachable!(),
+        })
+    }
 }
 
 impl<T: Float> op::Op<T> for SubOp {
@@ -187,6 +203,20 @@ impl<T: Float> op::Op<T> for SubOp {
         let (gy1, gy2) = preprocess_gy(inputs[0], inputs[1], gy);
         vec![Some(gy1), Some(ops::neg(&gy2))]
     }
+
+    fn jvp(
+        &self,
+        _: &[&Tensor<T>],
+        y: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        Some(match (txs[0], txs[1]) {
+            (Some(t0), Some(t1)) => t0 - t1,
+            (Some(t0), None) => broadcast_tangent(t0.clone(), y),
+            (None, Some(t1)) => broadcast_tangent(ops::neg(t1), y),
+            (None, None) => unreachable!(),
+        })
+    }
 }
 
 impl<T: Float> op::Op<T> for MulOp {
@@ -208,6 +238,16 @@ impl<T: Float> op::Op<T> for MulOp {
         let (gy1, gy2) = preprocess_gy(x0, x1, gy);
         vec![Some(gy1 * x1), Some(gy2 * x0)]
     }
+
+    fn jvp(
+        &self,
+        xs: &[&Tensor<T>],
+        _: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        // Each term is already broadcast to the output shape.
+        sum_terms(txs[0].map(|t0| t0 * xs[1]), txs[1].map(|t1| xs[0] * t1))
+    }
 }
 
 impl<T: Float> op::Op<T> for DivOp {
@@ -250,8 +290,72 @@ impl<T: Float> op::Op<T> for DivOp {
             Some(ops::neg(x0) * ops::pow(x1, T::from(-2.).unwrap()) * gy2),
         ]
     }
+
+    fn jvp(
+        &self,
+        xs: &[&Tensor<T>],
+        y: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        // d(x0 / x1) = dx0 / x1 - y * dx1 / x1
+        let x1 = xs[1];
+        sum_terms(
+            txs[0].map(|t0| t0 / x1),
+            txs[1].map(|t1| ops::neg(&(y * t1 / x1))),
+        )
+    }
 }
 
+// Writes the result into the lhs array if no one reads it after this op;
+// otherwise falls back to the corresponding out-of-place op.
+macro_rules! impl_inplace_bin_op {
+    ($name:ident, $op_name:expr, $fallback:ident, $assign:expr) => {
+        impl<T: Float> op::Op<T> for $name {
+            fn name(&self) -> &str {
+                $op_name
+            }
+
+            fn compute<'v>(
+                &self,
+                mut ctx: crate::runtime::OpComputeContext<'v, T>,
+            ) -> op::ComputeResults<'v, T> {
+                let xs = ctx.grab_inputs();
+                if xs[1].broadcast(xs[0].shape()).is_some() {
+                    let x1 = xs[1].clone();
+                    if let Some(mut x0) = ctx.take_input(0) {
+                        x0.zip_mut_with(&x1, $assign);
+                        return vec![Ok(crate::ArrRepr::Owned(x0))];
+                    }
+                }
+                $fallback.compute(ctx)
+            }
+
+            fn grad(
+                &self,
+                gy: &Tensor<T>,
+                inputs: &[&Tensor<T>],
+                y: &Tensor<T>,
+            ) -> Vec<Option<Tensor<T>>> {
+                $fallback.grad(gy, inputs, y)
+            }
+
+            fn jvp(
+                &self,
+                xs: &[&Tensor<T>],
+                y: &Tensor<T>,
+                txs: &[Option<&Tensor<T>>],
+            ) -> Option<Tensor<T>> {
+                $fallback.jvp(xs, y, txs)
+            }
+        }
+    };
+}
+
+impl_inplace_bin_op!(InplaceAddOp, "InplaceAdd", AddOp, |a, &b| *a += b);
+impl_inplace_bin_op!(InplaceSubOp, "InplaceSub", SubOp, |a, &b| *a -= b);
+impl_inplace_bin_op!(InplaceMulOp, "InplaceMul", MulOp, |a, &b| *a *= b);
+impl_inplace_bin_op!(InplaceDivOp, "InplaceDiv", DivOp, |a, &b| *a /= b);
+
 // Reduce gy if broadcast occurred in the forward path.
 fn preprocess_gy<T: Float>(
     x0: &Tensor<T>,
@@ -271,6 +375,18 @@ fn preprocess_gy<T: Float>(
     (gy0, gy1)
 }
 
+// Broadcasts the tangent of an input to the output shape.
+fn broadcast_tangent<T: Float>(t: Tensor<T>, y: &Tensor<T>) -> Tensor<T> {
+    t + ops::zeros(&y.shape())
+}
+
+fn sum_terms<T: Float>(a: Option<Tensor<T>>, b: Option<Tensor<T>>) -> Option<Tensor<T>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    }
+}
+
 macro_rules! impl_bin_op_forward {
     ($forward_name:ident, $bin_op:tt) => {
         fn $forward_name<'v, T: Float>(x0: &NdArrayView<'v, T>, x1: &NdArrayView<'v, T>) -> crate::ArrRepr<'v, T>
@@ -20,6 +20,10 @@ impl<T: Float> op::Op<T> for Scalar<T> {
         "Scalar"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("val", self.val.to_f64().unwrap())
+    }
+
     fn compute<'v>(&self, _: crate::runtime::OpComputeContext<'v, T>) -> op::ComputeResults<'v, T> {
         vec![Ok(crate::ArrRepr::Owned(
             ndarray::arr0(self.val).into_dyn(),
@@ -117,7 +121,9 @@ impl<T: Float> op::Op<T> for Range {
 
         let true_shape = &[];
         if x0.shape() != true_shape || x1.shape() != true_shape || x2.shape() != true_shape {
-            panic!("Inputs to `range` should be 0-ranked tensors");
+            return vec![Err(op::ComputeException::Error(
+                "Inputs to `range` should be 0-ranked tensors".to_owned(),
+            ))];
         }
 
         let start = x0[ndarray::IxDyn(&[])];
@@ -139,6 +145,25 @@ impl<T: Float> op::Op<T> for ConvertToTensor<T> {
         "ConvertToTensor"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with(
+                "shape",
+                self.arr
+                    .shape()
+                    .iter()
+                    .map(|&d| d as i64)
+                    .collect::<Vec<_>>(),
+            )
+            .with(
+                "values",
+                self.arr
+                    .iter()
+                    .map(|a| a.to_f64().unwrap())
+                    .collect::<Vec<_>>(),
+            )
+    }
+
     fn compute<'v>(&self, _: crate::runtime::OpComputeContext<'v, T>) -> op::ComputeResults<'v, T> {
         vec![Ok(crate::ArrRepr::Owned(self.arr.clone()))]
     }
@@ -75,6 +75,13 @@ impl<T: Float> crate::op::Op<T> for Conv2D {
         "Conv2D"
     }
 
+    fn attributes(&self) -> crate::op::Attributes {
+        crate::op::Attributes::new()
+            .with("pad", self.pad)
+            .with("stride", self.stride)
+            .with("dilation", self.dilation)
+    }
+
     #[allow(unused_mut)]
     fn compute<'v>(
         &self,
@@ -287,6 +294,13 @@ impl<T: Float> crate::op::Op<T> for Conv2DWithCols {
         "Conv2DWithCols"
     }
 
+    fn attributes(&self) -> crate::op::Attributes {
+        crate::op::Attributes::new()
+            .with("pad", self.pad)
+            .with("stride", self.stride)
+            .with("dilation", self.dilation)
+    }
+
     #[allow(unused_mut)]
     fn compute<'v>(
         &self,
@@ -416,6 +430,13 @@ impl<T: Float> crate::op::Op<T> for Conv2DFilterGrad {
         "Conv2DFilterGrad"
     }
 
+    fn attributes(&self) -> crate::op::Attributes {
+        crate::op::Attributes::new()
+            .with("pad", self.pad)
+            .with("stride", self.stride)
+            .with("dilation", self.dilation)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
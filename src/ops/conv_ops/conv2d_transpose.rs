@@ -16,6 +16,13 @@ impl<T: Float> crate::op::Op<T> for Conv2DTranspose {
     fn name(&self) -> &str {
         "Conv2DTranspose"
     }
+
+    fn attributes(&self) -> crate::op::Attributes {
+        crate::op::Attributes::new()
+            .with("pad", self.pad)
+            .with("stride", self.stride)
+            .with("dilation", self.dilation)
+    }
     #[allow(unused_mut)]
     fn compute<'v>(
         &self,
@@ -183,6 +190,13 @@ impl<T: Float> crate::op::Op<T> for Conv2DTransposeFilterGrad {
         "Conv2DTransposeFilterGrad"
     }
 
+    fn attributes(&self) -> crate::op::Attributes {
+        crate::op::Attributes::new()
+            .with("pad", self.pad)
+            .with("stride", self.stride)
+            .with("dilation", self.dilation)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -8,15 +8,15 @@ pub struct MaxPool2D {
 }
 
 pub struct MaxPool2DGrad {
-    pad: usize,
-    stride: usize,
-    size: usize,
+    pub pad: usize,
+    pub stride: usize,
+    pub size: usize,
 }
 
 pub struct MaxPool2DGradGrad {
-    pad: usize,
-    stride: usize,
-    size: usize,
+    pub pad: usize,
+    pub stride: usize,
+    pub size: usize,
 }
 
 macro_rules! impl_max_pool {
@@ -167,6 +167,13 @@ impl<T: Float> crate::op::Op<T> for MaxPool2D {
         "MaxPool"
     }
 
+    fn attributes(&self) -> crate::op::Attributes {
+        crate::op::Attributes::new()
+            .with("pad", self.pad)
+            .with("stride", self.stride)
+            .with("size", self.size)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -265,6 +272,13 @@ impl<T: Float> crate::op::Op<T> for MaxPool2DGrad {
         "MaxPool2DGrad"
     }
 
+    fn attributes(&self) -> crate::op::Attributes {
+        crate::op::Attributes::new()
+            .with("pad", self.pad)
+            .with("stride", self.stride)
+            .with("size", self.size)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -311,6 +325,13 @@ impl<T: Float> crate::op::Op<T> for MaxPool2DGradGrad {
         "MaxPoolGradGrad"
     }
 
+    fn attributes(&self) -> crate::op::Attributes {
+        crate::op::Attributes::new()
+            .with("pad", self.pad)
+            .with("stride", self.stride)
+            .with("size", self.size)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -560,6 +560,12 @@ impl<T: Float> op::Op<T> for MatMul {
         "MatMul"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("transpose_a", self.transpose_a)
+            .with("transpose_b", self.transpose_b)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -570,16 +576,32 @@ impl<T: Float> op::Op<T> for MatMul {
         let x0_shape = x0.shape();
         let x1_shape = x1.shape();
 
-        assert_eq!(
-            x0_shape.len(),
-            2,
-            "First input to matmul should be a matrix"
-        );
-        assert_eq!(
-            x1_shape.len(),
-            2,
-            "Second input to matmul should be a matrix"
-        );
+        if x0_shape.len() != 2 {
+            return vec![Err(op::ComputeException::Error(
+                "First input to matmul should be a matrix".to_owned(),
+            ))];
+        }
+        if x1_shape.len() != 2 {
+            return vec![Err(op::ComputeException::Error(
+                "Second input to matmul should be a matrix".to_owned(),
+            ))];
+        }
+        let inner0 = if self.transpose_a {
+            x0_shape[0]
+        } else {
+            x0_shape[1]
+        };
+        let inner1 = if self.transpose_b {
+            x1_shape[1]
+        } else {
+            x1_shape[0]
+        };
+        if inner0 != inner1 {
+            return vec![Err(op::ComputeException::Error(format!(
+                "Inner dims mismatch: {:?} vs {:?}",
+                x0_shape, x1_shape
+            )))];
+        }
         #[cfg(feature = "mkl")]
         {
             if same_type::<T, f32>() {
@@ -607,22 +629,42 @@ impl<T: Float> op::Op<T> for MatMul {
     }
 
     fn grad(&self, gy: &Tensor<T>, inputs: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
-        let opa = Tensor::builder()
-            .set_inputs(vec![gy, inputs[1]])
-            .build(MatMul {
-                transpose_a: false,
-                transpose_b: true,
-            });
-
-        let opb = Tensor::builder()
-            .set_inputs(vec![inputs[0], gy])
-            .build(MatMul {
-                transpose_a: true,
-                transpose_b: false,
-            });
-
+        let matmul = |a: &Tensor<T>, b: &Tensor<T>, transpose_a, transpose_b| {
+            Tensor::builder().set_inputs(vec![a, b]).build(MatMul {
+                transpose_a,
+                transpose_b,
+            })
+        };
+        let (a, b) = (inputs[0], inputs[1]);
+        let (opa, opb) = match (self.transpose_a, self.transpose_b) {
+            (false, false) => (matmul(gy, b, false, true), matmul(a, gy, true, false)),
+            (false, true) => (matmul(gy, b, false, false), matmul(gy, a, true, false)),
+            (true, false) => (matmul(b, gy, false, true), matmul(a, gy, false, false)),
+            (true, true) => (matmul(b, gy, true, true), matmul(gy, a, true, true)),
+        };
         vec![Some(opa), Some(opb)]
     }
+
+    fn jvp(
+        &self,
+        xs: &[&Tensor<T>],
+        _: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        // d(a @ b) = da @ b + a @ db
+        let matmul = |a: &Tensor<T>, b: &Tensor<T>| {
+            Tensor::builder().set_inputs(vec![a, b]).build(MatMul {
+                transpose_a: self.transpose_a,
+                transpose_b: self.transpose_b,
+            })
+        };
+        let ta = txs[0].map(|t| matmul(t, xs[1]));
+        let tb = txs[1].map(|t| matmul(xs[0], t));
+        match (ta, tb) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        }
+    }
 }
 
 #[inline]
@@ -646,6 +688,12 @@ impl<T: Float> op::Op<T> for BatchMatMul {
         "BatchMatMul"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("transpose_a", self.transpose_a)
+            .with("transpose_b", self.transpose_b)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -670,7 +718,10 @@ impl<T: Float> op::Op<T> for BatchMatMul {
         );
 
         if rank0 != rank1 || shape0[..rank0 - 2] != shape1[..rank0 - 2] {
-            panic!("Input shapes mismatch: {:?} vs {:?}", shape0, shape1);
+            return vec![Err(op::ComputeException::Error(format!(
+                "Input shapes mismatch: {:?} vs {:?}",
+                shape0, shape1
+            )))];
         }
 
         let row0 = shape0[rank0 - 2];
@@ -0,0 +1,160 @@
+//! Module defining Adadelta optimizer
+use super::schedules::LearningRate;
+use crate::op;
+use crate::tensor::Tensor;
+use crate::Float;
+use ndarray::Zip;
+use std::io;
+
+// Names of the state variables in `OptimizerState`
+const SLOT_NAMES: [&str; 2] = ["acc", "acc_delta"];
+
+struct AdadeltaOp<T: Float> {
+    lr: LearningRate<T>,
+    rho: T,
+    eps: T,
+}
+
+impl<T: Float> crate::op::Op<T> for AdadeltaOp<T> {
+    fn name(&self) -> &str {
+        "Adadelta"
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
+    fn compute<'v>(
+        &self,
+        mut ctx: crate::runtime::OpComputeContext<'v, T>,
+    ) -> op::ComputeResults<'v, T> {
+        let (lr, rho, eps) = (self.lr.get(), self.rho, self.eps);
+        super::apply_update(&mut ctx, 2, |views, g| {
+            if let [var, acc, acc_delta] = views {
+                Zip::from(var)
+                    .and(acc)
+                    .and(acc_delta)
+                    .and(&g)
+                    .apply(|var, acc, acc_delta, &g| {
+                        *acc = rho * *acc + (T::one() - rho) * g * g;
+                        let delta = g * (*acc_delta + eps).sqrt() / (*acc + eps).sqrt();
+                        *acc_delta = rho * *acc_delta + (T::one() - rho) * delta * delta;
+                        *var -= lr * delta;
+                    });
+            }
+        });
+        vec![Err(crate::op::ComputeException::NoOutput)]
+    }
+
+    fn grad(&self, _: &Tensor<T>, _: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        vec![None]
+    }
+}
+
+/// Adadelta optimizer
+///
+/// The implementation is based on https://arxiv.org/abs/1212.5701.
+/// The step is scaled by the ratio of the moving RMS of the past updates to that of
+/// the gradients; `lr` is just a multiplier of it.
+///
+/// ```
+/// extern crate autograd as ag;
+/// use ag::gradient_descent_ops::Optimizer;
+///
+/// let ref w = ag::variable(ag::ndarray_ext::glorot_uniform::<f32>(&[28 * 28, 10]));
+/// let ref loss = ag::reduce_sum(&ag::square(w), &[0, 1], false);
+/// let grads = ag::grad(&[loss], &[w]);
+///
+/// let opt = ag::gradient_descent_ops::Adadelta::default();
+/// let update_ops = opt.compute_updates(&[w], &grads);
+/// ag::eval(&update_ops, &[]);
+/// ```
+pub struct Adadelta<T: Float> {
+    /// Learning rate
+    pub lr: T,
+    /// Decay rate of the moving averages
+    pub rho: T,
+    pub eps: T,
+    // Moving averages of the squared gradients and updates of each variable
+    states: super::StateMap<T, Vec<Tensor<T>>>,
+    schedule: Option<LearningRate<T>>,
+}
+
+impl<T: Float> Default for Adadelta<T> {
+    /// Instantiates `Adadelta` optimizer with the parameters in the original paper
+    /// (`rho = 0.95`, `eps = 1e-6`) and `lr = 1`.
+    fn default() -> Adadelta<T> {
+        Adadelta::new(T::one(), T::from(0.95).unwrap(), T::from(1e-06).unwrap())
+    }
+}
+
+impl<T: Float> Adadelta<T> {
+    pub fn new(lr: T, rho: T, eps: T) -> Adadelta<T> {
+        Adadelta {
+            lr,
+            rho,
+            eps,
+            states: super::StateMap::new(),
+            schedule: None,
+        }
+    }
+
+    /// Makes the learning rate follow `schedule` of `step`.
+    ///
+    /// Update ops read the rate when they are evaluated; the ones made before this
+    /// call keep the previous rate.
+    pub fn set_lr_schedule<S>(&mut self, schedule: S, step: &super::GlobalStep)
+    where
+        S: super::LearningRateSchedule<T> + 'static,
+    {
+        self.schedule = Some(LearningRate::scheduled(schedule, step));
+    }
+}
+
+impl<T: Float> super::Optimizer<T> for Adadelta<T> {
+    fn compute_updates<A: AsRef<Tensor<T>>>(
+        &self,
+        params: &[&Tensor<T>],
+        grads: &[A],
+    ) -> Vec<Tensor<T>> {
+        params
+            .iter()
+            .zip(grads)
+            .map(|(param, grad)| {
+                let slots = self
+                    .states
+                    .get_or_insert_with(param, |arr| super::zero_slots(arr, 2));
+                super::build_update(
+                    param,
+                    grad.as_ref(),
+                    &slots,
+                    AdadeltaOp {
+                        lr: LearningRate::new(self.lr, &self.schedule),
+                        rho: self.rho,
+                        eps: self.eps,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn state(&self, params: &[&Tensor<T>]) -> super::OptimizerState<T> {
+        let mut state = super::OptimizerState::new();
+        state.set_hyper_param("lr", self.lr);
+        state.set_hyper_param("rho", self.rho);
+        state.set_hyper_param("eps", self.eps);
+        super::save_slots(&self.states, params, &SLOT_NAMES, &mut state);
+        state
+    }
+
+    fn load_state(
+        &mut self,
+        params: &[&Tensor<T>],
+        state: &super::OptimizerState<T>,
+    ) -> io::Result<()> {
+        self.lr = state.hyper_param("lr")?;
+        self.rho = state.hyper_param("rho")?;
+        self.eps = state.hyper_param("eps")?;
+        super::load_slots(&self.states, params, &SLOT_NAMES, SLOT_NAMES.len(), state)
+    }
+}
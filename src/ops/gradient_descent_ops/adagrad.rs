@@ -0,0 +1,155 @@
+//! Module defining Adagrad optimizer
+use super::schedules::LearningRate;
+use crate::ndarray_ext::NdArray;
+use crate::op;
+use crate::tensor::Tensor;
+use crate::Float;
+use ndarray::Zip;
+use std::io;
+
+// Names of the state variables in `OptimizerState`
+const SLOT_NAMES: [&str; 1] = ["accumulator"];
+
+struct AdagradOp<T: Float> {
+    lr: LearningRate<T>,
+    eps: T,
+}
+
+impl<T: Float> crate::op::Op<T> for AdagradOp<T> {
+    fn name(&self) -> &str {
+        "Adagrad"
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
+    fn compute<'v>(
+        &self,
+        mut ctx: crate::runtime::OpComputeContext<'v, T>,
+    ) -> op::ComputeResults<'v, T> {
+        let (lr, eps) = (self.lr.get(), self.eps);
+        super::apply_update(&mut ctx, 1, |views, g| {
+            if let [var, acc] = views {
+                Zip::from(var).and(acc).and(&g).apply(|var, acc, &g| {
+                    *acc += g * g;
+                    *var -= lr * g / (acc.sqrt() + eps);
+                });
+            }
+        });
+        vec![Err(crate::op::ComputeException::NoOutput)]
+    }
+
+    fn grad(&self, _: &Tensor<T>, _: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        vec![None]
+    }
+}
+
+/// Adagrad optimizer
+///
+/// Scales the learning rate of each element by the root of the sum of its squared gradients.
+///
+/// ```
+/// extern crate autograd as ag;
+/// use ag::gradient_descent_ops::Optimizer;
+///
+/// let ref w = ag::variable(ag::ndarray_ext::glorot_uniform::<f32>(&[28 * 28, 10]));
+/// let ref loss = ag::reduce_sum(&ag::square(w), &[0, 1], false);
+/// let grads = ag::grad(&[loss], &[w]);
+///
+/// let opt = ag::gradient_descent_ops::Adagrad::default();
+/// let update_ops = opt.compute_updates(&[w], &grads);
+/// ag::eval(&update_ops, &[]);
+/// ```
+pub struct Adagrad<T: Float> {
+    /// Learning rate
+    pub lr: T,
+    /// Starting value of the accumulators
+    pub initial_accumulator_value: T,
+    pub eps: T,
+    // Accumulator of each variable
+    states: super::StateMap<T, Vec<Tensor<T>>>,
+    schedule: Option<LearningRate<T>>,
+}
+
+impl<T: Float> Default for Adagrad<T> {
+    /// Instantiates `Adagrad` optimizer with `lr = 0.01` and `initial_accumulator_value = 0.1`.
+    fn default() -> Adagrad<T> {
+        Adagrad::new(
+            T::from(0.01).unwrap(),
+            T::from(0.1).unwrap(),
+            T::from(1e-07).unwrap(),
+        )
+    }
+}
+
+impl<T: Float> Adagrad<T> {
+    pub fn new(lr: T, initial_accumulator_value: T, eps: T) -> Adagrad<T> {
+        Adagrad {
+            lr,
+            initial_accumulator_value,
+            eps,
+            states: super::StateMap::new(),
+            schedule: None,
+        }
+    }
+
+    /// Makes the learning rate follow `schedule` of `step`.
+    ///
+    /// Update ops read the rate when they are evaluated; the ones made before this
+    /// call keep the previous rate.
+    pub fn set_lr_schedule<S>(&mut self, schedule: S, step: &super::GlobalStep)
+    where
+        S: super::LearningRateSchedule<T> + 'static,
+    {
+        self.schedule = Some(LearningRate::scheduled(schedule, step));
+    }
+}
+
+impl<T: Float> super::Optimizer<T> for Adagrad<T> {
+    fn compute_updates<A: AsRef<Tensor<T>>>(
+        &self,
+        params: &[&Tensor<T>],
+        grads: &[A],
+    ) -> Vec<Tensor<T>> {
+        params
+            .iter()
+            .zip(grads)
+            .map(|(param, grad)| {
+                let slots = self.states.get_or_insert_with(param, |arr| {
+                    let acc = NdArray::from_elem(arr.shape(), self.initial_accumulator_value);
+                    vec![crate::ops::variable(acc)]
+                });
+                super::build_update(
+                    param,
+                    grad.as_ref(),
+                    &slots,
+                    AdagradOp {
+                        lr: LearningRate::new(self.lr, &self.schedule),
+                        eps: self.eps,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn state(&self, params: &[&Tensor<T>]) -> super::OptimizerState<T> {
+        let mut state = super::OptimizerState::new();
+        state.set_hyper_param("lr", self.lr);
+        state.set_hyper_param("initial_accumulator_value", self.initial_accumulator_value);
+        state.set_hyper_param("eps", self.eps);
+        super::save_slots(&self.states, params, &SLOT_NAMES, &mut state);
+        state
+    }
+
+    fn load_state(
+        &mut self,
+        params: &[&Tensor<T>],
+        state: &super::OptimizerState<T>,
+    ) -> io::Result<()> {
+        self.lr = state.hyper_param("lr")?;
+        self.initial_accumulator_value = state.hyper_param("initial_accumulator_value")?;
+        self.eps = state.hyper_param("eps")?;
+        super::load_slots(&self.states, params, &SLOT_NAMES, SLOT_NAMES.len(), state)
+    }
+}
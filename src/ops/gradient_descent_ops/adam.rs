@@ -1,17 +1,28 @@
 //! Module defining Adam optimizer
 extern crate ndarray;
 
-use crate::ndarray_ext::NdArray;
+use super::schedules::{GlobalStep, LearningRate};
+use super::state::param_key;
+use crate::ndarray_ext::{NdArrayView, NdArrayViewMut};
 use crate::tensor::Tensor;
 use crate::Float;
-use std::cell::Cell;
-use std::collections::btree_map::Entry;
-use std::collections::BTreeMap;
+use ndarray::Zip;
+use std::collections::BTreeSet;
+use std::io;
+
+// Names of the state variables in `OptimizerState`
+const SLOT_NAMES: [&str; 3] = ["m", "v", "v_max"];
 
 struct AdamOp<T: Float> {
+    alpha: LearningRate<T>,
     static_params: StaticParams<T>,
-    // `t` param in the original paper
-    t: Cell<T>,
+    // Decoupled weight decay rate of the variable
+    weight_decay: T,
+    amsgrad: bool,
+    // `t` param in the original paper, shared by all the variables
+    timestep: GlobalStep,
+    // Timestep of the last update of the variable
+    last_step: GlobalStep,
 }
 
 impl<T: Float> crate::op::Op<T> for AdamOp<T> {
@@ -19,59 +30,30 @@ impl<T: Float> crate::op::Op<T> for AdamOp<T> {
         "Adam"
     }
 
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
-        ctx: crate::runtime::OpComputeContext<'v, T>,
+        mut ctx: crate::runtime::OpComputeContext<'v, T>,
     ) -> crate::op::ComputeResults<'v, T> {
-        let StaticParams { alpha, eps, b1, b2 } = self.static_params;
-        let xs = ctx.grab_inputs();
-        let t = self.t.get();
-
-        // Make new m
-        let new_m = {
-            let tmp = T::one() - b1;
-            unsafe {
-                if let Some(arr) = ctx.node(2).get_persistent_array_mut() {
-                    arr.zip_mut_with(&xs[1], move |x2_elem, &g| {
-                        *x2_elem = *x2_elem * b1 + tmp * g
-                    });
-                }
-            }
-            // m is not empty
-            ctx.node(2).get_persistent_array().unwrap()
-        };
-
-        // Make new v
-        let new_v = {
-            let tmp = T::one() - b2;
-            unsafe {
-                if let Some(arr) = ctx.node(3).get_persistent_array_mut() {
-                    arr.zip_mut_with(&xs[1], move |x3_elem, &g| {
-                        *x3_elem = *x3_elem * b2 + tmp * g * g
-                    });
-                }
-            }
-            // v is not empty
-            ctx.node(3).get_persistent_array().unwrap()
-        };
-
-        // Make hat
-        let m_hat = {
-            let rhs = T::one() / (T::one() - b2.powf(t));
-            let v_hat = new_v.mapv(move |new_v_elem| new_v_elem * rhs);
-            let rhs = T::one() / (T::one() - b1.powf(t));
-            let mut m_hat = new_m.mapv(move |new_m_elem| new_m_elem * rhs);
-            m_hat.zip_mut_with(&v_hat, move |a, &b| (*a) /= b.sqrt() + eps);
-            m_hat
-        };
-
-        // Update t and variable
-        unsafe {
-            if let Some(arr) = ctx.node(0).get_persistent_array_mut() {
-                arr.zip_mut_with(&m_hat, move |l, &r| *l -= alpha * r);
-            }
+        // The first update in a step advances the timestep, and the others in the step
+        // follow it. So the variables share the same `t` however many times each update
+        // op is evaluated.
+        let mut t = self.timestep.get();
+        if self.last_step.get() == t {
+            t += 1;
+            self.timestep.set(t);
         }
-        self.t.set(t + T::one());
+        self.last_step.set(t);
+        let t = T::from(t).unwrap();
+        let alpha = self.alpha.get();
+        let num_slots = if self.amsgrad { 3 } else { 2 };
+        // With `IndexedSlices`, only the touched rows and their moments are updated ("lazy" Adam).
+        super::apply_update(&mut ctx, num_slots, |views, g| {
+            self.update(views, g, alpha, t)
+        });
 
         vec![Err(crate::op::ComputeException::NoOutput)]
     }
@@ -81,29 +63,74 @@ impl<T: Float> crate::op::Op<T> for AdamOp<T> {
     }
 }
 
-/// Use `Adam::vars_with_states` to instantiate this.
-#[doc(hidden)]
-pub struct StatefulVariable<'a, T: Float + 'a> {
-    pub var: &'a Tensor<T>,
-    pub state: StatefulParams<T>,
+impl<T: Float> AdamOp<T> {
+    // `views` are `[var, m, v, v_max (if amsgrad)]`.
+    fn update(&self, views: &mut [NdArrayViewMut<T>], grad: NdArrayView<T>, alpha: T, t: T) {
+        let (var, m, v, v_max) = match views {
+            [var, m, v] => (var, m, v, None),
+            [var, m, v, v_max] => (var, m, v, Some(v_max)),
+            _ => unreachable!(),
+        };
+        let StaticParams { eps, b1, b2 } = self.static_params;
+        let wd = self.weight_decay;
+        let m_rhs = T::one() / (T::one() - b1.powf(t));
+        let v_rhs = T::one() / (T::one() - b2.powf(t));
+        Zip::from(&mut *m)
+            .and(&mut *v)
+            .and(&grad)
+            .apply(|m, v, &g| {
+                *m = *m * b1 + (T::one() - b1) * g;
+                *v = *v * b2 + (T::one() - b2) * g * g;
+            });
+        // AMSGrad normalizes by the maximum of the past second moments.
+        let v = match v_max {
+            Some(v_max) => {
+                Zip::from(&mut *v_max)
+                    .and(&*v)
+                    .apply(|v_max, &v| *v_max = v_max.max(v));
+                v_max.view()
+            }
+            None => v.view(),
+        };
+        Zip::from(var).and(&*m).and(&v).apply(|var, &m, &v| {
+            let m_hat = m * m_rhs / ((v * v_rhs).sqrt() + eps);
+            *var -= alpha * (m_hat + wd * *var);
+        });
+    }
 }
 
+/// Formerly a variable paired with its moments, made by `Adam::vars_with_states`.
+#[deprecated(note = "Adam keeps the moments itself; pass the variables to `compute_updates`")]
+#[doc(hidden)]
+pub type StatefulVariable<'a, T> = &'a Tensor<T>;
+
 /// Adam optimizer
 ///
 /// The implementation is based on http://arxiv.org/abs/1412.6980v8.
-/// Please create state objects by use of `Adam::vars_with_states` beforehand.
+/// The moments of each variable are created at the first `compute_updates` for it,
+/// and kept in this object.
+///
+/// Nonzero `weight_decay` enables the decoupled weight decay of AdamW
+/// (https://arxiv.org/abs/1711.05101): `alpha * weight_decay * var` is subtracted
+/// from each variable apart from the Adam step. Use `exclude_from_weight_decay` for
+/// parameters which shouldn't decay, such as biases. `amsgrad` enables the AMSGrad
+/// variant (https://openreview.net/forum?id=ryQu7f-RZ).
+///
+/// The timestep `t` of the bias correction is shared by all the variables, and kept in
+/// an integer counter (see `Adam::timestep`).
 ///
 /// ```
 /// extern crate autograd as ag;
+/// use ag::gradient_descent_ops::Optimizer;
 ///
 /// // Define parameters to optimize.
 /// let w: ag::Tensor<f32> = ag::variable(ag::ndarray_ext::glorot_uniform(&[28 * 28, 10]));
 /// let b: ag::Tensor<f32> = ag::variable(ag::ndarray_ext::zeros(&[1, 10]));
-/// let params = ag::gradient_descent_ops::Adam::vars_with_states(&[&w, &b]);
 ///
 /// // Create update ops.
-/// let adam = ag::gradient_descent_ops::Adam::<f32>::default();
-/// // let update_ops: &[Tensor<f32>] = &adam.compute_updates(&params, grads);
+/// let mut adam = ag::gradient_descent_ops::Adam::<f32>::adamw(0.01);
+/// adam.exclude_from_weight_decay(&[&b]);
+/// // let update_ops: &[Tensor<f32>] = &adam.compute_updates(&[&w, &b], grads);
 /// ```
 ///
 /// See also https://github.com/raskr/rust-autograd/blob/master/examples/mlp_mnist.rs
@@ -112,95 +139,204 @@ pub struct Adam<T: Float> {
     pub eps: T,
     pub b1: T,
     pub b2: T,
+    /// Decoupled weight decay rate
+    pub weight_decay: T,
+    /// Uses AMSGrad
+    pub amsgrad: bool,
+    // `m`, `v` and `v_max` (if amsgrad) of each variable
+    states: super::StateMap<T, Vec<Tensor<T>>>,
+    // Timestep of the last update of each variable
+    last_steps: super::StateMap<T, GlobalStep>,
+    timestep: GlobalStep,
+    no_decay: BTreeSet<super::StateKey<T>>,
+    schedule: Option<LearningRate<T>>,
 }
 
 impl<T: Float> Default for Adam<T> {
     /// Instantiates `Adam` optimizer with the recommended parameters in the original paper.
     fn default() -> Adam<T> {
-        Adam {
-            alpha: T::from(0.001).unwrap(),
-            eps: T::from(1e-08).unwrap(),
-            b1: T::from(0.9).unwrap(),
-            b2: T::from(0.999).unwrap(),
-        }
+        Adam::new(
+            T::from(0.001).unwrap(),
+            T::from(1e-08).unwrap(),
+            T::from(0.9).unwrap(),
+            T::from(0.999).unwrap(),
+        )
     }
 }
 
 impl<T: Float> Adam<T> {
-    /// Creates stateful variable tensors used for Adam optimizer.
-    pub fn vars_with_states<'a>(tensors: &[&'a Tensor<T>]) -> Vec<StatefulVariable<'a, T>> {
-        let mut var2state = BTreeMap::<super::StateKey<'a, T>, StatefulParams<T>>::new();
-        tensors
-            .iter()
-            .map(|var| {
-                // let var = var.as_ref();
-                if let Some(var_arr) = var.get_persistent_array() {
-                    match var2state.entry(super::StateKey(var)) {
-                        Entry::Vacant(ent) => {
-                            let inserted = ent.insert(StatefulParams {
-                                m: crate::ops::variable(NdArray::zeros(var_arr.shape())),
-                                v: crate::ops::variable(NdArray::zeros(var_arr.shape())),
-                            });
-                            StatefulVariable {
-                                var,
-                                state: inserted.clone(),
-                            }
-                        }
-                        Entry::Occupied(ent) => StatefulVariable {
-                            var,
-                            state: ent.get().clone(),
-                        },
-                    }
-                } else {
-                    panic!("Can't optimize non-variable.")
-                }
-            })
-            .collect()
+    /// Instantiates `Adam` optimizer with the given parameters.
+    ///
+    /// Use this in place of the struct literal `Adam { alpha, eps, b1, b2 }` of 0.9.5.
+    pub fn new(alpha: T, eps: T, b1: T, b2: T) -> Adam<T> {
+        Adam {
+            alpha,
+            eps,
+            b1,
+            b2,
+            weight_decay: T::zero(),
+            amsgrad: false,
+            states: super::StateMap::new(),
+            last_steps: super::StateMap::new(),
+            timestep: GlobalStep::new(),
+            no_decay: BTreeSet::new(),
+            schedule: None,
+        }
+    }
+
+    /// Instantiates AdamW optimizer, i.e. the default `Adam` with the given `weight_decay`.
+    pub fn adamw(weight_decay: T) -> Adam<T> {
+        Adam {
+            weight_decay,
+            ..Adam::default()
+        }
+    }
+
+    /// Makes the learning rate (`alpha`) follow `schedule` of `step`.
+    ///
+    /// Update ops read the rate when they are evaluated; the ones made before this
+    /// call keep the previous rate.
+    pub fn set_lr_schedule<S>(&mut self, schedule: S, step: &super::GlobalStep)
+    where
+        S: super::LearningRateSchedule<T> + 'static,
+    {
+        self.schedule = Some(LearningRate::scheduled(schedule, step));
     }
 
-    /// Creates ops to optimize `params` with Adam.
+    /// Returns the timestep shared by the variables, i.e. `t` of the last update.
+    pub fn timestep(&self) -> &GlobalStep {
+        &self.timestep
+    }
+
+    /// Returns `tensors` as they are.
     ///
-    /// Evaluated results of the return values will be `None`.
-    pub fn compute_updates<A: AsRef<Tensor<T>>>(
+    /// `Adam` creates and keeps the moments of each variable in `compute_updates` now.
+    #[deprecated(note = "Adam keeps the moments itself; pass the variables to `compute_updates`")]
+    #[allow(deprecated)]
+    pub fn vars_with_states<'a>(tensors: &[&'a Tensor<T>]) -> Vec<StatefulVariable<'a, T>> {
+        tensors.to_vec()
+    }
+
+    /// Disables the weight decay of `params`.
+    pub fn exclude_from_weight_decay(&mut self, params: &[&Tensor<T>]) {
+        self.no_decay
+            .extend(params.iter().map(|&p| super::StateKey(p.clone())));
+    }
+}
+
+impl<T: Float> super::Optimizer<T> for Adam<T> {
+    fn compute_updates<A: AsRef<Tensor<T>>>(
         &self,
-        params: &[StatefulVariable<T>],
+        params: &[&Tensor<T>],
         grads: &[A],
     ) -> Vec<Tensor<T>> {
         params
             .iter()
             .zip(grads)
             .map(|(param, grad)| {
-                let StatefulParams { ref m, ref v } = param.state;
-                Tensor::builder()
-                    .set_inputs(vec![param.var, grad.as_ref(), m, v])
-                    .build(AdamOp {
-                        t: Cell::new(T::one()),
+                let mut moments = self
+                    .states
+                    .get_or_insert_with(param, |arr| super::zero_slots(arr, 2));
+                if self.amsgrad && moments.len() == 2 {
+                    // `v_max` is made lazily, so `amsgrad` can be turned on later.
+                    moments = self.states.modify(param, |moments| {
+                        let arr = param.get_persistent_array().unwrap();
+                        moments.extend(super::zero_slots(&arr, 1))
+                    });
+                }
+                let last_step = self
+                    .last_steps
+                    .get_or_insert_with(param, |_| GlobalStep::new());
+                let key = super::StateKey((*param).clone());
+                let weight_decay = if self.no_decay.contains(&key) {
+                    T::zero()
+                } else {
+                    self.weight_decay
+                };
+                // `v_max` left by `amsgrad` is kept but not updated.
+                let num_moments = if self.amsgrad { 3 } else { 2 };
+                super::build_update(
+                    param,
+                    grad.as_ref(),
+                    &moments[..num_moments],
+                    AdamOp {
+                        timestep: self.timestep.clone(),
+                        last_step,
+                        alpha: LearningRate::new(self.alpha, &self.schedule),
                         static_params: StaticParams {
-                            alpha: self.alpha,
                             eps: self.eps,
                             b1: self.b1,
                             b2: self.b2,
                         },
-                    })
+                        weight_decay,
+                        amsgrad: self.amsgrad,
+                    },
+                )
             })
             .collect()
     }
+
+    fn state(&self, params: &[&Tensor<T>]) -> super::OptimizerState<T> {
+        let mut state = super::OptimizerState::new();
+        state.set_hyper_param("alpha", self.alpha);
+        state.set_hyper_param("eps", self.eps);
+        state.set_hyper_param("b1", self.b1);
+        state.set_hyper_param("b2", self.b2);
+        state.set_hyper_param("weight_decay", self.weight_decay);
+        state.set_hyper_param("amsgrad", self.amsgrad as u8);
+        super::save_slots(&self.states, params, &SLOT_NAMES, &mut state);
+        state.set_hyper_param("timestep", self.timestep.get());
+        for (i, param) in params.iter().enumerate() {
+            if let Some(step) = self.last_steps.get(param) {
+                state.set_hyper_param(&param_key(i, "last_step"), step.get());
+            }
+            let excluded = self.no_decay.contains(&super::StateKey((*param).clone()));
+            state.set_hyper_param(&param_key(i, "no_decay"), excluded as u8);
+        }
+        state
+    }
+
+    fn load_state(
+        &mut self,
+        params: &[&Tensor<T>],
+        state: &super::OptimizerState<T>,
+    ) -> io::Result<()> {
+        self.alpha = state.hyper_param("alpha")?;
+        self.eps = state.hyper_param("eps")?;
+        self.b1 = state.hyper_param("b1")?;
+        self.b2 = state.hyper_param("b2")?;
+        self.weight_decay = state.hyper_param("weight_decay")?;
+        self.amsgrad = state.hyper_param::<u8>("amsgrad")? != 0;
+        let num_moments = if self.amsgrad { 3 } else { 2 };
+        super::load_slots(&self.states, params, &SLOT_NAMES, num_moments, state)?;
+        self.timestep.set(state.hyper_param("timestep")?);
+        for (i, param) in params.iter().enumerate() {
+            let key = param_key(i, "last_step");
+            if state.hyper_params.contains_key(&key) {
+                let step = self
+                    .last_steps
+                    .get_or_insert_with(param, |_| GlobalStep::new());
+                step.set(state.hyper_param(&key)?);
+            }
+            let key = param_key(i, "no_decay");
+            if state.hyper_params.contains_key(&key) {
+                let param = super::StateKey((*param).clone());
+                if state.hyper_param::<u8>(&key)? != 0 {
+                    self.no_decay.insert(param);
+                } else {
+                    self.no_decay.remove(&param);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-/// Holds Adam's static parameters (`alpha`, `eps`, `b1`, `b2`)
+/// Holds Adam's static parameters (`eps`, `b1`, `b2`)
 #[derive(Copy, Clone)]
 #[doc(hidden)]
 pub struct StaticParams<T: Float> {
-    pub alpha: T,
     pub eps: T,
     pub b1: T,
     pub b2: T,
 }
-
-/// Wrapper of state objects in Adam's computation (`m` and `v`)
-#[derive(Clone)]
-#[doc(hidden)]
-pub struct StatefulParams<T: Float> {
-    pub m: Tensor<T>,
-    pub v: Tensor<T>,
-}
@@ -1,49 +1,355 @@
 //! Provides gradient descent optimizers.
 extern crate ndarray;
 
+pub mod adadelta;
+pub mod adagrad;
 pub mod adam;
+pub mod momentum_sgd;
+pub mod rmsprop;
+pub mod schedules;
 #[allow(dead_code)]
 pub mod sgd;
+pub mod state;
 
+pub use self::adadelta::Adadelta;
+pub use self::adagrad::Adagrad;
 pub use self::adam::Adam;
-pub use self::sgd::SGD;
+pub use self::momentum_sgd::MomentumSGD;
+pub use self::rmsprop::RMSProp;
+pub use self::schedules::{
+    CosineWithWarmRestarts, ExponentialDecay, GlobalStep, LearningRateSchedule, LinearWarmup,
+    OneCycle, StepDecay,
+};
+pub use self::sgd::{ScheduledSGD, SGD};
+pub use self::state::OptimizerState;
 
+use crate::ndarray_ext::{NdArray, NdArrayView, NdArrayViewMut};
+use crate::op;
+use crate::runtime::OpComputeContext;
 use crate::tensor::Tensor;
 use crate::Float;
 use std::cmp::{Eq, Ordering, PartialEq};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Returns `(values, Some(indices))` if `grad` has `IndexedSlices`, otherwise `(grad, None)`.
+fn split_grad<T: Float>(grad: &Tensor<T>) -> (&Tensor<T>, Option<&Tensor<T>>) {
+    match grad.indexed_slices {
+        Some(ref s) => (&s.values, Some(&s.indices)),
+        None => (grad, None),
+    }
+}
+
+/// Sums up the rows of `values` with the same index.
+///
+/// Returns the unique indices in ascending order and the summed rows.
+/// Negative indices count from the end of the `num_rows` rows.
+fn unique_rows<T: Float>(
+    indices: &NdArrayView<T>,
+    values: &NdArrayView<T>,
+    num_rows: usize,
+) -> (Vec<usize>, NdArray<T>) {
+    let mut map = BTreeMap::new();
+    for (k, &i) in indices.iter().enumerate() {
+        let i = i.to_isize().unwrap();
+        let i = if i < 0 { num_rows as isize + i } else { i } as usize;
+        map.entry(i).or_insert_with(Vec::new).push(k);
+    }
+    let mut shape = values.shape().to_vec();
+    shape[0] = map.len();
+    let mut rows = NdArray::zeros(shape);
+    for (mut row, ks) in rows.outer_iter_mut().zip(map.values()) {
+        for &k in ks {
+            row += &values.index_axis(ndarray::Axis(0), k);
+        }
+    }
+    (map.into_keys().collect(), rows)
+}
+
+/// Builds an update op whose inputs are `[param, grad, slots.., indices?]`.
+///
+/// `slots` are the state variables of `param`. See also `apply_update`.
+fn build_update<T: Float, O: op::Op<T> + 'static>(
+    param: &Tensor<T>,
+    grad: &Tensor<T>,
+    slots: &[Tensor<T>],
+    op: O,
+) -> Tensor<T> {
+    let (grad, indices) = split_grad(grad);
+    let mut inputs = vec![param, grad];
+    inputs.extend(slots);
+    inputs.extend(indices);
+    Tensor::builder().set_inputs(inputs).build(op)
+}
+
+/// Calls `update` with the views of `[var, slots..]` and the gradient in an op built
+/// by `build_update`.
+///
+/// If the gradient is `IndexedSlices`, `update` is called for each touched row.
+fn apply_update<T: Float, F>(ctx: &mut OpComputeContext<T>, num_slots: usize, mut update: F)
+where
+    F: FnMut(&mut [NdArrayViewMut<T>], NdArrayView<T>),
+{
+    let arrays = std::iter::once(0)
+        .chain(2..2 + num_slots)
+        .map(|i| ctx.input_variable_mut(i))
+        .collect::<Option<Vec<_>>>();
+    let xs = ctx.grab_inputs();
+    if let Some(mut arrays) = arrays {
+        if let Some(indices) = xs.get(2 + num_slots) {
+            let (rows, grad) = unique_rows(indices, &xs[1], arrays[0].shape()[0]);
+            for (&i, g) in rows.iter().zip(grad.outer_iter()) {
+                let mut views = arrays
+                    .iter_mut()
+                    .map(|a| a.index_axis_mut(ndarray::Axis(0), i))
+                    .collect::<Vec<_>>();
+                update(&mut views, g);
+            }
+        } else {
+            let mut views = arrays.iter_mut().map(|a| a.view_mut()).collect::<Vec<_>>();
+            update(&mut views, xs[1].view());
+        }
+    }
+}
+
+/// Creates `num` zero-filled state variables shaped like `arr`.
+fn zero_slots<T: Float>(arr: &NdArray<T>, num: usize) -> Vec<Tensor<T>> {
+    (0..num)
+        .map(|_| crate::ops::variable(NdArray::zeros(arr.shape())))
+        .collect()
+}
+
+/// Common interface of the gradient descent optimizers.
+///
+/// Stateful optimizers own their state tensors (e.g. Adam's moments) per variable,
+/// so training code can be generic over optimizers.
+///
+/// ```
+/// extern crate autograd as ag;
+/// use ag::gradient_descent_ops::{Adam, Optimizer, SGD};
+///
+/// fn train_step<O: Optimizer<f32>>(opt: &O, w: &ag::Tensor<f32>) -> Vec<ag::Tensor<f32>> {
+///     let ref loss = ag::reduce_sum(&ag::square(w), &[0, 1], false);
+///     let grads = ag::grad(&[loss], &[w]);
+///     opt.compute_updates(&[w], &grads)
+/// }
+///
+/// let ref w = ag::variable(ag::ndarray_ext::standard_normal::<f32>(&[2, 2]));
+/// ag::eval(&train_step(&SGD::new(0.1), w), &[]);
+/// ag::eval(&train_step(&Adam::default(), w), &[]);
+/// ```
+pub trait Optimizer<T: Float> {
+    /// Creates ops to update `params` with `grads`.
+    ///
+    /// `params` must be variables.
+    /// Gradients having `IndexedSlices` update only the touched rows.
+    /// Evaluated results of the return values will be `None`.
+    fn compute_updates<A: AsRef<Tensor<T>>>(
+        &self,
+        params: &[&Tensor<T>],
+        grads: &[A],
+    ) -> Vec<Tensor<T>>;
+
+    /// Returns the hyper-parameters and the state arrays of `params`.
+    ///
+    /// Learning rate schedules are not included; save the count of their `GlobalStep`
+    /// separately.
+    fn state(&self, params: &[&Tensor<T>]) -> OptimizerState<T>;
+
+    /// Restores `state` returned by `state` with the same order of `params`.
+    ///
+    /// Restored hyper-parameters take effect in the update ops made after this call.
+    /// The arrays are copied into the existing state variables if any.
+    /// Returns `InvalidData` if a hyper-parameter or a state array in use is missing.
+    fn load_state(&mut self, params: &[&Tensor<T>], state: &OptimizerState<T>) -> io::Result<()>;
+
+    /// Writes the state of `params` (see `state`) to `path`.
+    fn save_state<P: AsRef<Path>>(&self, params: &[&Tensor<T>], path: P) -> io::Result<()> {
+        self.state(params).save(path)
+    }
+
+    /// Reads the state of `params` written by `save_state` from `path`.
+    ///
+    /// Together with the variables, training resumes exactly where it was saved.
+    ///
+    /// ```
+    /// extern crate autograd as ag;
+    /// use ag::gradient_descent_ops::{Adam, Optimizer};
+    ///
+    /// let ref w = ag::variable(ag::ndarray_ext::zeros::<f32>(&[3]));
+    /// let adam = Adam::default();
+    /// let update = adam.compute_updates(&[w], &[ag::ones(&[3])]);
+    /// ag::eval(&update, &[]);
+    /// let path = std::env::temp_dir().join("autograd_doctest_adam_state");
+    /// adam.save_state(&[w], &path).unwrap();
+    ///
+    /// // Resume training with a new optimizer.
+    /// let mut resumed = Adam::default();
+    /// resumed.restore_state(&[w], &path).unwrap();
+    /// assert_eq!(resumed.timestep().get(), 1);
+    /// ```
+    fn restore_state<P: AsRef<Path>>(&mut self, params: &[&Tensor<T>], path: P) -> io::Result<()> {
+        self.load_state(params, &OptimizerState::load(path)?)
+    }
+}
+
+/// Adds the state variables of `params` named `names` to `state`.
+fn save_slots<T: Float>(
+    states: &StateMap<T, Vec<Tensor<T>>>,
+    params: &[&Tensor<T>],
+    names: &[&str],
+    state: &mut OptimizerState<T>,
+) {
+    for (i, param) in params.iter().enumerate() {
+        for (slot, name) in states.get(param).unwrap_or_default().iter().zip(names) {
+            let arr = slot.get_persistent_array().unwrap().clone();
+            state.arrays.insert(state::param_key(i, name), arr);
+        }
+    }
+}
+
+/// Restores the state variables of `params` saved by `save_slots`.
+///
+/// The first `num_required` of `names` must be in `state`; the rest are optional.
+fn load_slots<T: Float>(
+    states: &StateMap<T, Vec<Tensor<T>>>,
+    params: &[&Tensor<T>],
+    names: &[&str],
+    num_required: usize,
+    state: &OptimizerState<T>,
+) -> io::Result<()> {
+    for (i, param) in params.iter().enumerate() {
+        let param_arr = param.get_persistent_array().unwrap();
+        let shape = param_arr.shape();
+        let mut arrays = Vec::new();
+        for (k, key) in names
+            .iter()
+            .map(|name| state::param_key(i, name))
+            .enumerate()
+        {
+            match state.arrays.get(&key) {
+                Some(arr) if arr.shape() != shape => {
+                    let msg = format!(
+                        "Shape mismatch of {}: {:?} vs {:?}",
+                        key,
+                        shape,
+                        arr.shape()
+                    );
+                    return Err(crate::checkpoint::invalid_data(msg));
+                }
+                Some(arr) => arrays.push((key, arr)),
+                None if k >= num_required => break,
+                None => {
+                    let msg = format!("Missing state array: {}", key);
+                    return Err(crate::checkpoint::invalid_data(msg));
+                }
+            }
+        }
+        if arrays.is_empty() {
+            continue;
+        }
+        let slots = states.get_or_insert_with(param, |_| Vec::new());
+        for (slot, (key, arr)) in slots.iter().zip(&arrays) {
+            state::assign(slot, arr, key)?;
+        }
+        if slots.len() < arrays.len() {
+            states.modify(param, |slots| {
+                let n = slots.len();
+                slots.extend(
+                    arrays[n..]
+                        .iter()
+                        .map(|(_, a)| crate::ops::variable((*a).clone())),
+                );
+            });
+        }
+    }
+    Ok(())
+}
 
 #[doc(hidden)]
 /// Key to access a state tensor.
 /// Stateful optimizers use this.
-pub struct StateKey<'a, T: Float + 'a>(pub &'a Tensor<T>);
+pub struct StateKey<T: Float>(pub Tensor<T>);
 
-impl<'a, T: Float> Eq for StateKey<'a, T> {}
+impl<T: Float> Eq for StateKey<T> {}
 
-impl<'a, T: Float> PartialEq for StateKey<'a, T> {
+impl<T: Float> PartialEq for StateKey<T> {
     #[inline]
-    /// Compares addresses of the two tensors.
+    /// Compares ids of the two tensors.
     /// This can be used for ordering-based data structures (e.g. BinaryTree).
-    fn eq(&self, other: &StateKey<'a, T>) -> bool {
-        (self.0 as *const _) == (other.0 as *const _)
+    fn eq(&self, other: &StateKey<T>) -> bool {
+        self.0.id() == other.0.id()
     }
 }
 
-impl<'a, T: Float> Ord for StateKey<'a, T> {
+impl<T: Float> Ord for StateKey<T> {
     #[inline]
-    /// Compares addresses of the two tensors.
+    /// Compares ids of the two tensors.
     /// This can be used for ordering-based data structures (e.g. BinaryTree).
     fn cmp(&self, other: &Self) -> Ordering {
-        let a = self.0 as *const Tensor<T>;
-        let b = other.0 as *const Tensor<T>;
-        a.cmp(&b)
+        self.0.id().cmp(&other.0.id())
     }
 }
 
-impl<'a, T: Float> PartialOrd for StateKey<'a, T> {
+impl<T: Float> PartialOrd for StateKey<T> {
     #[inline]
-    /// Compares addresses of the two tensors.
+    /// Compares ids of the two tensors.
     /// This can be used for ordering-based data structures (e.g. BinaryTree).
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
+
+/// State tensors of a stateful optimizer for each variable.
+struct StateMap<T: Float, S: Clone> {
+    map: Mutex<BTreeMap<StateKey<T>, S>>,
+}
+
+impl<T: Float, S: Clone> StateMap<T, S> {
+    fn new() -> StateMap<T, S> {
+        StateMap {
+            map: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the state of `var`, which is made by `init` from the array of `var` at first.
+    fn get_or_insert_with<F: FnOnce(&NdArray<T>) -> S>(&self, var: &Tensor<T>, init: F) -> S {
+        let arr = var
+            .get_persistent_array()
+            .expect("Can't optimize non-variable.");
+        let mut map = self.map.lock().unwrap();
+        map.entry(StateKey(var.clone()))
+            .or_insert_with(|| init(&arr))
+            .clone()
+    }
+
+    /// Returns the state of `var` if exists.
+    fn get(&self, var: &Tensor<T>) -> Option<S> {
+        let map = self.map.lock().unwrap();
+        map.get(&StateKey(var.clone())).cloned()
+    }
+
+    /// Modifies the state of `var` inserted by `get_or_insert_with`, and returns it.
+    fn modify<F: FnOnce(&mut S)>(&self, var: &Tensor<T>, f: F) -> S {
+        let mut map = self.map.lock().unwrap();
+        let state = map
+            .get_mut(&StateKey(var.clone()))
+            .expect("No state for the variable.");
+        f(state);
+        state.clone()
+    }
+}
+
+#[test]
+fn test_unique_rows() {
+    let indices = ndarray::arr1(&[3., -1., 0., 3.]).into_dyn();
+    let values = ndarray::arr2(&[[1., 2.], [3., 4.], [5., 6.], [7., 8.]]).into_dyn();
+    let (rows, summed) = unique_rows(&indices.view(), &values.view(), 5);
+    assert_eq!(rows, vec![0, 3, 4]);
+    assert_eq!(
+        summed,
+        ndarray::arr2(&[[5., 6.], [8., 10.], [3., 4.]]).into_dyn()
+    );
+}
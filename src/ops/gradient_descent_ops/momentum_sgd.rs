@@ -0,0 +1,150 @@
+//! Module defining momentum SGD optimizer
+use super::schedules::LearningRate;
+use crate::op;
+use crate::tensor::Tensor;
+use crate::Float;
+use ndarray::Zip;
+use std::io;
+
+// Names of the state variables in `OptimizerState`
+const SLOT_NAMES: [&str; 1] = ["velocity"];
+
+struct MomentumSGDOp<T: Float> {
+    lr: LearningRate<T>,
+    momentum: T,
+    nesterov: bool,
+}
+
+impl<T: Float> crate::op::Op<T> for MomentumSGDOp<T> {
+    fn name(&self) -> &str {
+        "MomentumSGD"
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
+    fn compute<'v>(
+        &self,
+        mut ctx: crate::runtime::OpComputeContext<'v, T>,
+    ) -> op::ComputeResults<'v, T> {
+        let lr = self.lr.get();
+        let (momentum, nesterov) = (self.momentum, self.nesterov);
+        super::apply_update(&mut ctx, 1, |views, g| {
+            if let [var, v] = views {
+                Zip::from(var).and(v).and(&g).apply(|var, v, &g| {
+                    *v = momentum * *v + g;
+                    if nesterov {
+                        *var -= lr * (g + momentum * *v);
+                    } else {
+                        *var -= lr * *v;
+                    }
+                });
+            }
+        });
+        vec![Err(crate::op::ComputeException::NoOutput)]
+    }
+
+    fn grad(&self, _: &Tensor<T>, _: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        vec![None]
+    }
+}
+
+/// SGD optimizer with momentum
+///
+/// Updates `v = momentum * v + grad` and `var -= lr * v`.
+/// With `nesterov`, the variable is updated by `lr * (grad + momentum * v)` instead.
+///
+/// ```
+/// extern crate autograd as ag;
+/// use ag::gradient_descent_ops::Optimizer;
+///
+/// let ref w = ag::variable(ag::ndarray_ext::glorot_uniform::<f32>(&[28 * 28, 10]));
+/// let ref loss = ag::reduce_sum(&ag::square(w), &[0, 1], false);
+/// let grads = ag::grad(&[loss], &[w]);
+///
+/// let opt = ag::gradient_descent_ops::MomentumSGD::new(0.01, 0.9, true);
+/// let update_ops = opt.compute_updates(&[w], &grads);
+/// ag::eval(&update_ops, &[]);
+/// ```
+pub struct MomentumSGD<T: Float> {
+    /// Learning rate
+    pub lr: T,
+    pub momentum: T,
+    /// Uses Nesterov momentum
+    pub nesterov: bool,
+    // Velocity of each variable
+    states: super::StateMap<T, Vec<Tensor<T>>>,
+    schedule: Option<LearningRate<T>>,
+}
+
+impl<T: Float> MomentumSGD<T> {
+    pub fn new(lr: T, momentum: T, nesterov: bool) -> MomentumSGD<T> {
+        MomentumSGD {
+            lr,
+            momentum,
+            nesterov,
+            states: super::StateMap::new(),
+            schedule: None,
+        }
+    }
+
+    /// Makes the learning rate follow `schedule` of `step`.
+    ///
+    /// Update ops read the rate when they are evaluated; the ones made before this
+    /// call keep the previous rate.
+    pub fn set_lr_schedule<S>(&mut self, schedule: S, step: &super::GlobalStep)
+    where
+        S: super::LearningRateSchedule<T> + 'static,
+    {
+        self.schedule = Some(LearningRate::scheduled(schedule, step));
+    }
+}
+
+impl<T: Float> super::Optimizer<T> for MomentumSGD<T> {
+    fn compute_updates<A: AsRef<Tensor<T>>>(
+        &self,
+        params: &[&Tensor<T>],
+        grads: &[A],
+    ) -> Vec<Tensor<T>> {
+        params
+            .iter()
+            .zip(grads)
+            .map(|(param, grad)| {
+                let slots = self
+                    .states
+                    .get_or_insert_with(param, |arr| super::zero_slots(arr, 1));
+                super::build_update(
+                    param,
+                    grad.as_ref(),
+                    &slots,
+                    MomentumSGDOp {
+                        lr: LearningRate::new(self.lr, &self.schedule),
+                        momentum: self.momentum,
+                        nesterov: self.nesterov,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn state(&self, params: &[&Tensor<T>]) -> super::OptimizerState<T> {
+        let mut state = super::OptimizerState::new();
+        state.set_hyper_param("lr", self.lr);
+        state.set_hyper_param("momentum", self.momentum);
+        state.set_hyper_param("nesterov", self.nesterov as u8);
+        super::save_slots(&self.states, params, &SLOT_NAMES, &mut state);
+        state
+    }
+
+    fn load_state(
+        &mut self,
+        params: &[&Tensor<T>],
+        state: &super::OptimizerState<T>,
+    ) -> io::Result<()> {
+        self.lr = state.hyper_param("lr")?;
+        self.momentum = state.hyper_param("momentum")?;
+        self.nesterov = state.hyper_param::<u8>("nesterov")? != 0;
+        super::load_slots(&self.states, params, &SLOT_NAMES, SLOT_NAMES.len(), state)
+    }
+}
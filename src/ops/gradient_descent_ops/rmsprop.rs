@@ -0,0 +1,202 @@
+//! Module defining RMSProp optimizer
+use super::schedules::LearningRate;
+use crate::op;
+use crate::tensor::Tensor;
+use crate::Float;
+use ndarray::Zip;
+use std::io;
+
+// Names of the state variables in `OptimizerState`
+const SLOT_NAMES: [&str; 3] = ["ms", "mom", "mg"];
+
+struct RMSPropOp<T: Float> {
+    lr: LearningRate<T>,
+    rho: T,
+    momentum: T,
+    eps: T,
+    centered: bool,
+}
+
+impl<T: Float> crate::op::Op<T> for RMSPropOp<T> {
+    fn name(&self) -> &str {
+        "RMSProp"
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
+    fn compute<'v>(
+        &self,
+        mut ctx: crate::runtime::OpComputeContext<'v, T>,
+    ) -> op::ComputeResults<'v, T> {
+        let lr = self.lr.get();
+        let (rho, momentum, eps, centered) = (self.rho, self.momentum, self.eps, self.centered);
+        let num_slots = if centered { 3 } else { 2 };
+        super::apply_update(&mut ctx, num_slots, |views, g| match views {
+            [var, ms, mom] => Zip::from(var)
+                .and(ms)
+                .and(mom)
+                .and(&g)
+                .apply(|var, ms, mom, &g| {
+                    *ms = rho * *ms + (T::one() - rho) * g * g;
+                    *mom = momentum * *mom + lr * g / (*ms + eps).sqrt();
+                    *var -= *mom;
+                }),
+            [var, ms, mom, mg] => {
+                Zip::from(var)
+                    .and(ms)
+                    .and(mom)
+                    .and(mg)
+                    .and(&g)
+                    .apply(|var, ms, mom, mg, &g| {
+                        *ms = rho * *ms + (T::one() - rho) * g * g;
+                        *mg = rho * *mg + (T::one() - rho) * g;
+                        *mom = momentum * *mom + lr * g / (*ms - *mg * *mg + eps).sqrt();
+                        *var -= *mom;
+                    })
+            }
+            _ => unreachable!(),
+        });
+        vec![Err(crate::op::ComputeException::NoOutput)]
+    }
+
+    fn grad(&self, _: &Tensor<T>, _: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        vec![None]
+    }
+}
+
+/// RMSProp optimizer
+///
+/// Divides the gradient by the root of the moving average of its square.
+/// The `centered` version also subtracts the squared moving average of the gradient,
+/// i.e. normalizes by the estimated variance.
+///
+/// ```
+/// extern crate autograd as ag;
+/// use ag::gradient_descent_ops::Optimizer;
+///
+/// let ref w = ag::variable(ag::ndarray_ext::glorot_uniform::<f32>(&[28 * 28, 10]));
+/// let ref loss = ag::reduce_sum(&ag::square(w), &[0, 1], false);
+/// let grads = ag::grad(&[loss], &[w]);
+///
+/// let mut opt = ag::gradient_descent_ops::RMSProp::default();
+/// opt.centered = true;
+/// let update_ops = opt.compute_updates(&[w], &grads);
+/// ag::eval(&update_ops, &[]);
+/// ```
+pub struct RMSProp<T: Float> {
+    /// Learning rate
+    pub lr: T,
+    /// Decay rate of the moving averages
+    pub rho: T,
+    pub momentum: T,
+    pub eps: T,
+    pub centered: bool,
+    // Mean square, momentum and mean gradient (if centered) of each variable
+    states: super::StateMap<T, Vec<Tensor<T>>>,
+    schedule: Option<LearningRate<T>>,
+}
+
+impl<T: Float> Default for RMSProp<T> {
+    /// Instantiates `RMSProp` optimizer with `lr = 0.001`, `rho = 0.9`,
+    /// no momentum and no centering.
+    fn default() -> RMSProp<T> {
+        RMSProp::new(
+            T::from(0.001).unwrap(),
+            T::from(0.9).unwrap(),
+            T::zero(),
+            T::from(1e-07).unwrap(),
+            false,
+        )
+    }
+}
+
+impl<T: Float> RMSProp<T> {
+    pub fn new(lr: T, rho: T, momentum: T, eps: T, centered: bool) -> RMSProp<T> {
+        RMSProp {
+            lr,
+            rho,
+            momentum,
+            eps,
+            centered,
+            states: super::StateMap::new(),
+            schedule: None,
+        }
+    }
+
+    /// Makes the learning rate follow `schedule` of `step`.
+    ///
+    /// Update ops read the rate when they are evaluated; the ones made before this
+    /// call keep the previous rate.
+    pub fn set_lr_schedule<S>(&mut self, schedule: S, step: &super::GlobalStep)
+    where
+        S: super::LearningRateSchedule<T> + 'static,
+    {
+        self.schedule = Some(LearningRate::scheduled(schedule, step));
+    }
+}
+
+impl<T: Float> super::Optimizer<T> for RMSProp<T> {
+    fn compute_updates<A: AsRef<Tensor<T>>>(
+        &self,
+        params: &[&Tensor<T>],
+        grads: &[A],
+    ) -> Vec<Tensor<T>> {
+        params
+            .iter()
+            .zip(grads)
+            .map(|(param, grad)| {
+                let mut slots = self
+                    .states
+                    .get_or_insert_with(param, |arr| super::zero_slots(arr, 2));
+                if self.centered && slots.len() == 2 {
+                    // The mean gradient is made lazily, so `centered` can be turned on later.
+                    slots = self.states.modify(param, |slots| {
+                        let arr = param.get_persistent_array().unwrap();
+                        slots.extend(super::zero_slots(&arr, 1))
+                    });
+                }
+                // A mean gradient left by `centered` is kept but not updated.
+                let num_slots = if self.centered { 3 } else { 2 };
+                super::build_update(
+                    param,
+                    grad.as_ref(),
+                    &slots[..num_slots],
+                    RMSPropOp {
+                        lr: LearningRate::new(self.lr, &self.schedule),
+                        rho: self.rho,
+                        momentum: self.momentum,
+                        eps: self.eps,
+                        centered: self.centered,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn state(&self, params: &[&Tensor<T>]) -> super::OptimizerState<T> {
+        let mut state = super::OptimizerState::new();
+        state.set_hyper_param("lr", self.lr);
+        state.set_hyper_param("rho", self.rho);
+        state.set_hyper_param("momentum", self.momentum);
+        state.set_hyper_param("eps", self.eps);
+        state.set_hyper_param("centered", self.centered as u8);
+        super::save_slots(&self.states, params, &SLOT_NAMES, &mut state);
+        state
+    }
+
+    fn load_state(
+        &mut self,
+        params: &[&Tensor<T>],
+        state: &super::OptimizerState<T>,
+    ) -> io::Result<()> {
+        self.lr = state.hyper_param("lr")?;
+        self.rho = state.hyper_param("rho")?;
+        self.momentum = state.hyper_param("momentum")?;
+        self.eps = state.hyper_param("eps")?;
+        self.centered = state.hyper_param::<u8>("centered")? != 0;
+        let num_slots = if self.centered { 3 } else { 2 };
+        super::load_slots(&self.states, params, &SLOT_NAMES, num_slots, state)
+    }
+}
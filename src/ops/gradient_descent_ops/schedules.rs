@@ -0,0 +1,359 @@
+//! Module defining learning rate schedules and the global step counter
+use crate::op;
+use crate::tensor::Tensor;
+use crate::Float;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Counter of training steps.
+///
+/// The count is an integer shared by the clones of this, so it's exact however long
+/// the training runs. It isn't a variable; save `get()` along with a checkpoint and
+/// `set` it back to resume. Optimizers with a schedule (see `LearningRateSchedule`)
+/// read it when their update ops are evaluated.
+///
+/// ```
+/// extern crate autograd as ag;
+/// use ag::gradient_descent_ops::{GlobalStep, LearningRateSchedule, Optimizer, ScheduledSGD, StepDecay};
+///
+/// let ref w = ag::variable(ag::ndarray_ext::zeros::<f32>(&[3]));
+/// let ref loss = ag::reduce_sum(&(w - 1.), &[0], false);
+/// let grads = ag::grad(&[loss], &[w]);
+///
+/// let step = GlobalStep::new();
+/// let schedule = StepDecay { initial_lr: 0.1, decay_rate: 0.5, step_size: 10 };
+/// let sgd = ScheduledSGD::new(schedule.clone(), &step);
+///
+/// // The increment comes after the update ops.
+/// let mut train_ops = sgd.compute_updates(&[w], &grads);
+/// train_ops.push(step.increment());
+/// for _ in 0..20 {
+///     ag::eval(&train_ops, &[]);
+/// }
+/// assert_eq!(step.get(), 20);
+/// assert_eq!(schedule.lr(step.get()), 0.025);
+/// ```
+#[derive(Clone, Default)]
+pub struct GlobalStep {
+    count: Arc<AtomicUsize>,
+}
+
+impl GlobalStep {
+    /// Creates a counter starting from zero.
+    pub fn new() -> GlobalStep {
+        GlobalStep::default()
+    }
+
+    /// Returns the current count.
+    pub fn get(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Sets the count, e.g. to resume training from a checkpoint.
+    pub fn set(&self, step: usize) {
+        self.count.store(step, Ordering::SeqCst)
+    }
+
+    /// Returns an op which increments the count.
+    ///
+    /// Side effects are evaluated in order, so put this after the update ops in
+    /// the evaluation targets. Evaluated result of the return value will be `None`.
+    pub fn increment<T: Float>(&self) -> Tensor<T> {
+        Tensor::builder().build(IncrementStep {
+            count: self.count.clone(),
+        })
+    }
+}
+
+struct IncrementStep {
+    count: Arc<AtomicUsize>,
+}
+
+impl<T: Float> op::Op<T> for IncrementStep {
+    fn name(&self) -> &str {
+        "IncrementStep"
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
+    fn compute<'v>(&self, _: crate::runtime::OpComputeContext<'v, T>) -> op::ComputeResults<'v, T> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        vec![Err(op::ComputeException::NoOutput)]
+    }
+
+    fn grad(&self, _: &Tensor<T>, _: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        vec![]
+    }
+}
+
+/// Learning rate as a function of the global step.
+pub trait LearningRateSchedule<T: Float>: Send + Sync {
+    /// Returns the learning rate at `step` (zero-based).
+    fn lr(&self, step: usize) -> T;
+}
+
+/// Learning rate of an update op, which is read at evaluation time if scheduled.
+#[derive(Clone)]
+pub(super) enum LearningRate<T: Float> {
+    Constant(T),
+    Scheduled(Arc<dyn LearningRateSchedule<T>>, GlobalStep),
+}
+
+impl<T: Float> LearningRate<T> {
+    /// `lr` unless `schedule` is set.
+    pub(super) fn new(lr: T, schedule: &Option<LearningRate<T>>) -> LearningRate<T> {
+        match schedule {
+            Some(s) => s.clone(),
+            None => LearningRate::Constant(lr),
+        }
+    }
+
+    pub(super) fn scheduled<S>(schedule: S, step: &GlobalStep) -> LearningRate<T>
+    where
+        S: LearningRateSchedule<T> + 'static,
+    {
+        LearningRate::Scheduled(Arc::new(schedule), step.clone())
+    }
+
+    /// Returns the current value.
+    pub(super) fn get(&self) -> T {
+        match self {
+            LearningRate::Constant(lr) => *lr,
+            LearningRate::Scheduled(schedule, step) => schedule.lr(step.get()),
+        }
+    }
+}
+
+/// Multiplies the learning rate by `decay_rate` every `step_size` steps.
+///
+/// `step_size` of zero is taken as 1.
+#[derive(Clone, Debug)]
+pub struct StepDecay<T: Float> {
+    pub initial_lr: T,
+    pub decay_rate: T,
+    pub step_size: usize,
+}
+
+impl<T: Float> LearningRateSchedule<T> for StepDecay<T> {
+    fn lr(&self, step: usize) -> T {
+        self.initial_lr * self.decay_rate.powi((step / self.step_size.max(1)) as i32)
+    }
+}
+
+/// Decays the learning rate by `decay_rate` per `decay_steps` steps continuously,
+/// or stepwise if `staircase`.
+///
+/// `decay_steps` of zero is taken as 1.
+#[derive(Clone, Debug)]
+pub struct ExponentialDecay<T: Float> {
+    pub initial_lr: T,
+    pub decay_rate: T,
+    pub decay_steps: usize,
+    pub staircase: bool,
+}
+
+impl<T: Float> LearningRateSchedule<T> for ExponentialDecay<T> {
+    fn lr(&self, step: usize) -> T {
+        let mut p = step as f64 / self.decay_steps.max(1) as f64;
+        if self.staircase {
+            p = p.floor();
+        }
+        self.initial_lr * self.decay_rate.powf(T::from(p).unwrap())
+    }
+}
+
+/// Cosine annealing with warm restarts (SGDR, https://arxiv.org/abs/1608.03983).
+///
+/// The learning rate goes from `max_lr` down to `min_lr` along a half cosine in
+/// `first_period` steps, and then restarts with the period multiplied by `period_mult`.
+/// Periods and multipliers of zero are taken as 1.
+#[derive(Clone, Debug)]
+pub struct CosineWithWarmRestarts<T: Float> {
+    pub max_lr: T,
+    pub min_lr: T,
+    pub first_period: usize,
+    pub period_mult: usize,
+}
+
+impl<T: Float> LearningRateSchedule<T> for CosineWithWarmRestarts<T> {
+    fn lr(&self, step: usize) -> T {
+        let first = self.first_period.max(1);
+        let (t, period) = if self.period_mult <= 1 {
+            ((step % first) as f64, first as f64)
+        } else {
+            // The `k`-th period starts at `first * (mult^k - 1) / (mult - 1)`.
+            let (first, mult, step) = (first as f64, self.period_mult as f64, step as f64);
+            let start = |k: f64| first * (mult.powf(k) - 1.) / (mult - 1.);
+            let mut k = (step * (mult - 1.) / first + 1.).log(mult).floor();
+            // Corrects rounding errors of the logarithm.
+            if start(k) > step {
+                k -= 1.;
+            } else if start(k + 1.) <= step {
+                k += 1.;
+            }
+            (step - start(k), first * mult.powf(k))
+        };
+        anneal_cos(self.max_lr, self.min_lr, t / period)
+    }
+}
+
+/// Increases the learning rate linearly for `warmup_steps` steps up to the start of
+/// `schedule`, which follows.
+///
+/// The learning rate at step `i < warmup_steps` is `(i + 1) / warmup_steps` times
+/// `schedule.lr(0)`, and `schedule.lr(i - warmup_steps)` after that.
+#[derive(Clone, Debug)]
+pub struct LinearWarmup<S> {
+    pub warmup_steps: usize,
+    pub schedule: S,
+}
+
+impl<T: Float, S: LearningRateSchedule<T>> LearningRateSchedule<T> for LinearWarmup<S> {
+    fn lr(&self, step: usize) -> T {
+        if step < self.warmup_steps {
+            let ratio = T::from(step + 1).unwrap() / T::from(self.warmup_steps).unwrap();
+            self.schedule.lr(0) * ratio
+        } else {
+            self.schedule.lr(step - self.warmup_steps)
+        }
+    }
+}
+
+/// The one-cycle policy (https://arxiv.org/abs/1708.07120).
+///
+/// The learning rate goes from `max_lr / div_factor` up to `max_lr` in the first
+/// `pct_start` of `total_steps`, and then down to `max_lr / div_factor / final_div_factor`,
+/// both along half cosines. It stays at the last value after `total_steps`.
+#[derive(Clone, Debug)]
+pub struct OneCycle<T: Float> {
+    pub max_lr: T,
+    pub total_steps: usize,
+    pub pct_start: f64,
+    pub div_factor: T,
+    pub final_div_factor: T,
+}
+
+impl<T: Float> OneCycle<T> {
+    /// Creates a schedule with `pct_start = 0.3`, `div_factor = 25` and `final_div_factor = 1e4`.
+    pub fn new(max_lr: T, total_steps: usize) -> OneCycle<T> {
+        OneCycle {
+            max_lr,
+            total_steps,
+            pct_start: 0.3,
+            div_factor: T::from(25.).unwrap(),
+            final_div_factor: T::from(1e4).unwrap(),
+        }
+    }
+}
+
+impl<T: Float> LearningRateSchedule<T> for OneCycle<T> {
+    fn lr(&self, step: usize) -> T {
+        let initial_lr = self.max_lr / self.div_factor;
+        let min_lr = initial_lr / self.final_div_factor;
+        let last = self.total_steps.saturating_sub(1).max(1) as f64;
+        let peak = (self.pct_start * last).max(1.);
+        let step = (step as f64).min(last);
+        if step <= peak {
+            anneal_cos(initial_lr, self.max_lr, step / peak)
+        } else {
+            anneal_cos(self.max_lr, min_lr, (step - peak) / (last - peak))
+        }
+    }
+}
+
+// Goes from `start` (pct = 0) to `end` (pct = 1) along a half cosine.
+fn anneal_cos<T: Float>(start: T, end: T, pct: f64) -> T {
+    let cos = T::from((PI * pct).cos() + 1.).unwrap();
+    end + (start - end) * cos / T::from(2.).unwrap()
+}
+
+#[test]
+fn test_schedules() {
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+
+    let exp = ExponentialDecay {
+        initial_lr: 1.,
+        decay_rate: 0.5,
+        decay_steps: 2,
+        staircase: false,
+    };
+    assert!(close(exp.lr(1), 0.5f64.sqrt()));
+
+    let cos = CosineWithWarmRestarts {
+        max_lr: 1.,
+        min_lr: 0.,
+        first_period: 2,
+        period_mult: 2,
+    };
+    let lrs = (0..7).map(|i| cos.lr(i)).collect::<Vec<f64>>();
+    assert!(close(lrs[1], 0.5));
+    assert!(close(lrs[2], 1.));
+    assert!(close(lrs[4], 0.5));
+    assert!(close(lrs[6], 1.));
+
+    let warmup = LinearWarmup {
+        warmup_steps: 4,
+        schedule: StepDecay {
+            initial_lr: 1.,
+            decay_rate: 0.1,
+            step_size: 1,
+        },
+    };
+    assert!(close(warmup.lr(0), 0.25));
+    assert!(close(warmup.lr(4), 1.));
+    assert!(close(warmup.lr(5), 0.1));
+
+    let one_cycle = OneCycle::new(1., 11);
+    assert!(close(one_cycle.lr(0), 0.04));
+    assert!(close(one_cycle.lr(3), 1.));
+    assert!(close(one_cycle.lr(10), 0.04 / 1e4));
+    assert!(close(one_cycle.lr(100), 0.04 / 1e4));
+
+    // Zero periods are taken as 1.
+    let step = StepDecay {
+        initial_lr: 1.,
+        decay_rate: 0.5,
+        step_size: 0,
+    };
+    assert!(close(step.lr(2), 0.25));
+    let exp = ExponentialDecay {
+        decay_steps: 0,
+        ..exp
+    };
+    assert!(close(exp.lr(2), 0.25));
+    let cos = CosineWithWarmRestarts {
+        first_period: 0,
+        period_mult: 0,
+        ..cos
+    };
+    assert!(close(cos.lr(3), 1.));
+
+    // Same as restarting period by period
+    let restarts = |cos: &CosineWithWarmRestarts<f64>, step| {
+        let (mut t, mut period) = (step, cos.first_period.max(1));
+        while t >= period {
+            t -= period;
+            period *= cos.period_mult.max(1);
+        }
+        anneal_cos(cos.max_lr, cos.min_lr, t as f64 / period as f64)
+    };
+    for &(first_period, period_mult) in &[(5, 1), (5, 3), (1, 2), (7, 10)] {
+        let cos = CosineWithWarmRestarts {
+            first_period,
+            period_mult,
+            ..cos
+        };
+        for i in 0..1000 {
+            assert!(close(cos.lr(i), restarts(&cos, i)));
+        }
+    }
+    let cos = CosineWithWarmRestarts {
+        first_period: 3,
+        period_mult: 1,
+        ..cos
+    };
+    assert!(close(cos.lr(usize::MAX), restarts(&cos, usize::MAX % 3)));
+}
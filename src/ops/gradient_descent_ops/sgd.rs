@@ -1,10 +1,12 @@
 //! Module defining stochastic gradient descent optimizer.
+use super::schedules::LearningRate;
 use crate::op;
 use crate::tensor::Tensor;
 use crate::Float;
+use std::io;
 
 struct SGDOp<T: Float> {
-    pub lr: T,
+    pub lr: LearningRate<T>,
 }
 
 impl<T: Float> crate::op::Op<T> for SGDOp<T> {
@@ -12,17 +14,16 @@ impl<T: Float> crate::op::Op<T> for SGDOp<T> {
         "SGD"
     }
 
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
-        ctx: crate::runtime::OpComputeContext<'v, T>,
+        mut ctx: crate::runtime::OpComputeContext<'v, T>,
     ) -> op::ComputeResults<'v, T> {
-        let xs = ctx.grab_inputs();
-        let grad = &xs[1];
-        unsafe {
-            if let Some(arr) = ctx.node(0).get_persistent_array_mut() {
-                arr.scaled_add(-self.lr, grad);
-            }
-        }
+        let lr = self.lr.get();
+        super::apply_update(&mut ctx, 0, |arrays, g| arrays[0].scaled_add(-lr, &g));
         vec![Err(crate::op::ComputeException::NoOutput)]
     }
 
@@ -35,8 +36,9 @@ impl<T: Float> crate::op::Op<T> for SGDOp<T> {
 ///
 /// ```
 /// extern crate autograd as ag;
+/// use ag::gradient_descent_ops::Optimizer;
 ///
-/// let sgd = ag::gradient_descent_ops::SGD { lr: 0.1 };
+/// let sgd = ag::gradient_descent_ops::SGD::new(0.1);
 /// // let update_ops = sgd.compute_updates(params, grads)
 /// ```
 ///
@@ -46,23 +48,81 @@ pub struct SGD<T: Float> {
     pub lr: T,
 }
 
-impl<'a, T: Float> SGD<T> {
-    /// Creates ops to optimize `params` with SGD.
-    ///
-    /// Evaluated results of the return values will be `None`.
-    pub fn compute_updates<A: AsRef<Tensor<T>>>(
+impl<T: Float> SGD<T> {
+    pub fn new(lr: T) -> SGD<T> {
+        SGD { lr }
+    }
+}
+
+impl<T: Float> super::Optimizer<T> for SGD<T> {
+    fn compute_updates<A: AsRef<Tensor<T>>>(
+        &self,
+        params: &[&Tensor<T>],
+        grads: &[A],
+    ) -> Vec<Tensor<T>> {
+        sgd_updates(&LearningRate::Constant(self.lr), params, grads)
+    }
+
+    fn state(&self, _: &[&Tensor<T>]) -> super::OptimizerState<T> {
+        let mut state = super::OptimizerState::new();
+        state.set_hyper_param("lr", self.lr);
+        state
+    }
+
+    fn load_state(&mut self, _: &[&Tensor<T>], state: &super::OptimizerState<T>) -> io::Result<()> {
+        self.lr = state.hyper_param("lr")?;
+        Ok(())
+    }
+}
+
+/// Vanilla SGD whose learning rate follows a schedule
+///
+/// Update ops read the rate when they are evaluated. See `GlobalStep`.
+pub struct ScheduledSGD<T: Float> {
+    lr: LearningRate<T>,
+}
+
+impl<T: Float> ScheduledSGD<T> {
+    /// Makes the learning rate follow `schedule` of `step`.
+    pub fn new<S>(schedule: S, step: &super::GlobalStep) -> ScheduledSGD<T>
+    where
+        S: super::LearningRateSchedule<T> + 'static,
+    {
+        ScheduledSGD {
+            lr: LearningRate::scheduled(schedule, step),
+        }
+    }
+}
+
+impl<T: Float> super::Optimizer<T> for ScheduledSGD<T> {
+    fn compute_updates<A: AsRef<Tensor<T>>>(
         &self,
-        params: &[&'a Tensor<T>],
+        params: &[&Tensor<T>],
         grads: &[A],
     ) -> Vec<Tensor<T>> {
-        params
-            .iter()
-            .zip(grads)
-            .map(|(param, grad)| {
-                Tensor::builder()
-                    .set_inputs(vec![param, grad.as_ref()])
-                    .build(SGDOp { lr: self.lr })
-            })
-            .collect()
+        sgd_updates(&self.lr, params, grads)
+    }
+
+    fn state(&self, _: &[&Tensor<T>]) -> super::OptimizerState<T> {
+        super::OptimizerState::new()
     }
+
+    fn load_state(&mut self, _: &[&Tensor<T>], _: &super::OptimizerState<T>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn sgd_updates<T: Float, A: AsRef<Tensor<T>>>(
+    lr: &LearningRate<T>,
+    params: &[&Tensor<T>],
+    grads: &[A],
+) -> Vec<Tensor<T>> {
+    params
+        .iter()
+        .zip(grads)
+        .map(|(param, grad)| {
+            let op = SGDOp { lr: lr.clone() };
+            super::build_update(param, grad.as_ref(), &[], op)
+        })
+        .collect()
 }
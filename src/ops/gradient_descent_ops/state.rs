@@ -0,0 +1,121 @@
+//! Module defining the serializable state of optimizers
+use crate::checkpoint::{
+    invalid_data, read_array, read_str, read_u64, write_array, write_str, write_u64,
+};
+use crate::ndarray_ext::NdArray;
+use crate::tensor::Tensor;
+use crate::Float;
+use num_traits::{NumCast, ToPrimitive};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"AGOPTST1";
+
+/// Hyper-parameters and state arrays (e.g. slot variables) of an optimizer.
+///
+/// See `Optimizer::state`. Arrays of the `i`-th parameter are keyed like `"{i}/m"`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptimizerState<T: Float> {
+    /// Hyper-parameters and step counts by name; flags are 0 or 1.
+    pub hyper_params: BTreeMap<String, f64>,
+    /// State arrays by name
+    pub arrays: BTreeMap<String, NdArray<T>>,
+}
+
+impl<T: Float> Default for OptimizerState<T> {
+    fn default() -> OptimizerState<T> {
+        OptimizerState::new()
+    }
+}
+
+impl<T: Float> OptimizerState<T> {
+    /// Creates an empty state.
+    pub fn new() -> OptimizerState<T> {
+        OptimizerState {
+            hyper_params: BTreeMap::new(),
+            arrays: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the hyper-parameter `name`.
+    pub fn set_hyper_param<U: ToPrimitive>(&mut self, name: &str, value: U) {
+        self.hyper_params
+            .insert(name.to_string(), value.to_f64().unwrap());
+    }
+
+    /// Returns the hyper-parameter `name`, or `InvalidData` error if it's missing or
+    /// not representable in `U` (e.g. NaN or a negative value for an integer).
+    pub fn hyper_param<U: NumCast>(&self, name: &str) -> io::Result<U> {
+        match self.hyper_params.get(name) {
+            Some(&v) => U::from(v)
+                .ok_or_else(|| invalid_data(format!("Invalid hyper-parameter: {} = {}", name, v))),
+            None => Err(invalid_data(format!("Missing hyper-parameter: {}", name))),
+        }
+    }
+
+    /// Writes this to `path` in a little-endian binary format.
+    ///
+    /// Arrays are written in the precision of `T`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        write_u64(&mut w, self.hyper_params.len() as u64)?;
+        for (name, &value) in &self.hyper_params {
+            write_str(&mut w, name)?;
+            w.write_all(&value.to_le_bytes())?;
+        }
+        write_u64(&mut w, self.arrays.len() as u64)?;
+        for (name, arr) in &self.arrays {
+            write_str(&mut w, name)?;
+            write_array(&mut w, arr)?;
+        }
+        w.flush()
+    }
+
+    /// Reads a state written by `save`, converting arrays to `T` if needed.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<OptimizerState<T>> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not an optimizer state file".to_string()));
+        }
+        let mut state = OptimizerState::new();
+        for _ in 0..read_u64(&mut r)? {
+            let name = read_str(&mut r)?;
+            let mut buf = [0; 8];
+            r.read_exact(&mut buf)?;
+            state.hyper_params.insert(name, f64::from_le_bytes(buf));
+        }
+        for _ in 0..read_u64(&mut r)? {
+            let name = read_str(&mut r)?;
+            state.arrays.insert(name, read_array(&mut r)?);
+        }
+        Ok(state)
+    }
+}
+
+/// Key of the array `name` of the `i`-th parameter
+pub(super) fn param_key(i: usize, name: &str) -> String {
+    format!("{}/{}", i, name)
+}
+
+/// Overwrites the array of variable `var` with `arr` of the same shape.
+pub(super) fn assign<T: Float>(var: &Tensor<T>, arr: &NdArray<T>, key: &str) -> io::Result<()> {
+    let mut dst = var
+        .get_persistent_array_mut()
+        .expect("Can't assign to non-variable.");
+    if dst.shape() != arr.shape() {
+        let msg = format!(
+            "Shape mismatch of {}: {:?} vs {:?}",
+            key,
+            dst.shape(),
+            arr.shape()
+        );
+        return Err(invalid_data(msg));
+    }
+    dst.assign(arr);
+    Ok(())
+}
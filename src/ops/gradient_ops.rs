@@ -1,6 +1,11 @@
+use crate::ndarray_ext::NdArray;
 use crate::op;
+use crate::runtime::CompiledGraph;
 use crate::tensor::Tensor;
 use crate::Float;
+use ndarray;
+use rayon::iter::*;
+use std::sync::{Arc, Mutex};
 
 pub struct StopGradient;
 
@@ -20,3 +25,259 @@ impl<T: Float> op::Op<T> for StopGradient {
         vec![None]
     }
 }
+
+/// Computes Jacobians row by row on a compiled subgraph of gradients.
+///
+/// Inputs are `[y, xs.., fed..]`, where `fed` are the values that the subgraph reads
+/// from the forward graph. The `i`-th output is the `(y size, xs[i] size)` Jacobian matrix.
+pub struct Jacobian<T: Float> {
+    // Targets: flattened gradients of `<y, seed>` for each x.
+    // Feed slots: `[seed, fed..]`
+    pub graph: CompiledGraph<T>,
+    pub num_xs: usize,
+}
+
+impl<T: Float> op::Op<T> for Jacobian<T> {
+    fn name(&self) -> &str {
+        "Jacobian"
+    }
+
+    fn compute<'v>(
+        &self,
+        ctx: crate::runtime::OpComputeContext<'v, T>,
+    ) -> op::ComputeResults<'v, T> {
+        let xs = ctx.grab_inputs();
+        let y_size = xs[0].len();
+        let fed = &xs[1 + self.num_xs..];
+
+        // Each row is the gradient for a one-hot seed.
+        let rows = (0..y_size)
+            .into_par_iter()
+            .map(|i| {
+                let mut seed = NdArray::zeros(ndarray::IxDyn(&[y_size]));
+                seed[i] = T::one();
+                let mut feeds = vec![seed.view()];
+                feeds.extend(fed.iter().map(|a| a.view()));
+                self.graph
+                    .try_run(feeds.as_slice())
+                    .map_err(|e| e.to_string())
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(e) => return vec![Err(op::ComputeException::Error(e))],
+        };
+
+        (0..self.num_xs)
+            .map(|j| {
+                let x_size = xs[1 + j].len();
+                let mut data = Vec::with_capacity(y_size * x_size);
+                for row in &rows {
+                    data.extend(row[j].as_ref().unwrap().iter().cloned());
+                }
+                let jac = NdArray::from_shape_vec(ndarray::IxDyn(&[y_size, x_size]), data);
+                Ok(crate::ArrRepr::Owned(jac.unwrap()))
+            })
+            .collect()
+    }
+
+    fn grad(&self, _: &Tensor<T>, xs: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        vec![None; xs.len()]
+    }
+}
+
+/// Symbolic gradient function of `CustomGradient`: `(gy, x, y) -> gx`
+pub type BackwardFn<T> = Box<dyn Fn(&Tensor<T>, &Tensor<T>, &Tensor<T>) -> Tensor<T> + Send + Sync>;
+
+/// Identity of the first input, whose gradient flows into the second input via `backward_fn`.
+pub struct CustomGradient<T: Float> {
+    pub backward_fn: BackwardFn<T>,
+}
+
+impl<T: Float> op::Op<T> for CustomGradient<T> {
+    fn name(&self) -> &str {
+        "CustomGradient"
+    }
+
+    fn compute<'v>(
+        &self,
+        ctx: crate::runtime::OpComputeContext<'v, T>,
+    ) -> op::ComputeResults<'v, T> {
+        vec![Ok(crate::ArrRepr::View(ctx.grab_inputs()[0].clone()))]
+    }
+
+    fn grad(&self, gy: &Tensor<T>, xs: &[&Tensor<T>], y: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        vec![None, Some((self.backward_fn)(gy, xs[1], y))]
+    }
+}
+
+/// Identity of the first input, evaluated after the second input.
+pub struct ControlDependency;
+
+impl<T: Float> op::Op<T> for ControlDependency {
+    fn name(&self) -> &str {
+        "ControlDependency"
+    }
+
+    fn compute<'v>(
+        &self,
+        ctx: crate::runtime::OpComputeContext<'v, T>,
+    ) -> op::ComputeResults<'v, T> {
+        vec![Ok(crate::ArrRepr::View(ctx.grab_inputs()[0].clone()))]
+    }
+
+    fn grad(&self, gy: &Tensor<T>, _: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        vec![Some(gy.clone()), None]
+    }
+}
+
+/// Forward function of a checkpointed segment.
+pub type SegmentFn<T> = Box<dyn Fn(&[Tensor<T>]) -> Vec<Tensor<T>> + Send + Sync>;
+
+/// Graph segment shared by the outputs of `ag::checkpoint`.
+pub struct Segment<T: Float> {
+    pub f: SegmentFn<T>,
+    // Gradients of the outputs collected in a gradient construction.
+    // Cleared when the construction ends, so that it doesn't make a cycle through them.
+    gys: Mutex<Option<OutputGrads<T>>>,
+}
+
+struct OutputGrads<T: Float> {
+    // Id of the gradient construction that collected these
+    scope: usize,
+    // (output index, gradient)
+    gys: Vec<(usize, Tensor<T>)>,
+}
+
+impl<T: Float> Segment<T> {
+    pub fn new(f: SegmentFn<T>) -> Segment<T> {
+        Segment {
+            f,
+            gys: Mutex::new(None),
+        }
+    }
+
+    fn push_grad(segment: &Arc<Segment<T>>, index: usize, gy: &Tensor<T>) {
+        let scope = match crate::gradient::current_gradient_scope() {
+            Some(scope) => scope,
+            None => return,
+        };
+        let mut gys = segment.gys.lock().unwrap();
+        match *gys {
+            Some(ref mut g) if g.scope == scope => g.gys.push((index, gy.clone())),
+            _ => {
+                *gys = Some(OutputGrads {
+                    scope,
+                    gys: vec![(index, gy.clone())],
+                });
+                let segment = segment.clone();
+                crate::gradient::on_gradient_scope_exit(Box::new(move || {
+                    *segment.gys.lock().unwrap() = None;
+                }));
+            }
+        }
+    }
+
+    fn take_grads(&self) -> Vec<(usize, Tensor<T>)> {
+        let scope = crate::gradient::current_gradient_scope();
+        match self.gys.lock().unwrap().take() {
+            Some(g) if Some(g.scope) == scope => g.gys,
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Joins the inputs of a segment; its gradient is that of the whole segment.
+///
+/// Outputs a dummy scalar. Each output of the segment depends on this, so in backprop
+/// this runs after the gradients of all the outputs are collected, and the segment is
+/// rebuilt and differentiated once for all of them.
+pub struct SegmentInputs<T: Float> {
+    pub segment: Arc<Segment<T>>,
+}
+
+impl<T: Float> op::Op<T> for SegmentInputs<T> {
+    fn name(&self) -> &str {
+        "SegmentInputs"
+    }
+
+    fn compute<'v>(&self, _: crate::runtime::OpComputeContext<'v, T>) -> op::ComputeResults<'v, T> {
+        vec![Ok(crate::ArrRepr::Owned(crate::ndarray_ext::zeros(&[])))]
+    }
+
+    fn grad(&self, _: &Tensor<T>, xs: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        let gys = self.segment.take_grads();
+        if gys.is_empty() {
+            return vec![None; xs.len()];
+        }
+        // Recomputation waits for a gradient so that its values live only during backprop.
+        let inputs = xs
+            .iter()
+            .map(|&x| {
+                Tensor::builder()
+                    .set_shape(x.shape())
+                    .set_inputs(vec![x, &gys[0].1])
+                    .build(ControlDependency)
+            })
+            .collect::<Vec<_>>();
+        let outputs = (self.segment.f)(inputs.as_slice());
+        let ys = gys.iter().map(|&(i, _)| &outputs[i]).collect::<Vec<_>>();
+        let gys = gys.iter().map(|(_, gy)| gy).collect::<Vec<_>>();
+        crate::gradient::symbolic_gradients_or_none(
+            ys.as_slice(),
+            inputs.iter().collect::<Vec<_>>().as_slice(),
+            gys.as_slice(),
+        )
+    }
+
+    fn jvp(&self, _: &[&Tensor<T>], _: &Tensor<T>, _: &[Option<&Tensor<T>>]) -> Option<Tensor<T>> {
+        Some(crate::ops::scalar(T::zero()))
+    }
+}
+
+/// Identity of the `index`-th output of a segment.
+///
+/// Inputs are `[output, SegmentInputs, segment inputs..]`. The gradient is passed on to
+/// `SegmentInputs`, which computes it on a copy of the segment rebuilt in backprop,
+/// so the forward values inside it needn't be kept.
+pub struct Checkpoint<T: Float> {
+    pub segment: Arc<Segment<T>>,
+    pub index: usize,
+}
+
+impl<T: Float> op::Op<T> for Checkpoint<T> {
+    fn name(&self) -> &str {
+        "Checkpoint"
+    }
+
+    fn compute<'v>(
+        &self,
+        ctx: crate::runtime::OpComputeContext<'v, T>,
+    ) -> op::ComputeResults<'v, T> {
+        vec![Ok(crate::ArrRepr::View(ctx.grab_inputs()[0].clone()))]
+    }
+
+    fn grad(&self, gy: &Tensor<T>, xs: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        Segment::push_grad(&self.segment, self.index, gy);
+        let mut gxs = vec![None, Some(crate::ops::scalar(T::zero()))];
+        gxs.resize(xs.len(), None);
+        gxs
+    }
+
+    fn jvp(
+        &self,
+        xs: &[&Tensor<T>],
+        _: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        // Forward mode keeps the values anyway, so the segment is simply rebuilt.
+        let inputs = xs[2..].iter().map(|&x| x.clone()).collect::<Vec<_>>();
+        let outputs = (self.segment.f)(inputs.as_slice());
+        let (wrt, tangents): (Vec<_>, Vec<_>) = inputs
+            .iter()
+            .zip(&txs[2..])
+            .filter_map(|(x, t)| t.map(|t| (x, t)))
+            .unzip();
+        Some(crate::gradient::symbolic_jvp(&[&outputs[self.index]], &wrt, &tangents).remove(0))
+    }
+}
@@ -3,9 +3,12 @@ use crate::op;
 use crate::tensor::Tensor;
 use crate::Float;
 
+/// Closure that `Hook` calls with the evaluated array.
+pub type HookFn<T> = Box<dyn Fn(&NdArrayView<T>) + Send + Sync>;
+
 pub struct Hook<T: Float> {
     pub name: Option<String>,
-    pub func: Box<dyn Fn(&NdArrayView<T>) -> ()>,
+    pub func: HookFn<T>,
 }
 
 impl<T: Float> op::Op<T> for Hook<T> {
@@ -13,6 +16,10 @@ impl<T: Float> op::Op<T> for Hook<T> {
         "Hook"
     }
 
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
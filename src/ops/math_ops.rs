@@ -334,6 +334,10 @@ impl<T: Float> op::Op<T> for Transpose {
         "Transpose"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("invert_axes", self.invert_axes)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -416,6 +420,12 @@ impl<T: Float> op::Op<T> for LogSumExp {
         "LogSumExp"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("axis", self.axis)
+            .with("keep_dims", self.keep_dims)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -442,6 +452,10 @@ impl<T: Float> op::Op<T> for Pow<T> {
         "Pow"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("a", self.a.to_f64().unwrap())
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -484,6 +498,10 @@ impl<T: Float> op::Op<T> for Log<T> {
         "Log"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("a", self.a.to_f64().unwrap())
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -513,6 +531,15 @@ impl<T: Float> op::Op<T> for Exp {
     fn grad(&self, gy: &Tensor<T>, _: &[&Tensor<T>], output: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
         vec![Some(output * gy)]
     }
+
+    fn jvp(
+        &self,
+        _: &[&Tensor<T>],
+        y: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        txs[0].map(|t| y * t)
+    }
 }
 
 impl<T: Float> op::Op<T> for Atanh {
@@ -594,6 +621,15 @@ impl<T: Float> op::Op<T> for Tanh {
     fn grad(&self, gy: &Tensor<T>, _: &[&Tensor<T>], y: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
         vec![Some(gy * (ops::scalar(T::one()) - ops::square(y)))]
     }
+
+    fn jvp(
+        &self,
+        _: &[&Tensor<T>],
+        y: &Tensor<T>,
+        txs: &[Option<&Tensor<T>>],
+    ) -> Option<Tensor<T>> {
+        txs[0].map(|t| t * (ops::scalar(T::one()) - ops::square(y)))
+    }
 }
 
 impl<T: Float> op::Op<T> for Cosh {
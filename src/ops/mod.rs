@@ -5,6 +5,7 @@ use crate::ndarray_ext::{ArrRng, NdArray};
 use crate::tensor::{ArrayLike, Tensor};
 use crate::Float;
 use rand::Rng;
+use std::sync::Arc;
 
 mod activation_ops;
 mod array_ops;
@@ -23,8 +24,11 @@ pub mod hook_ops;
 mod math_ops;
 mod random_ops;
 mod reduction_ops;
+mod registry;
 mod xent_ops;
 
+pub(crate) use self::registry::register_builtins;
+
 // ---------------------------------------
 // -- Ops to manipulate `Tensor` object --
 // ---------------------------------------
@@ -143,6 +147,8 @@ where
 /// # Returns
 /// Jacobians for each variable. Each one is a matrix of shape `(y_size, x size)`.
 ///
+/// See also [jacobian](fn.jacobian.html), which doesn't need `y_size`.
+///
 /// ```
 /// extern crate autograd as ag;
 ///
@@ -179,25 +185,208 @@ pub fn jacobians<T: Float>(
         .collect::<Vec<_>>()
 }
 
-/// (Experimental) Computes hessian vector product
-pub fn _hessian_vector_product<T: Float>(
-    ys: &[&Tensor<T>],
-    xs: &[&Tensor<T>],
-    vectors: &[&Tensor<T>],
-) -> Vec<Tensor<T>> {
+/// Computes jacobians without knowing the size of `y` in advance.
+///
+/// Unlike [jacobians](fn.jacobians.html), the gradient graph is built only once;
+/// it's evaluated for each element of `y` in parallel when the result is evaluated.
+/// The values it reads from the forward path to `y` are computed once and shared by
+/// all the rows, so random ops are sampled only once.
+///
+/// # Arguments
+/// * `y` - Target of differentiation.
+/// * `xs` - Tensors with which differentiate `y`.
+///
+/// # Returns
+/// Jacobians for each of `xs`. Each one is a matrix of shape `(y size, x size)`.
+/// They are not differentiable.
+///
+/// ```
+/// extern crate autograd as ag;
+///
+/// let ref a = ag::placeholder::<f32>(&[-1, 2]);
+/// let ref b = ag::variable(ag::ndarray_ext::standard_normal::<f32>(&[2, 3]));
+/// let ref c = ag::matmul(a, b);
+/// let ref j = ag::jacobian(c, &[a, b]);
+///
+/// let ref a_val = ag::ndarray_ext::standard_normal::<f32>(&[4, 2]);
+/// let ret = ag::eval(j, &[ag::Feed(a, a_val.view())]);
+/// assert_eq!(ret[0].as_ref().unwrap().shape(), &[4*3, 4*2]);
+/// assert_eq!(ret[1].as_ref().unwrap().shape(), &[4*3, 2*3]);
+/// ```
+pub fn jacobian<T: Float>(y: &Tensor<T>, xs: &[&Tensor<T>]) -> Vec<Tensor<T>> {
+    let seed = &placeholder(&[-1]);
+    let gy = &reshape(seed, &shape(y));
+    let rows = crate::gradient::symbolic_gradients(&[y], xs, &[gy])
+        .iter()
+        .map(flatten)
+        .collect::<Vec<_>>();
+
+    // Find the nodes that depend on `seed`; only they are computed for each row.
+    let mut depends = std::collections::HashMap::new();
+    let mut stack = rows.iter().map(|r| (r, false)).collect::<Vec<_>>();
+    while let Some((node, should_visit)) = stack.pop() {
+        if should_visit {
+            let d = node.id() == seed.id() || node.inputs.iter().any(|x| depends[&x.id()]);
+            depends.insert(node.id(), d);
+        } else if !depends.contains_key(&node.id()) {
+            stack.push((node, true));
+            for x in &node.inputs {
+                if !depends.contains_key(&x.id()) {
+                    stack.push((x, false));
+                }
+            }
+        }
+    }
+
+    // The other values they read are evaluated once by the outer graph and fed.
+    // Variables are fed too, as they're locked by the outer evaluation.
+    // Only `nth_tensor` reads non-first outputs, and it's fed as a whole if it doesn't
+    // depend on `seed`.
+    let mut fed = vec![];
+    let mut visited = std::collections::HashSet::new();
+    let mut stack = rows.iter().collect::<Vec<_>>();
+    while let Some(node) = stack.pop() {
+        if !visited.insert(node.id()) {
+            continue;
+        }
+        if depends[&node.id()] {
+            stack.extend(node.inputs.iter());
+        } else {
+            fed.push(node);
+        }
+    }
+
+    let mut feed_slots = vec![seed];
+    feed_slots.extend(fed.iter().cloned());
+    let op = gradient_ops::Jacobian {
+        graph: crate::runtime::CompiledGraph::with_feeds(&rows, &feed_slots),
+        num_xs: xs.len(),
+    };
+    let mut inputs = vec![y];
+    inputs.extend(xs);
+    inputs.extend(fed);
+    let jac = Tensor::builder().set_inputs(inputs).build(op);
+    (0..xs.len()).map(|i| nth_tensor(&jac, i)).collect()
+}
+
+/// Computes the hessian matrix of `y` with respect to `x`.
+///
+/// `y` is reduced to a scalar by summation.
+///
+/// # Returns
+/// A matrix of shape `(x size, x size)`. It's not differentiable.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let ref x = ag::variable(ndarray::arr1(&[1f64, 2.]));
+/// // y = x0^2 * x1
+/// let ref y = ag::square(&x.get(0)) * x.get(1);
+/// let ref h = ag::hessian(y, x);
+/// assert_eq!(h.eval(&[]).unwrap(), ndarray::arr2(&[[4., 2.], [2., 0.]]).into_dyn());
+/// ```
+pub fn hessian<T: Float>(y: &Tensor<T>, x: &Tensor<T>) -> Tensor<T> {
+    let g = &grad(&[y], &[x])[0];
+    jacobian(g, &[x]).remove(0)
+}
+
+/// Computes hessian-vector products without building the hessian.
+///
+/// `ys` are reduced to a scalar by summation like [grad](fn.grad.html).
+///
+/// # Arguments
+/// * `ys` - Targets of differentiation.
+/// * `xs` - Tensors with which differentiate `ys`.
+/// * `vs` - Vectors to multiply. `vs[i]` must have the same shape as `xs[i]`.
+///
+/// # Returns
+/// Symbolic products `H · vs` split into the same shapes as `xs`.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let ref x = ag::variable(ndarray::arr1(&[1f64, 2.]));
+/// let ref y = ag::square(&x.get(0)) * x.get(1);
+/// let ref v = ag::variable(ndarray::arr1(&[1f64, 1.]));
+/// let ref hv = ag::hvp(&[y], &[x], &[v])[0];
+/// assert_eq!(hv.eval(&[]).unwrap(), ndarray::arr1(&[6., 2.]).into_dyn());
+/// ```
+pub fn hvp<T, A, B, C>(ys: &[A], xs: &[B], vs: &[C]) -> Vec<Tensor<T>>
+where
+    T: Float,
+    A: AsRef<Tensor<T>>,
+    B: AsRef<Tensor<T>>,
+    C: AsRef<Tensor<T>>,
+{
+    assert_eq!(xs.len(), vs.len(), "`xs.len()` must match `vs.len()`");
     let grads = grad(ys, xs);
 
     let products = grads
         .iter()
-        .zip(vectors)
-        .map(|(g, &v)| g * v)
+        .zip(vs)
+        .map(|(g, v)| g * stop_gradient(v))
         .collect::<Vec<_>>();
 
-    let products = products.iter().map(|a| a).collect::<Vec<_>>();
-
     grad(products.as_slice(), xs)
 }
 
+#[doc(hidden)]
+/// Use [hvp](fn.hvp.html) instead.
+pub fn _hessian_vector_product<T: Float>(
+    ys: &[&Tensor<T>],
+    xs: &[&Tensor<T>],
+    vectors: &[&Tensor<T>],
+) -> Vec<Tensor<T>> {
+    hvp(ys, xs, vectors)
+}
+
+/// Computes Jacobian-vector products with forward-mode differentiation.
+///
+/// Unlike [grad](fn.grad.html), this pushes `tangents` forward from `xs` to `ys`,
+/// so `ys` need not be scalars.
+/// Ops implementing `Op::jvp` are differentiated directly;
+/// the others fall back to differentiating their `Op::grad` twice.
+///
+/// # Arguments
+/// * `ys` - Targets of differentiation.
+/// * `xs` - Tensors with which differentiate `ys`.
+/// * `tangents` - Directions of differentiation. `tangents[i]` must have the same shape as `xs[i]`.
+///
+/// # Returns
+/// Symbolic products `J(ys[i]) · tangents`, each of which has the same shape as `ys[i]`.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let ref x = ag::variable(ndarray::arr1(&[1f32, 2., 3.]));
+/// let ref y = ag::square(x) * 3.;
+/// let ref v = ag::variable(ndarray::arr1(&[1f32, 0., 2.]));
+///
+/// // dy/dx = 6x (elementwise)
+/// let ref jv = ag::jvp(&[y], &[x], &[v])[0];
+/// assert_eq!(jv.eval(&[]).unwrap().as_slice().unwrap(), &[6., 0., 36.]);
+/// ```
+pub fn jvp<T, A, B, C>(ys: &[A], xs: &[B], tangents: &[C]) -> Vec<Tensor<T>>
+where
+    T: Float,
+    A: AsRef<Tensor<T>>,
+    B: AsRef<Tensor<T>>,
+    C: AsRef<Tensor<T>>,
+{
+    crate::gradient::symbolic_jvp(
+        ys.iter().map(|a| a.as_ref()).collect::<Vec<_>>().as_slice(),
+        xs.iter().map(|a| a.as_ref()).collect::<Vec<_>>().as_slice(),
+        tangents
+            .iter()
+            .map(|a| a.as_ref())
+            .collect::<Vec<_>>()
+            .as_slice(),
+    )
+}
+
 /// Stops gradient propagation.
 ///
 /// Guarantees that the gradient is not propagated to the tensors behind this
@@ -209,6 +398,116 @@ pub fn stop_gradient<A: AsRef<Tensor<T>>, T: Float>(x: A) -> Tensor<T> {
         .build(gradient_ops::StopGradient)
 }
 
+/// Overrides the gradient of a function.
+///
+/// The result is evaluated as `forward_fn(x)`, but its gradient is `backward_fn(gy, x, y)`
+/// instead of the one derived from `forward_fn`.
+/// Like [stop_gradient](fn.stop_gradient.html), nothing is propagated into the tensors
+/// made in `forward_fn`.
+///
+/// # Arguments
+/// * `x` - Input of `forward_fn`.
+/// * `forward_fn` - Builds the symbolic output `y` from `x`.
+/// * `backward_fn` - Builds the symbolic gradient of `x` from the gradient of `y` (`gy`), `x` and `y`.
+///   The result must have the same shape as `x`.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let ref x = ag::variable(ndarray::arr1(&[-2f32, 0.5, 3.]));
+///
+/// // Straight-through estimator: binarizes in forward, and passes the gradient through
+/// // where |x| <= 1.
+/// let ref y = ag::custom_gradient(x, |x| ag::sign(x), |gy, x, _| {
+///     gy * ag::lesser_equal(&ag::abs(x), &ag::scalar(1.))
+/// });
+/// let ref g = ag::grad(&[y], &[x])[0];
+///
+/// assert_eq!(y.eval(&[]).unwrap(), ndarray::arr1(&[-1., 1., 1.]).into_dyn());
+/// assert_eq!(g.eval(&[]).unwrap(), ndarray::arr1(&[0., 1., 0.]).into_dyn());
+/// ```
+pub fn custom_gradient<T, A, F, G>(x: A, forward_fn: F, backward_fn: G) -> Tensor<T>
+where
+    T: Float,
+    A: AsRef<Tensor<T>>,
+    F: FnOnce(&Tensor<T>) -> Tensor<T>,
+    G: Fn(&Tensor<T>, &Tensor<T>, &Tensor<T>) -> Tensor<T> + Send + Sync + 'static,
+{
+    let x = x.as_ref();
+    let y = stop_gradient(forward_fn(x));
+    Tensor::builder()
+        .set_shape(y.shape())
+        .set_inputs(vec![&y, x])
+        .build(gradient_ops::CustomGradient {
+            backward_fn: Box::new(backward_fn),
+        })
+}
+
+/// Recomputes the forward values of `f` in backprop instead of keeping them (gradient checkpointing).
+///
+/// Returns `f(inputs)`. In the gradient graph, `f` is called again on the inputs and
+/// differentiated after the gradients of its outputs are available,
+/// so its intermediate values are freed as soon as the forward pass no longer needs them.
+/// This saves memory at the cost of one more forward computation of `f` per gradient.
+///
+/// The trade-off is tuned by the size of the segments: wrapping each step of an
+/// unrolled RNN keeps only the per-step inputs and outputs, and wrapping
+/// groups of steps recomputes less but keeps more.
+///
+/// NOTE: Gradients flow only into `inputs`.
+/// Variables used in `f` must be passed as `inputs` to get their gradients;
+/// otherwise they are treated as constants like the other tensors captured by `f`.
+///
+/// ```
+/// extern crate autograd as ag;
+///
+/// let ref x = ag::placeholder::<f32>(&[-1, 3]);
+/// let ref w = ag::variable(ag::ndarray_ext::standard_normal::<f32>(&[3, 3]));
+///
+/// let ys = ag::checkpoint(
+///     |xs| {
+///         let h = ag::tanh(&ag::matmul(&xs[0], &xs[1]));
+///         vec![ag::sigmoid(&h), h]
+///     },
+///     &[x, w],
+/// );
+/// let ref loss = ag::reduce_sum(&(&ys[0] * &ys[1]), &[0, 1], false);
+/// let ref g = ag::grad(&[loss], &[w])[0];
+///
+/// let ref x_val = ag::ndarray_ext::standard_normal(&[2, 3]);
+/// assert_eq!(g.eval(&[ag::Feed(x, x_val.view())]).unwrap().shape(), &[3, 3]);
+/// ```
+pub fn checkpoint<T, F>(f: F, inputs: &[&Tensor<T>]) -> Vec<Tensor<T>>
+where
+    T: Float,
+    F: Fn(&[Tensor<T>]) -> Vec<Tensor<T>> + Send + Sync + 'static,
+{
+    let segment = Arc::new(gradient_ops::Segment::new(Box::new(f)));
+    let xs = inputs.iter().map(|&x| x.clone()).collect::<Vec<_>>();
+    let ys = (segment.f)(xs.as_slice());
+    let joined = Tensor::builder()
+        .set_inputs(inputs.to_vec())
+        .build(gradient_ops::SegmentInputs {
+            segment: segment.clone(),
+        });
+    ys.iter()
+        .enumerate()
+        .map(|(index, y)| {
+            let y = stop_gradient(y);
+            let mut op_inputs = vec![&y, &joined];
+            op_inputs.extend_from_slice(inputs);
+            Tensor::builder()
+                .set_shape(y.shape())
+                .set_inputs(op_inputs)
+                .build(gradient_ops::Checkpoint {
+                    segment: segment.clone(),
+                    index,
+                })
+        })
+        .collect()
+}
+
 /// Creates a shared variable tensor from an ndarray.
 ///
 /// A shared variable can be mutated with gradient descent methods
@@ -226,11 +525,47 @@ pub fn stop_gradient<A: AsRef<Tensor<T>>, T: Float>(x: A) -> Tensor<T> {
 /// ```
 #[inline]
 pub fn variable<T: Float, D: ndarray::Dimension>(arr: ndarray::Array<T, D>) -> Tensor<T> {
+    variable_builder(arr).build(basic_source_ops::Variable)
+}
+
+/// Creates a shared variable tensor named `name` in the current name scope.
+///
+/// See `variable` and `name_scope`.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let ref w = ag::variable_named("w", ndarray::arr1(&[2f32]));
+/// assert_eq!(w.name(), Some("w"));
+/// ```
+#[inline]
+pub fn variable_named<T: Float, D: ndarray::Dimension>(
+    name: &str,
+    arr: ndarray::Array<T, D>,
+) -> Tensor<T> {
+    variable_builder(arr)
+        .set_name(name)
+        .build(basic_source_ops::Variable)
+}
+
+fn variable_builder<T: Float, D: ndarray::Dimension>(
+    arr: ndarray::Array<T, D>,
+) -> crate::tensor::TensorBuilder<T> {
     let arr = arr.into_dyn();
     Tensor::builder()
         .set_shape(convert_to_tensor(crate::ndarray_ext::shape_of(&arr)))
         .set_variable_array(arr)
-        .build(basic_source_ops::Variable)
+}
+
+/// Creates a shared variable tensor from a NumPy `.npy` file.
+///
+/// See `ndarray_ext::npy` for the supported arrays; `.npz` files can be loaded with
+/// `ndarray_ext::npy::load_npz` and passed to `variable`.
+pub fn variable_from_npy<T: Float, P: AsRef<std::path::Path>>(
+    path: P,
+) -> std::io::Result<Tensor<T>> {
+    crate::ndarray_ext::npy::load_npy(path).map(variable)
 }
 
 /// Creates a placeholder tensor.
@@ -250,6 +585,20 @@ pub fn variable<T: Float, D: ndarray::Dimension>(arr: ndarray::Array<T, D>) -> T
 /// ```
 #[inline]
 pub fn placeholder<T: Float>(shape_: &[isize]) -> Tensor<T> {
+    placeholder_builder(shape_).build(basic_source_ops::Placeholder)
+}
+
+/// Creates a placeholder tensor named `name` in the current name scope.
+///
+/// See `placeholder` and `name_scope`.
+#[inline]
+pub fn placeholder_named<T: Float>(name: &str, shape_: &[isize]) -> Tensor<T> {
+    placeholder_builder(shape_)
+        .set_name(name)
+        .build(basic_source_ops::Placeholder)
+}
+
+fn placeholder_builder<T: Float>(shape_: &[isize]) -> crate::tensor::TensorBuilder<T> {
     let b = Tensor::builder().set_is_placeholder(true);
     let rank = shape_.len();
     let b = if rank == 0 || -1 != shape_[0] {
@@ -266,8 +615,7 @@ pub fn placeholder<T: Float>(shape_: &[isize]) -> Tensor<T> {
     } else {
         b
     };
-    let b = b.set_known_shape(crate::tensor::KnownShape::new(shape_.to_vec()));
-    b.build(basic_source_ops::Placeholder)
+    b.set_known_shape(crate::tensor::KnownShape::new(shape_.to_vec()))
 }
 
 /// Creates a constant tensor.
@@ -282,6 +630,28 @@ pub fn placeholder<T: Float>(shape_: &[isize]) -> Tensor<T> {
 /// ```
 #[inline]
 pub fn constant<D, T>(arr: ndarray::Array<T, D>) -> Tensor<T>
+where
+    D: ndarray::Dimension,
+    T: Float,
+{
+    constant_builder(arr).build(basic_source_ops::Const)
+}
+
+/// Creates a constant tensor named `name` in the current name scope.
+///
+/// See `constant` and `name_scope`.
+#[inline]
+pub fn constant_named<D, T>(name: &str, arr: ndarray::Array<T, D>) -> Tensor<T>
+where
+    D: ndarray::Dimension,
+    T: Float,
+{
+    constant_builder(arr)
+        .set_name(name)
+        .build(basic_source_ops::Const)
+}
+
+fn constant_builder<D, T>(arr: ndarray::Array<T, D>) -> crate::tensor::TensorBuilder<T>
 where
     D: ndarray::Dimension,
     T: Float,
@@ -290,7 +660,6 @@ where
     Tensor::builder()
         .set_shape(convert_to_tensor(crate::ndarray_ext::shape_of(&arr)))
         .set_constant_array(arr)
-        .build(basic_source_ops::Const)
 }
 
 /// Returns the (symbolic) shape of input tensor
@@ -569,108 +938,92 @@ pub fn div<T: Float, A: AsRef<Tensor<T>>, B: AsRef<Tensor<T>>>(a: A, b: B) -> Te
     bin_op_helper(a, b, binary_ops::DivOp)
 }
 
-///// Inplace multiplication.
-///// This function takes `a`'s ownership.
-///// Note that this **doesn't** support gradient propagation.
-/////
-///// # Panics
-/////
-///// In case of `a` is a `constant`.
-/////
-///// ```
-///// extern crate ndarray;
-///// extern crate autograd as ag;
-/////
-/////
-///// let a = ag::ones(&[2]);
-///// let ref b: ag::Tensor<f32> = ag::zeros(&[2]);
-///// let ref c = ag::mul_inplace(a, b);
-/////
-///// assert_eq!(c.eval(&[]), Some(ndarray::arr1(&[0., 0.]).into_dyn()));
-///// ```
-//pub fn mul_inplace<T: Float, A: AsRef<Tensor<T>>>(a: Tensor<T>, b: A) -> Tensor<T> {
-//    Tensor::builder()
-//        .set_inputs(vec![&a, b.as_ref()])
-//        .set_shape(a.shape())
-//        .build(binary_ops::InplaceMulOp)
-//}
-
-///// Inplace division.
-/////
-///// This function takes `a`'s ownership.
-///// Note that this **doesn't** support gradient propagation.
-/////
-///// # Panics
-/////
-///// In case of `a` is a `constant`.
-/////
-///// ```
-///// extern crate ndarray;
-///// extern crate autograd as ag;
-/////
-///// let a = ag::ones(&[2]);
-///// let ref c = ag::div_inplace(a, &ag::scalar(2.));
-/////
-///// assert_eq!(c.eval(&[]), Some(ndarray::arr1(&[0.5, 0.5]).into_dyn()));
-///// ```
-//pub fn div_inplace<T: Float, A: AsRef<Tensor<T>>>(a: Tensor<T>, b: A) -> Tensor<T> {
-//    Tensor::builder()
-//        .set_inputs(vec![&a, b.as_ref()])
-//        .set_shape(a.shape())
-//        .build(binary_ops::InplaceDivOp)
-//}
-
-///// Inplace addition
-/////
-///// Returns `a` after performing `a += b`.
-///// This function takes `a`'s ownership.
-/////
-///// # Panics
-/////
-///// In case of `a` is a `constant`.
-/////
-///// ```
-///// extern crate ndarray;
-///// extern crate autograd as ag;
-/////
-///// let a = ag::ones(&[2]);
-///// let ref b = ag::ones(&[2]);
-///// let ref c = ag::add_inplace(a, b);
-/////
-///// assert_eq!(c.eval(&[]), Some(ndarray::arr1(&[2., 2.]).into_dyn()));
-///// ```
-//pub fn add_inplace<T: Float, A: AsRef<Tensor<T>>>(a: Tensor<T>, b: A) -> Tensor<T> {
-//    Tensor::builder()
-//        .set_inputs(vec![&a, b.as_ref()])
-//        .set_shape(a.shape())
-//        .build(binary_ops::InplaceAddOp)
-//}
-
-///// Inplace subtraction
-/////
-///// Returns `a` after performing `a -= b`.
-///// This function takes `a`'s ownership.
-/////
-///// # Panics
-/////
-///// In case of `a` is a `constant`.
-/////
-///// ```
-///// extern crate ndarray;
-///// extern crate autograd as ag;
-/////
-///// let a = ag::ones(&[2, 2]);
-///// let ref b = ag::ones(&[2, 2]);
-///// let ref c = ag::sub_inplace(a, b);
-/////
-///// assert_eq!(c.eval(&[]), Some(ndarray::arr2(&[[0., 0.], [0., 0.]]).into_dyn()));
-///// ```
-//pub fn sub_inplace<T: Float, A: AsRef<Tensor<T>>>(a: Tensor<T>, b: A) -> Tensor<T> {
-//    Tensor::builder()
-//        .set_inputs(vec![&a, b.as_ref()])
-//        .set_shape(a.shape())
-//        .build(binary_ops::InplaceSubOp)
-//}
+/// Inplace multiplication.
+///
+/// Returns `a * b`. The result is written into `a`'s array if no other node reads
+/// it after this op; otherwise a new array is allocated.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let a = ag::ones(&[2]);
+/// let ref b: ag::Tensor<f32> = ag::zeros(&[2]);
+/// let ref c = ag::mul_inplace(a, b);
+///
+/// assert_eq!(c.eval(&[]), Some(ndarray::arr1(&[0., 0.]).into_dyn()));
+/// ```
+pub fn mul_inplace<T: Float, A: AsRef<Tensor<T>>>(a: Tensor<T>, b: A) -> Tensor<T> {
+    Tensor::builder()
+        .set_inputs(vec![&a, b.as_ref()])
+        .set_shape(a.shape())
+        .build(binary_ops::InplaceMulOp)
+}
+
+/// Inplace division.
+///
+/// Returns `a / b`. The result is written into `a`'s array if no other node reads
+/// it after this op; otherwise a new array is allocated.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let a = ag::ones(&[2]);
+/// let ref c = ag::div_inplace(a, &ag::scalar(2.));
+///
+/// assert_eq!(c.eval(&[]), Some(ndarray::arr1(&[0.5, 0.5]).into_dyn()));
+/// ```
+pub fn div_inplace<T: Float, A: AsRef<Tensor<T>>>(a: Tensor<T>, b: A) -> Tensor<T> {
+    Tensor::builder()
+        .set_inputs(vec![&a, b.as_ref()])
+        .set_shape(a.shape())
+        .build(binary_ops::InplaceDivOp)
+}
+
+/// Inplace addition
+///
+/// Returns `a + b`. The result is written into `a`'s array if no other node reads
+/// it after this op; otherwise a new array is allocated.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let a = ag::ones(&[2]);
+/// let ref b = ag::ones(&[2]);
+/// let ref c = ag::add_inplace(a, b);
+///
+/// assert_eq!(c.eval(&[]), Some(ndarray::arr1(&[2., 2.]).into_dyn()));
+/// ```
+pub fn add_inplace<T: Float, A: AsRef<Tensor<T>>>(a: Tensor<T>, b: A) -> Tensor<T> {
+    Tensor::builder()
+        .set_inputs(vec![&a, b.as_ref()])
+        .set_shape(a.shape())
+        .build(binary_ops::InplaceAddOp)
+}
+
+/// Inplace subtraction
+///
+/// Returns `a - b`. The result is written into `a`'s array if no other node reads
+/// it after this op; otherwise a new array is allocated.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let a = ag::ones(&[2, 2]);
+/// let ref b = ag::ones(&[2, 2]);
+/// let ref c = ag::sub_inplace(a, b);
+///
+/// assert_eq!(c.eval(&[]), Some(ndarray::arr2(&[[0., 0.], [0., 0.]]).into_dyn()));
+/// ```
+pub fn sub_inplace<T: Float, A: AsRef<Tensor<T>>>(a: Tensor<T>, b: A) -> Tensor<T> {
+    Tensor::builder()
+        .set_inputs(vec![&a, b.as_ref()])
+        .set_shape(a.shape())
+        .build(binary_ops::InplaceSubOp)
+}
 
 /// Elementwise sqrt
 pub fn sqrt<T: Float, A: AsRef<Tensor<T>>>(x: A) -> Tensor<T> {
@@ -760,10 +1113,23 @@ pub fn add_n<T: Float>(xs: &[&Tensor<T>]) -> Tensor<T> {
     if len == 1 {
         xs[0].clone()
     } else {
-        Tensor::builder()
+        let b = Tensor::builder()
             .set_inputs(xs.to_vec())
-            .set_shape(xs[0].shape())
-            .build(array_ops::AddN)
+            .set_shape(xs[0].shape());
+        // Sum of sparse gradients stays sparse.
+        let slices = xs.iter().map(|x| x.indexed_slices.as_ref());
+        let b = match slices.collect::<Option<Vec<_>>>() {
+            Some(slices) => {
+                let indices = slices.iter().map(|s| &s.indices).collect::<Vec<_>>();
+                let values = slices.iter().map(|s| &s.values).collect::<Vec<_>>();
+                b.set_indexed_slices(crate::tensor::IndexedSlices {
+                    indices: concat(indices.as_slice(), 0),
+                    values: concat(values.as_slice(), 0),
+                })
+            }
+            None => b,
+        };
+        b.build(array_ops::AddN)
     }
 }
 
@@ -1866,7 +2232,7 @@ pub fn random_normal<T: Float, AL: ArrayLike<T>>(shape: &AL, mean: f64, stddev:
 }
 
 /// Outputs values sampled from the normal distribution.
-pub fn random_normal_rng<T: Float, AL: ArrayLike<T>, R: Rng + 'static>(
+pub fn random_normal_rng<T: Float, AL: ArrayLike<T>, R: Rng + Send + 'static>(
     arr_rng: ArrRng<T, R>,
     shape: &AL,
     mean: f64,
@@ -1887,7 +2253,7 @@ pub fn random_uniform<T: Float, AL: ArrayLike<T>>(shape: &AL, min: f64, max: f64
 /// Outputs values sampled from the uniform distribution.
 ///
 /// See https://github.com/raskr/rust-autograd/issues/1.
-pub fn random_uniform_rng<T: Float, AL: ArrayLike<T>, R: Rng + 'static>(
+pub fn random_uniform_rng<T: Float, AL: ArrayLike<T>, R: Rng + Send + 'static>(
     arr_rng: ArrRng<T, R>,
     shape: &AL,
     min: f64,
@@ -1908,7 +2274,7 @@ pub fn standard_normal<T: Float, AL: ArrayLike<T>>(shape: &AL) -> Tensor<T> {
 /// Outputs values sampled from the standard normal distribution.
 ///
 /// See https://github.com/raskr/rust-autograd/issues/1.
-pub fn standard_normal_rng<T: Float, AL: ArrayLike<T>, R: Rng + 'static>(
+pub fn standard_normal_rng<T: Float, AL: ArrayLike<T>, R: Rng + Send + 'static>(
     arr_rng: ArrRng<T, R>,
     shape: &AL,
 ) -> Tensor<T> {
@@ -1927,7 +2293,7 @@ pub fn standard_uniform<T: Float, AL: ArrayLike<T>>(shape: &AL) -> Tensor<T> {
 /// Outputs values sampled from the standard uniform distribution.
 ///
 /// See https://github.com/raskr/rust-autograd/issues/1.
-pub fn standard_uniform_rng<T: Float, AL: ArrayLike<T>, R: Rng + 'static>(
+pub fn standard_uniform_rng<T: Float, AL: ArrayLike<T>, R: Rng + Send + 'static>(
     arr_rng: ArrRng<T, R>,
     shape: &AL,
 ) -> Tensor<T> {
@@ -1946,7 +2312,7 @@ pub fn bernoulli<T: Float, AL: ArrayLike<T>>(shape: &AL, p: f64) -> Tensor<T> {
 /// Outputs values sampled from the bernoulli distribution.
 ///
 /// See https://github.com/raskr/rust-autograd/issues/1.
-pub fn bernoulli_rng<T: Float, AL: ArrayLike<T>, R: Rng + 'static>(
+pub fn bernoulli_rng<T: Float, AL: ArrayLike<T>, R: Rng + Send + 'static>(
     arr_rng: ArrRng<T, R>,
     shape: &AL,
     p: f64,
@@ -1966,7 +2332,7 @@ pub fn random_exp<T: Float, AL: ArrayLike<T>>(shape: &AL, lambda: f64) -> Tensor
 /// Outputs values sampled from the exponential distribution.
 ///
 /// See https://github.com/raskr/rust-autograd/issues/1.
-pub fn random_exp_rng<T: Float + 'static, AL: ArrayLike<T>, R: Rng + 'static>(
+pub fn random_exp_rng<T: Float + 'static, AL: ArrayLike<T>, R: Rng + Send + 'static>(
     arr_rng: ArrRng<T, R>,
     shape: &AL,
     lambda: f64,
@@ -1990,7 +2356,7 @@ pub fn random_gamma<T: Float, AL: ArrayLike<T>>(
 /// Outputs values sampled from the gamma distribution.
 ///
 /// See https://github.com/raskr/rust-autograd/issues/1.
-pub fn random_gamma_rng<T: Float, AL: ArrayLike<T>, R: Rng + 'static>(
+pub fn random_gamma_rng<T: Float, AL: ArrayLike<T>, R: Rng + Send + 'static>(
     arr_rng: ArrRng<T, R>,
     shape: &AL,
     shape_param: f64,
@@ -2011,7 +2377,7 @@ pub fn log_normal<T: Float, AL: ArrayLike<T>>(shape: &AL, mean: f64, stddev: f64
 /// Outputs values sampled from the log-normal distribution.
 ///
 /// See https://github.com/raskr/rust-autograd/issues/1.
-pub fn log_normal_rng<T: Float, AL: ArrayLike<T>, R: Rng + 'static>(
+pub fn log_normal_rng<T: Float, AL: ArrayLike<T>, R: Rng + Send + 'static>(
     arr_rng: ArrRng<T, R>,
     shape: &AL,
     mean: f64,
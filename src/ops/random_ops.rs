@@ -106,11 +106,21 @@ impl<T: Float, R> Gamma<T, R> {
     }
 }
 
-impl<T: Float, R: Rng> op::Op<T> for RandomNormal<T, R> {
+impl<T: Float, R: Rng + Send> op::Op<T> for RandomNormal<T, R> {
     fn name(&self) -> &str {
         "RandomNormal"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("mean", self.mean)
+            .with("stddev", self.stddev)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -129,11 +139,21 @@ impl<T: Float, R: Rng> op::Op<T> for RandomNormal<T, R> {
     }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for RandomUniform<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for RandomUniform<T, R> {
     fn name(&self) -> &str {
         "RandomUniform"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("min", self.min)
+            .with("max", self.max)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -152,11 +172,15 @@ impl<R: Rng, T: Float> op::Op<T> for RandomUniform<T, R> {
     }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for StandardNormal<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for StandardNormal<T, R> {
     fn name(&self) -> &str {
         "StandardNormal"
     }
 
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -173,11 +197,15 @@ impl<R: Rng, T: Float> op::Op<T> for StandardNormal<T, R> {
     }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for StandardUniform<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for StandardUniform<T, R> {
     fn name(&self) -> &str {
         "StandardUniform"
     }
 
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -194,11 +222,19 @@ impl<R: Rng, T: Float> op::Op<T> for StandardUniform<T, R> {
     }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for Bernoulli<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for Bernoulli<T, R> {
     fn name(&self) -> &str {
         "Bernoulli"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("p", self.p)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -215,11 +251,19 @@ impl<R: Rng, T: Float> op::Op<T> for Bernoulli<T, R> {
     }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for Exponential<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for Exponential<T, R> {
     fn name(&self) -> &str {
         "Exponential"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("lambda", self.lambda)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -236,11 +280,21 @@ impl<R: Rng, T: Float> op::Op<T> for Exponential<T, R> {
     }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for LogNormal<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for LogNormal<T, R> {
     fn name(&self) -> &str {
         "LogNormal"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("mean", self.mean)
+            .with("stddev", self.stddev)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -259,11 +313,21 @@ impl<R: Rng, T: Float> op::Op<T> for LogNormal<T, R> {
     }
 }
 
-impl<R: Rng, T: Float> op::Op<T> for Gamma<T, R> {
+impl<R: Rng + Send, T: Float> op::Op<T> for Gamma<T, R> {
     fn name(&self) -> &str {
         "Gamma"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("shape_param", self.shape_param)
+            .with("scale", self.scale)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -136,7 +136,7 @@ impl<T: Float> op::Op<T> for ReduceSumToScalar {
     }
 }
 
-struct ReduceSumToScalarGrad;
+pub struct ReduceSumToScalarGrad;
 
 impl<T: Float> op::Op<T> for ReduceSumToScalarGrad {
     fn name(&self) -> &str {
@@ -167,6 +167,12 @@ impl<T: Float> op::Op<T> for ReduceSum {
         "ReduceSum"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("keep_dims", self.keep_dims)
+            .with("sparse_axes", self.sparse_axes)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -194,6 +200,12 @@ impl<T: Float> op::Op<T> for ReduceMean {
         "ReduceMean"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("keep_dims", self.keep_dims)
+            .with("sparse_axes", self.sparse_axes)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -253,6 +265,12 @@ impl<T: Float> op::Op<T> for ReduceProd {
         "ReduceProd"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("keep_dims", self.keep_dims)
+            .with("sparse_axes", self.sparse_axes)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -287,6 +305,12 @@ impl<T: Float> op::Op<T> for ReduceMin {
         "ReduceMin"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("keep_dims", self.keep_dims)
+            .with("sparse_axes", self.sparse_axes)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -312,6 +336,12 @@ impl<T: Float> op::Op<T> for ReduceMax {
         "ReduceMax"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("keep_dims", self.keep_dims)
+            .with("sparse_axes", self.sparse_axes)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -364,6 +394,12 @@ impl<T: Float> op::Op<T> for ArgMax {
         "ArgMax"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with("axis", self.axis)
+            .with("keep_dim", self.keep_dim)
+    }
+
     // cf. https://github.com/tensorflow/compiler/tf2xla/kernels/index_ops.cc
     fn compute<'v>(
         &self,
@@ -443,6 +479,15 @@ impl<T: Float> op::Op<T> for ReduceGradCommon {
         "ReduceGradCommon"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new()
+            .with(
+                "should_make_broadcast_dims",
+                self.should_make_broadcast_dims,
+            )
+            .with("sparse_axes", self.sparse_axes)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
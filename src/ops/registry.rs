@@ -0,0 +1,371 @@
+//! Constructors of the built-in ops for `graph_def::OpRegistry`
+use super::*;
+use crate::checkpoint::invalid_data;
+use crate::graph_def::OpRegistry;
+use crate::op::{Attributes, Op};
+use std::io;
+
+fn float<T: Float>(attrs: &Attributes, name: &str) -> io::Result<T> {
+    attrs.get::<f64>(name).map(|a| T::from(a).unwrap())
+}
+
+macro_rules! register_unit_ops {
+    ($registry:expr, $($op:expr),* $(,)*) => {
+        $(
+            $registry.register(Op::<T>::name(&$op), |_| Ok(Box::new($op)));
+        )*
+    };
+}
+
+macro_rules! register_conv_ops {
+    ($registry:expr, $($module:ident::$op:ident),*) => {
+        $(
+            let op = conv_ops::$module::$op {
+                pad: 0,
+                stride: 0,
+                dilation: 0,
+            };
+            $registry.register(Op::<T>::name(&op), |a| {
+                Ok(Box::new(conv_ops::$module::$op {
+                    pad: a.get("pad")?,
+                    stride: a.get("stride")?,
+                    dilation: a.get("dilation")?,
+                }))
+            });
+        )*
+    };
+}
+
+macro_rules! register_pool_ops {
+    ($registry:expr, $($op:ident),*) => {
+        $(
+            let op = conv_ops::max_pool2d::$op {
+                pad: 0,
+                stride: 0,
+                size: 0,
+            };
+            $registry.register(Op::<T>::name(&op), |a| {
+                Ok(Box::new(conv_ops::max_pool2d::$op {
+                    pad: a.get("pad")?,
+                    stride: a.get("stride")?,
+                    size: a.get("size")?,
+                }))
+            });
+        )*
+    };
+}
+
+macro_rules! register_reduce_ops {
+    ($registry:expr, $($op:ident),*) => {
+        $(
+            let op = reduction_ops::$op {
+                keep_dims: false,
+                sparse_axes: false,
+            };
+            $registry.register(Op::<T>::name(&op), |a| {
+                Ok(Box::new(reduction_ops::$op {
+                    keep_dims: a.get("keep_dims")?,
+                    sparse_axes: a.get("sparse_axes")?,
+                }))
+            });
+        )*
+    };
+}
+
+/// Registers all the built-in ops but the ones holding closures, graphs or
+/// optimizer states.
+pub(crate) fn register_builtins<T: Float>(r: &mut OpRegistry<T>) {
+    register_unit_ops!(
+        r,
+        activation_ops::Identity,
+        activation_ops::ReLU,
+        activation_ops::Sigmoid,
+        activation_ops::Softplus,
+        array_ops::ExpandDims,
+        array_ops::Squeeze,
+        array_ops::AddN,
+        array_ops::SetDiff1D,
+        array_ops::Shape,
+        array_ops::Rank,
+        array_ops::Size,
+        array_ops::Reshape,
+        array_ops::InferBinOpShape,
+        basic_source_ops::Variable,
+        basic_source_ops::Const,
+        basic_source_ops::Placeholder,
+        binary_ops::AddOp,
+        binary_ops::SubOp,
+        binary_ops::MulOp,
+        binary_ops::DivOp,
+        binary_ops::InplaceAddOp,
+        binary_ops::InplaceSubOp,
+        binary_ops::InplaceMulOp,
+        binary_ops::InplaceDivOp,
+        binary_ops::PreprocessBinOpGrad,
+        binary_ops::PreprocessBinOpGradGrad,
+        const_gen_ops::Zeros,
+        const_gen_ops::Ones,
+        const_gen_ops::Range,
+        dot_ops::TensordotPreprocess,
+        gradient_ops::StopGradient,
+        gradient_ops::ControlDependency,
+        math_ops::Sin,
+        math_ops::Cos,
+        math_ops::Tan,
+        math_ops::Asin,
+        math_ops::Acos,
+        math_ops::Atan,
+        math_ops::Sinh,
+        math_ops::Cosh,
+        math_ops::Tanh,
+        math_ops::Asinh,
+        math_ops::Acosh,
+        math_ops::Atanh,
+        math_ops::Exp,
+        math_ops::Sqrt,
+        math_ops::NegOp,
+        math_ops::Floor,
+        math_ops::Ceil,
+        math_ops::Sign,
+        math_ops::Reciprocal,
+        math_ops::Square,
+        math_ops::Abs,
+        reduction_ops::ReduceSumToScalar,
+        reduction_ops::ReduceSumToScalarGrad,
+        xent_ops::SoftmaxCrossEntropy,
+        xent_ops::SparseSoftmaxCrossEntropy,
+        xent_ops::SparseSoftmaxCrossEntropyGrad,
+        xent_ops::SigmoidCrossEntropy,
+    );
+
+    // activation_ops
+    r.register("ELU", |a| {
+        Ok(Box::new(activation_ops::ELU {
+            alpha: float(a, "alpha")?,
+        }))
+    });
+    r.register("ELUGrad", |a| {
+        Ok(Box::new(activation_ops::ELUGrad {
+            alpha: float(a, "alpha")?,
+        }))
+    });
+    r.register("Softmax", |a| {
+        Ok(Box::new(activation_ops::Softmax {
+            axis: a.get("axis")?,
+        }))
+    });
+
+    // array_ops
+    r.register("Slice", |a| {
+        let indices = array_ops::slice_indices_from_attr(&a.get::<Vec<i64>>("indices")?)
+            .ok_or_else(|| invalid_data("Invalid slice indices".to_string()))?;
+        Ok(Box::new(array_ops::Slice { indices }))
+    });
+    r.register("SliceGrad", |a| {
+        let indices = array_ops::slice_indices_from_attr(&a.get::<Vec<i64>>("indices")?)
+            .ok_or_else(|| invalid_data("Invalid slice indices".to_string()))?;
+        Ok(Box::new(array_ops::SliceGrad { indices }))
+    });
+    r.register("Split", |a| {
+        Ok(Box::new(array_ops::Split {
+            axis: a.get("axis")?,
+            start_index: a.get("start_index")?,
+            end_index: a.get("end_index")?,
+        }))
+    });
+    r.register("SplitGrad", |a| {
+        Ok(Box::new(array_ops::SplitGrad {
+            axis: a.get("axis")?,
+            start_index: a.get("start_index")?,
+            end_index: a.get("end_index")?,
+        }))
+    });
+    r.register("Tile", |a| {
+        Ok(Box::new(array_ops::Tile {
+            axis: a.get("axis")?,
+            num: a.get("num")?,
+        }))
+    });
+    r.register("Concat", |a| {
+        Ok(Box::new(array_ops::Concat {
+            axis: a.get("axis")?,
+        }))
+    });
+    r.register("ConcatGrad", |a| {
+        Ok(Box::new(array_ops::ConcatGrad {
+            axis: a.get("axis")?,
+            index: a.get("index")?,
+        }))
+    });
+    r.register("Clip", |a| {
+        Ok(Box::new(array_ops::Clip {
+            min: float(a, "min")?,
+            max: float(a, "max")?,
+        }))
+    });
+    r.register("ClipGrad", |a| {
+        Ok(Box::new(array_ops::ClipGrad {
+            min: float(a, "min")?,
+            max: float(a, "max")?,
+        }))
+    });
+    r.register("Gather", |a| {
+        Ok(Box::new(array_ops::Gather {
+            axis: a.get("axis")?,
+            should_normalize_negative_indices: a.get("should_normalize_negative_indices")?,
+        }))
+    });
+    r.register("GatherGrad", |a| {
+        Ok(Box::new(array_ops::GatherGrad {
+            axis: a.get("axis")?,
+        }))
+    });
+    r.register("IndexOp", |a| {
+        Ok(Box::new(array_ops::IndexOp {
+            index: a.get("index")?,
+        }))
+    });
+    r.register("IndexOpGrad", |a| {
+        Ok(Box::new(array_ops::IndexOpGrad {
+            index: a.get("index")?,
+        }))
+    });
+
+    // const_gen_ops
+    r.register("ConvertToTensor", |a| {
+        let shape = a
+            .get::<Vec<i64>>("shape")?
+            .iter()
+            .map(|&d| d as usize)
+            .collect::<Vec<_>>();
+        let values = a
+            .get::<Vec<f64>>("values")?
+            .iter()
+            .map(|&v| T::from(v).unwrap())
+            .collect::<Vec<_>>();
+        let arr = NdArray::from_shape_vec(ndarray::IxDyn(&shape), values)
+            .map_err(|_| invalid_data("Invalid shape of ConvertToTensor".to_string()))?;
+        Ok(Box::new(const_gen_ops::ConvertToTensor { arr }))
+    });
+    r.register("Scalar", |a| {
+        Ok(Box::new(const_gen_ops::Scalar {
+            val: float(a, "val")?,
+        }))
+    });
+
+    // conv_ops
+    register_conv_ops!(
+        r,
+        conv2d::Conv2D,
+        conv2d::Conv2DWithCols,
+        conv2d::Conv2DFilterGrad,
+        conv2d_transpose::Conv2DTranspose,
+        conv2d_transpose::Conv2DTransposeFilterGrad
+    );
+    register_pool_ops!(r, MaxPool2D, MaxPool2DGrad, MaxPool2DGradGrad);
+
+    // dot_ops
+    r.register("MatMul", |a| {
+        Ok(Box::new(dot_ops::MatMul {
+            transpose_a: a.get("transpose_a")?,
+            transpose_b: a.get("transpose_b")?,
+        }))
+    });
+    r.register("BatchMatMul", |a| {
+        Ok(Box::new(dot_ops::BatchMatMul {
+            transpose_a: a.get("transpose_a")?,
+            transpose_b: a.get("transpose_b")?,
+        }))
+    });
+
+    // math_ops
+    r.register("Log", |a| Ok(Box::new(math_ops::Log { a: float(a, "a")? })));
+    r.register("Pow", |a| Ok(Box::new(math_ops::Pow { a: float(a, "a")? })));
+    r.register("LogSumExp", |a| {
+        Ok(Box::new(math_ops::LogSumExp {
+            axis: a.get("axis")?,
+            keep_dims: a.get("keep_dims")?,
+        }))
+    });
+    r.register("Transpose", |a| {
+        Ok(Box::new(math_ops::Transpose {
+            invert_axes: a.get("invert_axes")?,
+        }))
+    });
+
+    // random_ops, which get fresh generators
+    r.register("RandomUniform", |a| {
+        let (min, max) = (a.get("min")?, a.get("max")?);
+        Ok(Box::new(random_ops::RandomUniform::new(
+            ArrRng::default(),
+            min,
+            max,
+        )))
+    });
+    r.register("RandomNormal", |a| {
+        let (mean, stddev) = (a.get("mean")?, a.get("stddev")?);
+        Ok(Box::new(random_ops::RandomNormal::new(
+            ArrRng::default(),
+            mean,
+            stddev,
+        )))
+    });
+    r.register("StandardNormal", |_| {
+        Ok(Box::new(random_ops::StandardNormal::new(ArrRng::default())))
+    });
+    r.register("StandardUniform", |_| {
+        Ok(Box::new(
+            random_ops::StandardUniform::new(ArrRng::default()),
+        ))
+    });
+    r.register("Bernoulli", |a| {
+        Ok(Box::new(random_ops::Bernoulli::new(
+            ArrRng::default(),
+            a.get("p")?,
+        )))
+    });
+    r.register("Exponential", |a| {
+        Ok(Box::new(random_ops::Exponential::new(
+            ArrRng::default(),
+            a.get("lambda")?,
+        )))
+    });
+    r.register("LogNormal", |a| {
+        let (mean, stddev) = (a.get("mean")?, a.get("stddev")?);
+        Ok(Box::new(random_ops::LogNormal::new(
+            ArrRng::default(),
+            mean,
+            stddev,
+        )))
+    });
+    r.register("Gamma", |a| {
+        let (shape_param, scale) = (a.get("shape_param")?, a.get("scale")?);
+        Ok(Box::new(random_ops::Gamma::new(
+            ArrRng::default(),
+            shape_param,
+            scale,
+        )))
+    });
+
+    // reduction_ops
+    register_reduce_ops!(r, ReduceSum, ReduceMean, ReduceProd, ReduceMin, ReduceMax);
+    r.register("ArgMax", |a| {
+        Ok(Box::new(reduction_ops::ArgMax {
+            axis: a.get("axis")?,
+            keep_dim: a.get("keep_dim")?,
+        }))
+    });
+    r.register("ReduceGradCommon", |a| {
+        Ok(Box::new(reduction_ops::ReduceGradCommon {
+            should_make_broadcast_dims: a.get("should_make_broadcast_dims")?,
+            sparse_axes: a.get("sparse_axes")?,
+        }))
+    });
+
+    // xent_ops
+    r.register("LogSoftmax", |a| {
+        Ok(Box::new(xent_ops::LogSoftmax {
+            axis: a.get("axis")?,
+        }))
+    });
+}
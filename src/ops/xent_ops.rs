@@ -18,6 +18,10 @@ impl<T: Float> op::Op<T> for LogSoftmax {
         "LogSoftmax"
     }
 
+    fn attributes(&self) -> op::Attributes {
+        op::Attributes::new().with("axis", self.axis)
+    }
+
     fn compute<'v>(
         &self,
         ctx: crate::runtime::OpComputeContext<'v, T>,
@@ -91,12 +95,16 @@ impl<T: Float> op::Op<T> for SparseSoftmaxCrossEntropy {
         // validation
         {
             let t_shape = t.shape();
-            assert_eq!(log_x.ndim(), 2, "Bad first argument's shape");
+            if log_x.ndim() != 2 {
+                return vec![Err(op::ComputeException::Error(
+                    "Bad first argument's shape".to_owned(),
+                ))];
+            }
             let t_rank = t_shape.len();
-            if t_rank == 2 {
-                assert_eq!(t_shape[1], 1, "Bad second argument's shape");
-            } else if t_rank != 1 {
-                panic!("Bad second argument's shape");
+            if (t_rank == 2 && t_shape[1] != 1) || (t_rank != 1 && t_rank != 2) {
+                return vec![Err(op::ComputeException::Error(
+                    "Bad second argument's shape".to_owned(),
+                ))];
             }
         }
 
@@ -0,0 +1,320 @@
+//! Defining things related to profiling of evaluation.
+use crate::op;
+use crate::tensor::Tensor;
+use crate::Float;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::time::Duration;
+
+/// Timing and memory usage of the ops executed in evaluation.
+///
+/// Pass this to `CompiledGraph::run_profiled` or `Eval::run_profiled`.
+/// Records of multiple runs are accumulated one after another.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let ref x = ag::placeholder(&[-1, 3]);
+/// let ref w = ag::variable(ag::ndarray_ext::ones::<f32>(&[3, 2]));
+/// let ref y = ag::sigmoid(&ag::matmul(x, w));
+///
+/// let graph = ag::CompiledGraph::new(&[y], &[x]);
+/// let mut profile = ag::Profile::new();
+/// for _ in 0..3 {
+///     graph.run_profiled(&[ag::ndarray_ext::zeros(&[4, 3]).view()], &mut profile);
+/// }
+///
+/// let by_op = profile.by_op();
+/// let matmul = by_op.iter().find(|a| a.op_name == "MatMul").unwrap();
+/// assert_eq!(matmul.calls, 3);
+/// assert_eq!(matmul.allocated_bytes, 3 * 4 * 2 * 4);
+///
+/// println!("{}", profile);
+/// // Writes a file for chrome://tracing or Perfetto
+/// // std::fs::write("trace.json", profile.to_chrome_trace());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    /// Records of the executed ops in the order of completion.
+    pub records: Vec<OpRecord>,
+    // Total wall time of the profiled runs
+    wall_time: Duration,
+}
+
+/// Record of an op execution.
+#[derive(Clone, Debug)]
+pub struct OpRecord {
+    /// Index of the node in the execution order of the graph; see `Profile::by_node`.
+    pub node: usize,
+    /// `Op::name` of the node.
+    pub op_name: String,
+    /// Name of the node if it's named (see `Tensor::name`).
+    pub tensor_name: Option<String>,
+    /// Start time, measured from the beginning of the first profiled run.
+    pub start: Duration,
+    /// Wall time of `Op::compute`.
+    pub elapsed: Duration,
+    /// 0 for the calling thread, `i + 1` for the `i`-th worker of the rayon thread pool.
+    pub thread: usize,
+    /// Shapes of the output arrays; empty for `ComputeException::NoOutput`.
+    pub output_shapes: Vec<Vec<usize>>,
+    /// Size of the newly allocated output arrays in bytes.
+    ///
+    /// Outputs that are views of the inputs, or input arrays that the op took with
+    /// `OpComputeContext::take_input` and wrote into, don't count.
+    pub allocated_bytes: usize,
+}
+
+/// Aggregated records of an op type or a node.
+#[derive(Clone, Debug)]
+pub struct OpSummary {
+    pub op_name: String,
+    /// Number of executions.
+    pub calls: usize,
+    /// Sum of the wall times.
+    pub total_time: Duration,
+    /// Sum of the allocated bytes.
+    pub allocated_bytes: usize,
+}
+
+impl OpRecord {
+    // `reused[i]` is true if `ys[i]` is an input array taken by the op.
+    pub(crate) fn new<'v, T: Float>(
+        node: usize,
+        tensor: &Tensor<T>,
+        ys: &op::ComputeResults<'v, T>,
+        reused: &[bool],
+        start: Duration,
+        elapsed: Duration,
+    ) -> OpRecord {
+        let mut output_shapes = Vec::with_capacity(ys.len());
+        let mut allocated_bytes = 0;
+        for (y, &reused) in ys.iter().zip(reused) {
+            match y {
+                Ok(crate::ArrRepr::Owned(arr)) => {
+                    output_shapes.push(arr.shape().to_vec());
+                    if !reused {
+                        allocated_bytes += arr.len() * mem::size_of::<T>();
+                    }
+                }
+                Ok(crate::ArrRepr::View(arr)) => output_shapes.push(arr.shape().to_vec()),
+                Err(_) => {}
+            }
+        }
+        OpRecord {
+            node,
+            op_name: tensor.op.name().to_owned(),
+            tensor_name: tensor.name().map(|s| s.to_owned()),
+            start,
+            elapsed,
+            thread: rayon::current_thread_index().map(|i| i + 1).unwrap_or(0),
+            output_shapes,
+            allocated_bytes,
+        }
+    }
+}
+
+impl Profile {
+    /// Creates an empty profile.
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Total wall time of the profiled runs.
+    #[inline]
+    pub fn wall_time(&self) -> Duration {
+        self.wall_time
+    }
+
+    #[inline]
+    pub(crate) fn add_wall_time(&mut self, elapsed: Duration) {
+        self.wall_time += elapsed;
+    }
+
+    /// Aggregates the records by `Op::name`, in descending order of the total time.
+    pub fn by_op(&self) -> Vec<OpSummary> {
+        self.summarize(|r| r.op_name.as_str())
+            .into_iter()
+            .map(|(_, s)| s)
+            .collect()
+    }
+
+    /// Aggregates the records by node, in descending order of the total time.
+    ///
+    /// Each item is paired with `OpRecord::node`. Node indices are only meaningful within
+    /// a graph, so the profile should only hold runs of a single graph (the same
+    /// `CompiledGraph`, or `Eval::run_profiled` with the same targets); otherwise
+    /// unrelated nodes are merged.
+    pub fn by_node(&self) -> Vec<(usize, OpSummary)> {
+        self.summarize(|r| r.node)
+    }
+
+    fn summarize<'a, K, F>(&'a self, key: F) -> Vec<(K, OpSummary)>
+    where
+        K: std::hash::Hash + Eq + Copy,
+        F: Fn(&'a OpRecord) -> K,
+    {
+        let mut map: HashMap<K, usize> = HashMap::new();
+        let mut ret: Vec<(K, OpSummary)> = Vec::new();
+        for r in &self.records {
+            let i = *map.entry(key(r)).or_insert_with(|| {
+                ret.push((
+                    key(r),
+                    OpSummary {
+                        op_name: r.op_name.clone(),
+                        calls: 0,
+                        total_time: Duration::default(),
+                        allocated_bytes: 0,
+                    },
+                ));
+                ret.len() - 1
+            });
+            let s = &mut ret[i].1;
+            s.calls += 1;
+            s.total_time += r.elapsed;
+            s.allocated_bytes += r.allocated_bytes;
+        }
+        // Stable sort keeps the order of first execution among ties.
+        ret.sort_by_key(|a| Reverse(a.1.total_time));
+        ret
+    }
+
+    /// Returns the records in Chrome trace-event JSON.
+    ///
+    /// The result can be opened in `chrome://tracing` or Perfetto.
+    pub fn to_chrome_trace(&self) -> String {
+        let events = self
+            .records
+            .iter()
+            .map(|r| {
+                let tensor_name = match r.tensor_name {
+                    Some(ref name) => format!(",\"tensor_name\":\"{}\"", escape_json(name)),
+                    None => String::new(),
+                };
+                format!(
+                    "{{\"name\":\"{}\",\"cat\":\"op\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":0,\"tid\":{},\
+                     \"args\":{{\"node\":{},\"output_shapes\":{:?},\"allocated_bytes\":{}{}}}}}",
+                    escape_json(&r.op_name),
+                    micros(r.start),
+                    micros(r.elapsed),
+                    r.thread,
+                    r.node,
+                    r.output_shapes,
+                    r.allocated_bytes,
+                    tensor_name
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("{{\"traceEvents\":[{}]}}", events.join(","))
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<24} {:>8} {:>12} {:>12} {:>14}",
+            "op", "calls", "total(ms)", "mean(us)", "allocated(B)"
+        )?;
+        for s in self.by_op() {
+            writeln!(
+                f,
+                "{:<24} {:>8} {:>12.3} {:>12.1} {:>14}",
+                s.op_name,
+                s.calls,
+                micros(s.total_time) / 1000.,
+                micros(s.total_time) / s.calls as f64,
+                s.allocated_bytes
+            )?;
+        }
+        write!(f, "wall time: {:.3}ms", micros(self.wall_time) / 1000.)
+    }
+}
+
+#[inline]
+fn micros(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1e6 + f64::from(d.subsec_nanos()) / 1e3
+}
+
+fn escape_json(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_profile() {
+    let ref x = crate::ops::placeholder::<f32>(&[2, 3]);
+    let ref a = crate::ops::reshape(x, &[3, 2]);
+    let ref b = crate::ops::matmul(x, a);
+    let graph = crate::CompiledGraph::new(&[b], &[x]);
+    let mut profile = Profile::new();
+    let arr = crate::ndarray_ext::ones::<f32>(&[2, 3]);
+    graph.run_profiled(&[arr.view()], &mut profile);
+    graph.run_profiled(&[arr.view()], &mut profile);
+
+    let by_node = profile.by_node();
+    assert_eq!(by_node.len() * 2, profile.records.len());
+    assert!(by_node.iter().all(|(_, s)| s.calls == 2));
+    let reshape = profile
+        .records
+        .iter()
+        .find(|r| r.op_name == "Reshape")
+        .unwrap();
+    assert_eq!(reshape.output_shapes, vec![vec![3, 2]]);
+    // Reshape returns a view.
+    assert_eq!(reshape.allocated_bytes, 0);
+    let matmul = profile.by_op().into_iter().find(|s| s.op_name == "MatMul");
+    assert_eq!(matmul.unwrap().allocated_bytes, 2 * 2 * 2 * 4);
+
+    // Runs don't overlap.
+    let last = profile.records.iter().map(|r| r.start).max().unwrap();
+    assert!(last < profile.wall_time());
+    let first_run_end = profile.records[..by_node.len()]
+        .iter()
+        .map(|r| r.start + r.elapsed)
+        .max()
+        .unwrap();
+    assert!(profile.records[by_node.len()..]
+        .iter()
+        .all(|r| r.start >= first_run_end));
+
+    let trace = profile.to_chrome_trace();
+    assert!(trace.starts_with("{\"traceEvents\":[{\"name\":"));
+    assert_eq!(trace.matches("\"ph\":\"X\"").count(), profile.records.len());
+}
+
+#[test]
+fn test_inplace_allocations() {
+    let ref x = crate::ops::placeholder::<f32>(&[2, 3]);
+    let ref a = crate::ops::reshape(x, &[3, 2]);
+    let ref c = crate::ops::add_inplace(crate::ops::matmul(x, a), crate::ops::matmul(x, a));
+    let graph = crate::CompiledGraph::new(&[c], &[x]);
+    let mut profile = Profile::new();
+    let arr = crate::ndarray_ext::ones::<f32>(&[2, 3]);
+    graph.run_profiled(&[arr.view()], &mut profile);
+
+    let add = profile
+        .records
+        .iter()
+        .find(|r| r.op_name == "InplaceAdd")
+        .unwrap();
+    assert_eq!(add.output_shapes, vec![vec![2, 2]]);
+    // The output is the lhs buffer.
+    assert_eq!(add.allocated_bytes, 0);
+}
+
+#[test]
+fn test_escape_json() {
+    assert_eq!(escape_json("a\"b\\c\n"), "a\\\"b\\\\c\\u000a");
+}
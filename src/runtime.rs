@@ -1,10 +1,19 @@
 use crate::ndarray_ext::{NdArray, NdArrayView};
 use crate::op;
+use crate::profiler::{OpRecord, Profile};
 use crate::tensor::Tensor;
 use crate::Float;
 use ndarray;
+use rayon::iter::*;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
 use std::mem;
-use std::rc::Rc;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::{Arc, Mutex, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
 
 /// Helper structure for batched evaluation.
 ///
@@ -26,12 +35,34 @@ use std::rc::Rc;
 /// ```
 pub struct Eval<'k, T: Float> {
     buf: Vec<&'k Tensor<T>>,
+    parallel: bool,
+    check_numerics: bool,
 }
 
 impl<'c, 'k, 'v, T: Float> Eval<'k, T> {
     /// Instantiates a new evaluation session.
     pub fn new() -> Self {
-        Eval { buf: Vec::new() }
+        Eval {
+            buf: Vec::new(),
+            parallel: false,
+            check_numerics: false,
+        }
+    }
+
+    /// Enables or disables parallel execution of independent nodes.
+    ///
+    /// See [CompiledGraph::set_parallel](struct.CompiledGraph.html#method.set_parallel).
+    pub fn set_parallel(&mut self, parallel: bool) -> &mut Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// Enables or disables the check for NaN and infinity in every op output.
+    ///
+    /// See [CompiledGraph::set_check_numerics](struct.CompiledGraph.html#method.set_check_numerics).
+    pub fn set_check_numerics(&mut self, check: bool) -> &mut Self {
+        self.check_numerics = check;
+        self
     }
 
     /// Appends a tensor to the back of the evaluation targets.
@@ -53,78 +84,301 @@ impl<'c, 'k, 'v, T: Float> Eval<'k, T> {
     ///
     /// `feeds` is a stream of `(placeholder tensor, its value)`
     pub fn run(&'k self, feeds: &'c [crate::runtime::Feed<'k, 'v, T>]) -> Vec<Option<NdArray<T>>> {
-        eval(&self.buf, feeds)
+        match self.try_run(feeds) {
+            Ok(ret) => ret,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Evaluates the buffered tensors.
+    ///
+    /// Same as `run` except that errors are reported by the return value.
+    /// See also [try_eval](../fn.try_eval.html).
+    pub fn try_run(
+        &'k self,
+        feeds: &'c [crate::runtime::Feed<'k, 'v, T>],
+    ) -> Result<Vec<Option<NdArray<T>>>, EvalError> {
+        let graph = self.compile();
+        let feeds = graph.feed_slots(feeds)?;
+        graph.try_run(&feeds)
+    }
+
+    /// Evaluates the buffered tensors same as `run`, recording each op execution into `profile`.
+    ///
+    /// See [Profile](profiler/struct.Profile.html).
+    pub fn run_profiled(
+        &'k self,
+        feeds: &'c [crate::runtime::Feed<'k, 'v, T>],
+        profile: &mut Profile,
+    ) -> Vec<Option<NdArray<T>>> {
+        let graph = self.compile();
+        match graph.feed_slots(feeds) {
+            Ok(feeds) => graph.run_profiled(&feeds, profile),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn compile(&self) -> CompiledGraph<T> {
+        let no_placeholders: &[&Tensor<T>] = &[];
+        let mut graph =
+            CompiledGraph::compile(&self.buf, no_placeholders, FeedMode::AppendUnlisted);
+        graph
+            .set_parallel(self.parallel)
+            .set_check_numerics(self.check_numerics);
+        graph
     }
 }
 
-// Context in evaluation of `node`
-pub struct OpComputeContext<'v, T: Float> {
-    nodes: Vec<Tensor<T>>,
-    xs: Vec<NdArrayView<'v, T>>,
+/// Error in evaluation of a graph.
+///
+/// Returned by `ag::try_eval` and `ag::Eval::try_run`.
+#[derive(Clone, Debug)]
+pub struct EvalError {
+    /// `Op::name` of the node that failed.
+    pub op_name: String,
+    /// Name of the node that failed if it's named (see `Tensor::name`).
+    pub tensor_name: Option<String>,
+    /// Input nodes of the failed op, formatted as `Tensor`'s `Debug`, which is the
+    /// `Op::name` if unnamed.
+    pub input_names: Vec<String>,
+    /// Shapes of the input arrays given to the failed op.
+    pub input_shapes: Vec<Vec<usize>>,
+    /// What went wrong.
+    ///
+    /// Boxed to keep `Result<_, EvalError>` small.
+    pub kind: Box<EvalErrorKind>,
 }
 
-impl<'v, T: Float> OpComputeContext<'v, T> {
-    #[inline]
-    pub fn new(nodes: Vec<Tensor<T>>, xs: Vec<NdArrayView<'v, T>>) -> Self {
-        OpComputeContext { nodes, xs }
-    }
+/// Reason of an `EvalError`.
+#[derive(Clone, Debug)]
+pub enum EvalErrorKind {
+    /// A placeholder reachable from the targets was not fed.
+    PlaceholderUnfilled,
+    /// A fed array doesn't match the placeholder's shape.
+    PlaceholderShapeMismatch {
+        expected: Vec<isize>,
+        actual: Vec<usize>,
+    },
+    /// `Op::compute` returned `ComputeException::Error`.
+    ComputeFailed(String),
+    /// `Op::compute` panicked.
+    Panicked(String),
+    /// An output array contains NaN or infinity.
+    ///
+    /// Reported only if `set_check_numerics(true)` is given.
+    NonFinite {
+        /// Index of the output array
+        output_index: usize,
+        stats: NumericStats,
+        /// `true` if the op is a part of a gradient graph, i.e. the value appeared in backprop.
+        in_gradient_graph: bool,
+    },
+}
 
-    #[inline]
-    pub fn node(&self, idx: usize) -> &Tensor<T> {
-        &self.nodes[idx]
+/// Statistics of an array containing NaN or infinity.
+#[derive(Clone, Debug)]
+pub struct NumericStats {
+    pub shape: Vec<usize>,
+    pub nan_count: usize,
+    pub pos_inf_count: usize,
+    pub neg_inf_count: usize,
+    /// Minimum of the finite elements; NaN if there are none.
+    pub min: f64,
+    /// Maximum of the finite elements; NaN if there are none.
+    pub max: f64,
+    /// Mean of the finite elements; NaN if there are none.
+    pub mean: f64,
+}
+
+impl NumericStats {
+    // Returns `None` if all the elements are finite.
+    fn of<T: Float>(arr: &NdArrayView<T>) -> Option<NumericStats> {
+        if arr.iter().all(|a| a.is_finite()) {
+            return None;
+        }
+        let mut stats = NumericStats {
+            shape: arr.shape().to_vec(),
+            nan_count: 0,
+            pos_inf_count: 0,
+            neg_inf_count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0.,
+        };
+        let mut finite_count = 0;
+        for &a in arr.iter() {
+            if a.is_nan() {
+                stats.nan_count += 1;
+            } else if a.is_infinite() {
+                if a > T::zero() {
+                    stats.pos_inf_count += 1;
+                } else {
+                    stats.neg_inf_count += 1;
+                }
+            } else {
+                let a = a.to_f64().unwrap();
+                stats.min = stats.min.min(a);
+                stats.max = stats.max.max(a);
+                stats.mean += a;
+                finite_count += 1;
+            }
+        }
+        if finite_count == 0 {
+            stats.min = f64::NAN;
+            stats.max = f64::NAN;
+            stats.mean = f64::NAN;
+        } else {
+            stats.mean /= finite_count as f64;
+        }
+        Some(stats)
     }
+}
 
-    #[inline]
-    pub fn grab_inputs(&self) -> &[NdArrayView<'v, T>] {
-        self.xs.as_slice()
+impl fmt::Display for NumericStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} NaN, {} +inf, {} -inf in shape {:?}; finite min={}, max={}, mean={}",
+            self.nan_count,
+            self.pos_inf_count,
+            self.neg_inf_count,
+            self.shape,
+            self.min,
+            self.max,
+            self.mean
+        )
     }
 }
 
-#[derive(Clone, Copy)]
-enum ValueType {
-    Owned,
-    View,
-    NoOutput,
+impl fmt::Display for EvalErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalErrorKind::PlaceholderUnfilled => write!(f, "placeholder unfilled"),
+            EvalErrorKind::PlaceholderShapeMismatch { expected, actual } => write!(
+                f,
+                "placeholder required {:?}, but got {:?}",
+                expected, actual
+            ),
+            EvalErrorKind::ComputeFailed(reason) => write!(f, "{}", reason),
+            EvalErrorKind::Panicked(reason) => write!(f, "panicked: {}", reason),
+            EvalErrorKind::NonFinite {
+                output_index,
+                stats,
+                in_gradient_graph,
+            } => write!(
+                f,
+                "output {} in the {} graph is not finite: {}",
+                output_index,
+                if *in_gradient_graph {
+                    "gradient"
+                } else {
+                    "forward"
+                },
+                stats
+            ),
+        }
+    }
 }
 
-struct ValueInfo<T: Float> {
-    ty: ValueType,
-    // key to lookup the value
-    key: usize,
-    // Owned array
-    value: Option<NdArray<T>>,
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.tensor_name {
+            Some(ref name) => write!(f, "{} ({})", name, self.op_name)?,
+            None => write!(f, "{}", self.op_name)?,
+        }
+        write!(
+            f,
+            " failed: {} (inputs: {:?}, input shapes: {:?})",
+            self.kind, self.input_names, self.input_shapes
+        )
+    }
 }
 
-impl<T: Float> ValueInfo<T> {
+impl error::Error for EvalError {}
+
+impl EvalError {
     #[inline]
-    fn new(ty: ValueType, key: usize) -> ValueInfo<T> {
-        ValueInfo {
-            ty,
-            key,
-            value: None,
+    fn new<T: Float>(node: &Tensor<T>, input_shapes: Vec<Vec<usize>>, kind: EvalErrorKind) -> Self {
+        EvalError {
+            op_name: node.op.name().to_owned(),
+            tensor_name: node.name().map(|s| s.to_owned()),
+            input_names: node.inputs.iter().map(|x| x.label()).collect(),
+            input_shapes,
+            kind: Box::new(kind),
         }
     }
 }
 
-struct NodeWithValueInfo<'k, T: Float> {
-    node: &'k Tensor<T>,
-    info_list: Vec<ValueInfo<T>>,
-    contains_no_output: bool,
+// Extracts the message of a panic caught in `Op::compute`.
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
+// Context in evaluation of `node`
+pub struct OpComputeContext<'v, T: Float> {
+    nodes: Vec<Tensor<T>>,
+    xs: Vec<NdArrayView<'v, T>>,
+    // Input arrays that nobody reads after this op
+    xs_mut: Vec<Option<Consumable<T>>>,
+    // Input variables that this op can update
+    vars_mut: Vec<Option<VariableMut<T>>>,
 }
 
-impl<'k, T: Float> Tensor<T> {
+impl<'v, T: Float> OpComputeContext<'v, T> {
     #[inline]
-    fn with_value_info(
-        &'k self,
-        info_list: Vec<ValueInfo<T>>,
-        contains_no_output: bool,
-    ) -> NodeWithValueInfo<'k, T> {
-        NodeWithValueInfo {
-            node: self,
-            info_list,
-            contains_no_output,
+    pub fn new(nodes: Vec<Tensor<T>>, xs: Vec<NdArrayView<'v, T>>) -> Self {
+        OpComputeContext {
+            nodes,
+            xs,
+            xs_mut: Vec::new(),
+            vars_mut: Vec::new(),
+        }
+    }
+
+    /// Takes the `i`-th input array if no other node reads it after this op.
+    ///
+    /// An op can write its result into the array to avoid allocation, and then return
+    /// it as an `ArrRepr::Owned`. `grab_inputs()[i]` must not be read after this.
+    /// Returns `None` if the array is still used, e.g. by another node, as an evaluation
+    /// target, or as a variable, constant or placeholder.
+    #[inline]
+    pub fn take_input(&mut self, i: usize) -> Option<NdArray<T>> {
+        match self.xs_mut.get(i) {
+            Some(Some(cell)) => cell.lock().unwrap().take(),
+            _ => None,
+        }
+    }
+
+    /// Returns the array of the `i`-th input to update it if it's a variable.
+    ///
+    /// Only ops with side effects (see `Op::has_side_effects`) can update variables, and
+    /// the evaluation holds the write lock of them meanwhile.
+    /// `grab_inputs()[i]` must not be read while the returned reference is used.
+    /// Returns `None` the second time.
+    #[inline]
+    pub fn input_variable_mut(&mut self, i: usize) -> Option<&'v mut NdArray<T>> {
+        match self.vars_mut.get_mut(i).and_then(Option::take) {
+            // The variable is locked until the end of the evaluation.
+            Some(var) => Some(unsafe { &mut *var.0 }),
+            None => None,
         }
     }
+
+    #[inline]
+    pub fn node(&self, idx: usize) -> &Tensor<T> {
+        &self.nodes[idx]
+    }
+
+    #[inline]
+    pub fn grab_inputs(&self) -> &[NdArrayView<'v, T>] {
+        self.xs.as_slice()
+    }
 }
 
 /// An object sent to `ag::Eval`, `ag::eval` or `Tensor::eval` to fill a placeholder tensor
@@ -146,215 +400,956 @@ pub struct Feed<'k, 'f, T: Float>(
     pub ndarray::ArrayView<'f, T, ndarray::IxDyn>, // its value
 );
 
-/// Evaluates given symbolic tensors.
+// Location of an array that a step reads.
+#[derive(Clone, Copy, PartialEq)]
+enum Source {
+    // Persistent array of `CompiledGraph::persistents[i]`
+    Persistent(usize),
+    // Value of the `i`-th feed slot
+    Feed(usize),
+    // `j`-th output of `CompiledGraph::steps[i]`
+    Output(usize, usize),
+}
+
+// How `CompiledGraph::compile` treats the feed slots
+#[derive(Clone, Copy, PartialEq)]
+enum FeedMode {
+    // Every placeholder reachable from the targets must be listed.
+    Listed,
+    // Placeholders missing in the list are appended to the feed slots.
+    AppendUnlisted,
+    // Any tensors can be listed; the nodes behind them are not visited.
+    Any,
+}
+
+// A node to be computed in evaluation, with resolved locations of its inputs.
+struct Step<T: Float> {
+    node: Tensor<T>,
+    inputs: Vec<Source>,
+}
+
+// Execution order of the steps and liveness of their values.
+struct Schedule {
+    // Groups of steps run one after another; steps in a group are independent of each other.
+    waves: Vec<Vec<usize>>,
+    // `release_after[w]` lists the steps whose values are dead once `waves[w]` has run
+    release_after: Vec<Vec<usize>>,
+    // `consumes[i][j]` is true if `steps[i]` is the only and last reader of its `j`-th input
+    consumes: Vec<Vec<bool>>,
+}
+
+impl Schedule {
+    // Sequential schedule runs the steps one by one in DFS order.
+    //
+    // Parallel schedule puts each pure step into the first wave after all of its inputs,
+    // while a step with side effects makes a wave of its own that no other step crosses.
+    // So side effects are ordered as in the sequential one.
+    fn new<T: Float>(steps: &[Step<T>], target_sources: &[Source], parallel: bool) -> Schedule {
+        let mut waves: Vec<Vec<usize>> = Vec::new();
+        let mut wave_of = Vec::with_capacity(steps.len());
+        // The first wave that the next step can join
+        let mut barrier = 0;
+        for (i, step) in steps.iter().enumerate() {
+            let w = if !parallel || step.node.op.has_side_effects() {
+                barrier = waves.len() + 1;
+                waves.len()
+            } else {
+                step.inputs
+                    .iter()
+                    .filter_map(|src| match *src {
+                        Source::Output(k, _) => Some(wave_of[k] + 1),
+                        _ => None,
+                    })
+                    .fold(barrier, usize::max)
+            };
+            if w == waves.len() {
+                waves.push(Vec::new());
+            }
+            waves[w].push(i);
+            wave_of.push(w);
+        }
+
+        // Liveness: a value is dead after its last consumer unless it's a target.
+        let mut last_use: Vec<Option<usize>> = vec![None; steps.len()];
+        for (i, step) in steps.iter().enumerate() {
+            for src in &step.inputs {
+                if let Source::Output(k, _) = *src {
+                    last_use[k] = last_use[k].max(Some(wave_of[i]));
+                }
+            }
+        }
+        for src in target_sources {
+            if let Source::Output(k, _) = *src {
+                last_use[k] = None;
+            }
+        }
+        let mut release_after = vec![Vec::new(); waves.len()];
+        for (k, &last) in last_use.iter().enumerate() {
+            if let Some(w) = last {
+                release_after[w].push(k);
+            }
+        }
+
+        // A step can overwrite an input only if no other read of it remains in the wave.
+        let consumes = steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                let w = wave_of[i];
+                let reads = |k| {
+                    waves[w]
+                        .iter()
+                        .flat_map(|&i2| &steps[i2].inputs)
+                        .filter(|src| match **src {
+                            Source::Output(k2, _) => k2 == k,
+                            _ => false,
+                        })
+                        .count()
+                };
+                step.inputs
+                    .iter()
+                    .map(|src| match *src {
+                        Source::Output(k, _) => last_use[k] == Some(w) && reads(k) == 1,
+                        _ => false,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Schedule {
+            waves,
+            release_after,
+            consumes,
+        }
+    }
+}
+
+/// Evaluation plan of a graph, built once and run many times.
 ///
-/// Each return value can be `None`;
-/// for example, evaluation of `gradient_descent_ops::*`
-/// would result in `None`.
+/// `ag::eval` traverses the graph on every call.
+/// `CompiledGraph` does that only once at construction: the execution order of nodes,
+/// the liveness of each intermediate value and the feed slots of placeholders are
+/// computed beforehand, so that `run` only executes ops.
+/// This pays off when the same tensors are evaluated many times, e.g. update ops in a training loop.
 ///
-/// NOTE: All the runtime errors are not reported by return values, but by "panic"
-/// for convenience.
+/// Values for placeholders are given to `run` in the order of `placeholders` passed to `new`.
 ///
 /// ```
 /// extern crate ndarray;
 /// extern crate autograd as ag;
 ///
-/// let ref a = ag::zeros(&[2]);
-/// let ref b = ag::ones(&[2]);
+/// let ref x = ag::placeholder(&[-1]);
+/// let ref y = 2. * x;
+/// let ref z = y * y;
 ///
-/// // eval two tensors at once.
-/// let evaluated = ag::eval(&[a, b], &[]);
-/// assert_eq!(evaluated[0], Some(ndarray::arr1(&[0., 0.]).into_dyn()));
-/// assert_eq!(evaluated[1], Some(ndarray::arr1(&[1., 1.]).into_dyn()));
+/// let graph = ag::CompiledGraph::new(&[y, z], &[x]);
+///
+/// for i in 0..3 {
+///     let arr = ndarray::arr1(&[i as f64]).into_dyn();
+///     let ret = graph.run(&[arr.view()]);
+///     assert_eq!(ret[0], Some(ndarray::arr1(&[(2 * i) as f64]).into_dyn()));
+///     assert_eq!(ret[1], Some(ndarray::arr1(&[(4 * i * i) as f64]).into_dyn()));
+/// }
 /// ```
-#[allow(mutable_transmutes, unused_mut)]
-pub fn eval<'slice, 'node, 'feed, K, T>(
-    tensors: &'node [K],
-    feeds: &'slice [Feed<'node, 'feed, T>],
-) -> Vec<Option<NdArray<T>>>
-where
-    K: AsRef<Tensor<T>>,
-    T: Float,
-{
-    let mut node_info_storage: Vec<NodeWithValueInfo<T>> = Vec::new();
-    let mut owned_storage: Vec<NdArray<T>> = Vec::new();
+pub struct CompiledGraph<T: Float> {
+    targets: Vec<Tensor<T>>,
+    target_sources: Vec<Source>,
+    // Non-source nodes in execution order
+    steps: Vec<Step<T>>,
+    // Variables and constants
+    persistents: Vec<Tensor<T>>,
+    // Whether each of `persistents` is a variable read by an op with side effects
+    updated: Vec<bool>,
+    // Feed slots
+    placeholders: Vec<Tensor<T>>,
+    schedule: Schedule,
+    parallel: bool,
+    check_numerics: bool,
+}
+
+impl<T: Float> CompiledGraph<T> {
+    /// Compiles the subgraph needed to evaluate `targets`.
+    ///
+    /// `placeholders` defines the feed slots.
+    ///
+    /// # Panics
+    ///
+    /// In case a placeholder reachable from `targets` is missing in `placeholders`.
+    pub fn new<K, P>(targets: &[K], placeholders: &[P]) -> CompiledGraph<T>
+    where
+        K: AsRef<Tensor<T>>,
+        P: AsRef<Tensor<T>>,
+    {
+        CompiledGraph::compile(targets, placeholders, FeedMode::Listed)
+    }
 
+    // Same as `new` except that `feeds` can be any tensors, whose values are given
+    // instead of computed.
+    pub(crate) fn with_feeds<K, P>(targets: &[K], feeds: &[P]) -> CompiledGraph<T>
+    where
+        K: AsRef<Tensor<T>>,
+        P: AsRef<Tensor<T>>,
     {
-        let mut view_storage: Vec<NdArrayView<T>> = Vec::new();
-        let mut feed_store: Vec<NdArrayView<'feed, T>> = Vec::new();
+        CompiledGraph::compile(targets, feeds, FeedMode::Any)
+    }
+
+    /// Evaluation targets in the order given to `new`.
+    #[inline]
+    pub fn targets(&self) -> &[Tensor<T>] {
+        self.targets.as_slice()
+    }
+
+    /// Feed slots in the order given to `new`.
+    #[inline]
+    pub fn placeholders(&self) -> &[Tensor<T>] {
+        self.placeholders.as_slice()
+    }
+
+    /// Enables or disables parallel execution (disabled by default).
+    ///
+    /// In parallel mode, nodes independent of each other, e.g. the gate slices of an LSTM
+    /// or multiple loss heads, are computed concurrently on the rayon thread pool.
+    /// Ops with side effects (see `Op::has_side_effects`) are still run one by one in the
+    /// same order as the sequential mode, so results are deterministic.
+    ///
+    /// ```
+    /// extern crate ndarray;
+    /// extern crate autograd as ag;
+    ///
+    /// let ref x = ag::placeholder(&[2]);
+    /// let ref a = ag::sigmoid(x);
+    /// let ref b = ag::tanh(x);
+    ///
+    /// let mut graph = ag::CompiledGraph::new(&[a * b], &[x]);
+    /// graph.set_parallel(true);
+    ///
+    /// let arr = ndarray::arr1(&[0., 1.]).into_dyn();
+    /// assert_eq!(graph.run(&[arr.view()]), ag::eval(&[a * b], &[ag::Feed(x, arr.view())]));
+    /// ```
+    pub fn set_parallel(&mut self, parallel: bool) -> &mut Self {
+        if parallel != self.parallel {
+            self.schedule = Schedule::new(&self.steps, &self.target_sources, parallel);
+            self.parallel = parallel;
+        }
+        self
+    }
+
+    /// Returns `true` if parallel execution is enabled.
+    #[inline]
+    pub fn is_parallel(&self) -> bool {
+        self.parallel
+    }
+
+    /// Enables or disables the check for NaN and infinity (disabled by default).
+    ///
+    /// When enabled, every output array of every op is scanned, and the evaluation stops
+    /// at the first op that produced a non-finite value with `EvalErrorKind::NonFinite`.
+    /// The error tells whether the op belongs to a gradient graph made by `ag::grad`.
+    ///
+    /// ```
+    /// extern crate ndarray;
+    /// extern crate autograd as ag;
+    ///
+    /// let ref x = ag::placeholder(&[2]);
+    /// let ref y = ag::sqrt(x);
+    /// let ref g = ag::grad(&[y], &[x])[0];
+    ///
+    /// let mut graph = ag::CompiledGraph::new(&[y, g], &[x]);
+    /// graph.set_check_numerics(true);
+    ///
+    /// let arr = ndarray::arr1(&[0., 1.]).into_dyn();
+    /// let err = graph.try_run(&[arr.view()]).unwrap_err();
+    /// match *err.kind {
+    ///     ag::EvalErrorKind::NonFinite { in_gradient_graph, ref stats, .. } => {
+    ///         assert!(in_gradient_graph);
+    ///         assert_eq!(stats.pos_inf_count, 1);
+    ///     }
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn set_check_numerics(&mut self, check: bool) -> &mut Self {
+        self.check_numerics = check;
+        self
+    }
+
+    /// Evaluates the targets.
+    ///
+    /// `feeds[i]` fills `self.placeholders()[i]`.
+    ///
+    /// NOTE: Runtime errors are reported by "panic" as `ag::eval` does.
+    pub fn run(&self, feeds: &[NdArrayView<T>]) -> Vec<Option<NdArray<T>>> {
+        match self.try_run(feeds) {
+            Ok(ret) => ret,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Evaluates the targets, reporting runtime errors by the return value.
+    ///
+    /// `feeds[i]` fills `self.placeholders()[i]`.
+    pub fn try_run(&self, feeds: &[NdArrayView<T>]) -> Result<Vec<Option<NdArray<T>>>, EvalError> {
+        self.validate_feeds(feeds)?;
+        self.execute(feeds, None)
+    }
+
+    /// Evaluates the targets same as `run`, recording each op execution into `profile`.
+    ///
+    /// See [Profile](profiler/struct.Profile.html).
+    pub fn run_profiled(
+        &self,
+        feeds: &[NdArrayView<T>],
+        profile: &mut Profile,
+    ) -> Vec<Option<NdArray<T>>> {
+        match self
+            .validate_feeds(feeds)
+            .and_then(|_| self.execute(feeds, Some(profile)))
+        {
+            Ok(ret) => ret,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn validate_feeds(&self, feeds: &[NdArrayView<T>]) -> Result<(), EvalError> {
+        for (i, placeholder) in self.placeholders.iter().enumerate() {
+            let feed = match feeds.get(i) {
+                Some(feed) => feed,
+                None => {
+                    return Err(EvalError::new(
+                        placeholder,
+                        vec![],
+                        EvalErrorKind::PlaceholderUnfilled,
+                    ))
+                }
+            };
+            // Only placeholders have known shapes.
+            let known_shape = match placeholder.known_shape {
+                Some(ref known_shape) => known_shape,
+                None => continue,
+            };
+            if !known_shape.validate(feed.shape()) {
+                return Err(EvalError::new(
+                    placeholder,
+                    vec![],
+                    EvalErrorKind::PlaceholderShapeMismatch {
+                        expected: known_shape.get().to_vec(),
+                        actual: feed.shape().to_vec(),
+                    },
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn compile<K, P>(targets: &[K], placeholders: &[P], mode: FeedMode) -> CompiledGraph<T>
+    where
+        K: AsRef<Tensor<T>>,
+        P: AsRef<Tensor<T>>,
+    {
+        let mut steps: Vec<Step<T>> = Vec::new();
+        let mut persistents: Vec<Tensor<T>> = Vec::new();
+        let mut feed_slots: Vec<Tensor<T>> = Vec::new();
+        // `Tensor::id` => location of its value
+        let mut lookup: HashMap<usize, Source> = HashMap::new();
+
+        for p in placeholders {
+            let p = p.as_ref();
+            assert!(
+                p.is_placeholder || mode == FeedMode::Any,
+                "{} is not a placeholder",
+                p.label()
+            );
+            if let Entry::Vacant(ent) = lookup.entry(p.id()) {
+                ent.insert(Source::Feed(feed_slots.len()));
+                feed_slots.push(p.clone());
+            }
+        }
 
         let mut dfs_stack = Vec::<(&Tensor<T>, bool)>::with_capacity(100);
-        for t in tensors.iter() {
+        // Reversed so that the targets are visited, and their side effects occur, in order.
+        for t in targets.iter().rev() {
             dfs_stack.push((t.as_ref(), false));
         }
 
-        // Obtain array resources while visiting nodes in topological order.
+        // Resolve the execution order and the locations of input arrays.
         // Stack-based depth-first-search is used to avoid stack overflow in explicit recursion.
         while let Some((node, is_parent)) = dfs_stack.pop() {
             if is_parent {
                 // Visit this node
+                if lookup.contains_key(&node.id()) {
+                    continue;
+                }
                 if node.is_placeholder {
-                    node.resource_lookup_key.set(feed_store.len());
-                    let mut found = None;
-                    for feed in feeds {
-                        if Rc::ptr_eq(feed.0, node) {
-                            let clone = feed.1.clone();
-                            if !node.known_shape.as_ref().unwrap().validate(clone.shape()) {
-                                panic!(
-                                    "Shape error: placeholder required {:?}, but got {:?}",
-                                    node.known_shape.as_ref().unwrap().get(),
-                                    clone.shape()
-                                );
-                            }
-                            found = Some(clone);
-
-                            break;
-                        }
-                    }
-                    unsafe {
-                        mem::transmute::<_, &mut Vec<_>>(&feed_store)
-                            .push(found.expect("Placeholder unfilled."))
-                    }
+                    assert!(
+                        mode == FeedMode::AppendUnlisted,
+                        "Placeholder reachable from the targets is not given."
+                    );
+                    lookup.insert(node.id(), Source::Feed(feed_slots.len()));
+                    feed_slots.push(node.clone());
+                } else if node.has_persistent_array() {
+                    lookup.insert(node.id(), Source::Persistent(persistents.len()));
+                    persistents.push(node.clone());
                 } else {
-                    if visited(node, &node_info_storage) {
-                        continue;
+                    let inputs = node
+                        .inputs
+                        .iter()
+                        .zip(&node.input_indices)
+                        .map(|(x, &i)| source_of(x, i, &lookup))
+                        .collect();
+                    lookup.insert(node.id(), Source::Output(steps.len(), 0));
+                    steps.push(Step {
+                        node: node.clone(),
+                        inputs,
+                    });
+                }
+            } else {
+                // Update dfs stack
+                dfs_stack.push((node, true));
+                // Push children if needed
+                for child in &node.inputs {
+                    if !lookup.contains_key(&child.id()) {
+                        dfs_stack.push((child, false));
                     }
-                    node.resource_lookup_key.set(node_info_storage.len());
-                    if !node.has_persistent_array() {
-                        // Aggregate input arrays
-                        let mut err = None;
-                        let mut xs = Vec::with_capacity(node.inputs.len());
-                        for (x, &i) in node.inputs.iter().zip(&node.input_indices) {
-                            if let Some(per) = x.get_persistent_array() {
-                                xs.push(per.view());
-                            } else if x.is_placeholder {
-                                xs.push(feed_store[x.resource_lookup_key.get()].view());
-                            } else {
-                                // Require computed outputs
-                                let k = x.resource_lookup_key.get();
-                                if node_info_storage[k].contains_no_output {
-                                    err = Some(vec![Err(op::ComputeException::NoOutput)]);
-                                    break;
-                                } else {
-                                    let info = &node_info_storage[k].info_list[i];
-                                    match info.ty {
-                                        ValueType::Owned => {
-                                            xs.push(owned_storage[info.key].view());
-                                        }
-                                        ValueType::View => {
-                                            // Clone the view
-                                            xs.push(view_storage[info.key].clone());
-                                        }
-                                        ValueType::NoOutput => {
-                                            err = Some(vec![Err(op::ComputeException::NoOutput)]);
-                                            break;
-                                        }
-                                    }
-                                }
+                }
+            }
+        }
+
+        let target_sources = targets
+            .iter()
+            .map(|t| source_of(t.as_ref(), 0, &lookup))
+            .collect::<Vec<_>>();
+        let schedule = Schedule::new(&steps, &target_sources, false);
+
+        let mut updated = vec![false; persistents.len()];
+        for step in steps.iter().filter(|s| s.node.op.has_side_effects()) {
+            for src in &step.inputs {
+                if let Source::Persistent(k) = *src {
+                    updated[k] = persistents[k].is_variable();
+                }
+            }
+        }
+
+        CompiledGraph {
+            targets: targets.iter().map(|t| t.as_ref().clone()).collect(),
+            target_sources,
+            steps,
+            persistents,
+            updated,
+            placeholders: feed_slots,
+            schedule,
+            parallel: false,
+            check_numerics: false,
+        }
+    }
+
+    // Resolves `feeds` into the feed slots.
+    fn feed_slots<'k, 'f>(
+        &self,
+        feeds: &[Feed<'k, 'f, T>],
+    ) -> Result<Vec<NdArrayView<'f, T>>, EvalError> {
+        let mut ret = Vec::with_capacity(self.placeholders.len());
+        for placeholder in &self.placeholders {
+            match feeds.iter().find(|feed| Arc::ptr_eq(feed.0, placeholder)) {
+                Some(feed) => ret.push(feed.1.clone()),
+                None => {
+                    return Err(EvalError::new(
+                        placeholder,
+                        vec![],
+                        EvalErrorKind::PlaceholderUnfilled,
+                    ))
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    // Runs the steps wave by wave. `feeds` are already validated.
+    fn execute<'v>(
+        &'v self,
+        feeds: &'v [NdArrayView<T>],
+        mut profile: Option<&mut Profile>,
+    ) -> Result<Vec<Option<NdArray<T>>>, EvalError> {
+        let origin = Instant::now();
+        // Records of this run follow those of the previous runs.
+        let offset = profile.as_ref().map(|p| p.wall_time());
+        // Outlives the values viewing the arrays.
+        let persistents = LockedArrays::new(&self.persistents, &self.updated);
+        let mut buffers = Buffers::new();
+        let mut values: Vec<Option<StepValue<'v, T>>> =
+            (0..self.steps.len()).map(|_| None).collect();
+
+        for (w, wave) in self.schedule.waves.iter().enumerate() {
+            // Aggregate input arrays
+            let mut inputs = Vec::with_capacity(wave.len());
+            for &i in wave {
+                let step = &self.steps[i];
+                let has_side_effects = step.node.op.has_side_effects();
+                let mut xs = Some(Vec::with_capacity(step.inputs.len()));
+                let mut xs_mut = Vec::with_capacity(step.inputs.len());
+                let mut vars_mut = Vec::with_capacity(step.inputs.len());
+                let mut input_pins = Vec::new();
+                for (j, (src, &consumes)) in step
+                    .inputs
+                    .iter()
+                    .zip(&self.schedule.consumes[i])
+                    .enumerate()
+                {
+                    let mut x_mut = None;
+                    let mut var_mut = None;
+                    let x = match *src {
+                        Source::Persistent(k) => {
+                            // Each variable is given to the op once even if it's read twice.
+                            if has_side_effects && !step.inputs[..j].contains(src) {
+                                var_mut = persistents.array_mut(k);
                             }
+                            Some(persistents.view(k))
                         }
-                        // Call Op::compute
-                        let ys: op::ComputeResults<_> = match err {
-                            Some(e) => e,
-                            _ => node.op.compute(OpComputeContext {
-                                nodes: node.inputs.clone(),
-                                xs,
-                            }),
-                        };
-                        // Aggregate compute result
-                        let mut info_list = Vec::with_capacity(ys.len());
-                        let mut contains_no_output = false;
-                        for y in ys {
-                            match y {
-                                Ok(crate::ArrRepr::Owned(val)) => {
-                                    info_list.push(ValueInfo::new(
-                                        ValueType::Owned,
-                                        owned_storage.len(),
-                                    ));
-                                    unsafe {
-                                        // safe
-                                        mem::transmute::<_, &mut Vec<_>>(&owned_storage).push(val);
+                        Source::Feed(k) => Some(feeds[k].view()),
+                        Source::Output(k, j) => {
+                            // Inputs are computed in earlier waves.
+                            let value = values[k].as_ref().unwrap();
+                            input_pins.extend_from_slice(&value.pinned);
+                            if value.contains_no_output {
+                                None
+                            } else {
+                                match value.outputs[j] {
+                                    Value::Owned(ref v, key) => {
+                                        if consumes && buffers.pins[key] == 1 {
+                                            x_mut = buffers.take(key);
+                                        }
+                                        Some(v.clone())
                                     }
-                                }
-                                Ok(crate::ArrRepr::View(val)) => {
-                                    info_list
-                                        .push(ValueInfo::new(ValueType::View, view_storage.len()));
-                                    view_storage.push(val);
-                                }
-                                _ => {
-                                    info_list.push(ValueInfo::new(ValueType::NoOutput, 0));
-                                    contains_no_output = true;
+                                    Value::View(ref v) => Some(v.clone()),
+                                    Value::NoOutput => None,
                                 }
                             }
                         }
-                        node_info_storage.push(node.with_value_info(info_list, contains_no_output))
                     };
+                    match x {
+                        Some(x) => {
+                            xs.as_mut().unwrap().push(x);
+                            xs_mut.push(x_mut);
+                            vars_mut.push(var_mut);
+                        }
+                        None => {
+                            xs = None;
+                            break;
+                        }
+                    }
                 }
+                inputs.push(StepInputs {
+                    step: i,
+                    xs,
+                    xs_mut,
+                    vars_mut,
+                    pins: input_pins,
+                });
+            }
+
+            // Call Op::compute
+            let compute = |inputs: StepInputs<'v, T>| {
+                let node = &self.steps[inputs.step].node;
+                let started = offset.map(|offset| (offset + origin.elapsed(), Instant::now()));
+                // Addresses of the arrays the op can take, to spot outputs written into them.
+                let input_buffers = match started {
+                    Some(_) => inputs
+                        .xs_mut
+                        .iter()
+                        .flatten()
+                        .filter_map(|(cell, _)| cell.lock().unwrap().as_ref().map(|a| a.as_ptr()))
+                        .collect(),
+                    None => Vec::new(),
+                };
+                let ys = match inputs.xs {
+                    Some(xs) => {
+                        let xs_mut = inputs.xs_mut.iter();
+                        let xs_mut = xs_mut.map(|x| x.as_ref().map(|(cell, _)| cell.clone()));
+                        let vars_mut = inputs.vars_mut;
+                        compute(node, xs, xs_mut.collect(), vars_mut, self.check_numerics)?
+                    }
+                    None => vec![Err(op::ComputeException::NoOutput)],
+                };
+                let step = inputs.step;
+                let record = started.map(|(start, instant)| {
+                    let elapsed = instant.elapsed();
+                    let reused = ys
+                        .iter()
+                        .map(|y| match y {
+                            Ok(crate::ArrRepr::Owned(arr)) => input_buffers.contains(&arr.as_ptr()),
+                            _ => false,
+                        })
+                        .collect::<Vec<_>>();
+                    OpRecord::new(step, node, &ys, &reused, start, elapsed)
+                });
+                Ok((inputs.step, ys, inputs.xs_mut, inputs.pins, record))
+            };
+            let results: Vec<Result<_, EvalError>> = if inputs.len() == 1 {
+                inputs.into_iter().map(compute).collect()
             } else {
-                // Update dfs stack
-                dfs_stack.push((node, true));
-                // Push children if needed
-                for child in &node.inputs {
-                    if !visited(child, &node_info_storage) {
-                        dfs_stack.push((child, false));
+                inputs.into_par_iter().map(compute).collect()
+            };
+
+            // Aggregate compute results in the order of steps
+            for result in results {
+                let (i, ys, xs_mut, input_pins, record) = result?;
+                // Inputs the op didn't take may still be viewed by its outputs.
+                for (cell, x) in xs_mut.into_iter().flatten() {
+                    if let Some(arr) = cell.lock().unwrap().take() {
+                        buffers.arrays[x] = Some(arr);
                     }
                 }
+                if let (Some(profile), Some(record)) = (profile.as_mut(), record) {
+                    profile.records.push(record);
+                }
+                let mut outputs = Vec::with_capacity(ys.len());
+                let mut pinned = Vec::new();
+                let mut contains_no_output = false;
+                let mut contains_view = false;
+                for y in ys {
+                    match y {
+                        Ok(crate::ArrRepr::Owned(val)) => {
+                            let key = buffers.push(val);
+                            pinned.push(key);
+                            outputs.push(Value::Owned(buffers.view(key), key));
+                        }
+                        Ok(crate::ArrRepr::View(val)) => {
+                            contains_view = true;
+                            outputs.push(Value::View(val));
+                        }
+                        _ => {
+                            outputs.push(Value::NoOutput);
+                            contains_no_output = true;
+                        }
+                    }
+                }
+                if contains_view {
+                    // A view may point to any of the input arrays; keep them alive.
+                    for key in input_pins {
+                        buffers.pins[key] += 1;
+                        pinned.push(key);
+                    }
+                }
+                values[i] = Some(StepValue {
+                    outputs,
+                    pinned,
+                    contains_no_output,
+                });
             }
-        } // while loop end
-
-        // process array views
-        for t in tensors {
-            let t = t.as_ref();
-            if !t.is_placeholder && !t.has_persistent_array() {
-                let info = &mut node_info_storage[t.resource_lookup_key.get()].info_list[0];
-                if let ValueType::View = info.ty {
-                    info.value = Some(view_storage[info.key].to_owned());
+
+            // Drop values no longer used, and arrays no longer viewed.
+            for &k in &self.schedule.release_after[w] {
+                if let Some(value) = values[k].take() {
+                    for key in value.pinned {
+                        buffers.unpin(key);
+                    }
                 }
             }
         }
-    } // lifetime of views ends here
 
-    for t in tensors {
-        let t = t.as_ref();
-        if !t.is_placeholder && !t.has_persistent_array() {
-            let info = &mut node_info_storage[t.resource_lookup_key.get()].info_list[0];
-            if let ValueType::Owned = info.ty {
-                info.value = Some(owned_storage[info.key].to_owned());
-            }
+        // Convert views into owned arrays before moving out `buffers`.
+        let mut ret: Vec<Option<NdArray<T>>> = Vec::with_capacity(self.targets.len());
+        let mut owned_targets = Vec::new();
+        for (t, src) in self.target_sources.iter().enumerate() {
+            let arr = match *src {
+                Source::Persistent(k) => Some(persistents.view(k).to_owned()),
+                Source::Feed(k) => Some(feeds[k].to_owned()),
+                Source::Output(k, j) => match values[k].as_ref().unwrap().outputs[j] {
+                    Value::View(ref v) => Some(v.to_owned()),
+                    Value::Owned(_, key) => {
+                        owned_targets.push((t, key));
+                        None
+                    }
+                    Value::NoOutput => None,
+                },
+            };
+            ret.push(arr);
+        }
+        mem::drop(values);
+        for (i, &(t, key)) in owned_targets.iter().enumerate() {
+            ret[t] = match buffers.arrays[key].take() {
+                Some(arr) => Some(arr),
+                None => {
+                    // The same tensor is requested twice.
+                    let &(prev, _) = owned_targets[..i].iter().find(|a| a.1 == key).unwrap();
+                    ret[prev].clone()
+                }
+            };
         }
+        if let Some(profile) = profile {
+            profile.add_wall_time(origin.elapsed());
+        }
+        Ok(ret)
     }
+}
 
-    let mut ret: Vec<Option<NdArray<T>>> = Vec::with_capacity(tensors.len());
-    for t in tensors {
-        let t = t.as_ref();
-        let arr = if let Some(per) = t.get_persistent_array() {
-            // rare case
-            Some(per.clone())
-        } else if t.is_placeholder {
-            // rare case
-            let mut found = None;
-            for feed in feeds {
-                if Rc::ptr_eq(feed.0, t) {
-                    found = Some(&feed.1);
-                    break;
-                }
+// Calls `Op::compute` of `node`, converting failures into `EvalError`.
+//
+// `check_numerics`: non-finite values in the outputs are also errors.
+fn compute<'v, T: Float>(
+    node: &Tensor<T>,
+    xs: Vec<NdArrayView<'v, T>>,
+    xs_mut: Vec<Option<Consumable<T>>>,
+    vars_mut: Vec<Option<VariableMut<T>>>,
+    check_numerics: bool,
+) -> Result<op::ComputeResults<'v, T>, EvalError> {
+    let input_shapes = || xs.iter().map(|x| x.shape().to_vec()).collect::<Vec<_>>();
+    let ctx = OpComputeContext {
+        nodes: node.inputs.clone(),
+        xs: xs.clone(),
+        xs_mut,
+        vars_mut,
+    };
+    let ys = panic::catch_unwind(AssertUnwindSafe(|| node.op.compute(ctx))).map_err(|payload| {
+        EvalError::new(
+            node,
+            input_shapes(),
+            EvalErrorKind::Panicked(panic_message(payload)),
+        )
+    })?;
+    for (i, y) in ys.iter().enumerate() {
+        let stats = match y {
+            Err(op::ComputeException::Error(reason)) => {
+                return Err(EvalError::new(
+                    node,
+                    input_shapes(),
+                    EvalErrorKind::ComputeFailed(reason.clone()),
+                ));
             }
-            Some(found.expect("Placeholder unfilled.").to_owned())
-        } else {
-            mem::replace(
-                &mut node_info_storage[t.resource_lookup_key.get()].info_list[0].value,
-                None,
-            )
+            Ok(_) if !check_numerics => None,
+            Ok(crate::ArrRepr::Owned(arr)) => NumericStats::of(&arr.view()),
+            Ok(crate::ArrRepr::View(arr)) => NumericStats::of(arr),
+            Err(op::ComputeException::NoOutput) => None,
         };
-        ret.push(arr);
+        if let Some(stats) = stats {
+            return Err(EvalError::new(
+                node,
+                input_shapes(),
+                EvalErrorKind::NonFinite {
+                    output_index: i,
+                    stats,
+                    in_gradient_graph: node.in_gradient_graph,
+                },
+            ));
+        }
+    }
+    Ok(ys)
+}
+
+// Owned arrays made in an evaluation.
+//
+// Values view the heap memory of these arrays, which doesn't move while the list grows.
+// Each array is dropped as soon as no value pins it.
+struct Buffers<T: Float> {
+    arrays: Vec<Option<NdArray<T>>>,
+    // Number of values that may view each array
+    pins: Vec<usize>,
+}
+
+impl<T: Float> Buffers<T> {
+    #[inline]
+    fn new() -> Self {
+        Buffers {
+            arrays: Vec::new(),
+            pins: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, arr: NdArray<T>) -> usize {
+        self.arrays.push(Some(arr));
+        self.pins.push(1);
+        self.arrays.len() - 1
+    }
+
+    #[inline]
+    fn view<'v>(&self, key: usize) -> NdArrayView<'v, T> {
+        // The array lives until its last pin is removed.
+        unsafe { mem::transmute(self.arrays[key].as_ref().unwrap().view()) }
+    }
+
+    // Moves the array out so that an op can own it; the views of it must not be read
+    // unless it is put back.
+    #[inline]
+    fn take(&mut self, key: usize) -> Option<(Consumable<T>, usize)> {
+        self.arrays[key]
+            .take()
+            .map(|arr| (Arc::new(Mutex::new(Some(arr))), key))
+    }
+
+    #[inline]
+    fn unpin(&mut self, key: usize) {
+        self.pins[key] -= 1;
+        if self.pins[key] == 0 {
+            self.arrays[key] = None;
+        }
+    }
+}
+
+// An input array handed over to the op that reads it last; `None` once the op takes it.
+type Consumable<T> = Arc<Mutex<Option<NdArray<T>>>>;
+
+// Persistent arrays of a graph locked for a run.
+//
+// Variables read by ops with side effects are locked for writing, and the others for
+// reading. Locks are taken in the order of tensor ids so that concurrent runs sharing
+// arrays don't deadlock.
+struct LockedArrays<'g, T: Float> {
+    // In the order of `CompiledGraph::persistents`, and `Some` if locked for writing
+    arrays: Vec<(*const NdArray<T>, Option<*mut NdArray<T>>)>,
+    _read_guards: Vec<RwLockReadGuard<'g, NdArray<T>>>,
+    _write_guards: Vec<RwLockWriteGuard<'g, NdArray<T>>>,
+}
+
+impl<'g, T: Float> LockedArrays<'g, T> {
+    fn new(persistents: &'g [Tensor<T>], updated: &[bool]) -> Self {
+        let mut order = (0..persistents.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&k| persistents[k].id());
+        let mut arrays = vec![(ptr::null(), None); persistents.len()];
+        let mut read_guards = Vec::new();
+        let mut write_guards = Vec::new();
+        for k in order {
+            // unwrap is safe
+            if updated[k] {
+                let mut guard = persistents[k].get_persistent_array_mut().unwrap();
+                let arr = &mut *guard as *mut NdArray<T>;
+                arrays[k] = (arr as *const NdArray<T>, Some(arr));
+                write_guards.push(guard);
+            } else {
+                let guard = persistents[k].get_persistent_array().unwrap();
+                arrays[k] = (&*guard as *const NdArray<T>, None);
+                read_guards.push(guard);
+            }
+        }
+        LockedArrays {
+            arrays,
+            _read_guards: read_guards,
+            _write_guards: write_guards,
+        }
+    }
+
+    #[inline]
+    fn view<'v>(&self, k: usize) -> NdArrayView<'v, T> {
+        // The array is locked until `self` is dropped.
+        unsafe { (*self.arrays[k].0).view() }
+    }
+
+    #[inline]
+    fn array_mut(&self, k: usize) -> Option<VariableMut<T>> {
+        self.arrays[k].1.map(VariableMut)
+    }
+}
+
+// Variable locked for writing, which only the op with side effects reading it accesses
+// since such an op is computed alone.
+struct VariableMut<T: Float>(*mut NdArray<T>);
+
+unsafe impl<T: Float> Send for VariableMut<T> {}
+
+// Input arrays of a step in evaluation
+struct StepInputs<'v, T: Float> {
+    step: usize,
+    // `None` if any input is missing
+    xs: Option<Vec<NdArrayView<'v, T>>>,
+    // Arrays taken out of `buffers` and their keys
+    xs_mut: Vec<Option<(Consumable<T>, usize)>>,
+    // Variables that the op can update
+    vars_mut: Vec<Option<VariableMut<T>>>,
+    // Buffers that views returned from the step may point to
+    pins: Vec<usize>,
+}
+
+// Output of a step in evaluation
+enum Value<'v, T: Float> {
+    // View of an owned array and its key in `buffers`
+    Owned(NdArrayView<'v, T>, usize),
+    View(NdArrayView<'v, T>),
+    NoOutput,
+}
+
+struct StepValue<'v, T: Float> {
+    outputs: Vec<Value<'v, T>>,
+    // Keys of the buffers this value may view
+    pinned: Vec<usize>,
+    contains_no_output: bool,
+}
+
+#[inline]
+fn source_of<T: Float>(
+    x: &Tensor<T>,
+    output_index: usize,
+    lookup: &HashMap<usize, Source>,
+) -> Source {
+    match lookup[&x.id()] {
+        Source::Output(k, _) => Source::Output(k, output_index),
+        source => source,
     }
-    ret
 }
 
-#[inline(always)]
-fn visited<T: Float>(node: &Tensor<T>, node_info_storage: &Vec<NodeWithValueInfo<T>>) -> bool {
-    let k = node.resource_lookup_key.get();
-    k < node_info_storage.len() && Rc::ptr_eq(node, node_info_storage[k].node)
+/// Evaluates given symbolic tensors.
+///
+/// Each return value can be `None`;
+/// for example, evaluation of `gradient_descent_ops::*`
+/// would result in `None`.
+///
+/// NOTE: All the runtime errors are not reported by return values, but by "panic"
+/// for convenience. Use [try_eval](fn.try_eval.html) to handle them.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let ref a = ag::zeros(&[2]);
+/// let ref b = ag::ones(&[2]);
+///
+/// // eval two tensors at once.
+/// let evaluated = ag::eval(&[a, b], &[]);
+/// assert_eq!(evaluated[0], Some(ndarray::arr1(&[0., 0.]).into_dyn()));
+/// assert_eq!(evaluated[1], Some(ndarray::arr1(&[1., 1.]).into_dyn()));
+/// ```
+///
+/// See also [CompiledGraph](struct.CompiledGraph.html) for repeated evaluation.
+pub fn eval<'slice, 'node, 'feed, K, T>(
+    tensors: &'node [K],
+    feeds: &'slice [Feed<'node, 'feed, T>],
+) -> Vec<Option<NdArray<T>>>
+where
+    K: AsRef<Tensor<T>>,
+    T: Float,
+{
+    match try_eval(tensors, feeds) {
+        Ok(ret) => ret,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Evaluates given symbolic tensors, reporting runtime errors by the return value.
+///
+/// Unfilled placeholders, placeholder shape mismatches and failures of `Op::compute`
+/// (including panics in it) result in `Err(ag::EvalError)`.
+///
+/// ```
+/// extern crate ndarray;
+/// extern crate autograd as ag;
+///
+/// let ref x = ag::placeholder::<f32>(&[2, 3]);
+/// let ref y = ag::reshape(x, &[5]);
+///
+/// let arr = ndarray::Array::zeros((2, 3)).into_dyn();
+/// let err = ag::try_eval(&[y], &[ag::Feed(x, arr.view())]).unwrap_err();
+/// assert_eq!(err.op_name, "Reshape");
+/// assert_eq!(err.input_shapes, vec![vec![2, 3], vec![1]]);
+/// ```
+pub fn try_eval<'slice, 'node, 'feed, K, T>(
+    tensors: &'node [K],
+    feeds: &'slice [Feed<'node, 'feed, T>],
+) -> Result<Vec<Option<NdArray<T>>>, EvalError>
+where
+    K: AsRef<Tensor<T>>,
+    T: Float,
+{
+    let no_placeholders: &[&Tensor<T>] = &[];
+    let graph = CompiledGraph::compile(tensors, no_placeholders, FeedMode::AppendUnlisted);
+    let feeds = graph.feed_slots(feeds)?;
+    graph.try_run(&feeds)
 }
 
 #[test]
@@ -372,6 +1367,31 @@ fn test_constant_eval() {
     assert_eq!(Some(arr.clone().into_dyn()), crate::variable(arr).eval(&[]));
 }
 
+#[test]
+fn test_try_eval_errors() {
+    let ref x = crate::ops::placeholder::<f32>(&[2, 3]);
+    let ref y = crate::ops::matmul(x, x);
+
+    let err = try_eval(&[y], &[]).unwrap_err();
+    assert_eq!(err.op_name, "Placeholder");
+    match *err.kind {
+        EvalErrorKind::PlaceholderUnfilled => {}
+        _ => panic!("unexpected error: {}", err),
+    }
+
+    let arr = crate::ndarray_ext::zeros::<f32>(&[3, 2]);
+    let err = try_eval(&[y], &[Feed(x, arr.view())]).unwrap_err();
+    match *err.kind {
+        EvalErrorKind::PlaceholderShapeMismatch { .. } => {}
+        _ => panic!("unexpected error: {}", err),
+    }
+
+    let arr = crate::ndarray_ext::zeros::<f32>(&[2, 3]);
+    let err = try_eval(&[y], &[Feed(x, arr.view())]).unwrap_err();
+    assert_eq!(err.op_name, "MatMul");
+    assert_eq!(err.input_shapes, vec![vec![2, 3], vec![2, 3]]);
+}
+
 #[test]
 fn test_placeholder_eval() {
     let arr = crate::ndarray_ext::ones::<f32>(&[3, 2, 1]);
@@ -379,3 +1399,235 @@ fn test_placeholder_eval() {
     let eval_result = eval(&[v], &[Feed(v, arr.view())]);
     assert_eq!(eval_result[0], Some(arr));
 }
+
+#[test]
+fn test_compiled_graph() {
+    let ref x = crate::ops::placeholder::<f32>(&[-1, 2]);
+    let ref v = crate::ops::variable(crate::ndarray_ext::ones::<f32>(&[2, 2]));
+    let ref y = crate::ops::matmul(x, v);
+    let ref z = y + x;
+    let graph = CompiledGraph::new(&[z, y, z, v], &[x]);
+    for n in 1..4 {
+        let arr = crate::ndarray_ext::ones::<f32>(&[n, 2]);
+        let ret = graph.run(&[arr.view()]);
+        assert_eq!(
+            ret[0],
+            Some(NdArray::from_elem(ndarray::IxDyn(&[n, 2]), 3.))
+        );
+        assert_eq!(
+            ret[1],
+            Some(NdArray::from_elem(ndarray::IxDyn(&[n, 2]), 2.))
+        );
+        assert_eq!(ret[0], ret[2]);
+        assert_eq!(ret[3], v.get_persistent_array().map(|a| a.clone()));
+    }
+}
+
+#[test]
+#[should_panic(expected = "Placeholder reachable from the targets is not given.")]
+fn test_compiled_graph_unlisted_placeholder() {
+    let ref x = crate::ops::placeholder::<f32>(&[]);
+    let ref y = crate::ops::placeholder::<f32>(&[]);
+    let no_placeholders: &[&Tensor<f32>] = &[];
+    CompiledGraph::new(&[x + y], no_placeholders);
+}
+
+#[cfg(test)]
+struct ExpectInputMut(bool);
+
+#[cfg(test)]
+impl<T: Float> op::Op<T> for ExpectInputMut {
+    fn name(&self) -> &str {
+        "ExpectInputMut"
+    }
+
+    fn compute<'v>(&self, mut ctx: OpComputeContext<'v, T>) -> op::ComputeResults<'v, T> {
+        let x = ctx.take_input(0);
+        assert_eq!(x.is_some(), self.0);
+        match x {
+            Some(x) => vec![Ok(crate::ArrRepr::Owned(x))],
+            None => vec![Ok(crate::ArrRepr::View(ctx.grab_inputs()[0].clone()))],
+        }
+    }
+
+    fn grad(&self, _: &Tensor<T>, _: &[&Tensor<T>], _: &Tensor<T>) -> Vec<Option<Tensor<T>>> {
+        vec![None]
+    }
+}
+
+#[test]
+fn test_buffer_reuse() {
+    let ref x = crate::ops::placeholder::<f32>(&[2, 2]);
+    let ref a = x * 2.;
+    // `a` is read by `d` after `c`, so `c` mustn't overwrite it.
+    let ref c = crate::ops::add_inplace(a.clone(), crate::ops::scalar(1.));
+    let ref d = c + a;
+    // `e`'s array is dead after `f`, so `f` overwrites it.
+    let ref e = x * 3.;
+    let ref f = crate::ops::mul_inplace(e.clone(), crate::ops::scalar(2.));
+    let arr = crate::ndarray_ext::ones::<f32>(&[2, 2]);
+    let ret = eval(&[a, c, d, f], &[Feed(x, arr.view())]);
+    assert_eq!(
+        ret[0],
+        Some(NdArray::from_elem(ndarray::IxDyn(&[2, 2]), 2.))
+    );
+    assert_eq!(
+        ret[1],
+        Some(NdArray::from_elem(ndarray::IxDyn(&[2, 2]), 3.))
+    );
+    assert_eq!(
+        ret[2],
+        Some(NdArray::from_elem(ndarray::IxDyn(&[2, 2]), 5.))
+    );
+    assert_eq!(
+        ret[3],
+        Some(NdArray::from_elem(ndarray::IxDyn(&[2, 2]), 6.))
+    );
+
+    let expect = |x: &Tensor<f32>, a: bool| Tensor::builder().set_input(x).build(ExpectInputMut(a));
+    // Placeholders and targets are never writable.
+    let ref y = expect(x, false);
+    let ref z = expect(&(x * 2.), true);
+    let ref w = x * 2.;
+    eval(&[y, z, w, &expect(w, false)], &[Feed(x, arr.view())]);
+    // Views keep their source alive.
+    let ref v = crate::ops::reshape(x * 2., &[4]);
+    let ref u = expect(&crate::ops::reshape(v, &[2, 2]), false);
+    eval(&[u, &(v + 1.)], &[Feed(x, arr.view())]);
+}
+
+#[test]
+fn test_parallel_schedule() {
+    let ref x = crate::ops::placeholder::<f32>(&[2, 8]);
+    let ref w = crate::ops::variable(crate::ndarray_ext::standard_normal::<f32>(&[8, 8]));
+    let ref h = crate::ops::matmul(x, w);
+    // Gates of an LSTM cell
+    let ref i = crate::ops::sigmoid(&crate::ops::slice(h, &[0, 0], &[-1, 2]));
+    let ref f = crate::ops::sigmoid(&crate::ops::slice(h, &[0, 2], &[-1, 4]));
+    let ref o = crate::ops::sigmoid(&crate::ops::slice(h, &[0, 4], &[-1, 6]));
+    let ref g = crate::ops::tanh(&crate::ops::slice(h, &[0, 6], &[-1, 8]));
+    let ref c = f * 2. + i * g;
+    let ref y = o * crate::ops::tanh(c);
+    let ref loss = crate::ops::reduce_sum(y, &[0, 1], false);
+    let ref grads = crate::ops::grad(&[loss], &[w]);
+
+    let targets = [y, loss, &grads[0]];
+    let sequential = CompiledGraph::new(&targets, &[x]);
+    let mut parallel = CompiledGraph::new(&targets, &[x]);
+    parallel.set_parallel(true);
+    assert!(parallel.schedule.waves.len() < sequential.schedule.waves.len());
+    let sliced_wave = parallel
+        .schedule
+        .waves
+        .iter()
+        .find(|wave| {
+            wave.iter()
+                .filter(|&&i| parallel.steps[i].node.op.name() == "Slice")
+                .count()
+                == 4
+        })
+        .is_some();
+    assert!(sliced_wave);
+
+    let arr = crate::ndarray_ext::standard_normal::<f32>(&[2, 8]);
+    for _ in 0..3 {
+        assert_eq!(sequential.run(&[arr.view()]), parallel.run(&[arr.view()]));
+    }
+}
+
+#[test]
+fn test_parallel_side_effects() {
+    // Side effects must happen in the same order as the sequential mode.
+    let run = |parallel: bool| {
+        let ref x = crate::ops::variable(crate::ndarray_ext::ones::<f32>(&[2]));
+        let ref before = x * 1.;
+        use crate::ops::gradient_descent_ops::Optimizer;
+        let ref update = crate::ops::gradient_descent_ops::SGD::new(1.)
+            .compute_updates(&[x], &[crate::ops::ones(&[2])])
+            .remove(0);
+        let ref after = x * 1.;
+        let ref after_twice = crate::ops::mul_inplace(x * 1., crate::ops::scalar(2.));
+        let no_placeholders: &[&Tensor<f32>] = &[];
+        let mut graph = CompiledGraph::new(
+            &[before, after, update, after_twice, &(before + after)],
+            no_placeholders,
+        );
+        graph.set_parallel(parallel);
+        let mut ret = graph.run(&[]);
+        ret.push(x.get_persistent_array().map(|a| a.clone()));
+        ret
+    };
+    let ret = run(true);
+    assert_eq!(ret, run(false));
+    assert_eq!(ret[2], None);
+    assert_ne!(ret[0], ret[3]);
+}
+
+#[test]
+fn test_parallel_buffer_reuse() {
+    let ref x = crate::ops::placeholder::<f32>(&[2, 2]);
+    let ref a = x * 2.;
+    // `c` is the last reader of `a` in DFS order, but `d` reads `a` in the same wave.
+    let ref b = crate::ops::add_inplace(x * 1., crate::ops::scalar(1.));
+    let ref c = crate::ops::add_inplace(a.clone(), crate::ops::scalar(1.));
+    let ref d = a + b;
+    let arr = crate::ndarray_ext::ones::<f32>(&[2, 2]);
+    let ret = Eval::new()
+        .set_parallel(true)
+        .extend(&[c, d])
+        .run(&[Feed(x, arr.view())]);
+    assert_eq!(
+        ret[0],
+        Some(NdArray::from_elem(ndarray::IxDyn(&[2, 2]), 3.))
+    );
+    assert_eq!(
+        ret[1],
+        Some(NdArray::from_elem(ndarray::IxDyn(&[2, 2]), 4.))
+    );
+}
+
+#[test]
+fn test_check_numerics() {
+    let ref x = crate::ops::placeholder::<f32>(&[3]);
+    let ref y = crate::ops::log(x, std::f32::consts::E);
+    let ref z = crate::ops::reduce_sum(y, &[0], false);
+    let ref g = crate::ops::grad(&[z], &[x])[0];
+    let arr = ndarray::arr1(&[1., -1., 0.]).into_dyn();
+
+    // Not checked by default
+    eval(&[z], &[Feed(x, arr.view())]);
+
+    let err = Eval::new()
+        .set_check_numerics(true)
+        .extend(&[z, g])
+        .try_run(&[Feed(x, arr.view())])
+        .unwrap_err();
+    assert_eq!(err.op_name, "Log");
+    assert_eq!(err.input_names, vec!["Placeholder"]);
+    match *err.kind {
+        EvalErrorKind::NonFinite {
+            output_index,
+            stats,
+            in_gradient_graph,
+        } => {
+            assert_eq!(output_index, 0);
+            assert!(!in_gradient_graph);
+            assert_eq!(stats.nan_count, 1);
+            assert_eq!(stats.neg_inf_count, 1);
+            assert_eq!((stats.min, stats.max, stats.mean), (0., 0., 0.));
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+
+    // sqrt(0) is finite, but its gradient is not.
+    let ref g = crate::ops::grad(&[crate::ops::sqrt(x)], &[x])[0];
+    let arr = ndarray::arr1(&[1., 2., 0.]).into_dyn();
+    let mut graph = CompiledGraph::new(&[g], &[x]);
+    graph.set_check_numerics(true);
+    match *graph.try_run(&[arr.view()]).unwrap_err().kind {
+        EvalErrorKind::NonFinite {
+            in_gradient_graph, ..
+        } => assert!(in_gradient_graph),
+        _ => unreachable!(),
+    }
+}
@@ -6,15 +6,20 @@ use crate::Float;
 use crate::Int;
 use crate::NdArray;
 
-use std::cell::Cell;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fmt;
-use std::mem;
 use std::ops::{Add, Div, Mul, Sub};
 use std::ops::{Deref, DerefMut};
-use std::rc::Rc;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 /// Symbolic multi-dimensional array.
-pub struct Tensor<T: Float>(pub Rc<TensorCore<T>>);
+///
+/// Tensors are `Send + Sync`, so a graph can be shared with other threads and
+/// evaluated by several threads at the same time.
+/// Arrays of variables are locked while they're evaluated; an evaluation updating a
+/// variable (e.g. with `gradient_descent_ops`) waits for the others reading it, and vice versa.
+pub struct Tensor<T: Float>(pub Arc<TensorCore<T>>);
 
 #[doc(hidden)]
 pub struct TensorCore<T: Float> {
@@ -35,9 +40,6 @@ pub struct TensorCore<T: Float> {
     /// This is `Some` if this tensor is made from `ag::variable` or `ag::constant`.
     persistent_array: Option<PersistentArray<T>>,
 
-    /// Used to look up a evaluation result of this tensor.
-    pub resource_lookup_key: Cell<usize>,
-
     /// This tensor is placeholder or not.
     pub is_placeholder: bool,
 
@@ -55,39 +57,58 @@ pub struct TensorCore<T: Float> {
     /// Static shape of this tensor.
     /// Each dim size is *signed* for placeholders.
     pub known_shape: Option<KnownShape>,
+
+    /// This is `true` if this tensor was made in construction of gradients (`ag::grad` etc.).
+    pub in_gradient_graph: bool,
+
+    /// Sparse form of this tensor if this is a gradient of gathered rows.
+    pub indexed_slices: Option<IndexedSlices<T>>,
+
+    /// Name given with `TensorBuilder::set_name`, prefixed with its name scopes.
+    name: Option<String>,
 }
 
-enum PersistentArray<T: Float> {
-    Variable(NdArray<T>),
-    Constant(NdArray<T>),
+/// Sparse representation of a gradient which is non-zero only in some rows.
+///
+/// The dense gradient is zeros with `values[k]` added to its `indices[k]`-th row.
+/// Gradients of `ag::gather` along axis 0 have this, and the optimizers use it to
+/// update only the touched rows of a variable (e.g. an embedding table).
+#[derive(Clone)]
+pub struct IndexedSlices<T: Float> {
+    /// 1-D row indices, which may be duplicated or negative.
+    pub indices: Tensor<T>,
+    /// Rows of the gradient with shape `[indices.len(), dense_shape[1..]..]`.
+    pub values: Tensor<T>,
+}
+
+struct PersistentArray<T: Float> {
+    // Written only by update ops and restoration of variables
+    array: RwLock<NdArray<T>>,
+    is_variable: bool,
 }
 
 impl<T: Float> Tensor<T> {
-    /// Returns a reference to the persistent array.
+    /// Returns the persistent array locked for reading.
     ///
     /// Returns `Some` if this tensor is made from `ag::variable` or `ag::constant`.
-    pub fn get_persistent_array(&self) -> Option<&NdArray<T>> {
-        match self.persistent_array {
-            Some(ref a) => match a {
-                PersistentArray::Variable(ref arr) => Some(arr),
-                PersistentArray::Constant(ref arr) => Some(arr),
-            },
-            None => None,
-        }
+    /// Evaluations updating the variable wait until the returned guard is dropped.
+    pub fn get_persistent_array(&self) -> Option<RwLockReadGuard<'_, NdArray<T>>> {
+        self.persistent_array
+            .as_ref()
+            .map(|a| a.array.read().unwrap_or_else(PoisonError::into_inner))
     }
 
-    /// Returns a mutable reference to the persistent array.
+    /// Returns the persistent array locked for writing.
     ///
     /// Returns `Some` if this tensor is made from `ag::variable`.
-    pub unsafe fn get_persistent_array_mut(&self) -> Option<&mut NdArray<T>> {
-        mem::transmute(
-            self.persistent_array
-                .as_ref()
-                .and_then(|inner| match inner {
-                    PersistentArray::Variable(arr) => Some(arr),
-                    PersistentArray::Constant(_) => None,
-                }),
-        )
+    /// Evaluations reading the variable wait until the returned guard is dropped.
+    /// Ops can't call this in `Op::compute` since the evaluation holds the lock; use
+    /// `OpComputeContext::input_variable_mut` instead.
+    pub fn get_persistent_array_mut(&self) -> Option<RwLockWriteGuard<'_, NdArray<T>>> {
+        self.persistent_array
+            .as_ref()
+            .filter(|a| a.is_variable)
+            .map(|a| a.array.write().unwrap_or_else(PoisonError::into_inner))
     }
 
     /// Returns `True` if this tensor is made from `ag::variable` or `ag::constant`.
@@ -95,6 +116,130 @@ impl<T: Float> Tensor<T> {
     pub fn has_persistent_array(&self) -> bool {
         self.persistent_array.is_some()
     }
+
+    /// Returns `True` if this tensor is made from `ag::variable`.
+    #[inline]
+    pub fn is_variable(&self) -> bool {
+        matches!(self.persistent_array, Some(ref a) if a.is_variable)
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    /// Returns the address of `TensorCore`, which identifies this node while it's alive.
+    pub fn id(&self) -> usize {
+        &*self.0 as *const TensorCore<T> as usize
+    }
+
+    /// Returns the name of this tensor including its name scopes, e.g. `layer1/conv/w`.
+    ///
+    /// Returns `None` if this tensor is unnamed.
+    #[inline]
+    pub fn name(&self) -> Option<&str> {
+        self.0.name.as_deref()
+    }
+
+    // The name with `Op::name`, or `Op::name` alone if unnamed, used in messages.
+    pub(crate) fn label(&self) -> String {
+        match self.name() {
+            Some(name) => format!("{} ({})", name, self.op.name()),
+            None => self.op.name().to_owned(),
+        }
+    }
+}
+
+thread_local! {
+    // Scopes entered with `name_scope` in this thread, innermost last
+    static NAME_SCOPES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+struct NameScope;
+
+impl Drop for NameScope {
+    #[inline]
+    fn drop(&mut self) {
+        NAME_SCOPES.with(|s| s.borrow_mut().pop());
+    }
+}
+
+/// Calls `f` in the name scope `scope`.
+///
+/// Names given to tensors in `f` are prefixed with `scope/`. Scopes nest, and they're
+/// effective only in the calling thread.
+///
+/// ```
+/// extern crate autograd as ag;
+///
+/// let w = ag::name_scope("layer1", || {
+///     ag::name_scope("conv", || {
+///         ag::variable_named("w", ag::ndarray_ext::zeros::<f32>(&[3, 3]))
+///     })
+/// });
+/// assert_eq!(w.name(), Some("layer1/conv/w"));
+/// assert_eq!(format!("{:?}", w), "layer1/conv/w (Variable)");
+/// ```
+pub fn name_scope<R, F: FnOnce() -> R>(scope: &str, f: F) -> R {
+    assert!(!scope.is_empty(), "Name scope must not be empty");
+    NAME_SCOPES.with(|s| s.borrow_mut().push(scope.to_owned()));
+    // Leaves the scope even if `f` panics.
+    let _scope = NameScope;
+    f()
+}
+
+/// Returns `name` prefixed with the name scopes entered in this thread.
+fn scoped_name(name: &str) -> String {
+    NAME_SCOPES.with(|s| {
+        let mut ret = String::new();
+        for scope in s.borrow().iter() {
+            ret.push_str(scope);
+            ret.push('/');
+        }
+        ret.push_str(name);
+        ret
+    })
+}
+
+/// Returns the named tensors that `targets` depend on, keyed by their names.
+///
+/// Names needn't be unique; if some tensors share a name, the one found first in the
+/// depth-first search from `targets` is taken.
+///
+/// ```
+/// extern crate autograd as ag;
+///
+/// let ref x = ag::placeholder_named::<f32>("x", &[-1, 3]);
+/// let ref y = ag::name_scope("dense", || {
+///     let ref w = ag::variable_named("w", ag::ndarray_ext::zeros(&[3, 2]));
+///     ag::matmul(x, w)
+/// });
+///
+/// let tensors = ag::named_tensors(&[y]);
+/// assert_eq!(tensors.keys().collect::<Vec<_>>(), ["dense/w", "x"]);
+/// assert_eq!(&tensors["x"], x);
+/// ```
+pub fn named_tensors<T: Float, A: AsRef<Tensor<T>>>(targets: &[A]) -> BTreeMap<String, Tensor<T>> {
+    let mut visited = std::collections::BTreeSet::new();
+    let mut ret = BTreeMap::new();
+    let mut stack = targets.iter().rev().map(|t| t.as_ref()).collect::<Vec<_>>();
+    while let Some(t) = stack.pop() {
+        if !visited.insert(t.id()) {
+            continue;
+        }
+        if let Some(name) = t.name() {
+            ret.entry(name.to_owned()).or_insert_with(|| t.clone());
+        }
+        stack.extend(t.inputs.iter().rev());
+    }
+    ret
+}
+
+/// Returns the tensor named `name` that `targets` depend on.
+///
+/// See `named_tensors` for the tensors sharing a name.
+pub fn get_tensor_by_name<T: Float, A: AsRef<Tensor<T>>>(
+    targets: &[A],
+    name: &str,
+) -> Option<Tensor<T>> {
+    named_tensors(targets).remove(name)
 }
 
 /// Builder for `ag::Tensor`
@@ -107,6 +252,8 @@ pub struct TensorBuilder<T: Float> {
     input_indices: Option<Vec<usize>>,
     inputs_on_backprop: Option<Vec<Tensor<T>>>,
     known_shape: Option<KnownShape>,
+    indexed_slices: Option<IndexedSlices<T>>,
+    name: Option<String>,
 }
 
 #[doc(hidden)]
@@ -214,13 +361,19 @@ impl<T: Float> TensorBuilder<T> {
 
     #[inline]
     pub fn set_constant_array(mut self, a: NdArray<T>) -> TensorBuilder<T> {
-        self.persistent_array = Some(PersistentArray::Constant(a));
+        self.persistent_array = Some(PersistentArray {
+            array: RwLock::new(a),
+            is_variable: false,
+        });
         self
     }
 
     #[inline]
     pub fn set_variable_array(mut self, a: NdArray<T>) -> TensorBuilder<T> {
-        self.persistent_array = Some(PersistentArray::Variable(a));
+        self.persistent_array = Some(PersistentArray {
+            array: RwLock::new(a),
+            is_variable: true,
+        });
         self
     }
 
@@ -236,8 +389,28 @@ impl<T: Float> TensorBuilder<T> {
         self
     }
 
+    #[inline]
+    pub fn set_indexed_slices(mut self, a: IndexedSlices<T>) -> TensorBuilder<T> {
+        self.indexed_slices = Some(a);
+        self
+    }
+
+    /// Names the tensor `name` in the current name scope (see `ag::name_scope`).
+    #[inline]
+    pub fn set_name(mut self, name: &str) -> TensorBuilder<T> {
+        assert!(!name.is_empty(), "Tensor name must not be empty");
+        self.name = Some(scoped_name(name));
+        self
+    }
+
     #[inline]
     pub fn build<O: op::Op<T> + 'static>(self, op: O) -> Tensor<T> {
+        self.build_boxed(Box::new(op))
+    }
+
+    /// Same as `build`, but with a boxed op.
+    #[inline]
+    pub fn build_boxed(self, op: Box<dyn op::Op<T>>) -> Tensor<T> {
         let rank = if self.inputs.is_empty() {
             0
         } else {
@@ -256,18 +429,20 @@ impl<T: Float> TensorBuilder<T> {
             vec![0; self.inputs.len()]
         };
 
-        Tensor(Rc::new(TensorCore {
-            op: Box::new(op),
+        Tensor(Arc::new(TensorCore {
+            op,
             inputs: self.inputs,
             top_rank: rank,
             shape: self.shape,
             persistent_array: self.persistent_array,
             is_placeholder: self.is_placeholder,
-            resource_lookup_key: Cell::new(!0),
             is_differentiable: self.can_have_gradient,
             input_indices,
             inputs_on_backprop: self.inputs_on_backprop,
             known_shape: self.known_shape,
+            in_gradient_graph: crate::gradient::in_gradient_construction(),
+            indexed_slices: self.indexed_slices,
+            name: self.name,
         }))
     }
 }
@@ -284,6 +459,8 @@ impl<T: Float> Tensor<T> {
             input_indices: None,
             inputs_on_backprop: None,
             known_shape: None,
+            indexed_slices: None,
+            name: None,
         }
     }
 
@@ -433,7 +610,7 @@ impl<T: Float> Tensor<T> {
     /// // My shape: [4, 3]
     /// ```
     #[inline]
-    pub fn with_fn(&self, hook: Box<dyn Fn(&crate::ndarray::ArrayViewD<T>) -> ()>) -> Tensor<T> {
+    pub fn with_fn(&self, hook: crate::ops::hook_ops::HookFn<T>) -> Tensor<T> {
         crate::hook(crate::Hook::Raw(hook), self)
     }
 
@@ -481,7 +658,7 @@ impl<T: Float> Eq for Tensor<T> {}
 impl<T: Float> PartialEq for Tensor<T> {
     fn eq(&self, other: &Tensor<T>) -> bool {
         // compare addresses on the heap
-        Rc::ptr_eq(&self.0, &other.0)
+        Arc::ptr_eq(&self.0, &other.0)
     }
 }
 
@@ -500,14 +677,14 @@ impl<T: Float> Clone for Tensor<T> {
 }
 
 impl<T: Float> Deref for Tensor<T> {
-    type Target = Rc<TensorCore<T>>;
+    type Target = Arc<TensorCore<T>>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
 impl<T: Float> DerefMut for Tensor<T> {
-    fn deref_mut<'a>(&'a mut self) -> &'a mut Rc<TensorCore<T>> {
+    fn deref_mut(&mut self) -> &mut Arc<TensorCore<T>> {
         &mut self.0
     }
 }
@@ -518,12 +695,12 @@ impl<T: Float> fmt::Display for Tensor<T> {
             .0
             .inputs
             .iter()
-            .map(|a| a.op.name().to_string())
+            .map(|a| a.label())
             .collect::<Vec<String>>();
         write!(
             f,
             "name={}, inputs={:?}",
-            self.0.op.name(),
+            self.label(),
             input_names.as_slice()
         )
     }
@@ -34,22 +34,22 @@ pub fn check_theoretical_grads<'k, 'v, A, T>(
             th_grad.as_ref().unwrap().as_ptr()
         };
 
-        let v_arr = unsafe {
-            var_node
-                .get_persistent_array_mut()
-                .expect("This is not a variable")
+        let v_len = var_node
+            .get_persistent_array()
+            .expect("This is not a variable")
+            .len();
+        // The variable is locked only while it's written, since the evaluation reads it.
+        let set = |i: isize, value: T| unsafe {
+            let mut v_arr = var_node.get_persistent_array_mut().unwrap();
+            *v_arr.as_mut_ptr().offset(i) = value;
         };
-        let head_ptr: *mut T = v_arr.as_mut_ptr();
 
         // for each values
-        for i in 0..v_arr.len() as isize {
-            let evacuated;
+        for i in 0..v_len as isize {
+            let evacuated = unsafe { *var_node.get_persistent_array().unwrap().as_ptr().offset(i) };
 
             // perturbation (+)
-            unsafe {
-                evacuated = *head_ptr.offset(i);
-                *head_ptr.offset(i) = evacuated + eps;
-            }
+            set(i, evacuated + eps);
 
             // eval
             let obj_pos_orig = objective.eval(feeds).unwrap();
@@ -60,9 +60,7 @@ pub fn check_theoretical_grads<'k, 'v, A, T>(
             };
 
             // perturbation (-)
-            unsafe {
-                *head_ptr.offset(i) = evacuated - eps;
-            }
+            set(i, evacuated - eps);
 
             // eval
             let obj_neg_orig = objective.eval(feeds).unwrap();
@@ -73,9 +71,7 @@ pub fn check_theoretical_grads<'k, 'v, A, T>(
             };
 
             // restore
-            unsafe {
-                *head_ptr.offset(i) = evacuated;
-            }
+            set(i, evacuated);
 
             let two = T::one() + T::one();
             let g_num = (obj_pos - obj_neg).scalar_sum() / (two * eps);
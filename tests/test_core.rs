@@ -1,6 +1,8 @@
 extern crate autograd as ag;
 extern crate ndarray;
 
+use ag::gradient_descent_ops::Optimizer;
+
 struct MultiOutputOp;
 
 impl ag::op::Op<f32> for MultiOutputOp {
@@ -42,3 +44,1022 @@ fn test_hook() {
     let c = ag::matmul(a, b).with_fn(Box::new(|arr| println!("My shape: {:?}", arr.shape())));
     ag::eval(&[c], &[]);
 }
+
+struct FailingOp;
+
+impl ag::op::Op<f32> for FailingOp {
+    fn name(&self) -> &str {
+        "FailingOp"
+    }
+
+    fn compute<'v>(
+        &self,
+        _: ag::runtime::OpComputeContext<'v, f32>,
+    ) -> ag::op::ComputeResults<'v, f32> {
+        vec![Err(ag::op::ComputeException::Error("bad batch".to_owned()))]
+    }
+
+    fn grad(
+        &self,
+        _: &ag::Tensor<f32>,
+        _: &[&ag::Tensor<f32>],
+        _: &ag::Tensor<f32>,
+    ) -> Vec<Option<ag::Tensor<f32>>> {
+        vec![None]
+    }
+}
+
+#[test]
+fn test_try_eval() {
+    let ref x = ag::placeholder(&[-1, 2]);
+    let ref y = ag::Tensor::builder().set_input(x).build(FailingOp);
+    let ref z = 2. * y;
+    let arr = ag::ndarray_ext::zeros(&[3, 2]);
+    let err = ag::Eval::new()
+        .push(z)
+        .try_run(&[ag::Feed(x, arr.view())])
+        .unwrap_err();
+    assert_eq!(err.op_name, "FailingOp");
+    assert_eq!(err.input_shapes, vec![vec![3, 2]]);
+    match *err.kind {
+        ag::EvalErrorKind::ComputeFailed(ref reason) => assert_eq!(reason, "bad batch"),
+        _ => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn test_concurrent_eval() {
+    fn assert_send_sync<A: Send + Sync>() {}
+    assert_send_sync::<ag::Tensor<f32>>();
+    assert_send_sync::<ag::CompiledGraph<f32>>();
+
+    let x = ag::placeholder::<f32>(&[-1, 3]);
+    let w = ag::variable(ag::ndarray_ext::standard_normal::<f32>(&[3, 2]));
+    let y = ag::tanh(&ag::matmul(&x, &w));
+    let g = ag::grad(&[&y], &[&w]).remove(0);
+    let graph = std::sync::Arc::new(ag::CompiledGraph::new(&[&y], &[&x]));
+
+    let handles = (0..4)
+        .map(|i| {
+            let (x, y, g, graph) = (x.clone(), y.clone(), g.clone(), graph.clone());
+            std::thread::spawn(move || {
+                let arr = ag::ndarray_ext::ones::<f32>(&[i + 1, 3]);
+                for _ in 0..50 {
+                    let ret = ag::eval(&[&y, &g], &[ag::Feed(&x, arr.view())]);
+                    assert_eq!(ret[0], graph.run(&[arr.view()])[0]);
+                    assert_eq!(ret[0].as_ref().unwrap().shape(), &[i + 1, 2]);
+                    assert_eq!(ret[1].as_ref().unwrap().shape(), &[3, 2]);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn test_concurrent_updates() {
+    use ag::gradient_descent_ops::SGD;
+
+    // Every update subtracts 1 from all the elements.
+    let ref w = ag::variable(ag::ndarray_ext::zeros::<f64>(&[1000]));
+    let update = SGD::new(1.).compute_updates(&[w], &[ag::ones(&[1000])]);
+    let ref y = w * 2.;
+    let graph = std::sync::Arc::new(ag::CompiledGraph::new(&update, &[] as &[ag::Tensor<f64>]));
+
+    let handles = (0..4)
+        .map(|i| {
+            let (y, update, graph) = (y.clone(), update[0].clone(), graph.clone());
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    if i % 2 == 0 {
+                        graph.run(&[]);
+                    } else {
+                        // Reads and updates in one evaluation
+                        ag::eval(&[&y, &update], &[]);
+                    }
+                    // Updates are never seen halfway.
+                    let y = y.eval(&[]).unwrap();
+                    assert!(y.iter().all(|&a| a == y[0]));
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert!(w
+        .get_persistent_array()
+        .unwrap()
+        .iter()
+        .all(|&a| a == -400.));
+}
+
+#[test]
+fn test_jvp() {
+    let ref x = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[2, 3]));
+    let ref w = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[3, 4]));
+    let ref b = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[1, 4]));
+    let ref v = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[2, 3]));
+    // `Sin` and `Softmax` use the fallback.
+    let ref h = ag::tanh(&(ag::matmul(x, w) + b));
+    let ref z = ag::reshape(&ag::exp(&ag::matmul(x, w)), &[8]);
+    let ref y = ag::softmax(&(ag::sin(h) / ag::sigmoid(h) - ag::reshape(z, &[2, 4])), 1);
+
+    let ref jv = ag::jvp(&[y], &[x], &[v])[0];
+    let ref jac = ag::jacobians(y, &[x], 2 * 4)[0];
+    let ref expected = ag::reshape(&ag::matmul(jac, &ag::reshape(v, &[-1, 1])), &[2, 4]);
+    let ret = ag::eval(&[jv, expected], &[]);
+    assert_eq!(ret[0].as_ref().unwrap().shape(), &[2, 4]);
+    ret[0]
+        .as_ref()
+        .unwrap()
+        .iter()
+        .zip(ret[1].as_ref().unwrap())
+        .for_each(|(a, b)| assert!((a - b).abs() < 1e-8, "{} vs {}", a, b));
+
+    // `b` doesn't depend on `x`.
+    let ref jv = ag::jvp(&[b], &[x], &[v])[0];
+    assert_eq!(jv.eval(&[]).unwrap(), ag::ndarray_ext::zeros(&[1, 4]));
+}
+
+// Identity whose gradient is always zero, independently of `gy`
+struct ZeroGradOp;
+
+impl ag::op::Op<f64> for ZeroGradOp {
+    fn name(&self) -> &str {
+        "ZeroGradOp"
+    }
+
+    fn compute<'v>(
+        &self,
+        ctx: ag::runtime::OpComputeContext<'v, f64>,
+    ) -> ag::op::ComputeResults<'v, f64> {
+        vec![Ok(ag::ArrRepr::View(ctx.grab_inputs()[0].clone()))]
+    }
+
+    fn grad(
+        &self,
+        _: &ag::Tensor<f64>,
+        xs: &[&ag::Tensor<f64>],
+        _: &ag::Tensor<f64>,
+    ) -> Vec<Option<ag::Tensor<f64>>> {
+        vec![Some(ag::zeros(&xs[0].shape()))]
+    }
+}
+
+#[test]
+fn test_jvp_of_grad_without_gy() {
+    let ref x = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[2, 3]));
+    let ref v = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[2, 3]));
+    let ref y = ag::Tensor::builder()
+        .set_input(&ag::exp(x))
+        .build(ZeroGradOp);
+    let ref jv = ag::jvp(&[&(y * 2.)], &[x], &[v])[0];
+    assert_eq!(jv.eval(&[]).unwrap(), ag::ndarray_ext::zeros(&[2, 3]));
+}
+
+#[test]
+fn test_jacobian() {
+    let ref x = ag::placeholder::<f64>(&[-1, 3]);
+    let ref w = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[3, 2]));
+    let ref y = ag::tanh(&ag::matmul(x, w));
+    let ref x_val = ag::ndarray_ext::standard_normal::<f64>(&[4, 3]);
+
+    let ref j = ag::jacobian(y, &[x, w]);
+    let ref expected = ag::jacobians(y, &[x, w], 4 * 2);
+    let ret = ag::eval(
+        &[&j[0], &j[1], &expected[0], &expected[1]],
+        &[ag::Feed(x, x_val.view())],
+    );
+    assert_eq!(ret[0].as_ref().unwrap().shape(), &[8, 12]);
+    assert_eq!(ret[1].as_ref().unwrap().shape(), &[8, 6]);
+    for i in 0..2 {
+        let a = ret[i].as_ref().unwrap();
+        let b = ret[i + 2].as_ref().unwrap();
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-12));
+    }
+
+    // Every row sees the same sample of the random op.
+    let ref r = ag::random_normal(&[3], 0., 1.);
+    let ref v = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[3]));
+    let ref j = ag::jacobian(&(v * r), &[v])[0];
+    let ret = ag::eval(&[j, r], &[]);
+    let (j, r) = (ret[0].as_ref().unwrap(), ret[1].as_ref().unwrap());
+    for i in 0..3 {
+        for k in 0..3 {
+            assert_eq!(j[[i, k]], if i == k { r[i] } else { 0. });
+        }
+    }
+}
+
+#[test]
+fn test_hessian() {
+    let ref x = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[1, 3]));
+    let ref w = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[3, 2]));
+    let ref y = ag::reduce_sum(&ag::tanh(&ag::matmul(x, w)), &[0, 1], false);
+    let ref h = ag::hessian(y, x);
+    // The i-th column of the hessian is `H · e_i`.
+    let columns = (0..3)
+        .map(|i| {
+            let mut e = ag::ndarray_ext::zeros(&[1, 3]);
+            e[[0, i]] = 1.;
+            ag::hvp(&[y], &[x], &[ag::constant(e)]).remove(0)
+        })
+        .collect::<Vec<_>>();
+    let h = h.eval(&[]).unwrap();
+    assert_eq!(h.shape(), &[3, 3]);
+    for (i, col) in columns.iter().enumerate() {
+        let col = col.eval(&[]).unwrap();
+        for k in 0..3 {
+            assert!((h[[k, i]] - col[[0, k]]).abs() < 1e-12);
+            // symmetric
+            assert!((h[[k, i]] - h[[i, k]]).abs() < 1e-12);
+        }
+    }
+}
+
+#[test]
+fn test_custom_gradient() {
+    let ref x = ag::variable(ndarray::arr1(&[1f64, -2., 3.]));
+    let ref w = ag::variable(ndarray::arr1(&[2f64, 2., 2.]));
+    // Clips the gradient only in backward.
+    let ref y = ag::custom_gradient(
+        x,
+        |x| ag::square(x) * w,
+        |gy, x, _| ag::clip(&(gy * 2. * x), -1., 1.),
+    );
+    let ref z = y * 3.;
+    let ref g = ag::grad(&[z], &[x])[0];
+    let ret = ag::eval(&[y, g], &[]);
+    assert_eq!(
+        ret[0].as_ref().unwrap(),
+        &ndarray::arr1(&[2., 8., 18.]).into_dyn()
+    );
+    assert_eq!(
+        ret[1].as_ref().unwrap(),
+        &ndarray::arr1(&[1., -1., 1.]).into_dyn()
+    );
+}
+
+#[test]
+#[should_panic(expected = "Not differentiable")]
+fn test_custom_gradient_stops_forward() {
+    let ref x = ag::variable(ndarray::arr1(&[1f64, -2., 3.]));
+    let ref w = ag::variable(ndarray::arr1(&[2f64, 2., 2.]));
+    let ref y = ag::custom_gradient(x, |x| x * w, |gy, _, _| gy.clone());
+    // Same as `stop_gradient`: nothing flows into the forward graph.
+    ag::grad(&[y], &[w]);
+}
+
+#[test]
+fn test_checkpoint() {
+    let ref x = ag::placeholder::<f64>(&[-1, 3]);
+    let ref w = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[3, 3]));
+    let cell = |xs: &[ag::Tensor<f64>]| {
+        let ref h = ag::tanh(&ag::matmul(&xs[0], &xs[2]));
+        vec![h * &xs[1], ag::sigmoid(h)]
+    };
+
+    // Unrolls 3 steps with and without checkpointing.
+    let (mut h0, mut c0) = (x.clone(), ag::ones(&x.shape()));
+    let (mut h1, mut c1) = (x.clone(), ag::ones(&x.shape()));
+    for _ in 0..3 {
+        let ys = cell(&[h0, c0, w.clone()]);
+        h0 = ys[0].clone();
+        c0 = ys[1].clone();
+        let ys = ag::checkpoint(cell, &[&h1, &c1, w]);
+        h1 = ys[0].clone();
+        c1 = ys[1].clone();
+    }
+    let ref l0 = ag::reduce_sum(&(h0 * c0), &[0, 1], false);
+    let ref l1 = ag::reduce_sum(&(h1 * c1), &[0, 1], false);
+    let ref g0 = ag::grad(&[l0], &[w, x]);
+    let ref g1 = ag::grad(&[l1], &[w, x]);
+
+    let ref x_val = ag::ndarray_ext::standard_normal(&[2, 3]);
+    let graph = ag::CompiledGraph::new(&[&g0[0], &g0[1], &g1[0], &g1[1]], &[x]);
+    let mut profile = ag::Profile::new();
+    let ret = graph.run_profiled(&[x_val.view()], &mut profile);
+    for i in 0..2 {
+        let a = ret[i].as_ref().unwrap();
+        let b = ret[i + 2].as_ref().unwrap();
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-12));
+    }
+    // Each segment is computed twice in total: in forward and backprop.
+    let tanh_calls = profile
+        .by_op()
+        .iter()
+        .find(|s| s.op_name == "Tanh")
+        .unwrap()
+        .calls;
+    assert_eq!(tanh_calls, 3 + 3 * 2);
+
+    // Each segment is differentiated once for both of its outputs.
+    let matmul_calls = |g: &[ag::Tensor<f64>]| {
+        let graph = ag::CompiledGraph::new(&[&g[0], &g[1]], &[x]);
+        let mut profile = ag::Profile::new();
+        graph.run_profiled(&[x_val.view()], &mut profile);
+        let by_op = profile.by_op();
+        by_op.iter().find(|s| s.op_name == "MatMul").unwrap().calls
+    };
+    assert_eq!(matmul_calls(g1), matmul_calls(g0) + 3);
+
+    // Forward mode
+    let ref t = ag::ones(&[2, 3]);
+    let ref jvps = ag::jvp(&[l0, l1], &[x], &[t]);
+    let ret = ag::eval(jvps, &[ag::Feed(x, x_val.view())]);
+    let (a, b) = (ret[0].as_ref().unwrap(), ret[1].as_ref().unwrap());
+    assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-12));
+}
+
+#[test]
+fn test_optimizer_trait() {
+    fn train<O: Optimizer<f32>>(opt: &O) -> (f32, f32) {
+        let ref w = ag::variable(ndarray::arr1(&[1., -2., 3.]).into_dyn());
+        let ref loss = ag::reduce_sum(&ag::square(w), &[0], false);
+        let ref update = opt.compute_updates(&[w], &ag::grad(&[loss], &[w]));
+        let before = loss.eval(&[]).unwrap()[ndarray::IxDyn(&[])];
+        for _ in 0..10 {
+            ag::eval(update, &[]);
+        }
+        (before, loss.eval(&[]).unwrap()[ndarray::IxDyn(&[])])
+    }
+    let (before, after) = train(&ag::gradient_descent_ops::SGD::new(0.1));
+    assert!(after < before);
+    let (before, after) = train(&ag::gradient_descent_ops::Adam::new(0.1, 1e-8, 0.9, 0.999));
+    assert!(after < before);
+
+    // Adam keeps the same moments for a variable across calls.
+    let adam = ag::gradient_descent_ops::Adam::default();
+    let ref w = ag::variable(ag::ndarray_ext::zeros::<f32>(&[2]));
+    let ref g = ag::ones(&[2]);
+    let a = adam.compute_updates(&[w], &[g]).remove(0);
+    let b = adam.compute_updates(&[w], &[g]).remove(0);
+    assert_eq!(a.inputs[2].id(), b.inputs[2].id());
+    assert_eq!(a.inputs[3].id(), b.inputs[3].id());
+
+    // The former API still works.
+    #[allow(deprecated)]
+    let params = ag::gradient_descent_ops::Adam::vars_with_states(&[w]);
+    let c = adam.compute_updates(&params, &[g]).remove(0);
+    assert_eq!(a.inputs[2].id(), c.inputs[2].id());
+}
+
+#[test]
+fn test_first_order_optimizers() {
+    use ag::gradient_descent_ops::{Adadelta, Adagrad, MomentumSGD, RMSProp};
+
+    // Two steps from 1 with the constant gradient 2
+    fn two_steps<O: Optimizer<f64>>(opt: &O) -> f64 {
+        let ref w = ag::variable(ndarray::arr1(&[1.]).into_dyn());
+        let ref g = ag::constant(ndarray::arr1(&[2.]).into_dyn());
+        let ref update = opt.compute_updates(&[w], &[g]);
+        ag::eval(update, &[]);
+        ag::eval(update, &[]);
+        let w = w.get_persistent_array().unwrap()[0];
+        w
+    }
+    let assert_close = |a: f64, b: f64| assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+
+    // v = 2, 3.8
+    assert_close(
+        two_steps(&MomentumSGD::new(0.1, 0.9, false)),
+        1. - 0.1 * 5.8,
+    );
+    assert_close(
+        two_steps(&MomentumSGD::new(0.1, 0.9, true)),
+        1. - 0.1 * (2. + 0.9 * 2.) - 0.1 * (2. + 0.9 * 3.8),
+    );
+    // ms = 0.4, 0.76
+    assert_close(
+        two_steps(&RMSProp::new(0.1, 0.9, 0., 0., false)),
+        1. - 0.2 / 0.4f64.sqrt() - 0.2 / 0.76f64.sqrt(),
+    );
+    // mg = 0.2, 0.38
+    let mom1 = 0.2 / (0.4f64 - 0.04).sqrt();
+    let mom2 = 0.5 * mom1 + 0.2 / (0.76f64 - 0.38 * 0.38).sqrt();
+    assert_close(
+        two_steps(&RMSProp::new(0.1, 0.9, 0.5, 0., true)),
+        1. - mom1 - mom2,
+    );
+    // acc = 4.1, 8.1
+    assert_close(
+        two_steps(&Adagrad::new(0.1, 0.1, 0.)),
+        1. - 0.2 / 4.1f64.sqrt() - 0.2 / 8.1f64.sqrt(),
+    );
+    let (rho, eps) = (0.9f64, 1e-2);
+    let (mut acc, mut acc_delta, mut w) = (0f64, 0f64, 1.);
+    for _ in 0..2 {
+        acc = rho * acc + (1. - rho) * 4.;
+        let delta = 2. * (acc_delta + eps).sqrt() / (acc + eps).sqrt();
+        acc_delta = rho * acc_delta + (1. - rho) * delta * delta;
+        w -= delta;
+    }
+    assert_close(two_steps(&Adadelta::new(1., rho, eps)), w);
+
+    // Sparse gradients update the touched rows the same as the dense ones.
+    let ref indices = ag::constant(ndarray::arr1(&[2., 0., 2.]));
+    let updated = |sparse: bool| {
+        let ref v = ag::variable(ag::ndarray_ext::ones::<f64>(&[4, 2]));
+        let g = ag::grad(&[&ag::gather(v, indices, 0)], &[v]).remove(0);
+        let g = if sparse { g } else { ag::identity(&g) };
+        let opt = RMSProp::new(0.1, 0.9, 0.5, 1e-7, true);
+        ag::eval(&opt.compute_updates(&[v], &[g]), &[]);
+        let v = v.get_persistent_array().unwrap().clone();
+        v
+    };
+    let sparse = updated(true);
+    assert_eq!(sparse, updated(false));
+    assert_eq!(sparse[[1, 0]], 1.);
+    assert!(sparse[[2, 0]] < 1.);
+
+    // `centered` can be turned off after the mean gradient is made.
+    let ref w = ag::variable(ag::ndarray_ext::ones::<f64>(&[3, 2]));
+    let ref g = ag::constant(ag::ndarray_ext::ones::<f64>(&[3, 2]) * 2.);
+    let mut opt = RMSProp::new(0.1, 0.9, 0., 0., true);
+    ag::eval(&opt.compute_updates(&[w], &[g]), &[]);
+    opt.centered = false;
+    ag::eval(&opt.compute_updates(&[w], &[g]), &[]);
+    for &w in w.get_persistent_array().unwrap().iter() {
+        assert_close(w, 1. - 0.2 / 0.36f64.sqrt() - 0.2 / 0.76f64.sqrt());
+    }
+}
+
+#[test]
+fn test_adamw_and_amsgrad() {
+    use ag::gradient_descent_ops::Adam;
+
+    // Scalar reference of the update of `w` with gradients `gs`
+    let reference = |gs: &[f64], wd: f64, amsgrad: bool| {
+        let (alpha, eps, b1, b2) = (0.1f64, 1e-8, 0.9, 0.999);
+        let (mut w, mut m, mut v, mut v_max) = (1f64, 0f64, 0f64, 0f64);
+        for (t, &g) in gs.iter().enumerate() {
+            let t = t as f64 + 1.;
+            m = b1 * m + (1. - b1) * g;
+            v = b2 * v + (1. - b2) * g * g;
+            v_max = v_max.max(v);
+            let v = if amsgrad { v_max } else { v };
+            let m_hat = m / (1. - b1.powf(t)) / ((v / (1. - b2.powf(t))).sqrt() + eps);
+            w -= alpha * (m_hat + wd * w);
+        }
+        w
+    };
+    let gs = [2., 0., 0.];
+    let run = |adam: &mut Adam<f64>, exclude: bool| {
+        let ref w = ag::variable(ndarray::arr1(&[1.]).into_dyn());
+        let ref g = ag::placeholder(&[1]);
+        if exclude {
+            adam.exclude_from_weight_decay(&[w]);
+        }
+        let ref update = adam.compute_updates(&[w], &[g]);
+        for &gi in &gs {
+            let gi = ndarray::arr1(&[gi]).into_dyn();
+            ag::eval(update, &[ag::Feed(g, gi.view())]);
+        }
+        let w = w.get_persistent_array().unwrap()[0];
+        w
+    };
+    let new_adam = || Adam::new(0.1, 1e-8, 0.9, 0.999);
+    let assert_close = |a: f64, b: f64| assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+
+    assert_close(run(&mut new_adam(), false), reference(&gs, 0., false));
+    let adamw = || {
+        let mut adam = new_adam();
+        adam.weight_decay = 0.5;
+        adam
+    };
+    assert_close(run(&mut adamw(), false), reference(&gs, 0.5, false));
+    assert_close(run(&mut adamw(), true), reference(&gs, 0., false));
+    let mut amsgrad = new_adam();
+    amsgrad.amsgrad = true;
+    assert_close(run(&mut amsgrad, false), reference(&gs, 0., true));
+    assert!(reference(&gs, 0., true) != reference(&gs, 0., false));
+
+    // `amsgrad` can be turned off after `v_max` is made.
+    let mut amsgrad = new_adam();
+    amsgrad.amsgrad = true;
+    let ref w = ag::variable(ag::ndarray_ext::ones::<f64>(&[3, 2]));
+    let ref g = ag::constant(ag::ndarray_ext::ones::<f64>(&[3, 2]) * 2.);
+    ag::eval(&amsgrad.compute_updates(&[w], &[g]), &[]);
+    amsgrad.amsgrad = false;
+    ag::eval(&amsgrad.compute_updates(&[w], &[g]), &[]);
+    // `v` only grows with the constant gradient, so `v_max` is the same as `v`.
+    for &w in w.get_persistent_array().unwrap().iter() {
+        assert_close(w, reference(&[2., 2.], 0., false));
+    }
+}
+
+#[test]
+fn test_adam_shared_timestep() {
+    let adam = ag::gradient_descent_ops::Adam::new(0.1, 1e-8, 0.9, 0.999);
+    let ref a = ag::variable(ag::ndarray_ext::zeros::<f64>(&[2]));
+    let ref b = ag::variable(ag::ndarray_ext::zeros::<f64>(&[2]));
+    let ref g = ag::ones(&[2]);
+    let update_a = adam.compute_updates(&[a], &[g]);
+    let update_b = adam.compute_updates(&[b], &[g]);
+    for _ in 0..3 {
+        ag::eval(&update_a, &[]);
+    }
+    assert_eq!(adam.timestep().get(), 3);
+    ag::eval(&[&update_a[0], &update_b[0]], &[]);
+    assert_eq!(adam.timestep().get(), 4);
+
+    // The first update of `b` is bias-corrected with the shared `t = 4`.
+    let t = 4.;
+    let m_hat = 0.1 / (1. - 0.9f64.powf(t));
+    let v_hat = 0.001 / (1. - 0.999f64.powf(t));
+    let expected = -0.1 * m_hat / (v_hat.sqrt() + 1e-8);
+    assert!((b.get_persistent_array().unwrap()[0] - expected).abs() < 1e-12);
+
+    // Rebuilt ops continue from the timestep.
+    ag::eval(&adam.compute_updates(&[b], &[g]), &[]);
+    assert_eq!(adam.timestep().get(), 5);
+}
+
+#[test]
+fn test_optimizer_state() {
+    use ag::gradient_descent_ops::*;
+
+    // Compares 5 steps of training with 3 steps followed by 2 steps of a fresh optimizer
+    // restored from the file.
+    fn check<O: Optimizer<f64>>(
+        name: &str,
+        configured: &dyn Fn(&ag::Tensor<f64>) -> O,
+        mut fresh: O,
+    ) {
+        let init = ag::ndarray_ext::standard_normal::<f64>(&[3, 2]);
+        let train = |opt: &O, w: &ag::Tensor<f64>, steps| {
+            let ref loss = ag::reduce_sum(&ag::square(&ag::sin(w)), &[0, 1], false);
+            let ops = opt.compute_updates(&[w], &ag::grad(&[loss], &[w]));
+            for _ in 0..steps {
+                ag::eval(&ops, &[]);
+            }
+        };
+        let ref w = ag::variable(init.clone());
+        train(&configured(w), w, 5);
+
+        let ref w1 = ag::variable(init);
+        let opt = configured(w1);
+        train(&opt, w1, 3);
+        let path = std::env::temp_dir().join(format!("autograd_test_state_{}", name));
+        opt.save_state(&[w1], &path).unwrap();
+
+        let ref w2 = ag::variable(w1.get_persistent_array().unwrap().clone());
+        fresh.restore_state(&[w2], &path).unwrap();
+        train(&fresh, w2, 2);
+        assert_eq!(
+            *w.get_persistent_array().unwrap(),
+            *w2.get_persistent_array().unwrap(),
+            "{}",
+            name
+        );
+    }
+
+    check("sgd", &|_| SGD::new(0.1), SGD::new(1.));
+    check(
+        "momentum",
+        &|_| MomentumSGD::new(0.1, 0.9, true),
+        MomentumSGD::new(1., 0., false),
+    );
+    check(
+        "rmsprop",
+        &|_| RMSProp::new(0.01, 0.8, 0.5, 1e-6, true),
+        RMSProp::default(),
+    );
+    check(
+        "adagrad",
+        &|_| Adagrad::new(0.1, 0.2, 1e-6),
+        Adagrad::default(),
+    );
+    check(
+        "adadelta",
+        &|_| Adadelta::new(0.5, 0.9, 1e-5),
+        Adadelta::default(),
+    );
+    let adam = |_: &ag::Tensor<f64>| {
+        let mut adam = Adam::adamw(0.01);
+        adam.amsgrad = true;
+        adam
+    };
+    check("adam", &adam, Adam::default());
+    let adam_no_decay = |w: &ag::Tensor<f64>| {
+        let mut adam = Adam::adamw(0.01);
+        adam.exclude_from_weight_decay(&[w]);
+        adam
+    };
+    check("adam_no_decay", &adam_no_decay, Adam::default());
+
+    // Shape mismatch
+    let ref w = ag::variable(ag::ndarray_ext::zeros::<f64>(&[2]));
+    let ref v = ag::variable(ag::ndarray_ext::zeros::<f64>(&[3]));
+    let opt = MomentumSGD::new(0.1, 0.9, false);
+    ag::eval(&opt.compute_updates(&[w], &[ag::ones(&[2])]), &[]);
+    let state = opt.state(&[w]);
+    assert_eq!(
+        state.arrays["0/velocity"],
+        ndarray::arr1(&[1., 1.]).into_dyn()
+    );
+    // Copied into the existing slot
+    let mut opt = MomentumSGD::new(1., 0., true);
+    let ref update = opt.compute_updates(&[w], &[ag::ones(&[2])]);
+    opt.load_state(&[w], &state).unwrap();
+    assert_eq!(opt.state(&[w]), state);
+    let err = opt.load_state(&[v], &state);
+    assert_eq!(err.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    // Missing array
+    let mut missing = state.clone();
+    missing.arrays.clear();
+    let err = opt.load_state(&[w], &missing).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("0/velocity"));
+    // Out of range
+    let mut corrupt = Adam::<f64>::default().state(&[]);
+    corrupt
+        .hyper_params
+        .insert("timestep".to_string(), std::f64::NAN);
+    let err = Adam::default().load_state(&[], &corrupt).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    corrupt.hyper_params.insert("timestep".to_string(), -1.);
+    let err = Adam::default().load_state(&[], &corrupt).unwrap_err();
+    assert!(err.to_string().contains("timestep"));
+    // velocity = 0 * 1 + 1
+    ag::eval(update, &[]);
+    assert_eq!(
+        opt.state(&[w]).arrays["0/velocity"],
+        state.arrays["0/velocity"]
+    );
+}
+
+#[test]
+fn test_lr_schedules() {
+    use ag::gradient_descent_ops::{
+        ExponentialDecay, GlobalStep, LearningRateSchedule, ScheduledSGD, SGD,
+    };
+
+    let schedule = ExponentialDecay {
+        initial_lr: 0.1,
+        decay_rate: 0.5,
+        decay_steps: 1,
+        staircase: true,
+    };
+    let step = GlobalStep::new();
+    let ref w = ag::variable(ag::ndarray_ext::zeros::<f64>(&[2]));
+    let ref g = ag::ones(&[2]);
+    let ref constant = SGD { lr: 1. }.compute_updates(&[w], &[g]);
+    let sgd = ScheduledSGD::new(schedule.clone(), &step);
+    let mut train_ops = sgd.compute_updates(&[w], &[g]);
+    train_ops.push(step.increment());
+
+    for _ in 0..3 {
+        ag::eval(&train_ops, &[]);
+    }
+    assert_eq!(step.get(), 3);
+    let expected = -(0..3).map(|i| schedule.lr(i)).sum::<f64>();
+    assert_eq!(w.get_persistent_array().unwrap()[0], expected);
+
+    // Plain SGD keeps the constant rate.
+    ag::eval(constant, &[]);
+    assert_eq!(w.get_persistent_array().unwrap()[0], expected - 1.);
+
+    // The count is exact beyond the precision of `f32`.
+    step.set(1 << 24);
+    ag::eval(&[step.increment::<f32>()], &[]);
+    assert_eq!(step.get(), (1 << 24) + 1);
+}
+
+#[test]
+fn test_sparse_updates() {
+    let init = ag::ndarray_ext::standard_normal::<f64>(&[5, 3]);
+    let ref indices = ag::constant(ndarray::arr1(&[1., 3., 1.]));
+    let ref w = ag::constant(ag::ndarray_ext::standard_normal::<f64>(&[3, 3]));
+    let sgd = ag::gradient_descent_ops::SGD::new(0.1);
+
+    let updates = |sparse: bool| {
+        let adam = ag::gradient_descent_ops::Adam::default();
+        let ref a = ag::variable(init.clone());
+        let ref b = ag::variable(init.clone());
+        let grad_of = |v: &ag::Tensor<f64>| {
+            let ref loss = ag::gather(v, indices, 0) * w;
+            let g = ag::grad(&[loss], &[v]).remove(0);
+            assert!(g.indexed_slices.is_some());
+            // `identity` drops the sparse form.
+            if sparse {
+                g
+            } else {
+                ag::identity(&g)
+            }
+        };
+        let mut ops = sgd.compute_updates(&[a], &[grad_of(a)]);
+        ops.extend(adam.compute_updates(&[b], &[grad_of(b)]));
+
+        let mut profile = ag::Profile::new();
+        let graph = ag::CompiledGraph::new(&ops, &[] as &[ag::Tensor<f64>]);
+        graph.run_profiled(&[], &mut profile);
+        graph.run_profiled(&[], &mut profile);
+        let dense_calls = profile
+            .records
+            .iter()
+            .filter(|r| r.op_name == "GatherGrad")
+            .count();
+        assert_eq!(dense_calls, if sparse { 0 } else { 4 });
+        let a = a.get_persistent_array().unwrap().clone();
+        let b = b.get_persistent_array().unwrap().clone();
+        (a, b)
+    };
+
+    let (a0, b0) = updates(true);
+    let (a1, b1) = updates(false);
+    assert!(a0.iter().zip(&a1).all(|(x, y)| (x - y).abs() < 1e-12));
+    // Untouched rows
+    let row = |a: &ag::NdArray<f64>, i| a.index_axis(ndarray::Axis(0), i).to_owned();
+    for &i in &[0, 2, 4] {
+        assert_eq!(row(&a0, i), row(&init, i));
+        assert_eq!(row(&b0, i), row(&init, i));
+    }
+    // Touched rows are updated the same as the dense update while all the gradients
+    // of the other rows are zero.
+    assert!(b0.iter().zip(&b1).all(|(x, y)| (x - y).abs() < 1e-12));
+
+    // Accumulated gradients of multiple lookups stay sparse.
+    let ref v = ag::variable(init.clone());
+    let ref y =
+        ag::gather(v, indices, 0) + ag::gather_common(v, &ag::constant(ndarray::arr1(&[-1.])), 0);
+    let g = ag::grad(&[y], &[v]).remove(0);
+    let slices = g.indexed_slices.as_ref().unwrap();
+    let ret = ag::eval(&[&slices.indices, &slices.values], &[]);
+    assert_eq!(ret[0].as_ref().unwrap().shape(), &[4]);
+    assert_eq!(ret[1].as_ref().unwrap().shape(), &[4, 3]);
+}
+
+#[test]
+fn test_variable_checkpoint() {
+    use ag::checkpoint::{self, Checkpoint, CheckpointError};
+
+    let ref w = ag::variable(ag::ndarray_ext::standard_normal::<f32>(&[3, 2]));
+    let ref b = ag::variable(ag::ndarray_ext::standard_normal::<f32>(&[2]));
+    let path = std::env::temp_dir().join("autograd_test_checkpoint");
+    checkpoint::save(&path, &[("w", w), ("b", b)]).unwrap();
+
+    let ref w2 = ag::variable(ag::ndarray_ext::zeros::<f32>(&[3, 2]));
+    let ref b2 = ag::variable(ag::ndarray_ext::zeros::<f32>(&[2]));
+    checkpoint::load(&path, &[("b", b2), ("w", w2)]).unwrap();
+    assert_eq!(
+        *w.get_persistent_array().unwrap(),
+        *w2.get_persistent_array().unwrap()
+    );
+    assert_eq!(
+        *b.get_persistent_array().unwrap(),
+        *b2.get_persistent_array().unwrap()
+    );
+
+    // Missing and unexpected keys; nothing is loaded
+    let ref c = ag::variable(ag::ndarray_ext::zeros::<f32>(&[2]));
+    match checkpoint::load(&path, &[("w", c), ("c", c)]) {
+        Err(CheckpointError::KeyMismatch {
+            missing,
+            unexpected,
+        }) => {
+            assert_eq!(missing, vec!["c"]);
+            assert_eq!(unexpected, vec!["b"]);
+        }
+        _ => panic!("keys should mismatch"),
+    }
+
+    // Shape mismatch
+    let ref w3 = ag::variable(ag::ndarray_ext::zeros::<f32>(&[2, 3]));
+    match checkpoint::load(&path, &[("w", w3), ("b", b2)]) {
+        Err(CheckpointError::ShapeMismatch {
+            key,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(key, "w");
+            assert_eq!(expected, vec![2, 3]);
+            assert_eq!(actual, vec![3, 2]);
+        }
+        _ => panic!("shapes should mismatch"),
+    }
+    assert_eq!(w3.get_persistent_array().unwrap().sum(), 0.);
+
+    // f32 arrays are converted to f64
+    let ckpt = Checkpoint::<f64>::read(&path).unwrap();
+    let w_f64 = w.get_persistent_array().unwrap().mapv(|a| a as f64);
+    assert_eq!(ckpt.arrays["w"], w_f64);
+
+    // Corrupted lengths are reported without allocating for them.
+    let bytes = std::fs::read(&path).unwrap();
+    let corrupt = |offset: usize| {
+        let mut bytes = bytes.clone();
+        bytes[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let path = std::env::temp_dir().join("autograd_test_checkpoint_corrupt");
+        std::fs::write(&path, bytes).unwrap();
+        Checkpoint::<f32>::read(&path).unwrap_err().kind()
+    };
+    // magic, number of arrays, length of "b", "b", float size, ndim, shape
+    assert_eq!(corrupt(16), std::io::ErrorKind::InvalidData);
+    assert_eq!(corrupt(34), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_npy() {
+    use ag::ndarray::ShapeBuilder;
+    use ag::ndarray_ext::npy;
+    use std::io::Write;
+
+    let dir = std::env::temp_dir();
+    let a = ag::ndarray_ext::standard_normal::<f64>(&[2, 3, 4]);
+    npy::save_npy(dir.join("autograd_test_c.npy"), &a).unwrap();
+    assert_eq!(
+        npy::load_npy::<f64, _>(dir.join("autograd_test_c.npy")).unwrap(),
+        a
+    );
+
+    // Fortran order is kept in the file, and read back in C order.
+    let f = ag::NdArray::from_shape_vec(
+        ag::ndarray::IxDyn(&[2, 3]).f(),
+        vec![1f32, 4., 2., 5., 3., 6.],
+    )
+    .unwrap();
+    let mut buf = Vec::new();
+    npy::write_npy(&mut buf, &f).unwrap();
+    assert_eq!(buf.len() % 64, 24);
+    assert!(String::from_utf8_lossy(&buf[..64]).contains("'fortran_order': True"));
+    let c = npy::read_npy::<f32, _>(&mut &buf[..]).unwrap();
+    assert!(c.is_standard_layout());
+    assert_eq!(
+        c,
+        ag::ndarray::arr2(&[[1., 2., 3.], [4., 5., 6.]]).into_dyn()
+    );
+
+    // Big-endian float32 in Fortran order, as written by NumPy, read as f64
+    let mut buf = b"\x93NUMPY\x01\x00\x76\x00".to_vec();
+    let header = "{'descr': '>f4', 'fortran_order': True, 'shape': (2, 3), }";
+    buf.extend_from_slice(format!("{:<117}\n", header).as_bytes());
+    for &a in &[1f32, 4., 2., 5., 3., 6.] {
+        buf.write_all(&a.to_be_bytes()).unwrap();
+    }
+    let d = npy::read_npy::<f64, _>(&mut &buf[..]).unwrap();
+    assert_eq!(
+        d,
+        ag::ndarray::arr2(&[[1., 2., 3.], [4., 5., 6.]]).into_dyn()
+    );
+
+    // .npz
+    let path = dir.join("autograd_test.npz");
+    let s = ag::ndarray_ext::from_scalar(2f32);
+    npy::save_npz(&path, &[("w", &f), ("s", &s)]).unwrap();
+    let arrays = npy::load_npz::<f32, _>(&path).unwrap();
+    assert_eq!(arrays.keys().collect::<Vec<_>>(), vec!["s", "w"]);
+    assert_eq!(arrays["w"], f);
+    assert_eq!(arrays["s"], s);
+
+    let w = ag::variable(arrays["w"].clone());
+    assert_eq!(w.eval(&[]).unwrap(), c);
+}
+
+#[test]
+fn test_graph_to_dot() {
+    let ref x = ag::placeholder::<f32>(&[-1, 3]);
+    let ref w = ag::variable(ag::ndarray_ext::zeros(&[3, 2]));
+    let ref b = ag::constant(ag::ndarray_ext::zeros(&[1, 2]));
+    let ref y = ag::reduce_sum(&(ag::matmul(x, w) + b), &[0, 1], false);
+    let ref g = ag::grad(&[y], &[w]);
+
+    let dot = ag::graph_to_dot(&[y], false);
+    assert!(dot.contains("label=\"MatMul\", shape=box"));
+    assert!(dot.contains("[-1, 3]\\nplaceholder"));
+    assert!(dot.contains("[3, 2]\\nvariable"));
+    assert!(dot.contains("[1, 2]\\nconstant"));
+    assert!(!dot.contains("cluster_gradients"));
+    let edges = dot.lines().filter(|l| l.contains("->")).count();
+
+    // Gradient nodes are added in a cluster, with edges into the forward graph.
+    let with_grads = ag::graph_to_dot(&[y, &g[0]], true);
+    assert!(with_grads.contains("subgraph cluster_gradients"));
+    assert!(with_grads.lines().filter(|l| l.contains("->")).count() > edges);
+    assert!(!ag::graph_to_dot(&[y, &g[0]], false).contains("cluster_gradients"));
+}
+
+#[test]
+fn test_graph_def() {
+    use ag::graph_def::GraphDef;
+
+    // A small CNN
+    let ref x = ag::placeholder::<f64>(&[-1, 1, 6, 6]);
+    let ref t = ag::placeholder::<f64>(&[-1, 1]);
+    let ref w1 = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[2, 1, 3, 3]));
+    let ref w2 = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&[8, 3]));
+    let ref b2 = ag::constant(ag::ndarray_ext::ones::<f64>(&[1, 3]));
+    let ref h = ag::elu(&ag::conv2d(x, w1, 1, 1), 0.5);
+    let ref h = ag::max_pool2d(h, 2, 0, 2);
+    let ref h = ag::reshape(h, &[-1, 18]);
+    let ref h = ag::slice(h, &[0, 2], &[-1, 10]);
+    let ref logits = ag::clip(&(ag::matmul(h, w2) + b2), -10., 10.);
+    let ref y = ag::softmax(logits, 1);
+    let ref loss = ag::reduce_mean(&ag::sparse_softmax_cross_entropy(logits, t), &[0], false);
+
+    let path = std::env::temp_dir().join("autograd_test_graph");
+    GraphDef::new(&[x, t], &[y, loss])
+        .unwrap()
+        .save(&path)
+        .unwrap();
+    let def = GraphDef::<f64>::load(&path).unwrap();
+    assert_eq!(def, GraphDef::new(&[x, t], &[y, loss]).unwrap());
+    let graph = def.build().unwrap();
+
+    let x_val = ag::ndarray_ext::standard_normal(&[4, 1, 6, 6]);
+    let t_val = ag::ndarray::arr2(&[[0.], [1.], [2.], [1.]]).into_dyn();
+    let expected = ag::eval(
+        &[y, loss],
+        &[ag::Feed(x, x_val.view()), ag::Feed(t, t_val.view())],
+    );
+    let (x2, t2) = (&graph.inputs[0], &graph.inputs[1]);
+    let actual = ag::eval(
+        &graph.outputs,
+        &[ag::Feed(x2, x_val.view()), ag::Feed(t2, t_val.view())],
+    );
+    assert_eq!(expected, actual);
+
+    // The loaded graph can be trained too.
+    let mut vars = Vec::new();
+    ag::test_helper::visit_once(&graph.outputs[1], &mut |t: &ag::Tensor<f64>| {
+        if t.is_variable() {
+            vars.push(t.clone())
+        }
+    });
+    assert_eq!(vars.len(), 2);
+    let g = ag::grad(&[&graph.outputs[1]], &[&vars[0]]);
+    let g_val = g[0].eval(&[ag::Feed(x2, x_val.view()), ag::Feed(t2, t_val.view())]);
+    assert!(g_val.is_some());
+
+    // Placeholders must be in the inputs, and ops must be registered.
+    assert!(GraphDef::new(&[x], &[loss]).is_err());
+    let ref hooked = y.p();
+    assert!(GraphDef::new(&[x], &[hooked]).is_err());
+
+    // Attributes out of the range of the requested type are invalid.
+    let attrs = ag::op::Attributes::new().with("axis", -1i64);
+    assert_eq!(attrs.get::<isize>("axis").unwrap(), -1);
+    let err = attrs.get::<usize>("axis").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_named_tensors() {
+    use ag::graph_def::GraphDef;
+
+    let ref x = ag::placeholder_named::<f32>("x", &[-1, 3]);
+    let ref y = ag::name_scope("layer1", || {
+        let ref w = ag::name_scope("dense", || {
+            ag::variable_named("w", ag::ndarray_ext::zeros(&[3, 2]))
+        });
+        let ref b = ag::constant_named("b", ag::ndarray_ext::zeros(&[1, 2]));
+        ag::Tensor::builder()
+            .set_inputs(vec![&ag::matmul(x, w), b])
+            .set_name("out")
+            .build(FailingOp)
+    });
+    // Scopes are left even if the closure panics.
+    let result = std::panic::catch_unwind(|| ag::name_scope("broken", || panic!()));
+    assert!(result.is_err());
+    let ref z = ag::variable_named::<f32, _>("z", ag::ndarray_ext::zeros(&[1]));
+    assert_eq!(z.name(), Some("z"));
+    assert_eq!(ag::matmul(x, x).name(), None);
+
+    let names = ag::named_tensors(&[y]);
+    assert_eq!(
+        names.keys().collect::<Vec<_>>(),
+        ["layer1/b", "layer1/dense/w", "layer1/out", "x"]
+    );
+    let w = ag::get_tensor_by_name(&[y], "layer1/dense/w").unwrap();
+    assert!(w.is_variable());
+    assert!(ag::get_tensor_by_name(&[y], "w").is_none());
+    assert_eq!(format!("{:?}", w), "layer1/dense/w (Variable)");
+
+    // Errors, hooks and graph dumps show the names.
+    let arr = ag::ndarray_ext::zeros(&[4, 3]);
+    let err = ag::try_eval(&[y], &[ag::Feed(x, arr.view())]).unwrap_err();
+    assert_eq!(err.tensor_name.as_ref().unwrap(), "layer1/out");
+    assert_eq!(err.input_names, vec!["MatMul", "layer1/b (Const)"]);
+    assert!(err.to_string().starts_with("layer1/out (FailingOp) failed"));
+    let err = ag::try_eval(&[y], &[]).unwrap_err();
+    assert_eq!(err.tensor_name.as_ref().unwrap(), "x");
+    ag::eval(&[&w.p()], &[]);
+    let dot = ag::graph_to_dot(&[y], false);
+    assert!(dot.contains("label=\"layer1/dense/w\\nVariable\\n[3, 2]\\nvariable\""));
+
+    // Names are kept in graph definitions.
+    let mut registry = ag::graph_def::OpRegistry::default();
+    registry.register("FailingOp", |_| Ok(Box::new(FailingOp)));
+    let def = GraphDef::new_with_registry(&[x], &[y], &registry).unwrap();
+    let path = std::env::temp_dir().join("autograd_test_named_graph");
+    def.save(&path).unwrap();
+    let loaded = GraphDef::<f32>::load(&path).unwrap();
+    assert_eq!(def, loaded);
+    let graph = ag::name_scope("copy", || loaded.build_with_registry(&registry).unwrap());
+    assert_eq!(graph.inputs[0].name(), Some("copy/x"));
+    let names = ag::named_tensors(&graph.outputs);
+    assert!(names["copy/layer1/dense/w"].is_variable());
+}
@@ -10,6 +10,15 @@ fn get() {
     ag::test_helper::check_theoretical_grads(z, g.as_slice(), &[v], &[], 1e-3, 1e-3);
 }
 
+#[test]
+fn get_second_order() {
+    let ref v = ag::variable(ndarray::arr1(&[1., 2., 3.]));
+    let ref z: ag::Tensor<f64> = ag::square(v.get(1));
+    let ref g = ag::reduce_sum(&ag::grad(&[z], &[v])[0], &[0], false);
+    let ref gg = ag::grad(&[g], &[v]);
+    ag::test_helper::check_theoretical_grads(g, gg.as_slice(), &[v], &[], 1e-3, 1e-3);
+}
+
 #[test]
 fn add_n() {
     let ref v1 = ag::variable(ndarray::arr1(&[1., 2., 3.]));
@@ -174,6 +183,19 @@ fn matmul() {
     ag::test_helper::check_theoretical_grads(z, g.as_slice(), &[v], &[], 1e-3, 0.005);
 }
 
+#[test]
+fn matmul_transposed() {
+    for &(transpose_a, transpose_b) in &[(false, true), (true, false), (true, true)] {
+        let a_shape = if transpose_a { [2, 4] } else { [4, 2] };
+        let v_shape = if transpose_b { [3, 2] } else { [2, 3] };
+        let ref a = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&a_shape));
+        let ref v = ag::variable(ag::ndarray_ext::standard_normal::<f64>(&v_shape));
+        let ref z = ag::matmul_t(a, v, transpose_a, transpose_b);
+        let ref g = ag::grad(&[z], &[a, v]);
+        ag::test_helper::check_theoretical_grads(z, g.as_slice(), &[a, v], &[], 1e-3, 0.005);
+    }
+}
+
 #[test]
 fn batch_matmul() {
     let ref a = ag::constant(ag::ndarray_ext::standard_normal(&[2, 4, 2]));